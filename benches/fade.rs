@@ -0,0 +1,71 @@
+//! Allocations per update during a fade
+//!
+//! Runs a fade from 6500K to 3500K over three outputs through the ramp
+//! cache, the way the randr method does in continual mode, and counts
+//! the allocations made by each update. Run with `cargo bench`.
+
+extern crate redshift_rs;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use redshift_rs::colorramp::RampCache;
+use redshift_rs::transition::{ColorSetting, Kelvin};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const OUTPUTS: usize = 3;
+const RAMP_SIZE: usize = 1024;
+const TICKS: usize = 300;
+
+fn main() {
+    let mut caches: Vec<_> = (0..OUTPUTS).map(|_| RampCache::new()).collect();
+    let mut setting = ColorSetting::new();
+    let mut scratch = vec![0u16; RAMP_SIZE];
+
+    let mut per_tick = Vec::with_capacity(TICKS);
+    let start = Instant::now();
+    for tick in 0..TICKS {
+        // Every tick is a new temperature, so each one misses the cache
+        setting.temp = Kelvin(6500.0 - tick as f64 * 10.0);
+        setting.brightness = 1.0 - tick as f64 / TICKS as f64 * 0.3;
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for cache in caches.iter_mut() {
+            let ramps = cache.get(&setting, RAMP_SIZE);
+            scratch.copy_from_slice(&ramps.0);
+        }
+        per_tick.push(ALLOCATIONS.load(Ordering::Relaxed) - before);
+    }
+    let elapsed = start.elapsed();
+
+    let warm = &per_tick[20..];
+    println!("{} ticks, {} outputs, {} entry ramps", TICKS, OUTPUTS, RAMP_SIZE);
+    println!("allocations while filling the cache: {}", per_tick[..20].iter().sum::<usize>());
+    println!("allocations per tick once full:      {:.2}",
+             warm.iter().sum::<usize>() as f64 / warm.len() as f64);
+    println!("time per tick:                       {:.3} ms",
+             (elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 / 1e6) / TICKS as f64);
+}
@@ -0,0 +1,64 @@
+//! Generate build information for `redshift-rs --version --verbose`
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+/// The features declared in the `[features]` section of Cargo.toml,
+/// leaving out the implicit ones of optional dependencies
+fn declared_features() -> Vec<String> {
+    let manifest = fs::read_to_string(Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.toml")).unwrap();
+    manifest.lines()
+        .map(|line| line.trim())
+        .skip_while(|&line| line != "[features]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.find('=').map(|eq| line[..eq].trim().to_owned()))
+        .filter(|name| !name.is_empty() && name != "default")
+        .collect()
+}
+
+fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let mut f = File::create(Path::new(&out_dir).join("build_info.rs")).unwrap();
+
+    let mut features: Vec<String> = declared_features().into_iter()
+        .filter(|name| env::var_os(format!("CARGO_FEATURE_{}", name.to_uppercase().replace('-', "_"))).is_some())
+        .collect();
+    features.sort();
+
+    let commit = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .and_then(|out| if out.status.success() { String::from_utf8(out.stdout).ok() } else { None })
+        .map(|s| s.trim().to_owned());
+
+    writeln!(f, "pub const FEATURES: &'static [&'static str] = &{:?};", features).unwrap();
+    writeln!(f, "pub const GIT_COMMIT: Option<&'static str> = {:?};", commit).unwrap();
+    writeln!(f, "pub const TARGET: &'static str = {:?};", env::var("TARGET").unwrap()).unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    // HEAD only changes when switching branches, a commit moves the
+    // branch it points to, which may be packed. Missing files would
+    // rerun the script on every build, so only existing ones are
+    // watched.
+    let mut watched = vec![".git/HEAD".to_owned(), ".git/packed-refs".to_owned()];
+    if let Ok(head) = fs::read_to_string(".git/HEAD") {
+        if head.starts_with("ref: ") {
+            let branch = Path::new(".git").join(head["ref: ".len()..].trim());
+            // A packed branch gets a file of its own at the next commit
+            match branch.exists() {
+                true => watched.push(branch.display().to_string()),
+                false => watched.extend(branch.parent().map(|dir| dir.display().to_string())),
+            }
+        }
+    }
+    for path in watched.iter().filter(|path| Path::new(path).exists()) {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
@@ -0,0 +1,40 @@
+//! Load arbitrary config files, and parse the values in them like the
+//! binary does
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate redshift_rs;
+
+use std::str;
+
+use redshift_rs::config::Config;
+use redshift_rs::settings;
+
+fuzz_target!(|data: &[u8]| {
+    let contents = match str::from_utf8(data) {
+        Ok(contents) => contents,
+        Err(_) => return
+    };
+    let conf = match Config::parse(contents) {
+        Ok(conf) => conf,
+        Err(_) => return
+    };
+
+    let main = conf.main_section();
+    for key in &["temp-day", "temp-night"] {
+        if let Some(value) = conf.get_from(main, key) {
+            let _ = settings::parse_manual_temperature(value);
+        }
+    }
+    if let Some(value) = conf.get_from(main, "gamma") {
+        let _ = settings::parse_gamma(value);
+    }
+    if let Some(value) = conf.get_from(main, "brightness") {
+        let _ = settings::parse_brightness(value);
+    }
+    for key in &["start-dim", "full-night", "start-brighten", "full-day"] {
+        if let Some(value) = conf.get_from(main, key) {
+            let _ = settings::parse_keyframe(key, value);
+        }
+    }
+    let _ = conf.error::<()>(main, "temp-day", String::new());
+});
@@ -0,0 +1,24 @@
+//! Feed arbitrary strings to the command line value parsers
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate redshift_rs;
+
+use std::str;
+
+use redshift_rs::location::Location;
+use redshift_rs::settings;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(input) = str::from_utf8(data) {
+        let _ = settings::parse_temperature(input);
+        let _ = settings::parse_manual_temperature(input);
+        let _ = settings::parse_brightness(input);
+        let _ = settings::parse_gamma(input);
+        let _ = settings::parse_method_option("randr", input);
+        let _ = settings::parse_sweep(input);
+        let _ = settings::parse_time(input, 0.0);
+        let _ = settings::parse_keyframe("start-dim", input);
+        let _ = settings::parse_quantize_temp(input);
+        let _ = input.parse::<Location>();
+    }
+});
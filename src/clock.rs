@@ -0,0 +1,215 @@
+//! Where the time comes from
+//!
+//! Times are seconds since the epoch (UTC) everywhere, as f64. Local
+//! time only comes in when printing or parsing, through `local_tm`, so
+//! the solar elevation, and so the period, cannot be thrown off by
+//! daylight saving changes. A `Clock` lets the engine be run on a
+//! simulated time, as frontends and tests may want.
+//!
+//! Times are printed with `format_time` and `format_clock`, in 24 or
+//! 12 hour format as set by `set_time_format`.
+
+use std::ffi::CStr;
+use std::mem;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+use time;
+
+/// How to print times of day (`time-format`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeFormat {
+    /// As the LC_TIME locale does
+    Locale,
+    /// 21:45
+    Hours24,
+    /// 9:45 PM
+    Hours12,
+}
+
+static TWELVE_HOUR: AtomicBool = AtomicBool::new(false);
+
+/// Print times in `format` from now on (24 hour until called)
+pub fn set_time_format(format: TimeFormat) {
+    let twelve_hour = match format {
+        TimeFormat::Locale => locale_is_12_hour(),
+        TimeFormat::Hours24 => false,
+        TimeFormat::Hours12 => true,
+    };
+    TWELVE_HOUR.store(twelve_hour, Ordering::Relaxed);
+}
+
+/// Whether the time format of the LC_TIME locale has AM and PM
+///
+/// The locale is only set for the lookup, so nothing else changes
+/// with it.
+fn locale_is_12_hour() -> bool {
+    unsafe {
+        if libc::setlocale(libc::LC_TIME, b"\0".as_ptr() as *const libc::c_char).is_null() {
+            return false
+        }
+        let format = CStr::from_ptr(libc::nl_langinfo(libc::T_FMT)).to_string_lossy().into_owned();
+        libc::setlocale(libc::LC_TIME, b"C\0".as_ptr() as *const libc::c_char);
+        is_12_hour(&format)
+    }
+}
+
+/// Whether the strftime format `format` has AM and PM
+fn is_12_hour(format: &str) -> bool {
+    ["%r", "%I", "%l", "%p"].iter().any(|conversion| format.contains(conversion))
+}
+
+/// Local time of day, as "21:45" or "9:45 PM"
+pub fn format_clock(t: f64) -> String {
+    let format = if TWELVE_HOUR.load(Ordering::Relaxed) { "%l:%M %p" } else { "%H:%M" };
+    time::strftime(format, &local_tm(t))
+        .map(|s| s.trim_start().to_owned())
+        .unwrap_or_else(|_| format!("@{}", t as i64))
+}
+
+/// Local date and time, as "YYYY-MM-DD 21:45" or "YYYY-MM-DD 9:45 PM"
+pub fn format_time(t: f64) -> String {
+    time::strftime("%Y-%m-%d", &local_tm(t))
+        .map(|date| format!("{} {}", date, format_clock(t)))
+        .unwrap_or_else(|_| format!("@{}", t as i64))
+}
+
+pub trait Clock: Send + Sync {
+    /// The current time, in seconds since the epoch
+    fn now(&self) -> f64;
+
+    /// Wait for `duration`, which a simulated clock may only count
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+
+    /// Wait for `duration`, or until `alarm` rings
+    fn sleep_or_alarm(&self, duration: Duration, alarm: &Alarm) {
+        alarm.wait(duration);
+    }
+}
+
+/// Cuts a `Clock::sleep_or_alarm` short, from another thread
+#[derive(Default)]
+pub struct Alarm {
+    rung: Mutex<bool>,
+    rings: Condvar,
+}
+
+impl Alarm {
+    pub fn new() -> Alarm {
+        Alarm::default()
+    }
+
+    /// End the wait going on, or else the next one
+    pub fn ring(&self) {
+        *self.rung.lock().unwrap() = true;
+        self.rings.notify_all();
+    }
+
+    /// Whether it rang since it was last silenced, silencing it
+    pub fn take(&self) -> bool {
+        mem::replace(&mut *self.rung.lock().unwrap(), false)
+    }
+
+    /// Wait for `duration`, or until it rings, returning whether it
+    /// did
+    pub fn wait(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut rung = self.rung.lock().unwrap();
+        while !*rung {
+            let now = Instant::now();
+            if now >= deadline {
+                return false
+            }
+            rung = self.rings.wait_timeout(rung, deadline - now).unwrap().0;
+        }
+        *rung = false;
+        true
+    }
+}
+
+/// The system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        systemtime_get_time()
+    }
+}
+
+/// The system time, in seconds since the epoch
+///
+/// Leap seconds are not counted: the system clock repeats or smears
+/// them, so at worst the time goes back by a second, which makes no
+/// visible difference to the elevation.
+pub fn systemtime_get_time() -> f64 {
+    let now = time::get_time();
+    now.sec as f64 + (now.nsec.max(0).min(999_999_999) as f64 / 1_000_000_000.0)
+}
+
+/// The whole second `t` is in, rounding down also before the epoch
+fn timespec(t: f64) -> time::Timespec {
+    time::Timespec::new(t.floor() as i64, 0)
+}
+
+/// `t` in local time, for printing and for dates
+pub fn local_tm(t: f64) -> time::Tm {
+    time::at(timespec(t))
+}
+
+/// `t` in UTC, for printing
+pub fn utc_tm(t: f64) -> time::Tm {
+    time::at_utc(timespec(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{Alarm, is_12_hour, utc_tm};
+
+    #[test]
+    fn test_utc_tm() {
+        // 2020-12-31 23:59:59.5 UTC, which must not round into 2021
+        let tm = utc_tm(1609459199.5);
+        assert_eq!((tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_sec), (2020, 12, 31, 59));
+        let tm = utc_tm(-0.5);
+        assert_eq!((tm.tm_year + 1900, tm.tm_sec), (1969, 59));
+    }
+
+    #[test]
+    fn test_is_12_hour() {
+        // C, de_DE and en_US in glibc
+        assert!(!is_12_hour("%H:%M:%S"));
+        assert!(!is_12_hour("%T"));
+        assert!(is_12_hour("%r"));
+        assert!(is_12_hour("%I:%M:%S %p"));
+    }
+
+    #[test]
+    fn alarm_cuts_waits_short() {
+        let alarm = Arc::new(Alarm::new());
+        assert!(!alarm.wait(Duration::from_millis(10)));
+
+        // Rung before the wait, which then ends at once
+        alarm.ring();
+        assert!(alarm.wait(Duration::from_secs(60)));
+        assert!(!alarm.take());
+
+        let ringer = alarm.clone();
+        let start = Instant::now();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            ringer.ring();
+        });
+        assert!(alarm.wait(Duration::from_secs(60)));
+        assert!(start.elapsed() < Duration::from_secs(30));
+        handle.join().unwrap();
+    }
+}
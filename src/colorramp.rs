@@ -1,4 +1,194 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use transition;
+use settings::{MIN_TEMP, MAX_TEMP};
+use super::NEUTRAL_TEMP;
+
+// For the whitepoint table, which is only set from the config file
+#[cfg(feature = "config")]
+use std::{fs::File, io::Read, path::Path, sync::RwLock};
+#[cfg(feature = "config")]
+use super::Result;
+
+#[cfg(feature = "config")]
+lazy_static! {
+    /// A user supplied table replacing BLACKBODY_COLOR
+    static ref WHITEPOINT_TABLE: RwLock<Option<WhitepointTable>> = RwLock::new(None);
+}
+
+/// White points at (not necessarily evenly spaced) temperatures,
+/// read from a CSV file of `KELVIN,R,G,B` rows
+///
+/// Only set from the config file, so only there with the config
+/// feature.
+#[cfg(feature = "config")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhitepointTable {
+    /// Sorted by temperature
+    rows: Vec<(f64, [f64; 3])>,
+}
+
+#[cfg(feature = "config")]
+impl WhitepointTable {
+
+    pub fn load(path: &Path) -> Result<WhitepointTable> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        WhitepointTable::parse(&contents)
+    }
+
+    /// Parse the CSV rows. Empty lines, lines starting with '#' and a
+    /// header line are skipped.
+    pub fn parse(contents: &str) -> Result<WhitepointTable> {
+        let mut rows: Vec<(f64, [f64; 3])> = vec![];
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let fields: Vec<_> = line.split(',').map(|field| field.trim().parse::<f64>()).collect();
+            let row = match fields.as_slice() {
+                &[Ok(k), Ok(r), Ok(g), Ok(b)] => (k, [r, g, b]),
+                _ if rows.is_empty() && n == 0 => continue,
+                _ => return malformed(format!("line {}: expected KELVIN,R,G,B", n + 1))
+            };
+            if !(row.1.iter().all(|&c| c >= 0.0 && c <= 1.0)) {
+                return malformed(format!("line {}: R, G and B must be between 0 and 1", n + 1))
+            }
+            if rows.last().map_or(false, |last| !(row.0 > last.0)) {
+                return malformed(format!("line {}: temperatures must be increasing", n + 1))
+            }
+            rows.push(row);
+        }
+        if rows.len() < 2 {
+            return malformed(format!("at least two rows are needed"))
+        }
+        Ok(WhitepointTable { rows: rows })
+    }
+
+    /// The white point at `temp`, interpolated between the nearest
+    /// rows and clamped to the ends of the table
+    fn white_point(&self, temp: f64) -> [f64; 3] {
+        let first = self.rows[0];
+        let last = self.rows[self.rows.len() - 1];
+        if temp <= first.0 {
+            return first.1
+        }
+        if temp >= last.0 {
+            return last.1
+        }
+        let i = self.rows.iter().position(|row| row.0 > temp).unwrap();
+        let (low, high) = (self.rows[i - 1], self.rows[i]);
+        interpolate_color((temp - low.0) / (high.0 - low.0), &low.1, &high.1)
+    }
+}
+
+/// Use `table` instead of the built-in blackbody table from now on
+#[cfg(feature = "config")]
+pub fn set_whitepoint_table(table: WhitepointTable) {
+    *WHITEPOINT_TABLE.write().unwrap() = Some(table);
+}
+
+#[cfg(feature = "config")]
+#[inline]
+fn malformed<T>(msg: String) -> Result<T> {
+    Err(From::from(format!("whitepoint table: {}", msg)))
+}
+
+/// The white point at `temp` in the table set with
+/// `set_whitepoint_table`, if any
+#[cfg(feature = "config")]
+fn custom_white_point(temp: f64) -> Option<[f64; 3]> {
+    WHITEPOINT_TABLE.read().unwrap().as_ref().map(|table| table.white_point(temp))
+}
+
+#[cfg(not(feature = "config"))]
+fn custom_white_point(_temp: f64) -> Option<[f64; 3]> {
+    None
+}
+
+/// Whether white points are scaled up to keep their luminance
+static PRESERVE_LUMINANCE: AtomicBool = AtomicBool::new(false);
+
+/// Contribution of linear red, green and blue to luminance (Y), for
+/// sRGB primaries
+const LUMINANCE_WEIGHTS: [f64; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Keep the luminance of white at that of neutral white from now on,
+/// instead of dimming the screen as the temperature drops
+pub fn set_preserve_luminance(preserve: bool) {
+    PRESERVE_LUMINANCE.store(preserve, Ordering::Relaxed);
+}
+
+/// Scale `white` so that its luminance is 1, as far as channels
+/// clipping at 1 allow
+///
+/// Light lost to a clipped channel is made up by the others, so at
+/// low temperatures, where red is already at 1, green and blue are
+/// raised further.
+fn preserve_luminance(white: [f64; 3]) -> [f64; 3] {
+    let mut white = white;
+    // Each round clips at least one more channel, or finishes
+    for _ in 0..3 {
+        let (mut clipped, mut unclipped) = (0.0, 0.0);
+        for c in 0..3 {
+            if white[c] >= 1.0 {
+                clipped += LUMINANCE_WEIGHTS[c];
+            } else {
+                unclipped += LUMINANCE_WEIGHTS[c] * white[c];
+            }
+        }
+        if unclipped <= 0.0 || clipped + unclipped >= 1.0 {
+            break
+        }
+        let scale = (1.0 - clipped) / unclipped;
+        for c in 0..3 {
+            if white[c] < 1.0 {
+                white[c] = (white[c] * scale).min(1.0);
+            }
+        }
+    }
+    white
+}
+
+/// The relative red, green and blue of white at `temp`, from the
+/// whitepoint table if one is set, else from the blackbody table
+pub fn white_point(temp: transition::Kelvin) -> [f64; 3] {
+    let temp = temp.0.max(MIN_TEMP as f64).min(MAX_TEMP as f64);
+    let white = match custom_white_point(temp) {
+        Some(white_points) => white_points,
+        None => {
+            let alpha = (temp % 100.0) / 100.0;
+            let temp_index = ((temp - MIN_TEMP as f64) / 100.0) as usize * 3;
+            interpolate_color(alpha,
+                              &BLACKBODY_COLOR[temp_index..temp_index+3],
+                              &BLACKBODY_COLOR[temp_index+3..temp_index+6])
+        }
+    };
+    if PRESERVE_LUMINANCE.load(Ordering::Relaxed) {
+        preserve_luminance(white)
+    } else {
+        white
+    }
+}
+
+/// Approximate contribution of the sRGB red, green and blue primaries
+/// to melanopic light (what the eye's ipRGCs, which set the body clock,
+/// respond to), at full intensity: the luminance weights times the
+/// primaries' melanopic daylight efficacy ratios (CIE S 026), scaled to
+/// sum to 1
+const MELANOPIC_WEIGHTS: [f64; 3] = [0.005, 0.42, 0.575];
+
+/// How much less melanopic ("blue") light white emits at `temp` and
+/// `brightness` than at 6500K and full brightness, from 0 to 1
+///
+/// Only a rough number: it assumes an sRGB display, and leaves out
+/// gamma, which does not change white.
+pub fn melanopic_reduction(temp: transition::Kelvin, brightness: f64) -> f64 {
+    let melanopic = |white: [f64; 3]| (0..3).map(|c| MELANOPIC_WEIGHTS[c] * white[c]).sum::<f64>();
+    let neutral = melanopic(white_point(NEUTRAL_TEMP.into()));
+    (1.0 - brightness * melanopic(white_point(temp)) / neutral).max(0.0).min(1.0)
+}
 
 /**
  * Generate color ramps from the given color setting and ramp size,
@@ -10,12 +200,7 @@ pub fn fill(gamma_r: &mut [u16],
             setting: &transition::ColorSetting,
             size: usize)
 {
-    let alpha = (setting.temp as f64 % 100.0) / 100.0;
-    let temp_index = (((setting.temp - 1000)/100)*3) as usize;
-
-    let white_points = interpolate_color(alpha,
-                                         &BLACKBODY_COLOR[temp_index..temp_index+3],
-                                         &BLACKBODY_COLOR[temp_index+3..temp_index+6]);
+    let white_points = white_point(setting.temp);
 
     let gammas = [gamma_r, gamma_g, gamma_b];
 
@@ -34,6 +219,133 @@ pub fn fill(gamma_r: &mut [u16],
     }
 }
 
+pub type Ramps = (Vec<u16>, Vec<u16>, Vec<u16>);
+
+/// Number of ramps kept by a `RampCache`
+const RAMP_CACHE_SIZE: usize = 16;
+
+/// Steps of the temperature in a `RampKey`, in Kelvin
+const TEMP_QUANTUM: f64 = 10.0;
+
+/// Steps of brightness and gamma in a `RampKey`
+const VALUE_QUANTUM: f64 = 0.01;
+
+/// Ramp size, temperature (10K steps), brightness and gamma (both in
+/// steps of 0.01)
+type RampKey = (usize, i32, i64, [i64; 3]);
+
+/// A small least-recently-used cache of ramps computed from identity
+/// ramps
+///
+/// During fades consecutive settings are close together, so settings
+/// are rounded to 10K and 0.01 steps to make them hit the cache. Steps
+/// smaller than that (--smooth, or slow transitions) would be lost to
+/// the rounding, so those ramps are computed exactly instead. Asking
+/// for the same setting again, as every CRTC after the first does,
+/// gives the same kind of ramps as the first time.
+pub struct RampCache {
+    /// Least recently used first
+    entries: Vec<(RampKey, Ramps)>,
+
+    /// The setting asked for last
+    last: Option<transition::ColorSetting>,
+
+    /// The ramps last computed exactly, bypassing the cache
+    exact: Ramps,
+
+    /// The setting of `exact`, if the last setting was computed exactly
+    exact_setting: Option<transition::ColorSetting>,
+}
+
+/// Whether going from `a` to `b` changes nothing by as much as the
+/// rounding of a `RampKey`
+fn below_quantum(a: &transition::ColorSetting, b: &transition::ColorSetting) -> bool {
+    (a.temp.0 - b.temp.0).abs() < TEMP_QUANTUM
+        && (a.brightness - b.brightness).abs() < VALUE_QUANTUM
+        && (0..3).all(|c| (a.gamma[c] - b.gamma[c]).abs() < VALUE_QUANTUM)
+}
+
+impl RampCache {
+    pub fn new() -> RampCache {
+        RampCache {
+            entries: Vec::with_capacity(RAMP_CACHE_SIZE),
+            last: None,
+            exact: (vec![], vec![], vec![]),
+            exact_setting: None,
+        }
+    }
+
+    /// The ramps of the given size for `setting`, rounded unless it
+    /// only moved a little since the last call
+    pub fn get(&mut self, setting: &transition::ColorSetting, size: usize) -> &Ramps {
+        let repeated = self.exact_setting.as_ref() == Some(setting);
+        let fine = repeated || self.last.as_ref().map_or(false, |last| last != setting && below_quantum(last, setting));
+        self.last = Some(setting.clone());
+        if fine {
+            if !repeated || self.exact.0.len() != size {
+                fill_from_identity(&mut self.exact, setting, size);
+                self.exact_setting = Some(setting.clone());
+            }
+            return &self.exact
+        }
+        self.exact_setting = None;
+
+        let quantize = |v: f64| (v / VALUE_QUANTUM).round() as i64;
+        let key = (size,
+                   (setting.temp.0 / TEMP_QUANTUM).round() as i32 * TEMP_QUANTUM as i32,
+                   quantize(setting.brightness),
+                   [quantize(setting.gamma[0]), quantize(setting.gamma[1]), quantize(setting.gamma[2])]);
+
+        match self.entries.iter().position(|&(ref k, _)| *k == key) {
+            Some(i) => {
+                let entry = self.entries.remove(i);
+                self.entries.push(entry);
+            }
+            None => {
+                // Reuse the buffers of the evicted entry, so that a
+                // full cache no longer allocates
+                let mut ramps = match self.entries.len() {
+                    RAMP_CACHE_SIZE => self.entries.remove(0).1,
+                    _ => (vec![], vec![], vec![])
+                };
+                let rounded = transition::ColorSetting {
+                    temp: key.1.into(),
+                    brightness: key.2 as f64 * VALUE_QUANTUM,
+                    gamma: [key.3[0] as f64 * VALUE_QUANTUM, key.3[1] as f64 * VALUE_QUANTUM, key.3[2] as f64 * VALUE_QUANTUM],
+                };
+                fill_from_identity(&mut ramps, &rounded, size);
+                self.entries.push((key, ramps));
+            }
+        }
+        &self.entries.last().unwrap().1
+    }
+}
+
+/// Whether `ramp` is (close enough to) a linear ramp, so that
+/// adjusting it gives the same result as adjusting an identity ramp
+pub fn is_identity(ramp: &[u16]) -> bool {
+    let u16_max1 = u16::max_value() as f64 + 1.0;
+    let size = ramp.len() as f64;
+    ramp.iter().enumerate().all(|(i, &v)| {
+        let linear = i as f64 / size * u16_max1;
+        (v as f64 - linear).abs() <= 256.0
+    })
+}
+
+/// Compute the ramps of `size` for `setting` from identity ramps,
+/// into `ramps` (only allocating if their size differs)
+fn fill_from_identity(ramps: &mut Ramps, setting: &transition::ColorSetting, size: usize) {
+    let u16_max1 = u16::max_value() as f64 + 1.0;
+    let (ref mut r, ref mut g, ref mut b) = *ramps;
+    for ramp in [&mut *r, &mut *g, &mut *b].iter_mut() {
+        ramp.resize(size, 0);
+        for (i, v) in ramp.iter_mut().enumerate() {
+            *v = ((i as f64 / size as f64) * u16_max1) as u16;
+        }
+    }
+    fill(&mut r[..], &mut g[..], &mut b[..], setting, size);
+}
+
 fn interpolate_color<'a>(a: f64, c1: &'a[f64], c2: &'a[f64]) -> [f64; 3] {
     [(1.0-a)*c1[0] + a*c2[0],
      (1.0-a)*c1[1] + a*c2[1],
@@ -288,3 +600,92 @@ const BLACKBODY_COLOR: [f64; 726] = [
 	0.62774186,  0.75306977,  1.00000000, /* 25000K */
 	0.62740336,  0.75282962,  1.00000000  /* 25100K */
 ];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use transition::{ColorSetting, Kelvin};
+
+    #[test]
+    #[cfg(feature = "config")]
+    fn whitepoint_table_interpolates() {
+        let table = WhitepointTable::parse("kelvin,r,g,b\n# comment\n1000,1,0.5,0\n\n3000,1,1,1\n").unwrap();
+        assert_eq!(table.white_point(500.0), [1.0, 0.5, 0.0]);
+        assert_eq!(table.white_point(2000.0), [1.0, 0.75, 0.5]);
+        assert_eq!(table.white_point(9000.0), [1.0, 1.0, 1.0]);
+
+        assert!(WhitepointTable::parse("1000,1,1,1\n").is_err());
+        assert!(WhitepointTable::parse("2000,1,1,1\n1000,1,1,1\n").is_err());
+        assert!(WhitepointTable::parse("1000,1,1,1\n2000,1,1\n").is_err());
+        assert!(WhitepointTable::parse("1000,1,1,1\n2000,1,1,NaN\n").is_err());
+    }
+
+    #[test]
+    fn luminance_is_preserved() {
+        let luminance = |white: [f64; 3]| (0..3).map(|c| LUMINANCE_WEIGHTS[c] * white[c]).sum::<f64>();
+
+        // Red is at 1 at 4500K, so only green and blue are raised
+        let warm = preserve_luminance([1.0, 0.8, 0.6]);
+        assert!((luminance(warm) - 1.0).abs() < 1e-9);
+        assert_eq!(warm[0], 1.0);
+        assert!(warm[1] > 0.8 && warm[1] <= 1.0 && warm[2] > 0.6 && warm[2] <= 1.0);
+
+        // Green clips before blue can make up the difference
+        let red = preserve_luminance([1.0, 0.2, 0.0]);
+        assert_eq!(red, [1.0, 1.0, 0.0]);
+        assert!(luminance(red) < 1.0);
+
+        assert_eq!(preserve_luminance([1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn melanopic_reduction_grows_as_it_warms() {
+        assert!(melanopic_reduction(6500.into(), 1.0).abs() < 1e-9);
+        assert!((melanopic_reduction(6500.into(), 0.5) - 0.5).abs() < 1e-9);
+        let (warm, warmer) = (melanopic_reduction(4500.into(), 1.0), melanopic_reduction(2500.into(), 1.0));
+        assert!(0.0 < warm && warm < warmer && warmer < 1.0);
+    }
+
+    #[test]
+    fn ramp_cache_rounds_and_evicts() {
+        let mut cache = RampCache::new();
+        let setting = |temp: i32| ColorSetting { temp: temp.into(), gamma: [1.0; 3], brightness: 1.0 };
+
+        let first = cache.get(&setting(4001), 256).clone();
+        cache.get(&setting(5000), 256);
+        assert_eq!(&first, cache.get(&setting(4004), 256));
+        assert_eq!(cache.entries.len(), 2);
+        assert!(first != *cache.get(&setting(4020), 256));
+
+        for temp in 0..RAMP_CACHE_SIZE as i32 {
+            cache.get(&setting(5000 + 10 * temp), 256);
+        }
+        assert_eq!(cache.entries.len(), RAMP_CACHE_SIZE);
+        assert!(cache.entries.iter().all(|&(key, _)| key.1 != 4000));
+    }
+
+    #[test]
+    fn ramp_cache_keeps_small_steps() {
+        let mut cache = RampCache::new();
+        let setting = |temp: f64| ColorSetting { temp: Kelvin(temp), gamma: [1.0; 3], brightness: 1.0 };
+        let exact = |temp: f64| {
+            let mut ramps = (vec![], vec![], vec![]);
+            fill_from_identity(&mut ramps, &setting(temp), 256);
+            ramps
+        };
+
+        cache.get(&setting(4000.0), 256);
+        assert_eq!(cache.get(&setting(4002.5), 256), &exact(4002.5));
+        assert_eq!(cache.get(&setting(4004.0), 256), &exact(4004.0));
+        assert_eq!(cache.entries.len(), 1);
+
+        // Unchanged settings, like the next CRTC asks for, stay exact
+        assert_eq!(cache.get(&setting(4004.0), 256), &exact(4004.0));
+        assert_eq!(cache.get(&setting(4004.0), 256), &exact(4004.0));
+        assert_eq!(cache.get(&setting(4004.0), 1024).0.len(), 1024);
+
+        // Larger steps are rounded, and stay rounded when repeated
+        assert_eq!(cache.get(&setting(4053.0), 256), &exact(4050.0));
+        assert_eq!(cache.get(&setting(4053.0), 256), &exact(4050.0));
+    }
+}
@@ -0,0 +1,340 @@
+//! Loading the configuration file
+//!
+//! The main configuration file may pull in another file with
+//! `include=PATH` (relative to the including file), and fragments in
+//! a `redshift.conf.d` directory next to it are merged in after it,
+//! in alphabetical order. Values from later files override earlier
+//! ones, so an included file overrides the file including it.
+//!
+//! A fragment is read as the main file is: an include in it is
+//! relative to the fragment, and may nest as deeply as the main
+//! file's includes, counting from the fragment.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use ini::Ini;
+use super::{Result, RedshiftError};
+
+/// Maximum depth of nested includes
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+pub type Properties = HashMap<String, String>;
+
+/// The merged contents of all configuration files
+pub struct Config {
+    sections: HashMap<Option<String>, Properties>,
+
+    /// File and line each (section, key) was last set at
+    positions: HashMap<(Option<String>, String), (PathBuf, usize)>,
+}
+
+impl Config {
+
+    /// A configuration with nothing set
+    pub fn new() -> Config {
+        Config { sections: HashMap::new(), positions: HashMap::new() }
+    }
+
+    /// Load the configuration rooted at `path`
+    ///
+    /// Returns `None` if neither the file itself nor any drop-in
+    /// fragments exist.
+    pub fn load(path: &Path) -> Result<Option<Config>> {
+        let mut conf = Config::new();
+        let mut found = false;
+
+        if path.is_file() {
+            conf.merge_file(path, 0)?;
+            found = true;
+        }
+
+        let mut drop_in_dir = path.as_os_str().to_owned();
+        drop_in_dir.push(".d");
+        for fragment in drop_ins(Path::new(&drop_in_dir))? {
+            conf.merge_file(&fragment, 0)?;
+            found = true;
+        }
+
+        Ok(if found { Some(conf) } else { None })
+    }
+
+    /// Parse a single configuration file from a string, without
+    /// following includes
+    pub fn parse(contents: &str) -> Result<Config> {
+        let mut conf = Config::new();
+        conf.merge_str(contents, Path::new("<string>"))?;
+        Ok(conf)
+    }
+
+    pub fn section(&self, name: Option<&str>) -> Option<&Properties> {
+        self.sections.get(&name.map(|s| s.to_owned()))
+    }
+
+    /// The section holding the general settings
+    ///
+    /// This is `[redshift]` as in upstream's config files, but keys at
+    /// the top of the file before any section header are accepted too
+    /// when there is no such section.
+    pub fn main_section(&self) -> Option<&'static str> {
+        if self.sections.contains_key(&Some("redshift".to_owned())) {
+            Some("redshift")
+        } else {
+            None
+        }
+    }
+
+    pub fn get_from(&self, section: Option<&str>, key: &str) -> Option<&str> {
+        self.section(section)
+            .and_then(|props| props.get(key))
+            .map(|value| value.as_str())
+    }
+
+    /// All the (section, key) pairs that are set
+    pub fn keys(&self) -> Vec<(Option<String>, String)> {
+        self.sections.iter()
+            .flat_map(|(section, props)| props.keys().map(move |key| (section.clone(), key.clone())))
+            .collect()
+    }
+
+    /// Where a key was set, as "FILE:LINE"
+    pub fn position(&self, section: Option<&str>, key: &str) -> Option<String> {
+        self.positions.get(&(section.map(|s| s.to_owned()), key.to_owned()))
+            .map(|&(ref path, line)| format!("{}:{}", path.display(), line))
+    }
+
+    /// A MalformedConfig error about `key`, prefixed with where it was set
+    pub fn error<T>(&self, section: Option<&str>, key: &str, msg: String) -> Result<T> {
+        match self.position(section, key) {
+            Some(position) => malformed_config(format!("{}: {}", position, msg)),
+            None => malformed_config(msg)
+        }
+    }
+
+    /// Set everything `other` sets, overriding values set here
+    ///
+    /// The main section of `other` goes into the main section here,
+    /// whether either of them is `[redshift]` or the top of the file.
+    pub fn overlay(&mut self, other: &Config) {
+        let main = match self.sections.is_empty() {
+            true => other.main_section(),
+            false => self.main_section(),
+        }.map(|s| s.to_owned());
+        let other_main = other.main_section().map(|s| s.to_owned());
+        let target = |section: &Option<String>| if *section == other_main { main.clone() } else { section.clone() };
+
+        for (section, props) in &other.sections {
+            let merged = self.sections.entry(target(section)).or_insert_with(HashMap::new);
+            for (key, value) in props {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+        for (&(ref section, ref key), position) in &other.positions {
+            self.positions.insert((target(section), key.clone()), position.clone());
+        }
+    }
+
+    fn merge_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return malformed_config(format!("{}: includes nested too deeply", path.display()));
+        }
+
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .or_else(|e| malformed_config(format!("could not read {}: {}", path.display(), e)))?;
+
+        let include = self.merge_str(&contents, path)?
+            .map(|include| path.parent().unwrap_or(Path::new(".")).join(include));
+        if let Some(include) = include {
+            self.merge_file(&include, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Merge in the contents of the file at `path`, returning the file
+    /// it includes, if any
+    ///
+    /// Each file may have its main settings in `[redshift]` or at the
+    /// top, whatever the others do, as they are overlaid.
+    fn merge_str(&mut self, contents: &str, path: &Path) -> Result<Option<String>> {
+        let ini = Ini::load_from_str(contents)
+            .or_else(|e| malformed_config(format!("could not read {}: {}", path.display(), e)))?;
+
+        let mut file = Config::new();
+        for (section, props) in ini.iter() {
+            let merged = file.sections.entry(section.clone()).or_insert_with(HashMap::new);
+            for (key, value) in props.iter() {
+                merged.insert(key.clone(), value.clone());
+            }
+        }
+        file.record_positions(contents, path);
+        self.overlay(&file);
+
+        Ok(ini.get_from(Some("redshift"), "include")
+           .or_else(|| ini.get_from(None::<String>, "include"))
+           .map(|include| include.to_owned()))
+    }
+
+    /// Find the line of each key in the file. The ini parser does not
+    /// keep track of lines, so this does a rough scan of its own.
+    fn record_positions(&mut self, contents: &str, path: &Path) {
+        let mut section = None;
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('[') && line.ends_with(']') {
+                section = Some(line[1..line.len()-1].trim().to_owned());
+            } else if let Some(eq) = line.find('=') {
+                if !line.starts_with(';') && !line.starts_with('#') {
+                    let key = line[..eq].trim().to_owned();
+                    self.positions.insert((section.clone(), key), (path.to_owned(), n + 1));
+                }
+            }
+        }
+    }
+}
+
+/// The `*.conf` files in `dir`, sorted by name
+fn drop_ins(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(vec![])
+    }
+    let mut fragments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |ext| ext == "conf") && path.is_file() {
+            fragments.push(path);
+        }
+    }
+    fragments.sort();
+    Ok(fragments)
+}
+
+#[inline]
+fn malformed_config<T>(msg: String) -> Result<T> {
+    Err(Box::new(RedshiftError::MalformedConfig(msg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::Config;
+
+    /// An empty directory for one test, with `files` written into it
+    fn dir_with(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("redshift-rs-config-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for &(path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn includes_are_relative_and_override() {
+        let dir = dir_with("include", &[
+            ("redshift.conf", "[redshift]\ntemp-day=5000\ntemp-night=3500\ninclude=local/machine.conf\n"),
+            ("local/machine.conf", "[redshift]\ntemp-day=4800\n[manual]\nlat=10\n"),
+        ]);
+        let conf = Config::load(&dir.join("redshift.conf")).unwrap().unwrap();
+        assert_eq!(conf.get_from(Some("redshift"), "temp-day"), Some("4800"));
+        assert_eq!(conf.get_from(Some("redshift"), "temp-night"), Some("3500"));
+        assert_eq!(conf.get_from(Some("manual"), "lat"), Some("10"));
+        assert_eq!(conf.position(Some("redshift"), "temp-day"),
+                   Some(format!("{}:2", dir.join("local/machine.conf").display())));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn drop_ins_override_in_order() {
+        let dir = dir_with("drop-ins", &[
+            ("redshift.conf", "[redshift]\ntemp-night=3600\ntemp-day=5000\n"),
+            ("redshift.conf.d/20-late.conf", "[redshift]\ntemp-night=3200\n"),
+            ("redshift.conf.d/10-early.conf", "[redshift]\ntemp-night=3000\ngamma=0.9\n"),
+            ("redshift.conf.d/README", "[redshift]\ngamma=0.5\n"),
+        ]);
+        let conf = Config::load(&dir.join("redshift.conf")).unwrap().unwrap();
+        assert_eq!(conf.get_from(Some("redshift"), "temp-night"), Some("3200"));
+        assert_eq!(conf.get_from(Some("redshift"), "temp-day"), Some("5000"));
+        assert_eq!(conf.get_from(Some("redshift"), "gamma"), Some("0.9"));
+
+        // Fragments alone are enough
+        fs::remove_file(dir.join("redshift.conf")).unwrap();
+        let conf = Config::load(&dir.join("redshift.conf")).unwrap().unwrap();
+        assert_eq!(conf.get_from(Some("redshift"), "temp-night"), Some("3200"));
+        assert!(Config::load(&dir.join("other.conf")).unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn includes_of_drop_ins() {
+        let dir = dir_with("drop-in-include", &[
+            ("redshift.conf", "temp-night=3600\ninclude=local.conf\n"),
+            ("local.conf", "[redshift]\ntemp-day=4800\n"),
+            ("redshift.conf.d/10-site.conf", "[redshift]\ninclude=site/night.conf\n"),
+            ("redshift.conf.d/site/night.conf", "temp-night=3000\n"),
+        ]);
+        let conf = Config::load(&dir.join("redshift.conf")).unwrap().unwrap();
+        let main = conf.main_section();
+        assert_eq!(conf.get_from(main, "temp-day"), Some("4800"));
+        assert_eq!(conf.get_from(main, "temp-night"), Some("3000"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // A fragment may nest as deep as the main file, whatever the
+        // main file's own includes, but no deeper
+        let chain: Vec<_> = (0..8).map(|n| (format!("redshift.conf.d/{}.inc", n),
+                                             format!("include={}.inc\n", n + 1))).collect();
+        let mut files: Vec<_> = chain.iter().map(|&(ref path, ref contents)| (path.as_str(), contents.as_str())).collect();
+        files.push(("redshift.conf.d/8.inc", "temp-day=5000\n"));
+        files.push(("redshift.conf", "include=redshift.conf.d/1.inc\n"));
+        files.push(("redshift.conf.d/10-deep.conf", "include=1.inc\n"));
+        let dir = dir_with("drop-in-depth", &files);
+        let conf = Config::load(&dir.join("redshift.conf")).unwrap().unwrap();
+        assert_eq!(conf.get_from(conf.main_section(), "temp-day"), Some("5000"));
+
+        fs::write(dir.join("redshift.conf.d/20-deeper.conf"), "include=0.inc\n").unwrap();
+        let e = Config::load(&dir.join("redshift.conf")).err().unwrap().to_string();
+        assert!(e.contains("includes nested too deeply"), "{}", e);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn main_sections_are_merged() {
+        let dir = dir_with("main-sections", &[
+            ("redshift.conf", "temp-day=5000\ntemp-night=3600\n"),
+            ("redshift.conf.d/10-night.conf", "[redshift]\ntemp-night=3200\n[manual]\nlat=10\n"),
+        ]);
+        let conf = Config::load(&dir.join("redshift.conf")).unwrap().unwrap();
+        let main = conf.main_section();
+        assert_eq!(main, None);
+        assert_eq!(conf.get_from(main, "temp-day"), Some("5000"));
+        assert_eq!(conf.get_from(main, "temp-night"), Some("3200"));
+        assert_eq!(conf.get_from(Some("manual"), "lat"), Some("10"));
+        assert_eq!(conf.position(main, "temp-night"),
+                   Some(format!("{}:2", dir.join("redshift.conf.d/10-night.conf").display())));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn broken_includes_are_errors() {
+        let dir = dir_with("broken", &[
+            ("cycle.conf", "[redshift]\ninclude=cycle-back.conf\n"),
+            ("cycle-back.conf", "[redshift]\ninclude=cycle.conf\n"),
+            ("missing.conf", "[redshift]\ninclude=nowhere.conf\n"),
+        ]);
+        let e = Config::load(&dir.join("cycle.conf")).err().unwrap().to_string();
+        assert!(e.contains("includes nested too deeply"), "{}", e);
+        let e = Config::load(&dir.join("missing.conf")).err().unwrap().to_string();
+        assert!(e.contains("could not read") && e.contains("nowhere.conf"), "{}", e);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
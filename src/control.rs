@@ -0,0 +1,813 @@
+//! The control socket
+//!
+//! Continual mode listens on `$XDG_RUNTIME_DIR/redshift-rs.sock`
+//! (unless `control-socket=0`) for frontends like tray applets. Only
+//! the user can reach it, as the runtime directory is theirs. Each
+//! line sent is a JSON-RPC 2.0 request, and each request with an id is
+//! answered with one line.
+//!
+//! Version 1 of the protocol has these methods:
+//!
+//! - `get_state`: `{"protocol": 1, "enabled": true, "period": "night",
+//!   "temperature": 3500, "brightness": 1, "gamma": [1, 1, 1],
+//!   "offsets": {"temperature": 0, "brightness": 0}}`, the setting
+//!   being the one of the last update
+//! - `set_enabled` `{"enabled": false}`: restore the screen and pause,
+//!   like USR2, or resume with a fade
+//! - `set_offsets` `{"temperature": -500, "brightness": -0.1}`: add to
+//!   what the schedule sets from now on, either being optional
+//! - `neutral` `{"seconds": 10}`: restore the screen, answering once
+//!   it is, and resume after that long, as for taking color accurate
+//!   screenshots. Calling it again starts the time over, and
+//!   `set_enabled` ends it.
+//! - `subscribe` `{"events": ["period", "enabled"]}`: answered like
+//!   `get_state`, and followed by a JSON-RPC notification (a request
+//!   without id) for each event, all of them unless `events` is given:
+//!   - `period` `{"period": "day"}` when the period changes
+//!   - `temperature` `{"temperature": 4000, "brightness": 1}` when
+//!     another setting was set
+//!   - `enabled` `{"enabled": false}` when paused or resumed
+//!   - `error` `{"message": "..."}` when setting it starts failing
+//!
+//! A subscriber that falls `QUEUE_LEN` notifications behind is
+//! disconnected, rather than holding up the updates.
+//!
+//! Later versions only add methods and members, and raise `protocol`.
+//! Errors have the JSON-RPC codes, and `POLICY_VIOLATION` for requests
+//! the system policy forbids, like disabling when `allow-disable=0`.
+//!
+//! With the socket unit `install-service` writes, systemd listens on
+//! the socket, and starts redshift-rs at the first connection, passing
+//! it the socket as described in sd_listen_fds(3).
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use chan;
+use libc;
+
+use engine::{Event, EventSource, Hook};
+use json::{self, Value};
+use location::Location;
+use transition::{ColorSetting, Period, TransitionScheme};
+use super::{Result, RedshiftError, MIN_TEMP, MAX_TEMP, MIN_BRIGHTNESS, MAX_BRIGHTNESS};
+
+pub const PROTOCOL_VERSION: i32 = 1;
+
+const SOCKET_NAME: &'static str = "redshift-rs.sock";
+
+/// The first file descriptor systemd passes
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Longest request line read, in bytes
+const MAX_REQUEST: u64 = 64 * 1024;
+
+/// Lines queued for a client, beyond which a subscriber is dropped
+const QUEUE_LEN: usize = 64;
+
+/// Longest time `neutral` can pause for, in seconds
+const MAX_NEUTRAL_SECS: f64 = 3600.0;
+
+/// How long `neutral` waits for the screen to be restored, in ms
+const NEUTRAL_WAIT: u64 = 1000;
+
+/// The events `subscribe` can ask for
+const EVENTS: &'static [&'static str] = &["period", "temperature", "enabled", "error"];
+
+// JSON-RPC error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+pub const POLICY_VIOLATION: i32 = 1;
+
+/// What the system policy keeps clients from changing
+#[derive(Debug, Clone, Default)]
+pub struct Locks {
+    /// Pausing, as with `allow-disable=0`
+    pub disable: bool,
+    pub temperature: bool,
+    pub brightness: bool,
+}
+
+/// The state reported to clients, kept by the `ControlHook`
+struct Shared {
+    enabled: bool,
+    period: &'static str,
+    setting: Option<ColorSetting>,
+    /// Added to the temperature and brightness of each update
+    offsets: (f64, f64),
+    subscribers: Vec<Subscriber>,
+    /// The `neutral` call that will resume, if one is waiting to
+    neutral: Option<u64>,
+    neutral_calls: u64,
+}
+
+impl Shared {
+    fn new() -> Shared {
+        Shared {
+            enabled: true,
+            period: "none",
+            setting: None,
+            offsets: (0.0, 0.0),
+            subscribers: vec![],
+            neutral: None,
+            neutral_calls: 0,
+        }
+    }
+
+    /// Queue the notification of `event` for the clients subscribed to
+    /// it, dropping those that cannot take it
+    fn notify(&mut self, event: &str, params: Value) {
+        if self.subscribers.is_empty() {
+            return
+        }
+        let line = Value::object(vec![("jsonrpc", "2.0".into()), ("method", event.into()), ("params", params)])
+            .to_string() + "\n";
+        self.subscribers.retain(|subscriber| {
+            if !subscriber.events.iter().any(|e| e == event) {
+                return true
+            }
+            match subscriber.client.lines.try_send(line.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Disconnecting a control client that does not keep up");
+                    subscriber.client.close();
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+/// Where the lines for a client go, to be written on a thread of
+/// their own
+#[derive(Clone)]
+struct Client {
+    lines: SyncSender<String>,
+    stream: Option<Arc<UnixStream>>,
+}
+
+impl Client {
+    fn close(&self) {
+        if let Some(ref stream) = self.stream {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+struct Subscriber {
+    client: Client,
+    events: Vec<String>,
+}
+
+/// Where the control socket is, in the user's runtime directory
+pub fn socket_path() -> Result<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Ok(PathBuf::from(dir).join(SOCKET_NAME)),
+        None => Err(From::from("XDG_RUNTIME_DIR is not set"))
+    }
+}
+
+/// The listening socket, run by the engine as an event source
+pub struct Server {
+    listener: UnixListener,
+    shared: Arc<Mutex<Shared>>,
+    locks: Locks,
+}
+
+impl Server {
+
+    /// Listen on the socket systemd passed, or else at `path`, failing
+    /// if another instance already does
+    ///
+    /// A socket left behind by an instance that did not exit cleanly
+    /// is replaced.
+    pub fn bind(path: &Path, locks: Locks) -> Result<(Server, ControlHook)> {
+        let (listener, owned_path) = match inherited()? {
+            Some(listener) => {
+                debug!("Listening on the control socket passed by systemd");
+                (listener, None)
+            }
+            None => (bind_at(path)?, Some(path.to_owned()))
+        };
+        let shared = Arc::new(Mutex::new(Shared::new()));
+        let hook = ControlHook { shared: shared.clone(), path: owned_path, fade: 0.0, notified: None,
+                                 failing: false };
+        Ok((Server { listener: listener, shared: shared, locks: locks }, hook))
+    }
+}
+
+fn bind_at(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(From::from(format!("another instance is listening on {}", path.display())))
+    }
+    if path.exists() {
+        debug!("Removing stale control socket {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+/// The listening socket systemd passed, if socket activated
+///
+/// The variables are removed, so that the commands run later (like
+/// the cues) do not take the socket for theirs.
+fn inherited() -> Result<Option<UnixListener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    let count = listen_fds(pid.as_ref().map(|s| s.as_str()), fds.as_ref().map(|s| s.as_str()), process::id());
+    for name in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    match count {
+        0 => Ok(None),
+        1 => {
+            let listener = unsafe {
+                libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+                UnixListener::from_raw_fd(LISTEN_FDS_START)
+            };
+            // Fails unless it is a Unix socket
+            listener.local_addr()
+                .map_err(|e| format!("systemd did not pass a Unix socket: {}", e))?;
+            Ok(Some(listener))
+        }
+        count => Err(From::from(format!("systemd passed {} sockets, rather than one", count)))
+    }
+}
+
+/// The number of sockets passed in LISTEN_FDS, if LISTEN_PID is `pid`
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> u32 {
+    if listen_pid.and_then(|listen_pid| listen_pid.parse().ok()) != Some(pid) {
+        return 0
+    }
+    listen_fds.and_then(|fds| fds.parse().ok()).unwrap_or(0)
+}
+
+impl EventSource for Server {
+    fn run(self: Box<Self>, events: chan::Sender<Event>) {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Could not accept a control connection: {}", e);
+                    continue
+                }
+            };
+            let shared = self.shared.clone();
+            let locks = self.locks.clone();
+            let events = events.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve(stream, &shared, &locks, &events) {
+                    debug!("Control connection closed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Answer the requests of one client until it hangs up
+///
+/// Responses and notifications are written by a thread of the
+/// client's, so that a client not reading cannot block the engine.
+fn serve(stream: UnixStream, shared: &Arc<Mutex<Shared>>, locks: &Locks, events: &chan::Sender<Event>)
+         -> Result<()> {
+    let (lines_tx, lines_rx) = mpsc::sync_channel::<String>(QUEUE_LEN);
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        for line in lines_rx.iter() {
+            if writer.write_all(line.as_bytes()).is_err() {
+                break
+            }
+        }
+    });
+    let client = Client { lines: lines_tx, stream: Some(Arc::new(stream.try_clone()?)) };
+
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.by_ref().take(MAX_REQUEST).read_line(&mut line)? == 0 {
+            return Ok(())
+        }
+        if !line.ends_with('\n') && line.len() as u64 == MAX_REQUEST {
+            let response = error_response(Value::Null, INVALID_REQUEST, "request too long");
+            let _ = client.lines.send(format!("{}\n", response));
+            return Ok(())
+        }
+        if line.trim().is_empty() {
+            continue
+        }
+        if let Some(response) = handle(&line, shared, locks, events, &client) {
+            if client.lines.send(format!("{}\n", response)).is_err() {
+                return Ok(())
+            }
+        }
+    }
+}
+
+/// The response to the request `line`, if it has an id
+///
+/// That of `subscribe` is queued for `client` right away instead, so
+/// that it comes before the first notification.
+fn handle(line: &str, shared: &Arc<Mutex<Shared>>, locks: &Locks, events: &chan::Sender<Event>, client: &Client)
+          -> Option<Value> {
+    let request = match json::parse(line) {
+        Ok(request) => request,
+        Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string()))
+    };
+    let id = request.get("id").cloned();
+    let method = match (request.get("jsonrpc").and_then(Value::as_str), request.get("method").and_then(Value::as_str)) {
+        (Some("2.0"), Some(method)) => method,
+        _ => return Some(error_response(id.unwrap_or(Value::Null), INVALID_REQUEST, "not a JSON-RPC 2.0 request"))
+    };
+    let no_params = Value::object(vec![]);
+    let params = match request.get("params") {
+        Some(params @ &Value::Object(_)) => params,
+        None => &no_params,
+        Some(_) => return id.map(|id| error_response(id, INVALID_PARAMS, "params must be an object"))
+    };
+    if method == "subscribe" {
+        return add_subscriber(id, params, shared, client)
+    }
+    let result = call(method, params, shared, locks, events);
+    id.map(|id| match result {
+        Ok(result) => Value::object(vec![("jsonrpc", "2.0".into()), ("result", result), ("id", id)]),
+        Err((code, message)) => error_response(id, code, &message),
+    })
+}
+
+fn error_response(id: Value, code: i32, message: &str) -> Value {
+    Value::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("error", Value::object(vec![("code", code.into()), ("message", message.into())])),
+        ("id", id),
+    ])
+}
+
+/// Run `method`, or fail with a JSON-RPC error code and message
+fn call(method: &str, params: &Value, shared: &Arc<Mutex<Shared>>, locks: &Locks, events: &chan::Sender<Event>)
+        -> ::std::result::Result<Value, (i32, String)> {
+    match method {
+        "get_state" => Ok(state(&shared.lock().unwrap())),
+        "set_enabled" => {
+            let enabled = params.get("enabled").and_then(Value::as_bool)
+                .ok_or_else(|| (INVALID_PARAMS, "enabled must be true or false".to_owned()))?;
+            if !enabled && locks.disable {
+                return Err((POLICY_VIOLATION, "redshift-rs cannot be disabled".to_owned()))
+            }
+            shared.lock().unwrap().neutral = None;
+            events.send(Event::Enable(enabled));
+            Ok(Value::Null)
+        }
+        "neutral" => {
+            let seconds = params.get("seconds").and_then(Value::as_f64)
+                .filter(|&seconds| seconds > 0.0 && seconds <= MAX_NEUTRAL_SECS)
+                .ok_or_else(|| (INVALID_PARAMS, format!("seconds must be a number above 0 and at most {}",
+                                                        MAX_NEUTRAL_SECS)))?;
+            if locks.disable {
+                return Err((POLICY_VIOLATION, "redshift-rs cannot be disabled".to_owned()))
+            }
+            let call = {
+                let mut shared = shared.lock().unwrap();
+                // Paused by other means, and to stay so
+                if !shared.enabled && shared.neutral.is_none() {
+                    return Ok(Value::Null)
+                }
+                shared.neutral_calls += 1;
+                shared.neutral = Some(shared.neutral_calls);
+                shared.neutral_calls
+            };
+            events.send(Event::Enable(false));
+
+            let resume_shared = shared.clone();
+            let resume_events = events.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis((seconds * 1000.0) as u64));
+                let mut shared = resume_shared.lock().unwrap();
+                if shared.neutral == Some(call) {
+                    shared.neutral = None;
+                    drop(shared);
+                    resume_events.send(Event::Enable(true));
+                }
+            });
+
+            // The hook tells when the engine restored the screen
+            for _ in 0..NEUTRAL_WAIT / 10 {
+                if !shared.lock().unwrap().enabled {
+                    break
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok(Value::Null)
+        }
+        "set_offsets" => {
+            let mut offsets = shared.lock().unwrap().offsets;
+            if let Some(temp) = params.get("temperature") {
+                let temp = temp.as_f64().filter(|temp| temp.abs() <= (MAX_TEMP - MIN_TEMP) as f64)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("temperature must be a number of at most {}K either way",
+                                                            MAX_TEMP - MIN_TEMP)))?;
+                if temp != offsets.0 && locks.temperature {
+                    return Err((POLICY_VIOLATION, "the temperature is locked".to_owned()))
+                }
+                offsets.0 = temp;
+            }
+            if let Some(brightness) = params.get("brightness") {
+                let brightness = brightness.as_f64().filter(|brightness| brightness.abs() <= MAX_BRIGHTNESS)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("brightness must be a number of at most {} either way",
+                                                            MAX_BRIGHTNESS)))?;
+                if brightness != offsets.1 && locks.brightness {
+                    return Err((POLICY_VIOLATION, "the brightness is locked".to_owned()))
+                }
+                offsets.1 = brightness;
+            }
+            shared.lock().unwrap().offsets = offsets;
+            events.send(Event::Update);
+            Ok(offsets_value(offsets))
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("no method '{}'", method)))
+    }
+}
+
+/// Add `client` to the subscribers, queuing the response first
+fn add_subscriber(id: Option<Value>, params: &Value, shared: &Mutex<Shared>, client: &Client) -> Option<Value> {
+    let events: Vec<String> = match params.get("events") {
+        None => EVENTS.iter().map(|&event| event.to_owned()).collect(),
+        Some(&Value::Array(ref names)) if names.iter().all(|name| name.as_str().map_or(false, is_event)) =>
+            names.iter().filter_map(Value::as_str).map(|name| name.to_owned()).collect(),
+        Some(_) => return id.map(|id| error_response(id, INVALID_PARAMS,
+                                                     &format!("events must be a list of {}", EVENTS.join(", "))))
+    };
+    let mut shared = shared.lock().unwrap();
+    if let Some(id) = id {
+        let response = Value::object(vec![("jsonrpc", "2.0".into()), ("result", state(&shared)), ("id", id)]);
+        if client.lines.try_send(format!("{}\n", response)).is_err() {
+            client.close();
+            return None
+        }
+    }
+    shared.subscribers.push(Subscriber { client: client.clone(), events: events });
+    None
+}
+
+fn is_event(name: &str) -> bool {
+    EVENTS.contains(&name)
+}
+
+fn state(shared: &Shared) -> Value {
+    let (temp, brightness, gamma) = match shared.setting {
+        Some(ref setting) => (Value::from(setting.temp.round()), Value::from(setting.brightness),
+                              Value::Array(setting.gamma.iter().map(|&g| Value::from(g)).collect())),
+        None => (Value::Null, Value::Null, Value::Null)
+    };
+    Value::object(vec![
+        ("protocol", PROTOCOL_VERSION.into()),
+        ("enabled", shared.enabled.into()),
+        ("period", shared.period.into()),
+        ("temperature", temp),
+        ("brightness", brightness),
+        ("gamma", gamma),
+        ("offsets", offsets_value(shared.offsets)),
+    ])
+}
+
+fn offsets_value(offsets: (f64, f64)) -> Value {
+    Value::object(vec![("temperature", offsets.0.into()), ("brightness", offsets.1.into())])
+}
+
+fn period_name(period: &Period) -> &'static str {
+    match *period {
+        Period::None => "none",
+        Period::Day => "day",
+        Period::Night => "night",
+        Period::Transition(_) => "transition",
+    }
+}
+
+/// Applies the offsets, and keeps the state clients are told and
+/// notifies them
+///
+/// Added after the other hooks, so that the state is the setting they
+/// leave.
+pub struct ControlHook {
+    shared: Arc<Mutex<Shared>>,
+    /// The socket to remove when stopping, unless systemd owns it
+    path: Option<PathBuf>,
+    /// How far a fade has gone to neutral, which the offsets go along
+    fade: f64,
+    /// The temperature and brightness last notified
+    notified: Option<(i32, f64)>,
+    failing: bool,
+}
+
+impl Hook for ControlHook {
+    fn before_update(&mut self, scheme: &mut TransitionScheme, _location: &mut Location, _now: f64) {
+        self.fade = if scheme.short_transition() { scheme.adjustment_alpha } else { 0.0 };
+    }
+
+    fn period_changed(&mut self, period: &Period, _prev: &Period, _scheme: &TransitionScheme,
+                      _location: &Location, _now: f64) {
+        let period = period_name(period);
+        let mut shared = self.shared.lock().unwrap();
+        shared.period = period;
+        shared.notify("period", Value::object(vec![("period", period.into())]));
+    }
+
+    fn adjust(&mut self, setting: &mut ColorSetting, _elev: f64, _period: &Period, _now: f64) -> bool {
+        let mut shared = self.shared.lock().unwrap();
+        let (temp, brightness) = shared.offsets;
+        if temp != 0.0 {
+            setting.temp.0 = (setting.temp.0 + temp * (1.0 - self.fade)).max(MIN_TEMP as f64).min(MAX_TEMP as f64);
+        }
+        if brightness != 0.0 {
+            setting.brightness = (setting.brightness + brightness * (1.0 - self.fade))
+                .max(MIN_BRIGHTNESS).min(MAX_BRIGHTNESS);
+        }
+        shared.setting = Some(setting.clone());
+        false
+    }
+
+    fn applied(&mut self, result: &Result<()>, _retries: u32, _elapsed: Duration, _now: f64) {
+        let mut shared = self.shared.lock().unwrap();
+        match *result {
+            Ok(()) => {
+                self.failing = false;
+                let setting = match shared.setting {
+                    Some(ref setting) => (setting.temp.round(), setting.brightness),
+                    None => return
+                };
+                if self.notified != Some(setting) {
+                    self.notified = Some(setting);
+                    shared.notify("temperature", Value::object(vec![("temperature", setting.0.into()),
+                                                                    ("brightness", setting.1.into())]));
+                }
+            }
+            Err(ref e) if !self.failing => {
+                self.failing = true;
+                shared.notify("error", Value::object(vec![("message", e.to_string().into())]));
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn paused_changed(&mut self, paused: bool, _now: f64) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.enabled = !paused;
+        shared.notify("enabled", Value::object(vec![("enabled", (!paused).into())]));
+        // Resuming sets it again, which is worth telling
+        self.notified = None;
+    }
+
+    fn stopped(&mut self, _now: f64) {
+        // Best effort, as the sandbox may not allow it; the next
+        // instance replaces the socket anyway
+        if let Some(ref path) = self.path {
+            if let Err(e) = fs::remove_file(path) {
+                debug!("Could not remove the control socket: {}", e);
+            }
+        }
+    }
+}
+
+/// Call `method` with `params` on the instance listening at `path`,
+/// and return its result
+pub fn request(path: &Path, method: &str, params: Value) -> Result<Value> {
+    Ok(call_over(path, method, params)?.0)
+}
+
+/// Subscribe to the events of the instance listening at `path`, as
+/// `params` of `subscribe` ask
+pub fn subscribe(path: &Path, params: Value) -> Result<Subscription> {
+    let (state, reader) = call_over(path, "subscribe", params)?;
+    Ok(Subscription { state: state, reader: reader })
+}
+
+/// The notifications of a subscription, until the instance exits
+pub struct Subscription {
+    /// The state when subscribing, as from `get_state`
+    pub state: Value,
+    reader: BufReader<UnixStream>,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Value>;
+
+    fn next(&mut self) -> Option<Result<Value>> {
+        match read_message(&mut self.reader) {
+            Ok(Some(message)) => Some(Ok(message)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e))
+        }
+    }
+}
+
+/// Send the request, and return its result, and the connection to
+/// read on
+fn call_over(path: &Path, method: &str, params: Value) -> Result<(Value, BufReader<UnixStream>)> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| format!("could not connect to {} (is redshift-rs running?): {}", path.display(), e))?;
+    let request = Value::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+        ("id", 1.into()),
+    ]);
+    writeln!(stream, "{}", request)?;
+    let mut reader = BufReader::new(stream);
+    let response = read_message(&mut reader)?
+        .ok_or_else(|| Box::new(RedshiftError::MalformedArgument(format!("no response to {}", method))))?;
+    if let Some(error) = response.get("error") {
+        let message = error.get("message").and_then(Value::as_str).unwrap_or("unknown error").to_owned();
+        return Err(match error.get("code").and_then(Value::as_f64) {
+            Some(code) if code == POLICY_VIOLATION as f64 => Box::new(RedshiftError::PolicyViolation(message)),
+            _ => From::from(message)
+        })
+    }
+    match response.get("result") {
+        Some(result) => Ok((result.clone(), reader)),
+        None => Err(From::from("the response has no result"))
+    }
+}
+
+/// The next line, parsed, or None at the end
+fn read_message(reader: &mut BufReader<UnixStream>) -> Result<Option<Value>> {
+    let mut line = String::new();
+    if reader.by_ref().take(MAX_REQUEST).read_line(&mut line)? == 0 {
+        return Ok(None)
+    }
+    Ok(Some(json::parse(&line)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+    use std::time::Duration;
+
+    use chan;
+
+    use engine::Event;
+    use json::{self, Value};
+    use super::{handle, listen_fds, Client, Locks, Shared};
+
+    fn shared() -> Arc<Mutex<Shared>> {
+        let mut shared = Shared::new();
+        shared.period = "night";
+        Arc::new(Mutex::new(shared))
+    }
+
+    fn client() -> (Client, Receiver<String>) {
+        let (lines_tx, lines_rx) = mpsc::sync_channel(2);
+        (Client { lines: lines_tx, stream: None }, lines_rx)
+    }
+
+    /// The response to `request`, and the event it sent, if any
+    fn respond(request: &str, shared: &Arc<Mutex<Shared>>, locks: &Locks) -> (Option<Value>, Option<Event>) {
+        let (events_tx, events_rx) = chan::sync(1);
+        let response = handle(request, shared, locks, &events_tx, &client().0);
+        drop(events_tx);
+        (response, events_rx.recv())
+    }
+
+    fn error_code(response: &Option<Value>) -> Option<f64> {
+        response.as_ref().and_then(|r| r.get("error")).and_then(|e| e.get("code")).and_then(Value::as_f64)
+    }
+
+    #[test]
+    fn test_get_state() {
+        let (response, event) = respond("{\"jsonrpc\":\"2.0\",\"method\":\"get_state\",\"id\":7}",
+                                        &shared(), &Locks::default());
+        assert_eq!(response.map(|r| r.to_string()), Some(
+            "{\"jsonrpc\":\"2.0\",\"result\":{\"protocol\":1,\"enabled\":true,\"period\":\"night\",\
+             \"temperature\":null,\"brightness\":null,\"gamma\":null,\
+             \"offsets\":{\"temperature\":0,\"brightness\":0}},\"id\":7}".to_owned()));
+        assert!(event.is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let locks = Locks::default();
+        assert_eq!(error_code(&respond("{\"jsonrpc\":", &shared(), &locks).0), Some(-32700.0));
+        assert_eq!(error_code(&respond("{\"method\":\"get_state\",\"id\":1}", &shared(), &locks).0), Some(-32600.0));
+        assert_eq!(error_code(&respond("{\"jsonrpc\":\"2.0\",\"method\":\"reboot\",\"id\":1}", &shared(), &locks).0),
+                   Some(-32601.0));
+        assert_eq!(error_code(&respond("{\"jsonrpc\":\"2.0\",\"method\":\"set_enabled\",\"id\":1}",
+                                       &shared(), &locks).0), Some(-32602.0));
+        // Notifications are not answered
+        assert!(respond("{\"jsonrpc\":\"2.0\",\"method\":\"reboot\"}", &shared(), &locks).0.is_none());
+    }
+
+    #[test]
+    fn test_set_enabled() {
+        let request = "{\"jsonrpc\":\"2.0\",\"method\":\"set_enabled\",\"params\":{\"enabled\":false},\"id\":1}";
+        let (_, event) = respond(request, &shared(), &Locks::default());
+        assert_eq!(event, Some(Event::Enable(false)));
+
+        let locks = Locks { disable: true, ..Locks::default() };
+        let (response, event) = respond(request, &shared(), &locks);
+        assert_eq!(error_code(&response), Some(1.0));
+        assert!(event.is_none());
+    }
+
+    #[test]
+    fn test_set_offsets() {
+        let shared = shared();
+        let request = "{\"jsonrpc\":\"2.0\",\"method\":\"set_offsets\",\"params\":{\"temperature\":-500},\"id\":1}";
+        let (response, event) = respond(request, &shared, &Locks::default());
+        assert_eq!(response.as_ref().and_then(|r| r.get("result")),
+                   Some(&json::parse("{\"temperature\":-500,\"brightness\":0}").unwrap()));
+        assert_eq!(event, Some(Event::Update));
+        assert_eq!(shared.lock().unwrap().offsets, (-500.0, 0.0));
+
+        let locks = Locks { brightness: true, ..Locks::default() };
+        let request = "{\"jsonrpc\":\"2.0\",\"method\":\"set_offsets\",\"params\":{\"brightness\":-0.2},\"id\":1}";
+        assert_eq!(error_code(&respond(request, &shared, &locks).0), Some(1.0));
+        assert_eq!(shared.lock().unwrap().offsets, (-500.0, 0.0));
+
+        let request = "{\"jsonrpc\":\"2.0\",\"method\":\"set_offsets\",\"params\":{\"temperature\":1e9},\"id\":1}";
+        assert_eq!(error_code(&respond(request, &shared, &Locks::default()).0), Some(-32602.0));
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let shared = shared();
+        let (events_tx, _events_rx) = chan::sync(1);
+        let (client, lines) = client();
+        let request = "{\"jsonrpc\":\"2.0\",\"method\":\"subscribe\",\"params\":{\"events\":[\"enabled\"]},\"id\":2}";
+        assert!(handle(request, &shared, &Locks::default(), &events_tx, &client).is_none());
+        let response = json::parse(&lines.try_recv().unwrap()).unwrap();
+        assert_eq!(response.get("result").and_then(|r| r.get("period")), Some(&Value::from("night")));
+
+        // Only the events asked for
+        shared.lock().unwrap().notify("period", Value::object(vec![("period", "day".into())]));
+        assert!(lines.try_recv().is_err());
+        shared.lock().unwrap().notify("enabled", Value::object(vec![("enabled", false.into())]));
+        assert_eq!(lines.try_recv().unwrap(),
+                   "{\"jsonrpc\":\"2.0\",\"method\":\"enabled\",\"params\":{\"enabled\":false}}\n");
+
+        // Dropped once it falls behind
+        for _ in 0..3 {
+            shared.lock().unwrap().notify("enabled", Value::object(vec![("enabled", true.into())]));
+        }
+        assert!(shared.lock().unwrap().subscribers.is_empty());
+
+        let request = "{\"jsonrpc\":\"2.0\",\"method\":\"subscribe\",\"params\":{\"events\":[\"weather\"]},\"id\":3}";
+        let response = handle(request, &shared, &Locks::default(), &events_tx, &client);
+        assert_eq!(error_code(&response), Some(-32602.0));
+    }
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("many"), 42), 0);
+    }
+
+    #[test]
+    fn test_neutral() {
+        let shared = shared();
+        // Paused and resumed as the engine would
+        let (events_tx, events_rx) = chan::sync(0);
+        let engine_shared = shared.clone();
+        let engine = thread::spawn(move || {
+            events_rx.iter().map(|event| {
+                if let Event::Enable(enabled) = event {
+                    engine_shared.lock().unwrap().enabled = enabled;
+                }
+                event
+            }).collect::<Vec<_>>()
+        });
+        let neutral = "{\"jsonrpc\":\"2.0\",\"method\":\"neutral\",\"params\":{\"seconds\":0.05},\"id\":1}";
+        let response = handle(neutral, &shared, &Locks::default(), &events_tx, &client().0);
+        assert_eq!(response.as_ref().and_then(|r| r.get("result")), Some(&Value::Null));
+        assert!(!shared.lock().unwrap().enabled);
+        thread::sleep(Duration::from_millis(200));
+        assert!(shared.lock().unwrap().enabled);
+
+        // Not resumed once set_enabled took over
+        handle(neutral, &shared, &Locks::default(), &events_tx, &client().0);
+        let disable = "{\"jsonrpc\":\"2.0\",\"method\":\"set_enabled\",\"params\":{\"enabled\":false},\"id\":1}";
+        handle(disable, &shared, &Locks::default(), &events_tx, &client().0);
+        thread::sleep(Duration::from_millis(200));
+        assert!(!shared.lock().unwrap().enabled);
+
+        let locks = Locks { disable: true, ..Locks::default() };
+        assert_eq!(error_code(&handle(neutral, &shared, &locks, &events_tx, &client().0)), Some(1.0));
+        drop(events_tx);
+        assert_eq!(engine.join().unwrap(), vec![Event::Enable(false), Event::Enable(true), Event::Enable(false),
+                                                Event::Enable(false)]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Elevation and period time logging
+//!
+//! With --log-elevation FILE, continual mode appends a CSV row per
+//! tick with the time, solar elevation, period and the resulting
+//! color setting. When the file grows past MAX_SIZE it is copied to
+//! FILE.1 (replacing any previous one) and emptied. Copying rather
+//! than renaming only writes to the two files, so that it still works
+//! in the sandbox, which allows nothing else in their directory.
+//!
+//! With --log-period-times FILE, it appends a row per day with the
+//! minutes spent in each period, once the day is over.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clock;
+
+use state::PeriodTimes;
+use transition::{ColorSetting, Period};
+use super::Result;
+
+const MAX_SIZE: u64 = 1024 * 1024;
+
+const HEADER: &'static str = "time,elevation,period,temperature,brightness";
+
+const PERIOD_TIMES_HEADER: &'static str = "date,day,transition,night";
+
+pub struct ElevationLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl ElevationLog {
+
+    pub fn open(path: &Path) -> Result<ElevationLog> {
+        Ok(ElevationLog {
+            path: path.to_owned(),
+            file: open_log(path, HEADER)?,
+        })
+    }
+
+    /// The files written to, creating FILE.1 if it does not exist yet,
+    /// so that the sandbox can allow writing to it
+    pub fn writable_paths(&self) -> Result<Vec<PathBuf>> {
+        OpenOptions::new().create(true).append(true).open(self.rotated())?;
+        Ok(vec![self.path.clone(), self.rotated()])
+    }
+
+    pub fn record(&mut self, now: f64, elevation: f64, period: &Period, setting: &ColorSetting) -> Result<()> {
+        if self.file.metadata()?.len() >= MAX_SIZE {
+            fs::copy(&self.path, self.rotated())?;
+            self.file.set_len(0)?;
+            writeln!(self.file, "{}", HEADER)?;
+        }
+
+        let tm = clock::utc_tm(now);
+        let period = match *period {
+            Period::None => "none",
+            Period::Day => "day",
+            Period::Night => "night",
+            Period::Transition(_) => "transition"
+        };
+        writeln!(self.file, "{},{:.4},{},{},{:.2}",
+                 tm.rfc3339(), elevation, period, setting.temp, setting.brightness)?;
+        Ok(())
+    }
+
+    fn rotated(&self) -> PathBuf {
+        let mut rotated = self.path.as_os_str().to_owned();
+        rotated.push(".1");
+        PathBuf::from(rotated)
+    }
+}
+
+pub struct PeriodTimesLog {
+    file: File,
+}
+
+impl PeriodTimesLog {
+
+    pub fn open(path: &Path) -> Result<PeriodTimesLog> {
+        Ok(PeriodTimesLog { file: open_log(path, PERIOD_TIMES_HEADER)? })
+    }
+
+    /// Append the minutes of the day of `times`, which is over
+    pub fn record(&mut self, times: &PeriodTimes) -> Result<()> {
+        let minutes = times.minutes();
+        writeln!(self.file, "{:04}-{:02}-{:02},{},{},{}",
+                 times.date / 10000, times.date / 100 % 100, times.date % 100, minutes[0], minutes[1], minutes[2])?;
+        Ok(())
+    }
+}
+
+/// Open the log for appending, writing the CSV `header` if it is empty
+fn open_log(path: &Path, header: &str) -> Result<File> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", header)?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::thread;
+
+    use sandbox::Sandbox;
+    use transition::{ColorSetting, Period};
+    use super::{ElevationLog, HEADER, MAX_SIZE};
+
+    #[test]
+    fn rotates_in_the_sandbox() {
+        let dir = env::temp_dir().join(format!("redshift-rs-datalog-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("elevation.csv");
+        fs::write(&path, vec![b'x'; MAX_SIZE as usize]).unwrap();
+
+        // Landlock restricts only the thread applying it
+        let log_path = path.clone();
+        let sandboxed = thread::spawn(move || {
+            let mut log = ElevationLog::open(&log_path).unwrap();
+            let mut sandbox = Sandbox::new();
+            for path in log.writable_paths().unwrap() {
+                sandbox.allow_write(&path);
+            }
+            if sandbox.apply().is_err() {
+                // No Landlock here
+                return false
+            }
+            let setting = ColorSetting { temp: 4500.into(), gamma: [1.0; 3], brightness: 1.0 };
+            log.record(0.0, 10.0, &Period::Day, &setting).unwrap();
+            true
+        }).join().unwrap();
+
+        if sandboxed {
+            assert_eq!(fs::metadata(dir.join("elevation.csv.1")).unwrap().len(), MAX_SIZE);
+            let contents = fs::read_to_string(&path).unwrap();
+            assert!(contents.starts_with(HEADER) && contents.lines().count() == 2, "{}", contents);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
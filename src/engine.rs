@@ -0,0 +1,787 @@
+//! The continual mode main loop, for embedding
+//!
+//! An `Engine` updates its adjusters every few seconds from a
+//! transition scheme and the solar elevation at a location, fading in
+//! at start and out at exit. Event sources run on threads of their own
+//! and send it `Event`s. Hooks are called at each step of an update,
+//! for whatever else a frontend does; the redshift-rs binary keeps its
+//! state file, statistics and break reminder this way.
+//!
+//! ```ignore
+//! let mut engine = Engine::new(scheme, location);
+//! engine.add_adjuster(gamma::init_gamma_method(None, &[])?);
+//! engine.add_event_source(|events: chan::Sender<Event>| {
+//!     // e.g. wait for the GUI's quit button
+//!     events.send(Event::Quit);
+//! });
+//! engine.run()?;
+//! ```
+//!
+//! Adjusters are started by the caller, and restored by `run()` when it
+//! returns.
+
+use std::mem;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chan;
+
+use clock::{Alarm, Clock, SystemClock};
+use gamma::{self, GammaMethod};
+use location::Location;
+use output::{ErrorSummary, Throttle};
+use solar;
+use transition::{ColorSetting, Kelvin, Period, TransitionScheme};
+use super::{Result, NEUTRAL_TEMP};
+
+/// Attempts at setting the temperature in one update, before waiting
+/// for the next one
+const SET_ATTEMPTS: u32 = 3;
+
+/// Delay (in ms) before the first retry, doubled for each one after
+const SET_RETRY_DELAY: u64 = 50;
+
+/// Time (in ms) between updates while paused, only to notice a
+/// second Reset soon
+const PAUSED_DELAY: u64 = 1000;
+
+/// Shortest time (in ms) from one update to one asked for by an
+/// Update event, so that a burst of them, as from a held hotkey,
+/// makes at most ten updates a second, the last with the final values
+const MIN_UPDATE_INTERVAL: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Fade out and stop. A second Quit, or one without fade_on_exit,
+    /// stops at once.
+    Quit,
+
+    /// Set the temperature at the next update, even if it did not
+    /// change, as after something else reset the display
+    Refresh,
+
+    /// Restore the adjusters at once, without fading, and pause until
+    /// the next Reset, which fades back in
+    Reset,
+
+    /// Pause as Reset does (false), or resume from a pause (true); a
+    /// no-op if already so
+    Enable(bool),
+
+    /// Update now rather than at the next tick, as after something the
+    /// hooks adjust the setting with changed. Updates asked for this
+    /// way are at least `MIN_UPDATE_INTERVAL` apart.
+    Update,
+
+    /// Pass the state of each output to the hooks, to report it
+    Status,
+}
+
+/// Something sending events to the engine, run on its own thread until
+/// it returns
+pub trait EventSource: Send {
+    fn run(self: Box<Self>, events: chan::Sender<Event>);
+}
+
+impl<F: FnOnce(chan::Sender<Event>) + Send> EventSource for F {
+    fn run(self: Box<Self>, events: chan::Sender<Event>) {
+        (*self)(events)
+    }
+}
+
+/// Called by the engine at each step of an update. Every method does
+/// nothing by default.
+pub trait Hook {
+    /// An `Event::Status` arrived, with the state of each output
+    fn status(&mut self, _outputs: &[gamma::OutputStatus]) {}
+
+    /// Before the elevation is computed, to change the scheme or the
+    /// location, as when the timezone changes
+    fn before_update(&mut self, _scheme: &mut TransitionScheme, _location: &mut Location, _now: f64) {}
+
+    /// The period changed from `prev` to `period`, as from Day to
+    /// Transition. The progress of a transition changing is not
+    /// reported here; `adjust` gets the period at every update.
+    fn period_changed(&mut self, _period: &Period, _prev: &Period, _scheme: &TransitionScheme,
+                      _location: &Location, _now: f64) {}
+
+    /// Change the setting about to be set. Returning true asks for
+    /// the next update soon, as during a fade.
+    fn adjust(&mut self, _setting: &mut ColorSetting, _elev: f64, _period: &Period, _now: f64) -> bool {
+        false
+    }
+
+    /// The setting was set, or failed to be after `retries` retries
+    fn applied(&mut self, _result: &Result<()>, _retries: u32, _elapsed: Duration, _now: f64) {}
+
+    /// The setting did not change, or updates are paused, so nothing
+    /// was set
+    fn skipped(&mut self) {}
+
+    /// Updates were paused, with the adjusters restored, or resumed
+    fn paused_changed(&mut self, _paused: bool, _now: f64) {}
+
+    /// The adjusters were restored and the engine is about to return
+    fn stopped(&mut self, _now: f64) {}
+}
+
+pub struct Engine {
+    pub scheme: TransitionScheme,
+    pub location: Location,
+
+    /// Update every half second during transitions
+    pub smooth: bool,
+
+    /// Fade back to neutral on the first Quit
+    pub fade_on_exit: bool,
+
+    /// The system clock, unless simulating
+    pub clock: Arc<Clock>,
+
+    adjusters: Vec<Box<GammaMethod>>,
+    sources: Vec<Box<EventSource>>,
+    hooks: Vec<Box<Hook>>,
+}
+
+impl Engine {
+
+    pub fn new(scheme: TransitionScheme, location: Location) -> Engine {
+        Engine {
+            scheme: scheme,
+            location: location,
+            smooth: false,
+            fade_on_exit: true,
+            clock: Arc::new(SystemClock),
+            adjusters: vec![],
+            sources: vec![],
+            hooks: vec![],
+        }
+    }
+
+    /// Set the temperature with `adjuster` too. It must be started
+    /// already.
+    pub fn add_adjuster(&mut self, adjuster: Box<GammaMethod>) {
+        self.adjusters.push(adjuster);
+    }
+
+    /// Run `source` on its own thread once the engine runs
+    pub fn add_event_source<S: EventSource + 'static>(&mut self, source: S) {
+        self.sources.push(Box::new(source));
+    }
+
+    pub fn add_hook<H: Hook + 'static>(&mut self, hook: H) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Run until quit, then restore the adjusters
+    pub fn run(mut self) -> Result<()> {
+        let mut adjuster: Box<GammaMethod> = if self.adjusters.len() == 1 {
+            self.adjusters.pop().unwrap()
+        } else {
+            Box::new(gamma::Dispatcher { methods: self.adjusters.drain(..).collect() })
+        };
+
+        // Kept open even without sources, so that receiving blocks
+        let (event_tx, event_rx) = chan::sync(0);
+        for source in self.sources.drain(..) {
+            let event_tx = event_tx.clone();
+            thread::spawn(move || source.run(event_tx));
+        }
+
+        let (timer_tx, timer_rx) = chan::sync(0);
+        let (sleep_tx, sleep_rx) = chan::sync(0);
+        let alarm = Arc::new(Alarm::new());
+        let timer_clock = self.clock.clone();
+        let timer_alarm = alarm.clone();
+        thread::spawn(move || {
+            for ms in sleep_rx.iter() {
+                timer_clock.sleep_or_alarm(Duration::from_millis(ms), &timer_alarm);
+                timer_tx.send(());
+            }
+        });
+        let mut timer = Timer { sleep_tx: sleep_tx, alarm: alarm, armed: false, rearm: None };
+
+        let scheme = &mut self.scheme;
+        let location = &mut self.location;
+        let hooks = &mut self.hooks;
+        let clock = &self.clock;
+
+        let mut now = clock.now();
+        let mut exiting = false;
+        let mut refresh = false;
+        let mut prev_color_setting = ColorSetting::new();
+        let mut prev_period = Period::None;
+        let mut failing = false;
+        let mut paused = false;
+        // Resuming fades in like the start does, if it does
+        let fade_in = scheme.short_transition();
+        let mut temp_log = Throttle::new();
+        let mut brightness_log = Throttle::new();
+        let mut errors = ErrorSummary::new("Setting the temperature");
+        // When the last update was, and whether one asked for too soon
+        // after it is waiting for the timer
+        let mut last_update = None;
+        let mut update_pending = false;
+        timer.set(0);
+        loop {
+            let mut update = false;
+            let mut requested = false;
+            let mut pause = None;
+            chan_select! {
+                event_rx.recv() -> event => {
+                    match event {
+                        Some(Event::Quit) => {
+                            if exiting || !self.fade_on_exit || paused {
+                                break // If already exiting, just exit immediately
+                            }
+                            exiting = true;
+                            scheme.short_trans_delta = 1;
+                            scheme.short_trans_len = 2;
+                            scheme.adjustment_alpha = 0.1;
+                        }
+                        Some(Event::Refresh) => refresh = true,
+                        Some(Event::Update) => requested = true,
+                        Some(Event::Reset) | Some(Event::Enable(false)) if exiting => break,
+                        Some(Event::Reset) => pause = Some(!paused),
+                        Some(Event::Enable(enable)) => pause = Some(!enable),
+                        Some(Event::Status) => {
+                            let outputs = adjuster.output_status();
+                            for hook in hooks.iter_mut() {
+                                hook.status(&outputs);
+                            }
+                        }
+                        // Not while run() holds a sender
+                        None => {}
+                    }
+                },
+                timer_rx.recv() => update = timer.woke(),
+            }
+
+            match pause {
+                Some(true) if !paused => {
+                    match adjuster.restore() {
+                        Ok(()) => info!("Reset, and paused until the next reset"),
+                        Err(e) => warn!("Could not reset: {}", e)
+                    }
+                    paused = true;
+                    prev_color_setting = ColorSetting::new();
+                    for hook in hooks.iter_mut() {
+                        hook.paused_changed(true, clock.now());
+                    }
+                }
+                Some(false) if paused => {
+                    info!("Resuming");
+                    paused = false;
+                    if fade_in {
+                        scheme.short_trans_delta = -1;
+                        scheme.adjustment_alpha = 1.0;
+                    }
+                    refresh = true;
+                    update = true;
+                    for hook in hooks.iter_mut() {
+                        hook.paused_changed(false, clock.now());
+                    }
+                }
+                _ => {}
+            }
+            if requested && !update && !update_pending {
+                let since = last_update.map_or(MIN_UPDATE_INTERVAL, |last| ((clock.now() - last) * 1000.0) as u64);
+                if since >= MIN_UPDATE_INTERVAL {
+                    update = true;
+                } else {
+                    update_pending = true;
+                    timer.set(MIN_UPDATE_INTERVAL - since);
+                }
+            }
+            if !update {
+                continue
+            }
+
+            now = clock.now();
+            last_update = Some(now);
+            update_pending = false;
+            if paused {
+                for hook in hooks.iter_mut() {
+                    hook.skipped();
+                }
+                timer.set(PAUSED_DELAY);
+                continue
+            }
+
+            for hook in hooks.iter_mut() {
+                hook.before_update(scheme, location, now);
+            }
+
+            // Compute elevation, and where the keyframes (if
+            // any) put it
+            let elev = solar::elevation(now, location);
+            let schedule_elev = scheme.schedule_elevation(now, location);
+
+            // Moving through a transition is no change of period
+            let period = scheme.get_period(schedule_elev);
+            if mem::discriminant(&period) != mem::discriminant(&prev_period) {
+                for hook in hooks.iter_mut() {
+                    hook.period_changed(&period, &prev_period, scheme, location, now);
+                }
+            }
+            prev_period = period;
+
+            // Interpolate between 6500K and calculated temperature
+            let mut color_setting = scheme.interpolate_color_settings(schedule_elev);
+            scheme.apply_jitter(&mut color_setting, now);
+            scheme.quantize(&mut color_setting);
+
+            /* Ongoing short transition? */
+            if scheme.short_transition() {
+                scheme.adjust_transition_alpha();
+                color_setting.temp = Kelvin(scheme.adjustment_alpha * NEUTRAL_TEMP as f64 +
+                                            (1.0-scheme.adjustment_alpha) * color_setting.temp.0);
+                color_setting.brightness = scheme.adjustment_alpha * 1.0 +
+                    (1.0-scheme.adjustment_alpha) * color_setting.brightness;
+            }
+
+            let mut hurry = false;
+            for hook in hooks.iter_mut() {
+                hurry |= hook.adjust(&mut color_setting, elev, &prev_period, now);
+            }
+
+            // Fades update ten times a second, so show only some of
+            // the steps, and the value they settle at
+            let settled = !scheme.short_transition() && !hurry;
+            if (color_setting.temp.round() != prev_color_setting.temp.round() || settled && temp_log.held()) &&
+                temp_log.allow(settled) {
+                verbose!("Color temperature: {}K", color_setting.temp);
+            }
+            if (color_setting.brightness != prev_color_setting.brightness ||
+                settled && brightness_log.held()) && brightness_log.allow(settled) {
+                verbose!("Brightness: {:?}", color_setting.brightness);
+            }
+            // A forced refresh sends the setting again, although
+            // the adjuster already set it
+            let forced = refresh || adjuster.needs_refresh();
+            if forced {
+                adjuster.invalidate();
+            }
+            // Compared to what was last set, so that a slow drift
+            // still gets there once it amounts to something
+            if !color_setting.approx_eq(&prev_color_setting) || failing || forced {
+                refresh = false;
+                prev_color_setting = color_setting.clone();
+                let started = Instant::now();
+                let mut retries = 0;
+                let result = set_temperature_retrying(&mut adjuster, &color_setting, &**clock, &mut retries);
+                match result {
+                    Ok(()) if failing => {
+                        info!("Setting the temperature works again, after {} failures", errors.succeeded());
+                        failing = false;
+                    }
+                    Ok(()) => {}
+                    Err(ref e) => {
+                        // Keep running, and try again at the next
+                        // regular update
+                        errors.failed(&e.to_string());
+                        failing = true;
+                    }
+                }
+                for hook in hooks.iter_mut() {
+                    hook.applied(&result, retries, started.elapsed(), now);
+                }
+            } else {
+                for hook in hooks.iter_mut() {
+                    hook.skipped();
+                }
+            }
+
+            if exiting && (failing || !scheme.short_transition()) {
+                break
+            }
+
+            // Sleep for 5 seconds or 0.1 second (0.5 second during
+            // transitions in smooth mode)
+            let in_transition = match prev_period {
+                Period::Transition(_) => true,
+                _ => false
+            };
+            timer.set(if failing {
+                5000
+            } else if scheme.short_transition() || hurry {
+                100
+            } else if self.smooth && in_transition {
+                500
+            } else {
+                5000
+            });
+        }
+        adjuster.restore()?;
+
+        for hook in hooks.iter_mut() {
+            hook.stopped(now);
+        }
+        Ok(())
+    }
+}
+
+/// Wakes the engine after a given time, once, from the timer thread
+///
+/// Setting it again while it is waiting cuts that wait short and
+/// starts the new one, so updates made between ticks can move the
+/// next tick.
+struct Timer {
+    sleep_tx: chan::Sender<u64>,
+    alarm: Arc<Alarm>,
+    armed: bool,
+    rearm: Option<u64>,
+}
+
+impl Timer {
+    /// Wake the engine in `ms` milliseconds, instead of when it would
+    fn set(&mut self, ms: u64) {
+        if self.armed {
+            self.rearm = Some(ms);
+            self.alarm.ring();
+        } else {
+            self.sleep_tx.send(ms);
+            self.armed = true;
+        }
+    }
+
+    /// The timer thread woke the engine; whether this is the tick set
+    /// last, rather than a wait cut short to start it
+    fn woke(&mut self) -> bool {
+        self.armed = false;
+        self.alarm.take();
+        match self.rearm.take() {
+            Some(ms) => {
+                self.set(ms);
+                false
+            }
+            None => true
+        }
+    }
+}
+
+/// Set the temperature, retrying a few times with increasing delays
+/// so that transient errors (like a busy X server) are ridden out
+fn set_temperature_retrying(adjuster: &mut Box<GammaMethod>, setting: &ColorSetting, clock: &Clock,
+                            retries: &mut u32) -> Result<()> {
+    let mut delay = SET_RETRY_DELAY;
+    loop {
+        match adjuster.set_temperature(setting) {
+            Ok(()) => return Ok(()),
+            Err(ref e) if *retries + 1 < SET_ATTEMPTS => {
+                debug!("Setting the temperature failed ({}), retrying in {} ms", e, delay);
+                *retries += 1;
+                clock.sleep(Duration::from_millis(delay));
+                delay *= 2;
+            }
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Condvar, Mutex, mpsc};
+    use std::time::Duration;
+
+    use chan;
+
+    use clock::{Alarm, Clock};
+    use gamma::GammaMethod;
+    use location::Location;
+    use solar;
+    use transition::{ColorSetting, Kelvin, Period, TransitionScheme};
+    use super::super::Result;
+    use super::{Engine, Event, Hook};
+
+    /// Copenhagen, and midnight there on 2021-01-01
+    const LAT: f64 = 55.7;
+    const LON: f64 = 12.6;
+    const NIGHT: f64 = 1609455600.0;
+
+    /// What happened, in order, as lines like "set 3500"
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    fn log(log: &Log, line: String) {
+        log.lock().unwrap().push(line);
+    }
+
+    /// The number of events sent by a `Script` that the engine has not
+    /// received yet
+    type InFlight = Arc<(Mutex<u32>, Condvar)>;
+
+    /// Simulated time, moved on by sleeping
+    ///
+    /// Sleeping waits for the events in flight to reach the engine
+    /// first, so that the timer never races them: an event sent at one
+    /// update is handled before the next.
+    struct FakeClock {
+        now: Mutex<f64>,
+        in_flight: InFlight,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> f64 {
+            *self.now.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.wait_for_events();
+            *self.now.lock().unwrap() += duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9;
+        }
+
+        fn sleep_or_alarm(&self, duration: Duration, alarm: &Alarm) {
+            // A wait cut short takes no simulated time
+            self.wait_for_events();
+            if !alarm.take() {
+                self.sleep(duration);
+            }
+        }
+    }
+
+    impl FakeClock {
+        fn wait_for_events(&self) {
+            let (ref count, ref received) = *self.in_flight;
+            let mut count = count.lock().unwrap();
+            while *count > 0 {
+                count = received.wait(count).unwrap();
+            }
+        }
+    }
+
+    /// Logs what it is asked to set, failing the first `failures` times,
+    /// and skips settings it already set, as the real methods do
+    struct FakeMethod {
+        log: Log,
+        failures: u32,
+        last_applied: Option<ColorSetting>,
+    }
+
+    impl GammaMethod for FakeMethod {
+        fn start(&mut self) -> Result<()> { Ok(()) }
+
+        fn set_temperature(&mut self, setting: &ColorSetting) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                log(&self.log, "fail".to_owned());
+                return Err(From::from("busy"))
+            }
+            if self.last_applied.as_ref() == Some(setting) {
+                log(&self.log, "unchanged".to_owned());
+                return Ok(())
+            }
+            log(&self.log, format!("set {}", setting.temp.round()));
+            self.last_applied = Some(setting.clone());
+            Ok(())
+        }
+
+        fn restore(&mut self) -> Result<()> {
+            log(&self.log, "restore".to_owned());
+            self.last_applied = None;
+            Ok(())
+        }
+
+        fn invalidate(&mut self) {
+            self.last_applied = None;
+        }
+    }
+
+    /// Logs the hooks called, and sends events from `script` after
+    /// the given number of updates
+    struct Script {
+        log: Log,
+        updates: u32,
+        script: Vec<(u32, Event)>,
+        events: mpsc::Sender<Event>,
+        in_flight: InFlight,
+    }
+
+    impl Script {
+        fn updated(&mut self) {
+            self.updates += 1;
+            let updates = self.updates;
+            while !self.script.is_empty() && self.script[0].0 <= updates {
+                *self.in_flight.0.lock().unwrap() += 1;
+                self.events.send(self.script.remove(0).1).unwrap();
+            }
+        }
+    }
+
+    impl Hook for Script {
+        fn before_update(&mut self, _scheme: &mut TransitionScheme, _location: &mut Location, _now: f64) {
+            log(&self.log, "before".to_owned());
+        }
+
+        fn period_changed(&mut self, period: &Period, _prev: &Period, _scheme: &TransitionScheme,
+                          _location: &Location, _now: f64) {
+            let name = match *period {
+                Period::Transition(_) => "Transition".to_owned(),
+                ref period => format!("{:?}", period)
+            };
+            log(&self.log, format!("period {}", name));
+        }
+
+        fn adjust(&mut self, _setting: &mut ColorSetting, _elev: f64, _period: &Period, _now: f64) -> bool {
+            log(&self.log, "adjust".to_owned());
+            false
+        }
+
+        fn applied(&mut self, result: &Result<()>, retries: u32, _elapsed: Duration, _now: f64) {
+            log(&self.log, format!("applied {} {}", if result.is_ok() { "ok" } else { "err" }, retries));
+            self.updated();
+        }
+
+        fn skipped(&mut self) {
+            log(&self.log, "skipped".to_owned());
+            self.updated();
+        }
+
+        fn paused_changed(&mut self, paused: bool, _now: f64) {
+            log(&self.log, format!("paused {}", paused));
+        }
+
+        fn stopped(&mut self, _now: f64) {
+            log(&self.log, "stopped".to_owned());
+        }
+    }
+
+    /// Run an engine at `start` with 6500K days and 3500K nights, without
+    /// the start fade unless `fade_in`, until the events of `script` stop
+    /// it, and return what happened
+    fn run(start: f64, fade_in: bool, fade_on_exit: bool, failures: u32, script: Vec<(u32, Event)>) -> Vec<String> {
+        let mut scheme = TransitionScheme::new();
+        scheme.day = ColorSetting { temp: Kelvin(6500.0), gamma: [1.0; 3], brightness: 1.0 };
+        scheme.night = ColorSetting { temp: Kelvin(3500.0), gamma: [1.0; 3], brightness: 1.0 };
+        if !fade_in {
+            scheme.short_trans_delta = 0;
+            scheme.adjustment_alpha = 0.0;
+        }
+        let lines = Log::default();
+        let (events_tx, events_rx) = mpsc::channel();
+        let in_flight = InFlight::default();
+        let mut engine = Engine::new(scheme, Location::new(LAT, LON));
+        engine.clock = Arc::new(FakeClock { now: Mutex::new(start), in_flight: in_flight.clone() });
+        engine.fade_on_exit = fade_on_exit;
+        engine.add_adjuster(Box::new(FakeMethod { log: lines.clone(), failures: failures, last_applied: None }));
+        engine.add_hook(Script { log: lines.clone(), updates: 0, script: script, events: events_tx,
+                                 in_flight: in_flight.clone() });
+        engine.add_event_source(move |events: chan::Sender<Event>| {
+            for event in events_rx.iter() {
+                // The channel has no buffer, so the engine has the
+                // event once this returns
+                events.send(event);
+                let (ref count, ref received) = *in_flight;
+                *count.lock().unwrap() -= 1;
+                received.notify_all();
+            }
+        });
+        engine.run().unwrap();
+        let lines = lines.lock().unwrap().clone();
+        lines
+    }
+
+    /// The temperatures set, in order
+    fn temps(lines: &[String]) -> Vec<i32> {
+        lines.iter().filter(|line| line.starts_with("set ")).map(|line| line[4..].parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn hooks_are_called_in_order() {
+        let lines = run(NIGHT, false, false, 0, vec![(2, Event::Quit)]);
+        assert_eq!(lines[..7].to_vec(), vec!["before", "period Night", "adjust", "set 3500", "applied ok 0",
+                                             "before", "adjust"]);
+        // Nothing changed, so nothing is set until quitting, at once
+        assert_eq!(lines[7], "skipped");
+        assert_eq!(temps(&lines), vec![3500]);
+        assert_eq!(lines[lines.len() - 2..].to_vec(), vec!["restore", "stopped"]);
+    }
+
+    #[test]
+    fn period_changes_once_per_transition() {
+        let location = Location::new(LAT, LON);
+        let scheme = TransitionScheme::new();
+        // Into the morning transition, which takes more than an hour
+        let start = (0..24 * 60).map(|minute| NIGHT + minute as f64 * 60.0)
+            .find(|&t| scheme.get_period(solar::elevation(t, &location)) != Period::Night)
+            .unwrap();
+        let lines = run(start, false, false, 0, vec![(100, Event::Quit)]);
+        let periods: Vec<_> = lines.iter().filter(|line| line.starts_with("period")).collect();
+        assert_eq!(periods, vec!["period Transition"]);
+        // While the temperature goes up all along
+        assert!(temps(&lines).windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn failed_updates_are_retried() {
+        let lines = run(NIGHT, false, false, 2, vec![(1, Event::Quit)]);
+        assert_eq!(lines[3..7].to_vec(), vec!["fail", "fail", "set 3500", "applied ok 2"]);
+
+        // Failing all attempts, and succeeding at the next update
+        let lines = run(NIGHT, false, false, 3, vec![(2, Event::Quit)]);
+        assert_eq!(lines[3..7].to_vec(), vec!["fail", "fail", "fail", "applied err 2"]);
+        assert_eq!(lines[7..11].to_vec(), vec!["before", "adjust", "set 3500", "applied ok 0"]);
+    }
+
+    #[test]
+    fn refresh_sends_the_setting_again() {
+        let lines = run(NIGHT, false, false, 0, vec![(2, Event::Refresh), (3, Event::Quit)]);
+        assert_eq!(temps(&lines), vec![3500, 3500]);
+        assert!(!lines.iter().any(|line| line == "unchanged"), "{:?}", lines);
+    }
+
+    #[test]
+    fn quit_fades_out() {
+        let lines = run(NIGHT, false, true, 0, vec![(2, Event::Quit)]);
+        let temps = temps(&lines);
+        assert!(temps.len() > 10);
+        assert!(temps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(temps[temps.len() - 1], 6500);
+        assert_eq!(lines[lines.len() - 2..].to_vec(), vec!["restore", "stopped"]);
+    }
+
+    #[test]
+    fn reset_pauses_and_resumes_with_a_fade() {
+        // The fades take 100 updates
+        let lines = run(NIGHT, true, false, 0, vec![(120, Event::Reset), (125, Event::Reset), (250, Event::Quit)]);
+        // Faded in at the start
+        assert!(temps(&lines)[0] > 6000);
+
+        // Restored at once, and paused
+        let reset = lines.iter().position(|line| line == "restore").unwrap();
+        let resumed = reset + lines[reset..].iter().position(|line| line.starts_with("set ")).unwrap();
+        // Then the update resuming, which sets the temperature again
+        let paused = &lines[reset + 1..resumed - 2];
+        assert_eq!(paused.first().map(|line| line.as_str()), Some("paused true"));
+        assert_eq!(paused.last().map(|line| line.as_str()), Some("paused false"));
+        let skipped = &paused[1..paused.len() - 1];
+        assert!(!skipped.is_empty() && skipped.iter().all(|line| line == "skipped"), "{:?}", paused);
+        assert_eq!(temps(&lines[..reset]).last(), Some(&3500));
+
+        // Fading in again from neutral
+        let after = temps(&lines[resumed..]);
+        assert!(after[0] > 6000);
+        assert!(after.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(after.last(), Some(&3500));
+    }
+
+    #[test]
+    fn enable_pauses_and_resumes_once() {
+        let lines = run(NIGHT, false, false, 0, vec![(2, Event::Enable(false)), (4, Event::Enable(false)),
+                                                     (5, Event::Enable(true)), (6, Event::Enable(true)),
+                                                     (8, Event::Quit)]);
+        let changes: Vec<_> = lines.iter().filter(|line| line.starts_with("paused")).collect();
+        assert_eq!(changes, vec!["paused true", "paused false"]);
+        assert_eq!(lines.iter().filter(|line| *line == "restore").count(), 2);
+        assert_eq!(temps(&lines), vec![3500, 3500]);
+    }
+
+    #[test]
+    fn update_events_are_coalesced() {
+        let mut script: Vec<_> = (0..10).map(|_| (2, Event::Update)).collect();
+        script.push((3, Event::Quit));
+        let lines = run(NIGHT, false, false, 0, script);
+        // The ten make one update, the third
+        assert_eq!(lines.iter().filter(|line| *line == "before").count(), 3);
+    }
+}
@@ -0,0 +1,151 @@
+//! Calendar exceptions to the schedule
+//!
+//! The file named by `exceptions` in the config file lists dates, or
+//! inclusive ranges of dates, on which the schedule is different:
+//!
+//! ```text
+//! # Christmas, no adjustment at all
+//! 2026-12-24..2026-12-26  off
+//! # Holiday in Cape Town
+//! 2027-01-10..2027-01-31  location -33.9:18.4
+//! 2027-02-01              temp 6500:4500
+//! ```
+//!
+//! Dates are in local time. When ranges overlap, the later line wins.
+//! Continual mode looks the date up again whenever it changes.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use time;
+
+use clock;
+use location::Location;
+use settings::parse_temperature;
+use super::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// No adjustment, day and night
+    Off,
+    /// Use this location instead, as (lat, lon)
+    Location(f64, f64),
+    /// Use these day and night temperatures instead
+    Temperatures(i32, i32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exceptions {
+    /// (first, last, action, line), with dates from `date_key`
+    entries: Vec<(u32, u32, Action, usize)>,
+}
+
+impl Exceptions {
+
+    pub fn load(path: &Path) -> Result<Exceptions> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        Exceptions::parse(&contents)
+    }
+
+    /// Parse "DATE[..DATE] ACTION" lines. Empty lines and lines
+    /// starting with '#' are skipped.
+    pub fn parse(contents: &str) -> Result<Exceptions> {
+        let mut entries = vec![];
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let mut fields = line.split_whitespace();
+            let dates = fields.next().unwrap_or("");
+            let (first, last) = match dates.find("..") {
+                Some(i) => (parse_date(&dates[..i]), parse_date(&dates[i+2..])),
+                None => (parse_date(dates), parse_date(dates))
+            };
+            let (first, last) = match (first, last) {
+                (Some(first), Some(last)) if first <= last => (first, last),
+                (Some(_), Some(_)) => return malformed(n, format!("{} ends before it starts", dates)),
+                _ => return malformed(n, format!("expected YYYY-MM-DD or YYYY-MM-DD..YYYY-MM-DD, not {}", dates))
+            };
+            let action = match (fields.next(), fields.next(), fields.next()) {
+                (Some("off"), None, _) => Action::Off,
+                (Some("location"), Some(location), None) => {
+                    let location: Location = location.parse()
+                        .or_else(|e| malformed(n, format!("{}", e)))?;
+                    Action::Location(location.lat, location.lon)
+                }
+                (Some("temp"), Some(temps), None) => {
+                    let (day, night) = parse_temperature(temps)
+                        .or_else(|e| malformed(n, format!("{}", e)))?;
+                    Action::Temperatures(day, night)
+                }
+                _ => return malformed(n, format!("expected off, location LAT:LON or temp DAY:NIGHT"))
+            };
+            entries.push((first, last, action, n + 1));
+        }
+        Ok(Exceptions { entries: entries })
+    }
+
+    /// The exception for the local date at `now`, if any
+    pub fn on(&self, now: f64) -> Option<&Action> {
+        self.on_date(date_key(now))
+    }
+
+    fn on_date(&self, date: u32) -> Option<&Action> {
+        self.entries.iter().rev()
+            .find(|&&(first, last, _, _)| first <= date && date <= last)
+            .map(|&(_, _, ref action, _)| action)
+    }
+
+    /// Every action, with the line it is on
+    pub fn actions(&self) -> Vec<(usize, &Action)> {
+        self.entries.iter().map(|&(_, _, ref action, line)| (line, action)).collect()
+    }
+}
+
+/// The local date at `now` as YYYYMMDD, which sorts like the dates
+pub fn date_key(now: f64) -> u32 {
+    key(&clock::local_tm(now))
+}
+
+fn key(tm: &time::Tm) -> u32 {
+    (tm.tm_year + 1900) as u32 * 10000 + (tm.tm_mon + 1) as u32 * 100 + tm.tm_mday as u32
+}
+
+fn parse_date(input: &str) -> Option<u32> {
+    // strptime ignores trailing input
+    if input.len() != "YYYY-MM-DD".len() {
+        return None
+    }
+    time::strptime(input, "%Y-%m-%d").ok().map(|tm| key(&tm))
+}
+
+#[inline]
+fn malformed<T>(n: usize, msg: String) -> Result<T> {
+    Err(From::from(format!("exceptions: line {}: {}", n + 1, msg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Exceptions, parse_date};
+
+    #[test]
+    fn test_exceptions() {
+        let exceptions = Exceptions::parse("# comment\n\
+                                            2026-12-24..2026-12-26 off\n\
+                                            2026-12-25 temp 6500:4500\n\
+                                            2027-01-10..2027-01-31  location -33.9:18.4\n").unwrap();
+        let on = |date: &str| parse_date(date).and_then(|date| exceptions.on_date(date).cloned());
+        assert_eq!(on("2026-12-23"), None);
+        assert_eq!(on("2026-12-24"), Some(Action::Off));
+        assert_eq!(on("2026-12-25"), Some(Action::Temperatures(6500, 4500)));
+        assert_eq!(on("2027-01-31"), Some(Action::Location(-33.9, 18.4)));
+
+        for input in &["2026-12-24", "2026-12-26..2026-12-24 off", "2026-12-24 of", "26-12-24 off",
+                       "2026-12-24 off now", "2026-12-24 temp 6500:", "2026-12-24 location 91:0"] {
+            assert!(Exceptions::parse(input).is_err(), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Importing the settings of f.lux
+//!
+//! `redshift-rs import-flux PATH` reads the preferences of f.lux, and
+//! prints them as a redshift-rs config file. It takes any of
+//!
+//! * a Windows registry export (`reg export "HKCU\Software\Michael
+//!   Herf\flux\Preferences" flux.reg`), in UTF-16 as regedit writes it,
+//!   or UTF-8
+//! * the macOS preferences, as the property list
+//!   (~/Library/Preferences/org.herf.Flux.plist, in XML with `plutil
+//!   -convert xml1`) or as printed by `defaults read org.herf.Flux`
+//!
+//! Only the location and the day and night temperatures have an
+//! equivalent. Key names are matched without case, as they differ
+//! between the versions of f.lux. Temperatures are either in Kelvin or
+//! named after f.lux's presets, like "Halogen".
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use location::{parse_latitude, parse_longitude};
+use settings::{MIN_TEMP, MAX_TEMP};
+use super::{Result, RedshiftError};
+
+/// f.lux's color presets, in Kelvin
+const PRESETS: &'static [(&'static str, i32)] = &[
+    ("ember", 1200),
+    ("candle", 1900),
+    ("warm incandescent", 2300),
+    ("incandescent", 2700),
+    ("halogen", 3400),
+    ("fluorescent", 4200),
+    ("sunlight", 5000),
+    ("daylight", 6500),
+];
+
+const LOCATION_KEYS: &'static [&'static str] = &["location"];
+const LAT_KEYS: &'static [&'static str] = &["lat", "latitude"];
+const LON_KEYS: &'static [&'static str] = &["lon", "long", "longitude"];
+const DAY_KEYS: &'static [&'static str] = &["daycolortemp", "daytemp", "day"];
+const NIGHT_KEYS: &'static [&'static str] = &["nightcolortemp", "nighttemp", "night"];
+
+/// What there is to import
+#[derive(Debug, Default, PartialEq)]
+pub struct Preferences {
+    pub location: Option<(f64, f64)>,
+    pub temp_day: Option<i32>,
+    pub temp_night: Option<i32>,
+}
+
+impl Preferences {
+    /// The preferences in `contents`, a registry export, property list
+    /// or `defaults read` output
+    pub fn parse(contents: &str) -> Result<Preferences> {
+        let values = if contents.contains("<plist") {
+            plist_values(contents)
+        } else {
+            line_values(contents)
+        };
+        let get = |keys: &[&str]| values.iter()
+            .find(|&&(ref key, _)| keys.contains(&key.to_lowercase().as_str()))
+            .map(|&(_, ref value)| value.as_str());
+
+        let mut prefs = Preferences::default();
+        if let Some(location) = get(LOCATION_KEYS) {
+            let mut parts = location.splitn(2, ',');
+            match (parts.next(), parts.next()) {
+                (Some(lat), Some(lon)) =>
+                    prefs.location = Some((parse_latitude(lat.trim())?, parse_longitude(lon.trim())?)),
+                _ => return malformed(format!("location: {} (expected LAT,LON)", location))
+            }
+        } else if let (Some(lat), Some(lon)) = (get(LAT_KEYS), get(LON_KEYS)) {
+            prefs.location = Some((parse_latitude(lat)?, parse_longitude(lon)?));
+        }
+        if let Some(temp) = get(DAY_KEYS) {
+            prefs.temp_day = Some(parse_flux_temperature(temp)?);
+        }
+        if let Some(temp) = get(NIGHT_KEYS) {
+            prefs.temp_night = Some(parse_flux_temperature(temp)?);
+        }
+        for &(ref key, _) in &values {
+            debug!("f.lux preference {}", key);
+        }
+        if prefs == Preferences::default() {
+            return malformed(format!("no location or temperatures found"))
+        }
+        Ok(prefs)
+    }
+
+    /// Read the preferences in the file at `path`
+    pub fn load(path: &Path) -> Result<Preferences> {
+        let mut bytes = vec![];
+        File::open(path).and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Preferences::parse(&decode(&bytes))
+            .map_err(|e| From::from(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The preferences as a redshift-rs config file
+    pub fn to_config(&self, source: &str) -> String {
+        let mut config = format!("; Imported from the f.lux preferences in {}\n[redshift]\n", source);
+        if let Some(temp) = self.temp_day {
+            config.push_str(&format!("temp-day={}\n", temp));
+        }
+        if let Some(temp) = self.temp_night {
+            config.push_str(&format!("temp-night={}\n", temp));
+        }
+        if let Some((lat, lon)) = self.location {
+            config.push_str(&format!("\n[manual]\nlat={}\nlon={}\n", lat, lon));
+        }
+        config
+    }
+}
+
+#[inline]
+fn malformed<T>(msg: String) -> Result<T> {
+    Err(Box::new(RedshiftError::MalformedArgument(msg)))
+}
+
+/// A temperature in Kelvin, or the name of a preset
+fn parse_flux_temperature(input: &str) -> Result<i32> {
+    let name = input.trim().to_lowercase().replace(|c| c == '_' || c == '-', " ");
+    let temp = match PRESETS.iter().find(|&&(preset, _)| preset == name) {
+        Some(&(_, temp)) => temp,
+        None => input.trim().parse::<f64>().map(|temp| temp.round() as i32).or_else(|_| malformed(format!(
+            "temperature: {} (expected Kelvin or one of {})",
+            input, PRESETS.iter().map(|&(preset, _)| preset).collect::<Vec<_>>().join(", "))))?
+    };
+    if temp < MIN_TEMP || temp > MAX_TEMP {
+        return malformed(format!("temperature: {} (must be between {}K and {}K)", input, MIN_TEMP, MAX_TEMP))
+    }
+    Ok(temp)
+}
+
+/// The text of a file, in UTF-16 if it starts with its byte order
+/// mark, as regedit writes them, or else UTF-8
+fn decode(bytes: &[u8]) -> String {
+    if bytes.starts_with(&[0xff, 0xfe]) {
+        let units: Vec<u16> = bytes[2..].chunks(2)
+            .map(|pair| pair[0] as u16 | (*pair.get(1).unwrap_or(&0) as u16) << 8)
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/// The values of `"Key"=dword:00000d48` and `"Key"="value"` lines of a
+/// registry export, or of `key = value;` lines of `defaults read`
+fn line_values(contents: &str) -> Vec<(String, String)> {
+    contents.lines()
+        .filter_map(|line| {
+            let mut parts = line.trim().splitn(2, '=');
+            let key = parts.next()?.trim().trim_matches('"');
+            let value = parts.next()?.trim().trim_end_matches(';').trim();
+            let value = if value.starts_with("dword:") {
+                u32::from_str_radix(&value["dword:".len()..], 16).ok()?.to_string()
+            } else {
+                value.trim_matches('"').to_owned()
+            };
+            if key.is_empty() { None } else { Some((key.to_owned(), value)) }
+        })
+        .collect()
+}
+
+/// The values of the `<key>` elements of a property list, each
+/// followed by an `<integer>`, `<real>` or `<string>`
+fn plist_values(contents: &str) -> Vec<(String, String)> {
+    let mut values = vec![];
+    let mut rest = contents;
+    while let Some(start) = rest.find("<key>") {
+        rest = &rest[start + "<key>".len()..];
+        let end = match rest.find("</key>") {
+            Some(end) => end,
+            None => break
+        };
+        let key = rest[..end].trim().to_owned();
+        rest = rest[end + "</key>".len()..].trim_start();
+        for tag in &["integer", "real", "string"] {
+            let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+            if rest.starts_with(&open) {
+                if let Some(end) = rest.find(&close) {
+                    values.push((key.clone(), rest[open.len()..end].trim().to_owned()));
+                }
+            }
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, Preferences};
+
+    #[test]
+    fn test_parse() {
+        let reg = "Windows Registry Editor Version 5.00\r\n\r\n\
+                   [HKEY_CURRENT_USER\\Software\\Michael Herf\\flux\\Preferences]\r\n\
+                   \"Lat\"=\"55.7\"\r\n\"Lon\"=\"12.6\"\r\n\
+                   \"Day\"=dword:00001964\r\n\"Night\"=\"Halogen\"\r\n";
+        let expected = Preferences { location: Some((55.7, 12.6)), temp_day: Some(6500), temp_night: Some(3400) };
+        assert_eq!(Preferences::parse(reg).unwrap(), expected);
+
+        // UTF-16, as regedit writes it
+        let mut utf16 = vec![0xff, 0xfe];
+        for unit in reg.encode_utf16() {
+            utf16.push(unit as u8);
+            utf16.push((unit >> 8) as u8);
+        }
+        assert_eq!(Preferences::parse(&decode(&utf16)).unwrap(), expected);
+
+        let defaults = "{\n    dayColorTemp = 6500;\n    location = \"55.7,12.6\";\n    \
+                        nightColorTemp = 3400;\n    wakeTime = 420;\n}\n";
+        assert_eq!(Preferences::parse(defaults).unwrap(), expected);
+
+        let plist = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\">\n<dict>\n\
+                     \t<key>dayColorTemp</key>\n\t<integer>6500</integer>\n\
+                     \t<key>location</key>\n\t<string>55.7,12.6</string>\n\
+                     \t<key>nightColorTemp</key>\n\t<real>3400.0</real>\n</dict>\n</plist>\n";
+        let prefs = Preferences::parse(plist).unwrap();
+        assert_eq!(prefs, expected);
+        assert_eq!(prefs.to_config("flux.plist"),
+                   "; Imported from the f.lux preferences in flux.plist\n[redshift]\n\
+                    temp-day=6500\ntemp-night=3400\n\n[manual]\nlat=55.7\nlon=12.6\n");
+
+        for bad in &["nightColorTemp = 500;", "nightColorTemp = Neon;", "location = \"55.7\";", "wakeTime = 420;"] {
+            assert!(Preferences::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
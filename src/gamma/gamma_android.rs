@@ -0,0 +1,309 @@
+//! Setting the display color on Android, best effort
+//!
+//! Android has no gamma ramps to set, so this goes through whichever
+//! of these works, in this order unless the `mode` option picks one:
+//!
+//! * `kcal`: the KCAL color controls of many custom kernels, in
+//!   /sys/devices/platform/kcal_ctrl.0. Scales each channel, so both
+//!   the color and the brightness are shown.
+//! * `surfaceflinger`: the color matrix SurfaceFlinger applies to the
+//!   whole screen, set with `service call` (transaction 1015, used by
+//!   the developer options). Also shows both, and needs root.
+//! * `night-display`: the Night Light of Android 7 and later, through
+//!   `settings put secure`. Only the temperature is shown, within the
+//!   range the device allows. Needs root, or running from `adb shell`.
+//!
+//! None of them take a gamma, which is ignored. From Termux, run
+//! redshift-rs as root (with su or tsu).
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use colorramp;
+use privilege;
+use transition;
+
+use super::{Capabilities, GammaMethod};
+use super::{Result, RedshiftError, NEUTRAL_TEMP};
+
+const KCAL_DIR: &'static str = "/sys/devices/platform/kcal_ctrl.0";
+
+/// Full scale of a KCAL channel
+const KCAL_MAX: f64 = 256.0;
+
+/// SurfaceFlinger's transaction for setting the color matrix
+const SURFACEFLINGER_COLOR_MATRIX: &'static str = "1015";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Kcal,
+    SurfaceFlinger,
+    NightDisplay,
+}
+
+/// The Night Light settings, as found at start
+struct NightDisplay {
+    activated: Option<String>,
+    temperature: Option<String>,
+    auto_mode: Option<String>,
+}
+
+pub struct AndroidState {
+    /// The mode selected with the `mode` option, tried in turn if not
+    /// set
+    wanted: Option<Mode>,
+    mode: Option<Mode>,
+
+    /// KCAL channels found at start
+    saved_kcal: Option<String>,
+    saved_night_display: Option<NightDisplay>,
+
+    /// What was last set, to skip running a command when nothing
+    /// changes
+    last: Option<String>,
+}
+
+/// Whether this is Android, where `service` and `settings` are
+/// Android's own commands
+fn is_android() -> bool {
+    Path::new("/system/build.prop").exists()
+}
+
+/// Run `program`, and return what it printed
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    debug!("Running {:?}", cmd);
+    let output = cmd.output()
+        .map_err(|e| format!("could not run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(From::from(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim())))
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn get_setting(name: &str) -> Result<Option<String>> {
+    let value = run("settings", &["get", "secure", name])?;
+    Ok(if value == "null" { None } else { Some(value) })
+}
+
+fn put_setting(name: &str, value: Option<&str>) -> Result<()> {
+    match value {
+        Some(value) => run("settings", &["put", "secure", name, value]),
+        None => run("settings", &["delete", "secure", name])
+    }.map(|_| ())
+}
+
+fn read_kcal() -> Result<String> {
+    let mut contents = String::new();
+    File::open(Path::new(KCAL_DIR).join("kcal"))?.read_to_string(&mut contents)?;
+    Ok(contents.trim().to_owned())
+}
+
+fn write_kcal(file: &str, value: &str) -> Result<()> {
+    let path = Path::new(KCAL_DIR).join(file);
+    OpenOptions::new().write(true).open(&path)
+        .and_then(|mut f| write!(f, "{}", value))
+        .map_err(|e| From::from(format!("could not write {}: {}", path.display(), e)))
+}
+
+/// Each channel of the white point of `setting`, dimmed to its
+/// brightness
+fn channel_scale(setting: &transition::ColorSetting) -> [f64; 3] {
+    let white = colorramp::white_point(setting.temp);
+    [white[0] * setting.brightness, white[1] * setting.brightness, white[2] * setting.brightness]
+}
+
+/// The KCAL channels for `setting`, as "R G B"
+fn kcal_value(setting: &transition::ColorSetting) -> String {
+    let scale = channel_scale(setting);
+    // A channel at 0 turns the panel off on some kernels
+    let channel = |c: usize| (scale[c] * KCAL_MAX).round().max(1.0).min(KCAL_MAX);
+    format!("{} {} {}", channel(0), channel(1), channel(2))
+}
+
+/// The arguments of `service call` setting the color matrix for
+/// `setting`, or clearing it for `None`
+fn surfaceflinger_args(setting: Option<&transition::ColorSetting>) -> Vec<String> {
+    let mut args: Vec<String> = ["call", "SurfaceFlinger", SURFACEFLINGER_COLOR_MATRIX, "i32"].iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    match setting {
+        Some(setting) => {
+            args.push("1".to_owned());
+            // A 4x4 matrix, scaling each channel on the diagonal
+            let scale = channel_scale(setting);
+            for row in 0..4 {
+                for column in 0..4 {
+                    let value = match (row == column, row) {
+                        (true, 3) => 1.0,
+                        (true, c) => scale[c],
+                        (false, _) => 0.0,
+                    };
+                    args.push("f".to_owned());
+                    args.push(format!("{:.6}", value));
+                }
+            }
+        }
+        None => args.push("0".to_owned())
+    }
+    args
+}
+
+impl AndroidState {
+    fn try_start(&mut self, mode: Mode) -> Result<()> {
+        match mode {
+            Mode::Kcal => {
+                self.saved_kcal = Some(read_kcal()
+                    .map_err(|e| format!("no KCAL color controls in {}: {}", KCAL_DIR, e))?);
+                write_kcal("kcal_enable", "1")?;
+            }
+            Mode::SurfaceFlinger => {
+                if !privilege::is_root() {
+                    return Err(From::from("setting SurfaceFlinger's color matrix needs root"))
+                }
+                run("service", &["check", "SurfaceFlinger"])?;
+            }
+            Mode::NightDisplay => {
+                self.saved_night_display = Some(NightDisplay {
+                    activated: get_setting("night_display_activated")?,
+                    temperature: get_setting("night_display_color_temperature")?,
+                    auto_mode: get_setting("night_display_auto_mode")?,
+                });
+                // Or the schedule of Android would fight ours
+                put_setting("night_display_auto_mode", Some("0"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, setting: Option<&transition::ColorSetting>) -> Result<()> {
+        let mode = self.mode.ok_or("the android method was not started")?;
+        let value = match (mode, setting) {
+            (Mode::Kcal, Some(setting)) => kcal_value(setting),
+            (Mode::Kcal, None) => self.saved_kcal.clone().unwrap_or(kcal_value(&transition::ColorSetting::new())),
+            (Mode::SurfaceFlinger, _) => surfaceflinger_args(setting).join(" "),
+            (Mode::NightDisplay, Some(setting)) => setting.temp.to_string(),
+            (Mode::NightDisplay, None) => "restore".to_owned(),
+        };
+        if self.last.as_ref() == Some(&value) {
+            return Ok(())
+        }
+        match (mode, setting) {
+            (Mode::Kcal, _) => write_kcal("kcal", &value)?,
+            (Mode::SurfaceFlinger, _) => {
+                let args = surfaceflinger_args(setting);
+                run("service", &args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>())?;
+            }
+            (Mode::NightDisplay, Some(setting)) if setting.temp.round() >= NEUTRAL_TEMP =>
+                put_setting("night_display_activated", Some("0"))?,
+            (Mode::NightDisplay, Some(_)) => {
+                put_setting("night_display_color_temperature", Some(&value))?;
+                put_setting("night_display_activated", Some("1"))?;
+            }
+            (Mode::NightDisplay, None) => {
+                if let Some(ref saved) = self.saved_night_display {
+                    put_setting("night_display_color_temperature", saved.temperature.as_ref().map(|s| s.as_str()))?;
+                    put_setting("night_display_activated", saved.activated.as_ref().map(|s| s.as_str()))?;
+                    put_setting("night_display_auto_mode", saved.auto_mode.as_ref().map(|s| s.as_str()))?;
+                }
+            }
+        }
+        self.last = Some(value);
+        Ok(())
+    }
+}
+
+impl GammaMethod for AndroidState {
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "mode" => {
+                self.wanted = Some(match value {
+                    "kcal" => Mode::Kcal,
+                    "surfaceflinger" => Mode::SurfaceFlinger,
+                    "night-display" => Mode::NightDisplay,
+                    _ => return Err(Box::new(RedshiftError::MalformedArgument(format!(
+                        "unknown android mode '{}' (expected kcal, surfaceflinger or night-display)", value))))
+                });
+                Ok(())
+            }
+            _ => Err(Box::new(RedshiftError::MalformedArgument(format!("unknown method option '{}'", key))))
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if !is_android() {
+            return Err(From::from("not running on Android"))
+        }
+        let modes = match self.wanted {
+            Some(mode) => vec![mode],
+            None => vec![Mode::Kcal, Mode::SurfaceFlinger, Mode::NightDisplay]
+        };
+        let mut errors = vec![];
+        for mode in modes {
+            match self.try_start(mode) {
+                Ok(()) => {
+                    debug!("Setting the display color with {:?}", mode);
+                    self.mode = Some(mode);
+                    self.last = None;
+                    return Ok(())
+                }
+                Err(e) => errors.push(format!("{}", e))
+            }
+        }
+        Err(From::from(errors.join("; ")))
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        self.set(Some(setting))
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        self.set(None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { color: true, brightness: self.mode != Some(Mode::NightDisplay) }
+    }
+
+    fn writable_paths(&self) -> Vec<PathBuf> {
+        match self.mode {
+            Some(Mode::Kcal) => vec![Path::new(KCAL_DIR).join("kcal")],
+            _ => vec![]
+        }
+    }
+}
+
+/// The init function
+pub fn init() -> Result<Box<GammaMethod>> {
+    Ok(Box::new(AndroidState {
+        wanted: None,
+        mode: None,
+        saved_kcal: None,
+        saved_night_display: None,
+        last: None,
+    }) as Box<GammaMethod>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{kcal_value, surfaceflinger_args};
+    use transition::{ColorSetting, Kelvin};
+
+    #[test]
+    fn test_values() {
+        let neutral = ColorSetting { temp: Kelvin(6500.0), gamma: [1.0; 3], brightness: 1.0 };
+        assert_eq!(kcal_value(&neutral), "256 256 256");
+        let dim = ColorSetting { temp: Kelvin(6500.0), gamma: [1.0; 3], brightness: 0.5 };
+        assert_eq!(kcal_value(&dim), "128 128 128");
+
+        let args = surfaceflinger_args(Some(&dim));
+        assert_eq!(args.len(), 4 + 1 + 32);
+        assert_eq!(&args[..5], &["call", "SurfaceFlinger", "1015", "i32", "1"]);
+        // Diagonal of red, then the last element
+        assert_eq!((args[6].as_str(), args[36].as_str()), ("0.500000", "1.000000"));
+        assert_eq!(surfaceflinger_args(None).join(" "), "call SurfaceFlinger 1015 i32 0");
+    }
+}
@@ -0,0 +1,147 @@
+//! Dimming a laptop panel's backlight through sysfs
+//!
+//! Only the brightness of a setting is used, scaled from the level
+//! found at start, so that the backlight dims from wherever it was
+//! left. Usually combined with a method for the color, as in
+//! `-m randr,backlight`.
+//!
+//! Writing `/sys/class/backlight/*/brightness` needs root, or a udev
+//! rule giving the user access to it.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use transition;
+
+use super::{Capabilities, GammaMethod, OutputStatus};
+use super::{Result, RedshiftError};
+
+const BACKLIGHT_DIR: &'static str = "/sys/class/backlight";
+
+pub struct BacklightState {
+    /// The device under BACKLIGHT_DIR, the first one if not set
+    device: Option<String>,
+    max: u64,
+
+    /// The level found at start, for scaling and restoring
+    saved: u64,
+
+    /// The level last written
+    last: Option<u64>,
+
+    /// The brightness it was for, unless restored since
+    applied: Option<f64>,
+}
+
+impl BacklightState {
+    fn dir(&self) -> PathBuf {
+        Path::new(BACKLIGHT_DIR).join(self.device.as_ref().map_or("", |s| s.as_str()))
+    }
+
+    fn write_level(&mut self, level: u64) -> Result<()> {
+        if self.last == Some(level) {
+            return Ok(())
+        }
+        let path = self.dir().join("brightness");
+        OpenOptions::new().write(true).open(&path)
+            .and_then(|mut f| write!(f, "{}", level))
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        self.last = Some(level);
+        Ok(())
+    }
+}
+
+fn read_value(path: &Path) -> Result<u64> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    contents.trim().parse()
+        .map_err(|_| From::from(format!("unexpected contents in {}: {}", path.display(), contents.trim())))
+}
+
+/// The level for `brightness`, scaled from the `saved` one
+pub fn level(saved: u64, max: u64, brightness: f64) -> u64 {
+    // Never all the way off, which would leave a black screen
+    let level = (saved as f64 * brightness).round() as u64;
+    level.max(1).min(max)
+}
+
+impl GammaMethod for BacklightState {
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "device" => {
+                self.device = Some(value.to_owned());
+                Ok(())
+            }
+            _ => Err(Box::new(RedshiftError::MalformedArgument(format!("unknown method option '{}'", key))))
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if self.device.is_none() {
+            let mut devices: Vec<String> = fs::read_dir(BACKLIGHT_DIR)
+                .map(|entries| entries.filter_map(|entry| entry.ok())
+                     .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                     .collect())
+                .unwrap_or(vec![]);
+            devices.sort();
+            match devices.into_iter().next() {
+                Some(device) => self.device = Some(device),
+                None => return Err(Box::new(RedshiftError::NoAdjustableOutputs(
+                    format!("no backlight found in {}", BACKLIGHT_DIR))))
+            }
+        }
+        let dir = self.dir();
+        self.max = read_value(&dir.join("max_brightness"))?;
+        self.saved = read_value(&dir.join("brightness"))?;
+        debug!("Backlight {} at {} of {}", dir.display(), self.saved, self.max);
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        let level = level(self.saved, self.max, setting.brightness);
+        self.write_level(level)?;
+        self.applied = Some(setting.brightness);
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        let saved = self.saved;
+        self.write_level(saved)?;
+        self.applied = None;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { color: false, brightness: true }
+    }
+
+    fn outputs(&self) -> Result<Vec<String>> {
+        Ok(self.device.iter().cloned().collect())
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        self.device.iter()
+            .map(|device| {
+                let mut status = OutputStatus::new(device, None);
+                status.brightness = self.applied;
+                if let Some(level) = self.last {
+                    status.overrides.push(format!("level {} of {}", level, self.max));
+                }
+                status
+            })
+            .collect()
+    }
+
+    fn writable_paths(&self) -> Vec<PathBuf> {
+        // Landlock checks the real path, under /sys/devices
+        fs::canonicalize(self.dir().join("brightness")).into_iter().collect()
+    }
+}
+
+pub fn init() -> Result<Box<GammaMethod>> {
+    if !Path::new(BACKLIGHT_DIR).is_dir() {
+        return Err(From::from(format!("{} does not exist", BACKLIGHT_DIR)))
+    }
+    Ok(Box::new(BacklightState { device: None, max: 0, saved: 0, last: None, applied: None }))
+}
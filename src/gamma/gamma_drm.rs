@@ -0,0 +1,1229 @@
+//! Setting gamma ramps through DRM/KMS, for consoles without X
+//!
+//! The DRM devices in /dev/dri (card0, card1, ...) are opened, or only
+//! the one given with the `card` option (a number or a path), and the
+//! ramps of their CRTCs set with the mode setting ioctls, as
+//! drmModeCrtcSetGamma does.
+//!
+//! CRTCs with the CTM and GAMMA_LUT color properties (most drivers
+//! using atomic mode setting) are set through those instead: the white
+//! point and brightness go in the color transformation matrix, and the
+//! gamma curve in the LUT after it, which only changes with the gamma.
+//! These survive some compositor resets that the legacy ramps do not.
+//! Setting `ctm=0`, or the properties failing, falls back to the
+//! legacy ramps.
+//!
+//! Setting ramps takes being the DRM master of a card, which the first
+//! program opening it becomes: this works from a bare console, but not
+//! while a display server, a Wayland compositor or kmscon holds the
+//! card. The device must also be readable and writable, which logind
+//! grants the user of the active seat (or membership of the video
+//! group).
+//!
+//! Built with the seat feature, the cards are opened through libseat
+//! instead (see `seat`), so that logind or seatd hands them over and
+//! makes us the master. Adjustments then pause while another VT or
+//! session is active, with the ramps restored, and resume on return.
+//! Without a seat, the same is done while started from a VT and
+//! another VT is shown, taking the cards back from a display server
+//! that ran there.
+//!
+//! On machines with several seats, each card belongs to the seat udev
+//! tagged it with (seat0 unless tagged). The `seat` option only adjusts
+//! the cards of one seat, the one of the session by default when using
+//! libseat, and `[seat:NAME]` sections of the config file limit the
+//! settings of the cards of a seat.
+//!
+//! Cards added later, like an eGPU being plugged in, are noticed from
+//! their uevents, and adjusted from the next update on. So are displays
+//! plugged into a card, as setting a mode may reset its ramps.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use colorramp;
+use transition;
+
+use super::{AdjustmentLimit, CrtcRamps, GammaMethod, OutputStatus};
+use super::{Result, RedshiftError};
+use super::vt;
+#[cfg(feature = "seat")]
+use super::seat;
+
+const DRI_DIR: &'static str = "/dev/dri";
+
+/// Where udev keeps the properties of devices, like their seat
+const UDEV_DATA_DIR: &'static str = "/run/udev/data";
+
+/// The seat of devices udev did not tag with one
+const DEFAULT_SEAT: &'static str = "seat0";
+
+// Request numbers of the mode setting ioctls, from drm.h
+const DRM_IOCTL_MODE_GETRESOURCES: u64 = 0xA0;
+const DRM_IOCTL_MODE_GETCRTC: u64 = 0xA1;
+const DRM_IOCTL_MODE_GETGAMMA: u64 = 0xA4;
+const DRM_IOCTL_MODE_SETGAMMA: u64 = 0xA5;
+const DRM_IOCTL_MODE_GETENCODER: u64 = 0xA6;
+const DRM_IOCTL_MODE_GETCONNECTOR: u64 = 0xA7;
+
+const DRM_IOCTL_MODE_GETPROPERTY: u64 = 0xAA;
+const DRM_IOCTL_MODE_OBJ_GETPROPERTIES: u64 = 0xB9;
+const DRM_IOCTL_MODE_OBJ_SETPROPERTY: u64 = 0xBA;
+const DRM_IOCTL_MODE_CREATEPROPBLOB: u64 = 0xBD;
+const DRM_IOCTL_MODE_DESTROYPROPBLOB: u64 = 0xBE;
+
+/// `_IO('d', 0x1e)`, taking no argument
+const DRM_IOCTL_SET_MASTER: u64 = 0x641E;
+
+/// `obj_type` of CRTCs in the property ioctls
+const DRM_MODE_OBJECT_CRTC: u32 = 0xcccccccc;
+
+/// `connection` of a connector with a display attached
+const DRM_MODE_CONNECTED: u32 = 1;
+
+/// Multicast groups of the uevents sent by the kernel, and by udev once
+/// it set up the device (and its permissions)
+const UEVENT_KERNEL_GROUP: u32 = 1;
+const UEVENT_UDEV_GROUP: u32 = 2;
+
+/// Names of the connector types, by their number, as the kernel names
+/// the connectors in /sys/class/drm
+const CONNECTOR_TYPES: &'static [&'static str] = &[
+    "Unknown", "VGA", "DVI-I", "DVI-D", "DVI-A", "Composite", "SVIDEO", "LVDS", "Component", "DIN", "DP",
+    "HDMI-A", "HDMI-B", "TV", "eDP", "Virtual", "DSI", "DPI", "Writeback", "SPI", "USB",
+];
+
+// The structures of drm_mode.h
+
+#[repr(C)]
+#[derive(Default)]
+struct CardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ModeInfo {
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    kind: u32,
+    name: [u8; 32],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ModeCrtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    mode: ModeInfo,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CrtcLut {
+    crtc_id: u32,
+    gamma_size: u32,
+    red: u64,
+    green: u64,
+    blue: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct GetEncoder {
+    encoder_id: u32,
+    encoder_type: u32,
+    crtc_id: u32,
+    possible_crtcs: u32,
+    possible_clones: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct GetConnector {
+    encoders_ptr: u64,
+    modes_ptr: u64,
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_modes: u32,
+    count_props: u32,
+    count_encoders: u32,
+    encoder_id: u32,
+    connector_id: u32,
+    connector_type: u32,
+    connector_type_id: u32,
+    connection: u32,
+    mm_width: u32,
+    mm_height: u32,
+    subpixel: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ObjGetProperties {
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_props: u32,
+    obj_id: u32,
+    obj_type: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct GetProperty {
+    values_ptr: u64,
+    enum_blob_ptr: u64,
+    prop_id: u32,
+    flags: u32,
+    name: [u8; 32],
+    count_values: u32,
+    count_enum_blobs: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ObjSetProperty {
+    value: u64,
+    prop_id: u32,
+    obj_id: u32,
+    obj_type: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CreateBlob {
+    data: u64,
+    length: u32,
+    blob_id: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct DestroyBlob {
+    blob_id: u32,
+}
+
+/// An entry of a GAMMA_LUT (`struct drm_color_lut`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ColorLut {
+    red: u16,
+    green: u16,
+    blue: u16,
+    reserved: u16,
+}
+
+/// The request of DRM ioctl `nr`, taking a `T` (`_IOWR('d', nr, T)`)
+fn request<T>(nr: u64) -> u64 {
+    (3 << 30) | ((mem::size_of::<T>() as u64) << 16) | (('d' as u64) << 8) | nr
+}
+
+/// How many times an ioctl is retried while the device is busy
+/// (EAGAIN), and how long to wait in between
+const BUSY_RETRIES: u32 = 10;
+const BUSY_WAIT: Duration = Duration::from_millis(10);
+
+fn ioctl<T>(file: &File, nr: u64, arg: &mut T) -> io::Result<()> {
+    let mut busy = 0;
+    loop {
+        if unsafe { libc::ioctl(file.as_raw_fd(), request::<T>(nr) as _, arg as *mut T) } == 0 {
+            return Ok(())
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::EAGAIN) if busy < BUSY_RETRIES => {
+                busy += 1;
+                thread::sleep(BUSY_WAIT);
+            }
+            _ => return Err(e)
+        }
+    }
+}
+
+/// The ids of the CRTCs and connectors of a card
+fn get_resources(file: &File) -> io::Result<(Vec<u32>, Vec<u32>)> {
+    let mut res = CardRes::default();
+    ioctl(file, DRM_IOCTL_MODE_GETRESOURCES, &mut res)?;
+    // Hotplugging between the two calls changes the counts
+    loop {
+        let mut crtcs = vec![0u32; res.count_crtcs as usize];
+        let mut connectors = vec![0u32; res.count_connectors as usize];
+        let mut filled = CardRes {
+            crtc_id_ptr: crtcs.as_mut_ptr() as u64,
+            connector_id_ptr: connectors.as_mut_ptr() as u64,
+            count_crtcs: res.count_crtcs,
+            count_connectors: res.count_connectors,
+            ..CardRes::default()
+        };
+        ioctl(file, DRM_IOCTL_MODE_GETRESOURCES, &mut filled)?;
+        if filled.count_crtcs <= res.count_crtcs && filled.count_connectors <= res.count_connectors {
+            crtcs.truncate(filled.count_crtcs as usize);
+            connectors.truncate(filled.count_connectors as usize);
+            return Ok((crtcs, connectors))
+        }
+        res = filled;
+    }
+}
+
+fn get_gamma(file: &File, crtc: u32, size: usize) -> io::Result<colorramp::Ramps> {
+    let mut ramps = (vec![0u16; size], vec![0u16; size], vec![0u16; size]);
+    let mut lut = CrtcLut {
+        crtc_id: crtc,
+        gamma_size: size as u32,
+        red: ramps.0.as_mut_ptr() as u64,
+        green: ramps.1.as_mut_ptr() as u64,
+        blue: ramps.2.as_mut_ptr() as u64,
+    };
+    ioctl(file, DRM_IOCTL_MODE_GETGAMMA, &mut lut)?;
+    Ok(ramps)
+}
+
+fn set_gamma(file: &File, crtc: u32, ramps: &colorramp::Ramps) -> io::Result<()> {
+    let mut lut = CrtcLut {
+        crtc_id: crtc,
+        gamma_size: ramps.0.len() as u32,
+        red: ramps.0.as_ptr() as u64,
+        green: ramps.1.as_ptr() as u64,
+        blue: ramps.2.as_ptr() as u64,
+    };
+    ioctl(file, DRM_IOCTL_MODE_SETGAMMA, &mut lut)
+}
+
+/// The ids and values of the properties of object `obj_id`
+fn get_properties(file: &File, obj_id: u32, obj_type: u32) -> io::Result<Vec<(u32, u64)>> {
+    let mut count = ObjGetProperties { obj_id: obj_id, obj_type: obj_type, ..ObjGetProperties::default() };
+    ioctl(file, DRM_IOCTL_MODE_OBJ_GETPROPERTIES, &mut count)?;
+    loop {
+        let mut ids = vec![0u32; count.count_props as usize];
+        let mut values = vec![0u64; count.count_props as usize];
+        let mut filled = ObjGetProperties {
+            props_ptr: ids.as_mut_ptr() as u64,
+            prop_values_ptr: values.as_mut_ptr() as u64,
+            count_props: count.count_props,
+            obj_id: obj_id,
+            obj_type: obj_type,
+        };
+        ioctl(file, DRM_IOCTL_MODE_OBJ_GETPROPERTIES, &mut filled)?;
+        if filled.count_props <= count.count_props {
+            ids.truncate(filled.count_props as usize);
+            return Ok(ids.into_iter().zip(values).collect())
+        }
+        count = filled;
+    }
+}
+
+/// The name of property `prop_id`
+fn property_name(file: &File, prop_id: u32) -> io::Result<String> {
+    let mut prop = GetProperty { prop_id: prop_id, ..GetProperty::default() };
+    ioctl(file, DRM_IOCTL_MODE_GETPROPERTY, &mut prop)?;
+    let len = prop.name.iter().position(|&b| b == 0).unwrap_or(prop.name.len());
+    Ok(String::from_utf8_lossy(&prop.name[..len]).into_owned())
+}
+
+/// The color properties of a CRTC
+struct ColorProps {
+    ctm: u32,
+    gamma_lut: u32,
+    lut_size: usize,
+}
+
+/// The color properties of `crtc`, if it has all of them
+fn color_props(file: &File, crtc: u32) -> io::Result<Option<ColorProps>> {
+    let (mut ctm, mut gamma_lut, mut lut_size) = (None, None, None);
+    for (id, value) in get_properties(file, crtc, DRM_MODE_OBJECT_CRTC)? {
+        match property_name(file, id)?.as_str() {
+            "CTM" => ctm = Some(id),
+            "GAMMA_LUT" => gamma_lut = Some(id),
+            "GAMMA_LUT_SIZE" => lut_size = Some(value as usize),
+            _ => {}
+        }
+    }
+    Ok(match (ctm, gamma_lut, lut_size) {
+        (Some(ctm), Some(gamma_lut), Some(lut_size)) if lut_size > 1 =>
+            Some(ColorProps { ctm: ctm, gamma_lut: gamma_lut, lut_size: lut_size }),
+        _ => None
+    })
+}
+
+/// Set property `prop_id` of `crtc` to a blob holding `data`
+fn set_blob_property<T>(file: &File, crtc: u32, prop_id: u32, data: &[T]) -> io::Result<()> {
+    let mut blob = CreateBlob {
+        data: data.as_ptr() as u64,
+        length: mem::size_of_val(data) as u32,
+        blob_id: 0,
+    };
+    ioctl(file, DRM_IOCTL_MODE_CREATEPROPBLOB, &mut blob)?;
+    let mut set = ObjSetProperty {
+        value: blob.blob_id as u64,
+        prop_id: prop_id,
+        obj_id: crtc,
+        obj_type: DRM_MODE_OBJECT_CRTC,
+    };
+    let result = ioctl(file, DRM_IOCTL_MODE_OBJ_SETPROPERTY, &mut set);
+    // The CRTC keeps its own reference to the blob, so ours can go
+    // whether or not it was set
+    let _ = ioctl(file, DRM_IOCTL_MODE_DESTROYPROPBLOB, &mut DestroyBlob { blob_id: blob.blob_id });
+    result
+}
+
+/// The color transformation matrix for the white point and brightness
+/// of `setting`, in S31.32 sign-magnitude fixed point
+fn ctm(setting: &transition::ColorSetting) -> [u64; 9] {
+    let white = colorramp::white_point(setting.temp);
+    let fixed = |v: f64| (v.max(0.0) * (1u64 << 32) as f64).round() as u64;
+    let mut matrix = [0u64; 9];
+    for c in 0..3 {
+        matrix[c * 4] = fixed(setting.brightness * white[c]);
+    }
+    matrix
+}
+
+/// A GAMMA_LUT of `size` entries with `gamma`, as the ramps would
+/// have for white at full brightness
+fn gamma_lut(gamma: &[f64; 3], size: usize) -> Vec<ColorLut> {
+    let curve = |x: f64, c: usize| (x.powf(gamma[c].recip()) * u16::max_value() as f64).round() as u16;
+    (0..size).map(|i| {
+        let x = i as f64 / (size - 1) as f64;
+        ColorLut { red: curve(x, 0), green: curve(x, 1), blue: curve(x, 2), reserved: 0 }
+    }).collect()
+}
+
+/// Become the DRM master of the card again, which works if no one
+/// else is, and the card was opened by us when no one was
+fn set_master(file: &File) -> io::Result<()> {
+    if unsafe { libc::ioctl(file.as_raw_fd(), DRM_IOCTL_SET_MASTER as _, 0) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// The name of a connector, like "HDMI-A-1"
+fn connector_name(kind: u32, index: u32) -> String {
+    format!("{}-{}", CONNECTOR_TYPES.get(kind as usize).unwrap_or(&"Unknown"), index)
+}
+
+/// The names of the connected outputs shown by each CRTC
+fn crtc_outputs(file: &File, connectors: &[u32]) -> io::Result<HashMap<u32, Vec<String>>> {
+    let mut outputs: HashMap<u32, Vec<String>> = HashMap::new();
+    for &id in connectors {
+        // With no room for modes the kernel would probe the outputs,
+        // which takes a while, so there is room for one
+        let mut mode = ModeInfo::default();
+        let mut connector = GetConnector {
+            connector_id: id,
+            modes_ptr: &mut mode as *mut ModeInfo as u64,
+            count_modes: 1,
+            ..GetConnector::default()
+        };
+        ioctl(file, DRM_IOCTL_MODE_GETCONNECTOR, &mut connector)?;
+        if connector.connection != DRM_MODE_CONNECTED || connector.encoder_id == 0 {
+            continue
+        }
+        let mut encoder = GetEncoder { encoder_id: connector.encoder_id, ..GetEncoder::default() };
+        ioctl(file, DRM_IOCTL_MODE_GETENCODER, &mut encoder)?;
+        if encoder.crtc_id != 0 {
+            outputs.entry(encoder.crtc_id).or_insert_with(Vec::new)
+                .push(connector_name(connector.connector_type, connector.connector_type_id));
+        }
+    }
+    Ok(outputs)
+}
+
+/// A socket receiving uevents, of devices being added, changed and
+/// removed
+struct Uevents {
+    fd: libc::c_int,
+}
+
+impl Uevents {
+    fn open() -> io::Result<Uevents> {
+        let fd = unsafe {
+            libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                         libc::NETLINK_KOBJECT_UEVENT)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        let uevents = Uevents { fd: fd };
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = UEVENT_KERNEL_GROUP | UEVENT_UDEV_GROUP;
+        let bound = unsafe {
+            libc::bind(fd, &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                       mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+        };
+        if bound != 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(uevents)
+    }
+
+    /// The events received so far, as their properties
+    fn pending(&self) -> Vec<HashMap<String, String>> {
+        let mut events = vec![];
+        let mut buf = [0u8; 8192];
+        loop {
+            let len = unsafe { libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if len <= 0 {
+                return events
+            }
+            events.push(parse_uevent(&buf[..len as usize]));
+        }
+    }
+}
+
+impl Drop for Uevents {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// The `KEY=VALUE` properties of a uevent, separated by NULs after a
+/// header: "ACTION@DEVPATH" from the kernel, a binary one from udev
+fn parse_uevent(data: &[u8]) -> HashMap<String, String> {
+    data.split(|&b| b == 0)
+        .filter_map(|field| {
+            let field = String::from_utf8_lossy(field);
+            let mut parts = field.splitn(2, '=');
+            let key = parts.next()?;
+            let value = parts.next()?;
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') {
+                return None
+            }
+            Some((key.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// The number of the card at `path`, if it is one rather than a render
+/// node
+fn card_number(path: &Path) -> Option<u32> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    if name.starts_with("card") {
+        name["card".len()..].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// The seat in the udev properties of a device, given as "E:KEY=VALUE"
+/// lines
+fn udev_seat(data: &str) -> Option<String> {
+    data.lines()
+        .find(|line| line.starts_with("E:ID_SEAT="))
+        .map(|line| line["E:ID_SEAT=".len()..].to_owned())
+        .filter(|seat| !seat.is_empty())
+}
+
+/// The major and minor numbers of device number `rdev`
+fn device_numbers(rdev: u64) -> (u64, u64) {
+    (((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff), (rdev & 0xff) | ((rdev >> 12) & !0xff))
+}
+
+/// The seat the card at `path` is attached to
+fn card_seat(path: &Path) -> String {
+    fs::metadata(path).ok()
+        .and_then(|meta| {
+            let (major, minor) = device_numbers(meta.rdev());
+            fs::read_to_string(Path::new(UDEV_DATA_DIR).join(format!("c{}:{}", major, minor))).ok()
+        })
+        .and_then(|data| udev_seat(&data))
+        .unwrap_or(DEFAULT_SEAT.to_owned())
+}
+
+struct Crtc {
+    id: u32,
+
+    /// The ramps found at start and those set since
+    ramps: CrtcRamps,
+
+    /// Names of the outputs driven by this CRTC
+    outputs: Vec<String>,
+
+    /// The CTM and GAMMA_LUT properties, unless the CRTC lacks them
+    /// or setting them failed
+    color: Option<ColorProps>,
+
+    /// The gamma the GAMMA_LUT was last set for
+    lut_gamma: Option<[f64; 3]>,
+}
+
+impl Crtc {
+    /// Set `setting` through the color properties
+    fn set_color(&mut self, file: &File, setting: &transition::ColorSetting) -> io::Result<()> {
+        let props = match self.color {
+            Some(ref props) => props,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "no color properties"))
+        };
+        if self.lut_gamma != Some(setting.gamma) {
+            set_blob_property(file, self.id, props.gamma_lut, &gamma_lut(&setting.gamma, props.lut_size))?;
+            self.lut_gamma = Some(setting.gamma);
+        }
+        set_blob_property(file, self.id, props.ctm, &ctm(setting))
+    }
+}
+
+struct Card {
+    path: PathBuf,
+    file: File,
+    crtcs: Vec<Crtc>,
+
+    /// The seat the card is attached to
+    seat: String,
+
+    /// The limit set for the seat, if any
+    limit: Option<AdjustmentLimit>,
+
+    /// The id of the card on the seat, if opened through it
+    #[cfg(feature = "seat")]
+    device: Option<i32>,
+}
+
+impl Card {
+    /// Open the card at `path`, and save the ramps of its CRTCs
+    fn open(path: &Path) -> Result<Card> {
+        let file = OpenOptions::new().read(true).write(true).open(path)
+            .map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+        Card::from_file(path, file)
+    }
+
+    /// Open the card at `path` through `seat`
+    #[cfg(feature = "seat")]
+    fn open_on(seat: &mut seat::Seat, path: &Path) -> Result<Card> {
+        let device = seat.open_device(path)
+            .map_err(|e| format!("could not open {} on {}: {}", path.display(), seat.name(), e))?;
+        let id = device.id;
+        match Card::from_file(path, device.file) {
+            Ok(mut card) => {
+                card.device = Some(id);
+                Ok(card)
+            }
+            Err(e) => {
+                seat.close_device(id);
+                Err(e)
+            }
+        }
+    }
+
+    fn from_file(path: &Path, file: File) -> Result<Card> {
+        let (crtc_ids, connectors) = get_resources(&file)
+            .map_err(|e| format!("{} does not support mode setting: {}", path.display(), e))?;
+        let mut outputs = crtc_outputs(&file, &connectors)?;
+
+        let mut crtcs = vec![];
+        for id in crtc_ids {
+            let mut info = ModeCrtc { crtc_id: id, ..ModeCrtc::default() };
+            ioctl(&file, DRM_IOCTL_MODE_GETCRTC, &mut info)?;
+            if info.gamma_size == 0 {
+                debug!("Skipping CRTC {} of {}, it does not support gamma ramps", id, path.display());
+                continue
+            }
+            let ramps = get_gamma(&file, id, info.gamma_size as usize)?;
+            let color = color_props(&file, id).unwrap_or_else(|e| {
+                debug!("Could not read the properties of CRTC {} of {}: {}", id, path.display(), e);
+                None
+            });
+            crtcs.push(Crtc {
+                id: id,
+                ramps: CrtcRamps::new(ramps),
+                outputs: outputs.remove(&id).unwrap_or(vec![]),
+                color: color,
+                lut_gamma: None,
+            });
+        }
+
+        // Setting the ramps found is the only way to tell whether we
+        // are the DRM master, which setting them takes
+        if let Some(crtc) = crtcs.first() {
+            match set_gamma(&file, crtc.id, &crtc.ramps.saved) {
+                Err(ref e) if e.raw_os_error() == Some(libc::EACCES) => {
+                    return Err(From::from(format!(
+                        "not the DRM master of {} (a display server, Wayland compositor or kmscon holds it)",
+                        path.display())))
+                }
+                result => result?
+            }
+        }
+        for crtc in crtcs.iter() {
+            debug!("{}: CRTC {} ({}) with {} ramp entries{}", path.display(), crtc.id,
+                   crtc.outputs.join(", "), crtc.ramps.size(),
+                   crtc.color.as_ref().map_or(String::new(), |props| format!(", CTM and {} LUT entries", props.lut_size)));
+        }
+        Ok(Card {
+            path: path.to_owned(),
+            file: file,
+            crtcs: crtcs,
+            seat: card_seat(path),
+            limit: None,
+            #[cfg(feature = "seat")]
+            device: None,
+        })
+    }
+
+    /// Find again which outputs each CRTC drives, after a display was
+    /// plugged in or out, and have the ramps set again
+    fn refresh_outputs(&mut self) -> Result<()> {
+        let (_, connectors) = get_resources(&self.file)?;
+        let mut outputs = crtc_outputs(&self.file, &connectors)?;
+        for crtc in self.crtcs.iter_mut() {
+            crtc.outputs = outputs.remove(&crtc.id).unwrap_or(vec![]);
+            crtc.ramps.last_applied = None;
+            crtc.lut_gamma = None;
+        }
+        Ok(())
+    }
+
+    fn apply_floors(&mut self, floors: &[(String, f64)]) {
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.set_floor(&crtc.outputs, floors);
+        }
+    }
+
+    fn apply_seat_limits(&mut self, limits: &[(String, AdjustmentLimit)]) {
+        self.limit = limits.iter().find(|&&(ref seat, _)| *seat == self.seat).map(|&(_, ref limit)| limit.clone());
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.last_applied = None;
+        }
+    }
+}
+
+/// The paths of the cards to adjust: the one given with the `card`
+/// option, or all of them
+fn card_paths(wanted: Option<&str>) -> Result<Vec<PathBuf>> {
+    match wanted {
+        Some(card) if card.chars().all(|c| c.is_digit(10)) => Ok(vec![Path::new(DRI_DIR).join(format!("card{}", card))]),
+        Some(card) => Ok(vec![PathBuf::from(card)]),
+        None => {
+            let mut cards: Vec<(u32, PathBuf)> = fs::read_dir(DRI_DIR)
+                .map_err(|e| format!("could not list {}: {}", DRI_DIR, e))?
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| card_number(&entry.path()).map(|n| (n, entry.path())))
+                .collect();
+            cards.sort();
+            Ok(cards.into_iter().map(|(_, path)| path).collect())
+        }
+    }
+}
+
+/// Open each of `paths` that can be adjusted, with `open`, failing
+/// with the reasons if none can
+fn open_cards<F: FnMut(&Path) -> Result<Card>>(paths: &[PathBuf], mut open: F) -> Result<Vec<Card>> {
+    let mut cards = vec![];
+    let mut errors = vec![];
+    for path in paths {
+        match open(path) {
+            Ok(card) => cards.push(card),
+            Err(e) => errors.push(e.to_string())
+        }
+    }
+    if cards.is_empty() {
+        return Err(From::from(if errors.is_empty() {
+            format!("no DRM devices in {}", DRI_DIR)
+        } else {
+            errors.join("; ")
+        }))
+    }
+    for error in errors {
+        verbose!("Not adjusting {}", error);
+    }
+    Ok(cards)
+}
+
+pub struct DrmState {
+    /// The card selected with the `card` option
+    card: Option<String>,
+
+    /// The seat selected with the `seat` option
+    only_seat: Option<String>,
+    cards: Vec<Card>,
+    ramp_cache: colorramp::RampCache,
+
+    /// Apply settings on top of the ramps found at start
+    preserve: bool,
+
+    /// Set CRTCs through their color properties where they have them
+    use_ctm: bool,
+
+    /// The brightness floors and seat limits set, for the cards added
+    /// later
+    floors: Vec<(String, f64)>,
+    seat_limits: Vec<(String, AdjustmentLimit)>,
+
+    /// Events of cards being added and removed, if they can be watched
+    uevents: Option<Uevents>,
+
+    /// Whether our session is not the active one, so that the cards
+    /// must be left alone
+    paused: bool,
+
+    /// Our VT, when started on one and not using a seat
+    vt: Option<vt::Watch>,
+
+    /// The seat the cards are opened on, if libseat could open it
+    #[cfg(feature = "seat")]
+    seat: Option<seat::Seat>,
+}
+
+impl DrmState {
+    /// Open the card at `path`, through the seat if there is one
+    fn open_card(&mut self, path: &Path) -> Result<Card> {
+        #[cfg(feature = "seat")]
+        {
+            if let Some(ref mut seat) = self.seat {
+                return Card::open_on(seat, path)
+            }
+        }
+        Card::open(path)
+    }
+
+    fn close_card(&mut self, card: Card) {
+        #[cfg(feature = "seat")]
+        {
+            if let (Some(ref mut seat), Some(id)) = (self.seat.as_mut(), card.device) {
+                seat.close_device(id);
+            }
+        }
+        drop(card);
+    }
+
+    /// Whether our session was switched back to (`Some(true)`) or
+    /// away from (`Some(false)`) since the last call
+    fn session_change(&mut self) -> Result<Option<bool>> {
+        #[cfg(feature = "seat")]
+        {
+            if let Some(ref mut seat) = self.seat {
+                return Ok(seat.dispatch()?.map(|change| change == seat::Change::Enabled))
+            }
+        }
+        Ok(self.vt.as_mut().and_then(|vt| vt.poll()))
+    }
+
+    /// Follow our session being switched away from and back to,
+    /// returning whether the ramps must be set again
+    fn follow_session(&mut self) -> Result<bool> {
+        match self.session_change()? {
+            Some(false) => {
+                // The cards are ours until the seat is told we are done
+                // with them, and on a console until a display server
+                // takes them, so the ramps can usually be restored
+                if let Err(e) = self.restore() {
+                    debug!("Could not restore the ramps before pausing: {}", e);
+                }
+                self.paused = true;
+                #[cfg(feature = "seat")]
+                {
+                    if let Some(ref mut seat) = self.seat {
+                        seat.disabled();
+                    }
+                }
+                verbose!("Another session is active, pausing");
+                Ok(false)
+            }
+            Some(true) => {
+                self.paused = false;
+                // A seat does this itself
+                for card in self.cards.iter() {
+                    if let Err(e) = set_master(&card.file) {
+                        debug!("Could not become the DRM master of {} again: {}", card.path.display(), e);
+                    }
+                }
+                verbose!("The session is active again, resuming");
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    /// Whether the card at `path` would have been adjusted at start
+    fn wanted(&self, path: &Path) -> bool {
+        match self.card {
+            Some(_) => card_paths(self.card.as_ref().map(|s| s.as_str()))
+                .map_or(false, |paths| paths.iter().any(|wanted| wanted == path)),
+            None => card_number(path).is_some()
+                && self.wanted_seat().map_or(true, |seat| card_seat(path) == seat),
+        }
+    }
+
+    /// The seat whose cards to adjust, unless it is all of them: the
+    /// one given with the `seat` option, or else the one of the session
+    fn wanted_seat(&self) -> Option<String> {
+        #[cfg(feature = "seat")]
+        {
+            if let (None, Some(ref seat)) = (self.only_seat.as_ref(), self.seat.as_ref()) {
+                return Some(seat.name())
+            }
+        }
+        self.only_seat.clone()
+    }
+
+    /// Handle one uevent, returning whether the ramps must be set again
+    fn handle_uevent(&mut self, event: &HashMap<String, String>) -> bool {
+        if event.get("SUBSYSTEM").map(|s| s.as_str()) != Some("drm") {
+            return false
+        }
+        let (action, path) = match (event.get("ACTION"), event.get("DEVNAME")) {
+            (Some(action), Some(name)) => (action.as_str(), Path::new("/dev").join(name)),
+            _ => return false
+        };
+        let open = self.cards.iter().position(|card| card.path == path);
+        match (action, open) {
+            ("add", None) if self.wanted(&path) && !self.paused => {
+                match self.open_card(&path) {
+                    Ok(mut card) => {
+                        verbose!("Adjusting {} too", path.display());
+                        card.apply_floors(&self.floors);
+                        card.apply_seat_limits(&self.seat_limits);
+                        self.cards.push(card);
+                        true
+                    }
+                    // udev announces the device again once it set its
+                    // permissions
+                    Err(e) => {
+                        debug!("Not adjusting the added {}: {}", path.display(), e);
+                        false
+                    }
+                }
+            }
+            ("remove", Some(i)) => {
+                verbose!("{} was removed", path.display());
+                let card = self.cards.remove(i);
+                self.close_card(card);
+                false
+            }
+            ("change", Some(i)) if event.get("HOTPLUG").map(|s| s.as_str()) == Some("1") => {
+                let card = &mut self.cards[i];
+                if let Err(e) = card.refresh_outputs() {
+                    debug!("Could not find the outputs of {}: {}", path.display(), e);
+                }
+                card.apply_floors(&self.floors);
+                true
+            }
+            _ => false
+        }
+    }
+}
+
+impl GammaMethod for DrmState {
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "card" => {
+                self.card = Some(value.to_owned());
+                Ok(())
+            }
+            "seat" => {
+                self.only_seat = Some(value.to_owned());
+                Ok(())
+            }
+            "ctm" => {
+                self.use_ctm = value != "0";
+                Ok(())
+            }
+            _ => Err(Box::new(RedshiftError::MalformedArgument(format!("unknown drm option '{}'", key))))
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let mut paths = card_paths(self.card.as_ref().map(|s| s.as_str()))?;
+        if self.card.is_none() {
+            if let Some(seat) = self.wanted_seat() {
+                paths.retain(|path| card_seat(path) == seat);
+                if paths.is_empty() {
+                    return Err(From::from(format!("no DRM devices of {} in {}", seat, DRI_DIR)))
+                }
+            }
+        }
+        for card in mem::replace(&mut self.cards, vec![]) {
+            self.close_card(card);
+        }
+        self.cards = open_cards(&paths, |path| self.open_card(path))?;
+        if self.cards.iter().all(|card| card.crtcs.is_empty()) {
+            let names: Vec<_> = self.cards.iter().map(|card| card.path.display().to_string()).collect();
+            return Err(Box::new(RedshiftError::NoAdjustableOutputs(names.join(", "))))
+        }
+        // Without a seat to say so, the VT shown tells whether our
+        // session is the active one
+        #[cfg(feature = "seat")]
+        let own_vt = if self.seat.is_some() { None } else { vt::own() };
+        #[cfg(not(feature = "seat"))]
+        let own_vt = vt::own();
+        self.vt = own_vt.map(vt::Watch::new);
+        self.paused = self.vt.as_ref().map_or(false, |vt| !vt.shown());
+
+        self.uevents = match Uevents::open() {
+            Ok(uevents) => Some(uevents),
+            Err(e) => {
+                debug!("Not watching for cards being added: {}", e);
+                None
+            }
+        };
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        self.follow_session()?;
+        if self.paused {
+            return Ok(())
+        }
+        for card in self.cards.iter_mut() {
+            let Card { ref path, ref file, ref mut crtcs, .. } = *card;
+            let setting = match card.limit {
+                Some(ref limit) => limit.apply(setting),
+                None => setting.clone()
+            };
+            for crtc in crtcs.iter_mut() {
+                let setting = crtc.ramps.floored(&setting);
+                if crtc.ramps.last_applied.as_ref() == Some(&setting) {
+                    continue
+                }
+                // The calibration in the saved ramps only goes on top
+                // of the legacy ramps
+                if self.use_ctm && crtc.color.is_some() && !crtc.ramps.preserves(self.preserve) {
+                    match crtc.set_color(file, &setting) {
+                        Ok(()) => {
+                            crtc.ramps.last_applied = Some(setting);
+                            continue
+                        }
+                        Err(e) => {
+                            verbose!("Using the legacy ramps of CRTC {} of {}, as setting its CTM failed: {}",
+                                     crtc.id, path.display(), e);
+                            crtc.color = None;
+                        }
+                    }
+                }
+                // Setting them replaces the GAMMA_LUT, and clears the CTM
+                crtc.lut_gamma = None;
+                set_gamma(file, crtc.id, crtc.ramps.ramps_for(&setting, self.preserve, &mut self.ramp_cache))
+                    .map_err(|e| format!("could not set the ramps of CRTC {} of {}: {}", crtc.id, path.display(), e))?;
+                crtc.ramps.last_applied = Some(setting);
+            }
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        // Restored when pausing already, and no longer ours to set
+        if self.paused {
+            return Ok(())
+        }
+        for card in self.cards.iter_mut() {
+            for crtc in card.crtcs.iter_mut() {
+                // Clears the CTM too, as there was none while no one
+                // else was the master
+                crtc.ramps.last_applied = None;
+                crtc.lut_gamma = None;
+                set_gamma(&card.file, crtc.id, &crtc.ramps.saved)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_preserve(&mut self, preserve: bool) {
+        self.preserve = preserve;
+    }
+
+    fn set_brightness_floors(&mut self, floors: &[(String, f64)]) -> Result<()> {
+        let crtcs = || self.cards.iter().flat_map(|card| card.crtcs.iter());
+        for &(ref name, _) in floors {
+            if !crtcs().any(|crtc| crtc.outputs.contains(name)) {
+                return Err(Box::new(RedshiftError::OutputNotFound(name.clone())))
+            }
+        }
+        for card in self.cards.iter_mut() {
+            card.apply_floors(floors);
+        }
+        self.floors = floors.to_vec();
+        Ok(())
+    }
+
+    fn set_seat_limits(&mut self, limits: &[(String, AdjustmentLimit)]) -> Result<()> {
+        for &(ref seat, _) in limits {
+            if !self.cards.iter().any(|card| card.seat == *seat) {
+                verbose!("No card of {} is adjusted, so its settings are not used for now", seat);
+            }
+        }
+        for card in self.cards.iter_mut() {
+            card.apply_seat_limits(limits);
+        }
+        self.seat_limits = limits.to_vec();
+        Ok(())
+    }
+
+    fn needs_refresh(&mut self) -> bool {
+        let resumed = self.follow_session().unwrap_or_else(|e| {
+            debug!("{}", e);
+            false
+        });
+        let events = match self.uevents {
+            Some(ref uevents) => uevents.pending(),
+            None => return resumed
+        };
+        // Every event is handled, so that none is missed
+        events.iter().fold(resumed, |refresh, event| self.handle_uevent(event) || refresh)
+    }
+
+    fn invalidate(&mut self) {
+        for crtc in self.cards.iter_mut().flat_map(|card| card.crtcs.iter_mut()) {
+            crtc.ramps.last_applied = None;
+        }
+    }
+
+    fn outputs(&self) -> Result<Vec<String>> {
+        Ok(self.cards.iter()
+           .flat_map(|card| card.crtcs.iter())
+           .flat_map(|crtc| crtc.outputs.iter().cloned())
+           .collect())
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        let mut statuses = vec![];
+        for (card, crtc) in self.cards.iter().flat_map(|card| card.crtcs.iter().map(move |crtc| (card, crtc))) {
+            let mut overrides = vec![];
+            if card.limit.is_some() {
+                overrides.push(format!("limited as on {}", card.seat));
+            }
+            overrides.extend(crtc.ramps.overrides(self.preserve));
+            for name in crtc.outputs.iter() {
+                let mut status = OutputStatus::new(name, crtc.ramps.last_applied.as_ref());
+                status.ramp_size = Some(crtc.ramps.size());
+                status.overrides = overrides.clone();
+                statuses.push(status);
+            }
+        }
+        statuses
+    }
+
+    fn writable_paths(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(DRI_DIR)]
+    }
+}
+
+/// The init function
+///
+/// Fails unless some card can be adjusted, so that automatic selection
+/// moves on while a display server holds the cards.
+pub fn init() -> Result<Box<GammaMethod>> {
+    let mut state = DrmState {
+        card: None,
+        only_seat: None,
+        cards: vec![],
+        ramp_cache: colorramp::RampCache::new(),
+        preserve: false,
+        use_ctm: true,
+        floors: vec![],
+        seat_limits: vec![],
+        uevents: None,
+        paused: false,
+        vt: None,
+        #[cfg(feature = "seat")]
+        seat: match seat::Seat::open() {
+            Ok(seat) => Some(seat),
+            Err(e) => {
+                debug!("Opening the cards directly, as {}", e);
+                None
+            }
+        },
+    };
+    let cards = open_cards(&card_paths(None)?, |path| state.open_card(path))?;
+    for card in cards {
+        state.close_card(card);
+    }
+    Ok(Box::new(state) as Box<GammaMethod>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests() {
+        // As in drm.h
+        assert_eq!(request::<CardRes>(DRM_IOCTL_MODE_GETRESOURCES), 0xC04064A0);
+        assert_eq!(request::<ModeCrtc>(DRM_IOCTL_MODE_GETCRTC), 0xC06864A1);
+        assert_eq!(request::<CrtcLut>(DRM_IOCTL_MODE_SETGAMMA), 0xC02064A5);
+        assert_eq!(request::<GetEncoder>(DRM_IOCTL_MODE_GETENCODER), 0xC01464A6);
+        assert_eq!(request::<GetConnector>(DRM_IOCTL_MODE_GETCONNECTOR), 0xC05064A7);
+        assert_eq!(request::<GetProperty>(DRM_IOCTL_MODE_GETPROPERTY), 0xC04064AA);
+        assert_eq!(request::<ObjGetProperties>(DRM_IOCTL_MODE_OBJ_GETPROPERTIES), 0xC02064B9);
+        assert_eq!(request::<ObjSetProperty>(DRM_IOCTL_MODE_OBJ_SETPROPERTY), 0xC01864BA);
+        assert_eq!(request::<CreateBlob>(DRM_IOCTL_MODE_CREATEPROPBLOB), 0xC01064BD);
+        assert_eq!(request::<DestroyBlob>(DRM_IOCTL_MODE_DESTROYPROPBLOB), 0xC00464BE);
+        assert_eq!(connector_name(11, 1), "HDMI-A-1");
+        assert_eq!(connector_name(99, 2), "Unknown-2");
+    }
+
+    #[test]
+    fn test_parse_uevent() {
+        let event = parse_uevent(b"add@/devices/pci0000:00/0000:00:02.0/drm/card1\0ACTION=add\0\
+                                   DEVNAME=dri/card1\0SUBSYSTEM=drm\0HOTPLUG=1\0MINOR=1\0");
+        assert_eq!(event.get("ACTION").map(|s| s.as_str()), Some("add"));
+        assert_eq!(event.get("DEVNAME").map(|s| s.as_str()), Some("dri/card1"));
+        assert_eq!(event.get("HOTPLUG").map(|s| s.as_str()), Some("1"));
+        assert_eq!(event.len(), 5);
+        assert_eq!(card_number(Path::new("/dev/dri/card1")), Some(1));
+        assert_eq!(card_number(Path::new("/dev/dri/renderD128")), None);
+        assert_eq!(card_number(Path::new("/dev/dri/card1-HDMI-A-1")), None);
+    }
+
+    #[test]
+    fn test_color_props() {
+        use transition::ColorSetting;
+
+        let neutral = ColorSetting { temp: 6500.into(), gamma: [1.0; 3], brightness: 0.5 };
+        assert_eq!(ctm(&neutral), [1 << 31, 0, 0, 0, 1 << 31, 0, 0, 0, 1 << 31]);
+        let warm = ctm(&ColorSetting { temp: 3000.into(), ..neutral });
+        assert!(warm[0] > warm[4] && warm[4] > warm[8]);
+
+        let lut = gamma_lut(&[1.0, 1.0, 2.0], 3);
+        assert_eq!(lut[0], ColorLut { red: 0, green: 0, blue: 0, reserved: 0 });
+        assert_eq!((lut[1].red, lut[1].blue), (32768, 46340));
+        assert_eq!(lut[2].green, 65535);
+    }
+
+    #[test]
+    fn test_udev_seat() {
+        assert_eq!(udev_seat("S:dri/by-path/pci-0000:01:00.0-card\nE:ID_SEAT=seat1\nG:seat\n"), Some("seat1".to_owned()));
+        assert_eq!(udev_seat("E:ID_FOR_SEAT=drm-pci-0000_00_02_0\nG:seat\n"), None);
+        assert_eq!(device_numbers(0xE201), (226, 1));
+        assert_eq!(device_numbers(0xE280), (226, 128));
+    }
+}
@@ -3,14 +3,75 @@ use xcb::randr;
 use transition;
 use colorramp;
 
-use super::GammaMethod;
-use super::Result;
+use super::{CrtcRamps, GammaMethod, OutputStatus};
+use super::{Result, RedshiftError};
+use NEUTRAL_TEMP;
 use std::error::Error;
 use std::fmt;
+use std::time::Instant;
+#[cfg(feature = "vblank")]
+use std::time::Duration;
+#[cfg(feature = "vblank")]
+use std::os::unix::io::AsRawFd;
+#[cfg(feature = "vblank")]
+use libc;
 
 const RANDR_MAJOR_VERSION: u32 = 1;
 const RANDR_MINOR_VERSION: u32 = 3;
 
+/// Weight of the newest sample in the running latency average
+const LATENCY_WEIGHT: f64 = 0.3;
+
+/// Longest wait for a vblank, about a frame at 30 Hz. With the CRTC off
+/// (or asleep with DPMS) none comes, and the ramps are set anyway.
+#[cfg(feature = "vblank")]
+const VBLANK_TIMEOUT_MS: u64 = 35;
+
+/// The two Present requests (and the one event) needed to wait for
+/// vblank. The xcb crate's own Present bindings do not build (in xcb
+/// 0.8.2), so they are declared here.
+#[cfg(feature = "vblank")]
+mod present {
+    use xcb::ffi::base::{xcb_connection_t, xcb_void_cookie_t};
+
+    pub const EXTENSION_NAME: &'static str = "Present";
+    pub const EVENT_MASK_COMPLETE_NOTIFY: u32 = 0x02;
+    /// The `event_type` of CompleteNotify events
+    pub const COMPLETE_NOTIFY: u16 = 1;
+
+    #[repr(C, packed)]
+    pub struct CompleteNotifyEvent {
+        pub response_type: u8,
+        pub extension: u8,
+        pub sequence: u16,
+        pub length: u32,
+        pub event_type: u16,
+        pub kind: u8,
+        pub mode: u8,
+        pub event: u32,
+        pub window: u32,
+        pub serial: u32,
+        pub ust: u64,
+        pub full_sequence: u32,
+        pub msc: u64,
+    }
+
+    #[link(name = "xcb-present")]
+    extern "C" {
+        pub fn xcb_present_notify_msc(c: *mut xcb_connection_t,
+                                      window: u32,
+                                      serial: u32,
+                                      target_msc: u64,
+                                      divisor: u64,
+                                      remainder: u64) -> xcb_void_cookie_t;
+
+        pub fn xcb_present_select_input_checked(c: *mut xcb_connection_t,
+                                                eid: u32,
+                                                window: u32,
+                                                event_mask: u32) -> xcb_void_cookie_t;
+    }
+}
+
 /// Wrapper for XCB and RandR errors
 pub enum RandrError<T> {
     Generic(xcb::Error<T>),
@@ -66,23 +127,64 @@ struct Crtc {
     /// The id of CRTC (gotten from XCB)
     id: u32,
 
-    /// The ramp size.
-    ramp_size: u32,
+    /// The ramps found at start and those set since
+    ramps: CrtcRamps,
 
-    /// The initial gamma ramp values - used for restore
-    saved_ramps: (Vec<u16>, Vec<u16>, Vec<u16>),
+    /// Running average of the time (in ms) it takes to apply a ramp
+    latency: Option<f64>,
 
-    /// A scratchpad for color computation - it saves the cost of
-    /// allocating three new arrays whenever set_temperature() is
-    /// called.
-    scratch: (Vec<u16>, Vec<u16>, Vec<u16>),
+    /// Names of the outputs driven by this CRTC
+    outputs: Vec<String>,
+
+    /// The setting of the ramps in `ramps.next`, if they still need
+    /// to be sent
+    pending: Option<transition::ColorSetting>,
+}
+
+/// What is needed to wait for vblank with the Present extension
+#[cfg(feature = "vblank")]
+struct Vblank {
+    /// Event id CompleteNotify events are selected with
+    eid: u32,
+
+    /// Major opcode of the Present extension, telling its events
+    /// apart from those of other extensions
+    opcode: u8,
+
+    /// Serial of the last vblank notification requested
+    serial: u32,
 }
 
 /// Wrapping struct for RandR state
 pub struct RandrState {
     conn: xcb::Connection,
-    window_dummy: u32,
-    crtcs: Vec<Crtc>
+    root: u32,
+    crtcs: Vec<Crtc>,
+
+    /// Ramps computed recently, shared by CRTCs of the same size
+    ramp_cache: colorramp::RampCache,
+
+    /// Set when updates are synchronized to vblank
+    #[cfg(feature = "vblank")]
+    vblank: Option<Vblank>,
+
+    /// Only adjust the CRTC showing the mouse pointer, leaving the
+    /// others neutral
+    follow_focus: bool,
+
+    /// The CRTC adjusted last with follow_focus
+    focused: Option<u32>,
+
+    /// Apply settings on top of the ramps found at start (like a
+    /// calibration), rather than on top of linear ramps
+    preserve: bool,
+
+    /// Leave CRTCs mirroring another one alone, for drivers that
+    /// apply the ramps of mirrored CRTCs twice
+    skip_mirrors: bool,
+
+    /// Only adjust the CRTCs of this provider (GPU), by name or index
+    provider: Option<String>,
 }
 
 impl RandrState {
@@ -93,50 +195,247 @@ impl RandrState {
 
         query_version(&conn)?;
 
-        let window_dummy = {
-            let setup = conn.get_setup();
-            let screen = setup.roots().nth(screen_num as usize).unwrap();
-            let window_dummy = conn.generate_id();
-
-            xcb::create_window(&conn, 0, window_dummy, screen.root(), 0, 0, 1,
-                               1, 0, 0, 0, &[]);
-            conn.flush();
-            window_dummy
-        };
+        // The root window is all RandR needs to find the screen
+        let root = conn.get_setup().roots().nth(screen_num as usize)
+            .ok_or("no such X screen")?
+            .root();
 
         Ok(RandrState {
             conn: conn,
-            window_dummy: window_dummy,
-            crtcs: vec![]
+            root: root,
+            crtcs: vec![],
+            ramp_cache: colorramp::RampCache::new(),
+            #[cfg(feature = "vblank")]
+            vblank: None,
+            follow_focus: false,
+            focused: None,
+            preserve: false,
+            skip_mirrors: false,
+            provider: None,
         })
     }
 
-    // Set the temperature for the indicated CRTC
-    fn set_crtc_temperatures(&mut self, setting: &transition::ColorSetting) -> Result<()> {
-        for crtc in self.crtcs.iter_mut() {
-            let (ref mut r, ref mut g, ref mut b) = crtc.scratch;
+    /// Ask for Present CompleteNotify events on the root window, so
+    /// that we can wait for vblank before setting gamma ramps
+    #[cfg(feature = "vblank")]
+    fn enable_vblank(&mut self) -> Result<()> {
+        let reply = xcb::query_extension(&self.conn, present::EXTENSION_NAME)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        if !reply.present() {
+            return Err(Box::new(RedshiftError::MissingExtension(present::EXTENSION_NAME.to_owned())))
+        }
 
-            let u16_max1 = u16::max_value() as f64 + 1.0;
-            let ramp_size = crtc.ramp_size as f64;
-            for i in 0 .. r.len() {
-                let v = ((i as f64 / ramp_size) * u16_max1) as u16;
-                r[i] = v;
-                g[i] = v;
-                b[i] = v;
+        let eid = self.conn.generate_id();
+        let cookie = xcb::VoidCookie {
+            cookie: unsafe {
+                present::xcb_present_select_input_checked(self.conn.get_raw_conn(), eid, self.root,
+                                                          present::EVENT_MASK_COMPLETE_NOTIFY)
+            },
+            conn: &self.conn,
+            checked: true
+        };
+        cookie.request_check().map_err(RandrError::generic)?;
+        self.vblank = Some(Vblank { eid: eid, opcode: reply.major_opcode(), serial: 0 });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "vblank"))]
+    fn enable_vblank(&mut self) -> Result<()> {
+        Err(Box::new(RedshiftError::MalformedArgument(
+            "vblank: redshift-rs was built without the vblank feature".to_owned())))
+    }
+
+    /// Wait for the next vblank of the CRTC showing (most of) the root
+    /// window, if updates are synchronized to it, for at most
+    /// VBLANK_TIMEOUT_MS
+    #[cfg(feature = "vblank")]
+    fn wait_for_vblank(&mut self) -> Result<()> {
+        let (eid, opcode, serial) = match self.vblank {
+            Some(ref mut vblank) => {
+                vblank.serial = vblank.serial.wrapping_add(1);
+                (vblank.eid, vblank.opcode, vblank.serial)
             }
+            None => return Ok(())
+        };
+        unsafe {
+            present::xcb_present_notify_msc(self.conn.get_raw_conn(), self.root, serial, 0, 1, 0);
+        }
+        self.conn.flush();
+
+        let deadline = Instant::now() + Duration::from_millis(VBLANK_TIMEOUT_MS);
+        loop {
+            while let Some(event) = self.conn.poll_for_event() {
+                if event.response_type() & !0x80 != xcb::GE_GENERIC {
+                    continue
+                }
+                // Every event is at least as large as the generic
+                // header, but only CompleteNotify events are as large
+                // as those
+                let header = unsafe { &*(event.ptr as *const xcb::ffi::xproto::xcb_ge_generic_event_t) };
+                if header.extension != opcode || header.event_type != present::COMPLETE_NOTIFY {
+                    continue
+                }
+                let notify = unsafe { &*(event.ptr as *const present::CompleteNotifyEvent) };
+                if notify.event == eid && notify.serial == serial {
+                    return Ok(())
+                }
+            }
+            self.conn.has_error().map_err(RandrError::conn)?;
 
-            // Compute new gamma ramps
-            colorramp::fill(&mut r[..], &mut g[..], &mut b[..],
-                            setting, crtc.ramp_size as usize);
+            let now = Instant::now();
+            if now >= deadline {
+                debug!("No vblank within {} ms, setting the ramps anyway", VBLANK_TIMEOUT_MS);
+                return Ok(())
+            }
+            let remaining = deadline - now;
+            let mut fd = libc::pollfd { fd: self.conn.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            let timeout = remaining.as_secs() as i64 * 1000 + remaining.subsec_millis() as i64 + 1;
+            unsafe { libc::poll(&mut fd, 1, timeout as libc::c_int) };
+        }
+    }
 
-            randr::set_crtc_gamma_checked(&self.conn,
-                                          crtc.id,
-                                          &r[..],
-                                          &g[..],
-                                          &b[..])
-                .request_check()
+    /// The CRTC showing the mouse pointer, taken to be the one in use
+    fn focused_crtc(&self) -> Result<Option<u32>> {
+        let pointer = xcb::query_pointer(&self.conn, self.root)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        let (x, y) = (pointer.root_x() as i32, pointer.root_y() as i32);
+
+        for crtc in self.crtcs.iter() {
+            let info = randr::get_crtc_info(&self.conn, crtc.id, xcb::CURRENT_TIME)
+                .get_reply()
+                .map_err(RandrError::generic)?;
+            let (left, top) = (info.x() as i32, info.y() as i32);
+            if info.mode() != 0
+                && x >= left && x < left + info.width() as i32
+                && y >= top && y < top + info.height() as i32 {
+                return Ok(Some(crtc.id))
+            }
+        }
+        Ok(None)
+    }
+
+    /// The CRTCs of the provider named or numbered `wanted`
+    fn provider_crtcs(&self, wanted: &str, timestamp: xcb::Timestamp) -> Result<Vec<u32>> {
+        // Providers came with RandR 1.4
+        let version = randr::query_version(&self.conn, RANDR_MAJOR_VERSION, 4)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        if version.minor_version() < 4 {
+            return Err(RandrError::unsupported_version(version.major_version(), version.minor_version()))
+        }
+
+        let reply = randr::get_providers(&self.conn, self.root)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        let mut providers = vec![];
+        for provider in reply.providers() {
+            let info = randr::get_provider_info(&self.conn, *provider, timestamp)
+                .get_reply()
                 .map_err(RandrError::generic)?;
+            debug!("Provider {}: {} with CRTCs {:?}", providers.len(), info.name(), info.crtcs());
+            providers.push(super::Provider { name: info.name().to_owned(), crtcs: info.crtcs().to_vec() });
         }
+        super::provider_crtcs(&providers, wanted)
+    }
+
+    /// The colorspace `output` sends to the display, if the driver
+    /// exposes it
+    ///
+    /// `colorspace_atom` is the atom of the property, or 0 if the X
+    /// server has never heard of it.
+    fn output_colorspace(&self, output: randr::Output, colorspace_atom: xcb::Atom) -> Result<Option<String>> {
+        if colorspace_atom == xcb::NONE {
+            return Ok(None)
+        }
+        let property = randr::get_output_property(&self.conn, output, colorspace_atom,
+                                                  xcb::ATOM_ATOM, 0, 1, false, false)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        if property.type_() != xcb::ATOM_ATOM || property.format() != 32 || property.num_items() == 0 {
+            return Ok(None)
+        }
+        let value = property.data();
+        let atom = u32::from_ne_bytes([value[0], value[1], value[2], value[3]]);
+        let name = xcb::get_atom_name(&self.conn, atom)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        Ok(Some(name.name().to_owned()))
+    }
+
+    // Set the temperature for the indicated CRTC
+    fn set_crtc_temperatures(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        let neutral = transition::ColorSetting {
+            temp: NEUTRAL_TEMP.into(),
+            gamma: [1.0, 1.0, 1.0],
+            brightness: 1.0
+        };
+        if self.follow_focus {
+            self.focused = self.focused_crtc()?;
+        }
+
+        for crtc in self.crtcs.iter_mut() {
+            let setting = crtc.ramps.floored(if self.follow_focus && self.focused != Some(crtc.id) {
+                &neutral
+            } else {
+                setting
+            });
+            // With follow-focus most CRTCs stay neutral, and need not
+            // be sent the same ramps on every update
+            if crtc.ramps.last_applied.as_ref() == Some(&setting) {
+                crtc.pending = None;
+                continue
+            }
+            crtc.ramps.fill_next(&setting, self.preserve, &mut self.ramp_cache);
+            crtc.pending = Some(setting);
+        }
+        if self.crtcs.iter().all(|crtc| crtc.pending.is_none()) {
+            return Ok(())
+        }
+
+        #[cfg(feature = "vblank")]
+        self.wait_for_vblank()?;
+
+        // Slow outputs (like those behind a DisplayLink dock) go
+        // first, so they finish around the same time as the rest
+        self.crtcs.sort_by(|a, b| {
+            b.latency.unwrap_or(0.0).partial_cmp(&a.latency.unwrap_or(0.0)).unwrap()
+        });
+
+        // Send all the ramps before waiting for any of the replies,
+        // rather than doing a round trip per CRTC
+        let start = Instant::now();
+        let conn = &self.conn;
+        let cookies: Vec<_> = self.crtcs.iter()
+            .filter(|crtc| crtc.pending.is_some())
+            .map(|crtc| {
+                let (ref r, ref g, ref b) = crtc.ramps.next;
+                randr::set_crtc_gamma_checked(conn, crtc.id, &r[..], &g[..], &b[..])
+            })
+            .collect();
+        self.conn.flush();
+
+        // The server handles the requests in order, so the time
+        // between two replies is what the later one took
+        let mut prev = 0.0;
+        let mut applied = 0;
+        for (cookie, crtc) in cookies.into_iter().zip(self.crtcs.iter_mut().filter(|crtc| crtc.pending.is_some())) {
+            cookie.request_check().map_err(RandrError::generic)?;
+            crtc.ramps.last_applied = crtc.pending.take();
+            applied += 1;
+            let since_start = start.elapsed();
+            let now = since_start.as_secs() as f64 * 1000.0 + since_start.subsec_nanos() as f64 / 1e6;
+            let ms = now - prev;
+            prev = now;
+
+            debug!("CRTC {} gamma ramp applied in {:.1} ms", crtc.id, ms);
+            crtc.latency = Some(match crtc.latency {
+                Some(avg) => (1.0 - LATENCY_WEIGHT) * avg + LATENCY_WEIGHT * ms,
+                None => ms
+            });
+        }
+        debug!("Gamma ramps applied to {} CRTCs in {:.1} ms", applied, prev);
         Ok(())
     }
 }
@@ -157,16 +456,47 @@ fn query_version(conn: &xcb::Connection) -> Result<()> {
 
 impl GammaMethod for RandrState {
 
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "vblank" => {
+                if value != "0" {
+                    self.enable_vblank()
+                } else {
+                    #[cfg(feature = "vblank")]
+                    {
+                        self.vblank = None;
+                    }
+                    Ok(())
+                }
+            }
+            "follow-focus" => {
+                self.follow_focus = value != "0";
+                Ok(())
+            }
+            "skip-mirrors" => {
+                self.skip_mirrors = value != "0";
+                Ok(())
+            }
+            "provider" => {
+                self.provider = Some(value.to_owned());
+                Ok(())
+            }
+            _ => Err(Box::new(RedshiftError::MalformedArgument(
+                format!("unknown randr option '{}'", key))))
+        }
+    }
+
     //
     // Restore saved gamma ramps
     //
-    fn restore(&self) -> Result<()> {
-        for crtc in self.crtcs.iter() {
+    fn restore(&mut self) -> Result<()> {
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.last_applied = None;
             randr::set_crtc_gamma_checked(&self.conn,
                                           crtc.id,
-                                          &crtc.saved_ramps.0[..],
-                                          &crtc.saved_ramps.1[..],
-                                          &crtc.saved_ramps.2[..])
+                                          &crtc.ramps.saved.0[..],
+                                          &crtc.ramps.saved.1[..],
+                                          &crtc.ramps.saved.2[..])
                 .request_check()
                 .map_err(RandrError::generic)?;
         }
@@ -177,32 +507,153 @@ impl GammaMethod for RandrState {
         self.set_crtc_temperatures(setting)
     }
 
+    fn set_preserve(&mut self, preserve: bool) {
+        self.preserve = preserve;
+    }
+
+    fn set_brightness_floors(&mut self, floors: &[(String, f64)]) -> Result<()> {
+        for &(ref name, _) in floors {
+            if !self.crtcs.iter().any(|crtc| crtc.outputs.contains(name)) {
+                return Err(Box::new(RedshiftError::OutputNotFound(name.clone())))
+            }
+        }
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.set_floor(&crtc.outputs, floors);
+        }
+        Ok(())
+    }
+
+    fn needs_refresh(&mut self) -> bool {
+        // Errors show up when the temperature is set
+        self.follow_focus && self.focused_crtc().ok() != Some(self.focused)
+    }
+
+    fn invalidate(&mut self) {
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.last_applied = None;
+        }
+    }
+
+    fn outputs(&self) -> Result<Vec<String>> {
+        let screen_resources = randr::get_screen_resources(&self.conn, self.root)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+
+        let mut names = vec![];
+        for output in screen_resources.outputs() {
+            let info = randr::get_output_info(&self.conn, *output,
+                                              screen_resources.config_timestamp())
+                .get_reply()
+                .map_err(RandrError::generic)?;
+            if info.connection() as u32 == randr::CONNECTION_CONNECTED {
+                names.push(String::from_utf8_lossy(info.name()).into_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        let mut statuses = vec![];
+        for crtc in self.crtcs.iter() {
+            let mut overrides = crtc.ramps.overrides(self.preserve);
+            if self.follow_focus && self.focused != Some(crtc.id) {
+                overrides.push("neutral without the pointer".to_owned());
+            }
+            for name in crtc.outputs.iter() {
+                let mut status = OutputStatus::new(name, crtc.ramps.last_applied.as_ref());
+                status.ramp_size = Some(crtc.ramps.size());
+                status.overrides = overrides.clone();
+                statuses.push(status);
+            }
+        }
+        statuses
+    }
+
     /// Find initial information on all the CRTCs
     fn start(&mut self) -> Result<()> {
         // Get list of CRTCs for the screen
-        let screen_resources = randr::get_screen_resources(&self.conn,
-                                                           self.window_dummy)
+        let screen_resources = randr::get_screen_resources(&self.conn, self.root)
             .get_reply()
             .map_err(RandrError::generic)?;
-        self.crtcs = Vec::with_capacity(screen_resources.num_crtcs() as usize);
+        // The CRTCs of a GPU driving outputs for another one (reverse
+        // PRIME) are only listed by its provider
+        let crtcs = match self.provider {
+            Some(ref wanted) => self.provider_crtcs(wanted, screen_resources.config_timestamp())?,
+            None => screen_resources.crtcs().to_vec()
+        };
+        self.crtcs = Vec::with_capacity(crtcs.len());
+        let mut skipped = vec![];
+        // Position and size of each CRTC kept, to find mirrors
+        let mut areas = vec![];
+        let colorspace_atom = xcb::intern_atom(&self.conn, true, super::COLORSPACE_PROPERTY)
+            .get_reply()
+            .map_err(RandrError::generic)?
+            .atom();
 
         // Save size and gamma ramps of all CRTCs
-        for crtc in screen_resources.crtcs() {
+        for crtc in crtcs.iter() {
             let gamma = randr::get_crtc_gamma(&self.conn, *crtc)
                 .get_reply()
                 .map_err(RandrError::generic)?;
 
-            let red = gamma.red().to_vec();
-            let green = gamma.green().to_vec();
-            let blue = gamma.blue().to_vec();
+            let info = randr::get_crtc_info(&self.conn, *crtc, screen_resources.config_timestamp())
+                .get_reply()
+                .map_err(RandrError::generic)?;
+            let mut outputs = vec![];
+            for output in info.outputs() {
+                let output_info = randr::get_output_info(&self.conn, *output,
+                                                         screen_resources.config_timestamp())
+                    .get_reply()
+                    .map_err(RandrError::generic)?;
+                let name = String::from_utf8_lossy(output_info.name()).into_owned();
+                if let Some(colorspace) = self.output_colorspace(*output, colorspace_atom)? {
+                    if super::is_wide_gamut(&colorspace) {
+                        super::warn_wide_gamut(&name, &colorspace);
+                    }
+                }
+                outputs.push(name);
+            }
+
+            // Virtual displays have no ramps, or names giving them
+            // away, and fail on every update
+            if gamma.size() == 0 || (!outputs.is_empty() && outputs.iter().all(|name| super::is_virtual_output(name))) {
+                debug!("Skipping CRTC {} ({}), it does not support gamma ramps", crtc, outputs.join(", "));
+                skipped.push(if outputs.is_empty() { format!("CRTC {}", crtc) } else { outputs.join(", ") });
+                continue
+            }
+
+            // A CRTC showing exactly the same area as an earlier one
+            // mirrors it
+            let area = (info.x(), info.y(), info.width(), info.height());
+            if info.mode() != 0 {
+                if let Some(&(_, mirrored)) = areas.iter().find(|&&(a, _)| a == area) {
+                    if self.skip_mirrors {
+                        debug!("Skipping CRTC {} ({}), it mirrors CRTC {}", crtc, outputs.join(", "), mirrored);
+                        // Floors set for its outputs apply to the CRTC
+                        // adjusted in its place
+                        if let Some(first) = self.crtcs.iter_mut().find(|c| c.id == mirrored) {
+                            first.outputs.extend(outputs);
+                        }
+                        continue
+                    }
+                    debug!("CRTC {} mirrors CRTC {}", crtc, mirrored);
+                } else {
+                    areas.push((area, *crtc));
+                }
+            }
 
+            let ramps = (gamma.red().to_vec(), gamma.green().to_vec(), gamma.blue().to_vec());
             self.crtcs.push(Crtc {
                 id: *crtc,
-                ramp_size: gamma.size() as u32,
-                saved_ramps: (red.clone(), green.clone(), blue.clone()),
-                scratch: (red, green, blue),
+                ramps: CrtcRamps::new(ramps),
+                latency: None,
+                outputs: outputs,
+                pending: None,
             });
         }
+        if self.crtcs.is_empty() && !skipped.is_empty() {
+            return Err(Box::new(RedshiftError::NoAdjustableOutputs(skipped.join(", "))))
+        }
         Ok(())
     }
 }
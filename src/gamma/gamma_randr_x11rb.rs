@@ -0,0 +1,276 @@
+//! RandR through x11rb's pure Rust X11 connection
+//!
+//! The same adjustments as the xcb based randr method, without linking
+//! libxcb, so that fully static (e.g. musl) binaries can be built with
+//! `--no-default-features --features randr-x11rb`. Synchronizing to
+//! vblank is left out, since it relies on libxcb-present.
+
+use x11rb;
+use x11rb::connection::Connection;
+use x11rb::protocol::randr::{self, ConnectionExt as RandrConnectionExt};
+use x11rb::protocol::xproto::ConnectionExt as XprotoConnectionExt;
+use x11rb::rust_connection::RustConnection;
+
+use transition;
+use colorramp;
+
+use super::{CrtcRamps, GammaMethod, OutputStatus};
+use super::{Result, RedshiftError};
+use NEUTRAL_TEMP;
+
+const RANDR_MAJOR_VERSION: u32 = 1;
+const RANDR_MINOR_VERSION: u32 = 3;
+
+struct Crtc {
+    id: randr::Crtc,
+
+    /// The ramps found at start and those set since
+    ramps: CrtcRamps,
+
+    /// Names of the outputs driven by this CRTC
+    outputs: Vec<String>,
+
+    /// The setting of the ramps in `ramps.next`, if they still need
+    /// to be sent
+    pending: Option<transition::ColorSetting>,
+}
+
+pub struct X11rbState {
+    conn: RustConnection,
+    root: u32,
+    crtcs: Vec<Crtc>,
+    ramp_cache: colorramp::RampCache,
+
+    /// Only adjust the CRTC showing the mouse pointer
+    follow_focus: bool,
+    focused: Option<randr::Crtc>,
+
+    /// Apply settings on top of the ramps found at start
+    preserve: bool,
+
+    /// Only adjust the CRTCs of this provider (GPU), by name or index
+    provider: Option<String>,
+}
+
+impl X11rbState {
+
+    fn init() -> Result<X11rbState> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+
+        let version = conn.randr_query_version(RANDR_MAJOR_VERSION, RANDR_MINOR_VERSION)?.reply()?;
+        if version.major_version != RANDR_MAJOR_VERSION || version.minor_version < RANDR_MINOR_VERSION {
+            return Err(From::from(format!("Unsupported RandR version ({}.{})",
+                                          version.major_version, version.minor_version)))
+        }
+
+        Ok(X11rbState {
+            conn: conn,
+            root: root,
+            crtcs: vec![],
+            ramp_cache: colorramp::RampCache::new(),
+            follow_focus: false,
+            focused: None,
+            preserve: false,
+            provider: None,
+        })
+    }
+
+    /// The CRTC showing the mouse pointer, taken to be the one in use
+    fn focused_crtc(&self) -> Result<Option<randr::Crtc>> {
+        let pointer = self.conn.query_pointer(self.root)?.reply()?;
+        let (x, y) = (pointer.root_x as i32, pointer.root_y as i32);
+
+        for crtc in self.crtcs.iter() {
+            let info = self.conn.randr_get_crtc_info(crtc.id, x11rb::CURRENT_TIME)?.reply()?;
+            let (left, top) = (info.x as i32, info.y as i32);
+            if info.mode != 0
+                && x >= left && x < left + info.width as i32
+                && y >= top && y < top + info.height as i32 {
+                return Ok(Some(crtc.id))
+            }
+        }
+        Ok(None)
+    }
+
+    /// The CRTCs of the provider named or numbered `wanted`
+    fn provider_crtcs(&self, wanted: &str, timestamp: u32) -> Result<Vec<randr::Crtc>> {
+        // Providers came with RandR 1.4
+        let version = self.conn.randr_query_version(RANDR_MAJOR_VERSION, 4)?.reply()?;
+        if version.minor_version < 4 {
+            return Err(From::from(format!("Unsupported RandR version ({}.{})",
+                                          version.major_version, version.minor_version)))
+        }
+
+        let mut providers = vec![];
+        for provider in self.conn.randr_get_providers(self.root)?.reply()?.providers {
+            let info = self.conn.randr_get_provider_info(provider, timestamp)?.reply()?;
+            let name = String::from_utf8_lossy(&info.name).into_owned();
+            debug!("Provider {}: {} with CRTCs {:?}", providers.len(), name, info.crtcs);
+            providers.push(super::Provider { name: name, crtcs: info.crtcs });
+        }
+        super::provider_crtcs(&providers, wanted)
+    }
+
+    fn output_name(&self, output: randr::Output, timestamp: u32) -> Result<(String, bool)> {
+        let info = self.conn.randr_get_output_info(output, timestamp)?.reply()?;
+        Ok((String::from_utf8_lossy(&info.name).into_owned(),
+            info.connection == randr::Connection::CONNECTED))
+    }
+}
+
+impl GammaMethod for X11rbState {
+
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "follow-focus" => {
+                self.follow_focus = value != "0";
+                Ok(())
+            }
+            "provider" => {
+                self.provider = Some(value.to_owned());
+                Ok(())
+            }
+            _ => Err(Box::new(RedshiftError::MalformedArgument(
+                format!("unknown randr option '{}'", key))))
+        }
+    }
+
+    /// Find initial information on all the CRTCs
+    fn start(&mut self) -> Result<()> {
+        let resources = self.conn.randr_get_screen_resources_current(self.root)?.reply()?;
+
+        // The CRTCs of a GPU driving outputs for another one (reverse
+        // PRIME) are only listed by its provider
+        let ids = match self.provider {
+            Some(ref wanted) => self.provider_crtcs(wanted, resources.config_timestamp)?,
+            None => resources.crtcs.clone()
+        };
+        let mut crtcs = Vec::with_capacity(ids.len());
+        for &id in ids.iter() {
+            let gamma = self.conn.randr_get_crtc_gamma(id)?.reply()?;
+            let info = self.conn.randr_get_crtc_info(id, resources.config_timestamp)?.reply()?;
+            let mut outputs = vec![];
+            for &output in info.outputs.iter() {
+                outputs.push(self.output_name(output, resources.config_timestamp)?.0);
+            }
+
+            crtcs.push(Crtc {
+                id: id,
+                ramps: CrtcRamps::new((gamma.red, gamma.green, gamma.blue)),
+                outputs: outputs,
+                pending: None,
+            });
+        }
+        self.crtcs = crtcs;
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        let neutral = transition::ColorSetting {
+            temp: NEUTRAL_TEMP.into(),
+            gamma: [1.0, 1.0, 1.0],
+            brightness: 1.0
+        };
+        if self.follow_focus {
+            self.focused = self.focused_crtc()?;
+        }
+
+        for crtc in self.crtcs.iter_mut() {
+            let setting = crtc.ramps.floored(if self.follow_focus && self.focused != Some(crtc.id) {
+                &neutral
+            } else {
+                setting
+            });
+            if crtc.ramps.last_applied.as_ref() == Some(&setting) {
+                crtc.pending = None;
+                continue
+            }
+            crtc.ramps.fill_next(&setting, self.preserve, &mut self.ramp_cache);
+            crtc.pending = Some(setting);
+        }
+
+        // Send all the ramps before waiting for any of the replies
+        let mut cookies = Vec::with_capacity(self.crtcs.len());
+        for crtc in self.crtcs.iter().filter(|crtc| crtc.pending.is_some()) {
+            let (ref r, ref g, ref b) = crtc.ramps.next;
+            cookies.push(self.conn.randr_set_crtc_gamma(crtc.id, r, g, b)?);
+        }
+        self.conn.flush()?;
+        for (cookie, crtc) in cookies.into_iter().zip(self.crtcs.iter_mut().filter(|crtc| crtc.pending.is_some())) {
+            cookie.check()?;
+            crtc.ramps.last_applied = crtc.pending.take();
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.last_applied = None;
+            let (ref r, ref g, ref b) = crtc.ramps.saved;
+            self.conn.randr_set_crtc_gamma(crtc.id, r, g, b)?.check()?;
+        }
+        Ok(())
+    }
+
+    fn set_preserve(&mut self, preserve: bool) {
+        self.preserve = preserve;
+    }
+
+    fn set_brightness_floors(&mut self, floors: &[(String, f64)]) -> Result<()> {
+        for &(ref name, _) in floors {
+            if !self.crtcs.iter().any(|crtc| crtc.outputs.contains(name)) {
+                return Err(Box::new(RedshiftError::OutputNotFound(name.clone())))
+            }
+        }
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.set_floor(&crtc.outputs, floors);
+        }
+        Ok(())
+    }
+
+    fn needs_refresh(&mut self) -> bool {
+        // Errors show up when the temperature is set
+        self.follow_focus && self.focused_crtc().ok() != Some(self.focused)
+    }
+
+    fn invalidate(&mut self) {
+        for crtc in self.crtcs.iter_mut() {
+            crtc.ramps.last_applied = None;
+        }
+    }
+
+    fn outputs(&self) -> Result<Vec<String>> {
+        let resources = self.conn.randr_get_screen_resources_current(self.root)?.reply()?;
+        let mut names = vec![];
+        for &output in resources.outputs.iter() {
+            let (name, connected) = self.output_name(output, resources.config_timestamp)?;
+            if connected {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        let mut statuses = vec![];
+        for crtc in self.crtcs.iter() {
+            let mut overrides = crtc.ramps.overrides(self.preserve);
+            if self.follow_focus && self.focused != Some(crtc.id) {
+                overrides.push("neutral without the pointer".to_owned());
+            }
+            for name in crtc.outputs.iter() {
+                let mut status = OutputStatus::new(name, crtc.ramps.last_applied.as_ref());
+                status.ramp_size = Some(crtc.ramps.size());
+                status.overrides = overrides.clone();
+                statuses.push(status);
+            }
+        }
+        statuses
+    }
+}
+
+/// The init function
+pub fn init() -> Result<Box<GammaMethod>> {
+    X11rbState::init().map(|state| Box::new(state) as Box<GammaMethod>)
+}
@@ -0,0 +1,188 @@
+//! Setting the gamma ramp of an X screen with the XF86VidMode
+//! extension
+//!
+//! A fallback for old drivers whose RandR gamma does not work. There
+//! is a single ramp for the whole screen, so every output shows the
+//! same setting, and brightness floors cannot be set per output. The
+//! screen is the default one of the display, or the one given with the
+//! `screen` option.
+//!
+//! The X server restores its ramps itself when another VT is shown, and
+//! sets ours again on return, but while away nothing is set, so that a
+//! switch back starts from the current setting.
+
+use xcb;
+use xcb::xf86vidmode;
+use transition;
+use colorramp;
+
+use super::{CrtcRamps, GammaMethod, OutputStatus};
+use super::{Result, RedshiftError};
+use super::vt;
+use std::error::Error;
+
+/// Name of the extension as the X server knows it
+const VIDMODE_EXTENSION: &'static str = "XFree86-VidModeExtension";
+
+/// Root window property holding the VT of the X server
+const VT_PROPERTY: &'static str = "XFree86_VT";
+
+fn vidmode_error<T>(e: xcb::Error<T>) -> Box<Error> {
+    From::from(format!("VidMode error: {}", e.error_code()))
+}
+
+/// The VT the X server runs on, if it says
+fn server_vt(conn: &xcb::Connection) -> Option<u32> {
+    let atom = xcb::intern_atom(conn, true, VT_PROPERTY).get_reply().ok()?.atom();
+    if atom == xcb::ATOM_NONE {
+        return None
+    }
+    let root = conn.get_setup().roots().next()?.root();
+    let reply = xcb::get_property(conn, false, root, atom, xcb::ATOM_INTEGER, 0, 1).get_reply().ok()?;
+    reply.value::<u32>().first().cloned()
+}
+
+fn set_ramps(conn: &xcb::Connection, screen_num: u16, ramps: &colorramp::Ramps) -> Result<()> {
+    xf86vidmode::set_gamma_ramp_checked(conn, screen_num, &ramps.0[..], &ramps.1[..], &ramps.2[..])
+        .request_check()
+        .map_err(vidmode_error)
+}
+
+pub struct VidmodeState {
+    conn: xcb::Connection,
+    screen_num: u16,
+
+    /// The ramps of the screen found at start and those set since
+    ramps: CrtcRamps,
+
+    ramp_cache: colorramp::RampCache,
+
+    /// Apply settings on top of the ramps found at start
+    preserve: bool,
+
+    /// The VT of the X server, and whether another one is shown
+    vt: Option<vt::Watch>,
+    paused: bool,
+}
+
+impl VidmodeState {
+    fn init() -> Result<VidmodeState> {
+        let (conn, screen_num) = xcb::Connection::connect(None)
+            .map_err(|e| format!("could not connect to the X server: {:?}", e))?;
+
+        let extension = xcb::query_extension(&conn, VIDMODE_EXTENSION)
+            .get_reply()
+            .map_err(vidmode_error)?;
+        if !extension.present() {
+            return Err(From::from("the X server does not support the XF86VidMode extension"))
+        }
+        let version = xf86vidmode::query_version(&conn)
+            .get_reply()
+            .map_err(vidmode_error)?;
+        debug!("XF86VidMode {}.{}", version.major_version(), version.minor_version());
+        let vt = server_vt(&conn).map(vt::Watch::new);
+
+        Ok(VidmodeState {
+            conn: conn,
+            screen_num: screen_num as u16,
+            ramps: CrtcRamps::new((vec![], vec![], vec![])),
+            ramp_cache: colorramp::RampCache::new(),
+            preserve: false,
+            paused: vt.as_ref().map_or(false, |vt| !vt.shown()),
+            vt: vt,
+        })
+    }
+
+    /// Follow the VT of the X server being switched away from and back
+    /// to, returning whether the ramps must be set again
+    fn follow_vt(&mut self) -> bool {
+        match self.vt.as_mut().and_then(|vt| vt.poll()) {
+            Some(false) => {
+                verbose!("Another VT is shown, pausing");
+                self.paused = true;
+                false
+            }
+            Some(true) => {
+                verbose!("The VT of the X server is shown again, resuming");
+                self.paused = false;
+                self.ramps.last_applied = None;
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl GammaMethod for VidmodeState {
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "screen" => {
+                let screens = self.conn.get_setup().roots().count();
+                match value.parse::<u16>() {
+                    Ok(screen) if (screen as usize) < screens => {
+                        self.screen_num = screen;
+                        Ok(())
+                    }
+                    _ => Err(Box::new(RedshiftError::MalformedArgument(
+                        format!("vidmode: no X screen '{}', there are {}", value, screens))))
+                }
+            }
+            _ => Err(Box::new(RedshiftError::MalformedArgument(format!("unknown vidmode option '{}'", key))))
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let size = xf86vidmode::get_gamma_ramp_size(&self.conn, self.screen_num)
+            .get_reply()
+            .map_err(vidmode_error)?
+            .size();
+        if size == 0 {
+            return Err(Box::new(RedshiftError::NoAdjustableOutputs(format!("X screen {}", self.screen_num))))
+        }
+        let gamma = xf86vidmode::get_gamma_ramp(&self.conn, self.screen_num, size)
+            .get_reply()
+            .map_err(vidmode_error)?;
+        debug!("X screen {}: {} ramp entries", self.screen_num, size);
+        self.ramps = CrtcRamps::new((gamma.red().to_vec(), gamma.green().to_vec(), gamma.blue().to_vec()));
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        self.follow_vt();
+        if self.paused || self.ramps.last_applied.as_ref() == Some(setting) {
+            return Ok(())
+        }
+        set_ramps(&self.conn, self.screen_num, self.ramps.ramps_for(setting, self.preserve, &mut self.ramp_cache))?;
+        self.ramps.last_applied = Some(setting.clone());
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        self.ramps.last_applied = None;
+        set_ramps(&self.conn, self.screen_num, &self.ramps.saved)
+    }
+
+    fn set_preserve(&mut self, preserve: bool) {
+        self.preserve = preserve;
+    }
+
+    fn needs_refresh(&mut self) -> bool {
+        self.follow_vt()
+    }
+
+    fn invalidate(&mut self) {
+        self.ramps.last_applied = None;
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        let mut status = OutputStatus::new(&format!("X screen {}", self.screen_num), self.ramps.last_applied.as_ref());
+        status.ramp_size = Some(self.ramps.size());
+        status.overrides = self.ramps.overrides(self.preserve);
+        vec![status]
+    }
+}
+
+/// The init function
+pub fn init() -> Result<Box<GammaMethod>> {
+    VidmodeState::init().map(|v| Box::new(v) as Box<GammaMethod>)
+}
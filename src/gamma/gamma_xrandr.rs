@@ -0,0 +1,305 @@
+//! Setting gamma by running the xrandr program
+//!
+//! A fallback for when linking against XCB is not wanted: xrandr's
+//! `--gamma` and `--brightness` are set for all outputs in a single
+//! invocation. xrandr cannot set arbitrary ramps, so each channel's
+//! white point is approximated by its gamma exponent (exact at mid
+//! grey), and the brightness is shared by all channels.
+//!
+//! Older versions of xrandr print the reciprocal of the gamma in
+//! `--verbose`, in which case restoring brings back the inverse of the
+//! gamma found at start. With linear ramps, the usual case, it makes
+//! no difference.
+
+use std::process::Command;
+
+use colorramp;
+use transition;
+
+use super::{GammaMethod, OutputStatus};
+use super::{Result, RedshiftError};
+use settings::{MIN_GAMMA, MAX_GAMMA};
+
+/// An active output, as listed by `xrandr --verbose`
+#[derive(Debug, Clone, PartialEq)]
+struct Output {
+    name: String,
+
+    /// Gamma and brightness found at start, used for restore
+    saved_gamma: [f64; 3],
+    saved_brightness: f64,
+
+    /// Colorimetry sent to the display, if the driver exposes it
+    colorspace: Option<String>,
+
+    /// Lowest brightness allowed on this output
+    min_brightness: f64,
+
+    /// Arguments last set, to skip running xrandr when nothing changes
+    last: Option<([f64; 3], f64)>,
+
+    /// The setting they were for
+    applied: Option<transition::ColorSetting>,
+}
+
+pub struct XrandrState {
+    outputs: Vec<Output>,
+}
+
+impl XrandrState {
+    fn init() -> Result<XrandrState> {
+        let output = Command::new("xrandr").arg("--version").output()
+            .map_err(|e| format!("could not run xrandr: {}", e))?;
+        if !output.status.success() {
+            return Err(From::from(format!("xrandr failed: {}", String::from_utf8_lossy(&output.stderr).trim())))
+        }
+        Ok(XrandrState { outputs: vec![] })
+    }
+
+    /// Run xrandr once, setting `settings` (gamma and brightness) on
+    /// the outputs at the same indices
+    fn run(&self, settings: &[([f64; 3], f64)]) -> Result<()> {
+        let mut cmd = Command::new("xrandr");
+        for (output, &(gamma, brightness)) in self.outputs.iter().zip(settings) {
+            cmd.arg("--output").arg(&output.name)
+                .arg("--gamma").arg(format!("{:.3}:{:.3}:{:.3}", gamma[0], gamma[1], gamma[2]))
+                .arg("--brightness").arg(format!("{:.3}", brightness));
+        }
+        debug!("Running {:?}", cmd);
+        let output = cmd.output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(From::from(format!("xrandr failed: {}", String::from_utf8_lossy(&output.stderr).trim())))
+        }
+    }
+}
+
+/// The xrandr gamma and brightness closest to `setting`
+///
+/// The ramps of the other methods are `(x * brightness * white)^(1/gamma)`
+/// per channel, which is `x^(1/gamma)` scaled by a per-channel factor.
+/// xrandr sets `x^(1/gamma) * brightness`, so the largest factor
+/// becomes the brightness and the others are folded into the
+/// exponent.
+pub fn xrandr_setting(setting: &transition::ColorSetting) -> ([f64; 3], f64) {
+    let white = colorramp::white_point(setting.temp);
+    let mut scale = [0.0; 3];
+    for c in 0..3 {
+        scale[c] = (setting.brightness * white[c]).powf(setting.gamma[c].recip());
+    }
+    let brightness = scale.iter().cloned().fold(0.0, f64::max);
+
+    let mut gamma = [1.0; 3];
+    for c in 0..3 {
+        // A channel with no light at all would need an infinite
+        // exponent
+        let exponent = setting.gamma[c].recip() + (brightness / scale[c]).log2();
+        gamma[c] = exponent.recip().max(MIN_GAMMA).min(MAX_GAMMA);
+    }
+    (gamma, brightness)
+}
+
+/// The active outputs in the output of `xrandr --verbose`
+fn parse_verbose(text: &str) -> Vec<Output> {
+    let mut outputs = vec![];
+    let mut current: Option<Output> = None;
+    for line in text.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            outputs.extend(current.take());
+            let mut words = line.split_whitespace();
+            if let (Some(name), Some("connected")) = (words.next(), words.next()) {
+                // Only outputs showing something have a geometry
+                let active = words.take(2).any(|word| word.contains('+'));
+                if active {
+                    current = Some(Output {
+                        name: name.to_owned(),
+                        saved_gamma: [1.0; 3],
+                        saved_brightness: 1.0,
+                        colorspace: None,
+                        min_brightness: 0.0,
+                        last: None,
+                        applied: None,
+                    });
+                }
+            }
+            continue
+        }
+        let output = match current {
+            Some(ref mut output) => output,
+            None => continue
+        };
+        let line = line.trim();
+        if line.starts_with("Gamma:") {
+            let values: Vec<f64> = line["Gamma:".len()..].trim().split(':')
+                .filter_map(|value| value.parse().ok())
+                .collect();
+            if values.len() == 3 {
+                output.saved_gamma = [values[0], values[1], values[2]];
+            }
+        } else if line.starts_with("Brightness:") {
+            if let Ok(brightness) = line["Brightness:".len()..].trim().parse() {
+                output.saved_brightness = brightness;
+            }
+        } else if line.starts_with(super::COLORSPACE_PROPERTY) && line[super::COLORSPACE_PROPERTY.len()..].starts_with(':') {
+            let value = line[super::COLORSPACE_PROPERTY.len() + 1..].trim();
+            if !value.is_empty() {
+                output.colorspace = Some(value.to_owned());
+            }
+        }
+    }
+    outputs.extend(current);
+    outputs
+}
+
+impl GammaMethod for XrandrState {
+
+    fn start(&mut self) -> Result<()> {
+        let output = Command::new("xrandr").arg("--verbose").output()?;
+        if !output.status.success() {
+            return Err(From::from(format!("xrandr failed: {}", String::from_utf8_lossy(&output.stderr).trim())))
+        }
+        let (outputs, skipped): (Vec<_>, Vec<_>) = parse_verbose(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .partition(|output| !super::is_virtual_output(&output.name));
+        if outputs.is_empty() && !skipped.is_empty() {
+            let names: Vec<_> = skipped.into_iter().map(|output| output.name).collect();
+            return Err(Box::new(RedshiftError::NoAdjustableOutputs(names.join(", "))))
+        }
+        self.outputs = outputs;
+        for output in self.outputs.iter() {
+            debug!("Output {}: gamma {:?}, brightness {}", output.name, output.saved_gamma, output.saved_brightness);
+            match output.colorspace {
+                Some(ref colorspace) if super::is_wide_gamut(colorspace) =>
+                    super::warn_wide_gamut(&output.name, colorspace),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        let applied: Vec<_> = self.outputs.iter()
+            .map(|output| {
+                let mut setting = setting.clone();
+                setting.brightness = setting.brightness.max(output.min_brightness);
+                setting
+            })
+            .collect();
+        let settings: Vec<_> = applied.iter().map(xrandr_setting).collect();
+
+        // Running xrandr takes a while, so skip it when the rounded
+        // values are the same as last time
+        let unchanged = self.outputs.iter().zip(settings.iter())
+            .all(|(output, setting)| output.last.map_or(false, |last| {
+                (0..3).all(|c| (last.0[c] - (setting.0)[c]).abs() < 5e-4)
+                    && (last.1 - setting.1).abs() < 5e-4
+            }));
+        if unchanged {
+            return Ok(())
+        }
+
+        self.run(&settings)?;
+        for ((output, setting), applied) in self.outputs.iter_mut().zip(settings).zip(applied) {
+            output.last = Some(setting);
+            output.applied = Some(applied);
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        let saved: Vec<_> = self.outputs.iter()
+            .map(|output| (output.saved_gamma, output.saved_brightness))
+            .collect();
+        self.run(&saved)?;
+        for output in self.outputs.iter_mut() {
+            output.applied = None;
+        }
+        Ok(())
+    }
+
+    fn set_brightness_floors(&mut self, floors: &[(String, f64)]) -> Result<()> {
+        for &(ref name, _) in floors {
+            if !self.outputs.iter().any(|output| output.name == *name) {
+                return Err(Box::new(RedshiftError::OutputNotFound(name.clone())))
+            }
+        }
+        for output in self.outputs.iter_mut() {
+            output.min_brightness = floors.iter()
+                .filter(|&&(ref name, _)| *name == output.name)
+                .fold(0.0, |min, &(_, floor)| min.max(floor));
+        }
+        Ok(())
+    }
+
+    fn outputs(&self) -> Result<Vec<String>> {
+        Ok(self.outputs.iter().map(|output| output.name.clone()).collect())
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        self.outputs.iter()
+            .map(|output| {
+                let mut status = OutputStatus::new(&output.name, output.applied.as_ref());
+                if output.min_brightness > 0.0 {
+                    status.overrides.push(format!("brightness floor {:.2}", output.min_brightness));
+                }
+                status
+            })
+            .collect()
+    }
+}
+
+/// The init function
+pub fn init() -> Result<Box<GammaMethod>> {
+    XrandrState::init().map(|state| Box::new(state) as Box<GammaMethod>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_verbose, xrandr_setting};
+    use transition::{ColorSetting, Kelvin};
+
+    #[test]
+    fn test_parse_verbose() {
+        let text = "\
+Screen 0: minimum 320 x 200, current 3840 x 1080, maximum 16384 x 16384
+eDP-1 connected primary 1920x1080+0+0 (0x46) normal (normal left inverted right x axis y axis) 309mm x 174mm
+\tIdentifier: 0x42
+\tGamma:      1.0:0.9:0.8
+\tBrightness: 0.70
+\tColorspace: BT2020_RGB
+\t\tsupported: Default, BT709_YCC, BT2020_RGB
+  1920x1080 (0x46) 138.700MHz +HSync -VSync *current +preferred
+HDMI-1 connected (normal left inverted right x axis y axis)
+\tGamma:      1.0:1.0:1.0
+DP-1 disconnected (normal left inverted right x axis y axis)
+DP-2 connected 1920x1080+1920+0 (0x47) normal (normal left inverted right x axis y axis) 527mm x 296mm
+\tBrightness: 1.0
+";
+        let outputs = parse_verbose(text);
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].name, "eDP-1");
+        assert_eq!(outputs[0].saved_gamma, [1.0, 0.9, 0.8]);
+        assert_eq!(outputs[0].saved_brightness, 0.7);
+        assert_eq!(outputs[0].colorspace, Some("BT2020_RGB".to_owned()));
+        assert_eq!(outputs[1].name, "DP-2");
+        assert_eq!(outputs[1].saved_gamma, [1.0, 1.0, 1.0]);
+        assert_eq!(outputs[1].colorspace, None);
+    }
+
+    #[test]
+    fn test_xrandr_setting() {
+        let neutral = xrandr_setting(&ColorSetting { temp: Kelvin(6500.0), gamma: [1.0; 3], brightness: 0.8 });
+        assert!((neutral.1 - 0.8).abs() < 1e-3);
+        assert!(neutral.0.iter().all(|g| (g - 1.0).abs() < 1e-3));
+
+        // At mid grey the result matches the ramps of the other methods
+        let setting = ColorSetting { temp: Kelvin(3500.0), gamma: [1.0, 0.9, 1.1], brightness: 0.9 };
+        let (gamma, brightness) = xrandr_setting(&setting);
+        let white = ::colorramp::white_point(Kelvin(3500.0));
+        for c in 0..3 {
+            let expected = (0.5 * setting.brightness * white[c]).powf(setting.gamma[c].recip());
+            assert!((0.5f64.powf(gamma[c].recip()) * brightness - expected).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Golden tests of what the methods set
+//!
+//! The exact ramps (and the xrandr, backlight and emulated brightness
+//! values) for a few representative settings are kept in
+//! golden_ramps.txt, so that a change to colorramp or to the scaling
+//! of a method shows up as a diff to review, rather than as a tint
+//! shift users notice. After an intended change, regenerate it with
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test golden
+//! ```
+//!
+//! and review the diff.
+
+use std::env;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::Write as IoWrite;
+use std::path::Path;
+
+use colorramp;
+use transition::{ColorSetting, Kelvin};
+use super::brightness_as_gamma;
+use super::gamma_backlight;
+use super::gamma_xrandr;
+
+const GOLDEN: &'static str = include_str!("golden_ramps.txt");
+
+/// (temperature, brightness, gamma, ramp size)
+const CASES: &'static [(i32, f64, [f64; 3], usize)] = &[
+    (6500, 1.0, [1.0, 1.0, 1.0], 256),
+    (5500, 1.0, [1.0, 1.0, 1.0], 256),
+    (3500, 1.0, [1.0, 1.0, 1.0], 256),
+    (1000, 1.0, [1.0, 1.0, 1.0], 256),
+    (4500, 0.6, [1.0, 1.0, 1.0], 256),
+    (5000, 1.0, [0.8, 0.9, 1.1], 256),
+    (4000, 1.0, [1.0, 1.0, 1.0], 16),
+    (3000, 0.8, [1.0, 1.0, 1.0], 1024),
+    (2500, 0.5, [1.2, 1.0, 0.9], 4096),
+];
+
+/// Ramps up to this size are written out in full, longer ones as 65
+/// samples and a hash of the whole ramp
+const FULL_SIZE: usize = 256;
+
+/// Backlight levels the backlight method scales, as (saved, max)
+const BACKLIGHT: (u64, u64) = (937, 1200);
+
+/// FNV-1a
+fn hash(ramp: &[u16]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for v in ramp {
+        for byte in &[*v as u8, (*v >> 8) as u8] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+fn write_ramps(out: &mut String, ramps: &colorramp::Ramps) {
+    for (channel, ramp) in [('r', &ramps.0), ('g', &ramps.1), ('b', &ramps.2)].iter() {
+        let values: Vec<_> = if ramp.len() <= FULL_SIZE {
+            ramp.iter().map(|v| v.to_string()).collect()
+        } else {
+            let step = ramp.len() / 64;
+            let mut samples: Vec<_> = ramp.iter().step_by(step).map(|v| v.to_string()).collect();
+            samples.push(ramp[ramp.len() - 1].to_string());
+            samples
+        };
+        if ramp.len() <= FULL_SIZE {
+            writeln!(out, "{} {}", channel, values.join(" ")).unwrap();
+        } else {
+            writeln!(out, "{} hash {:016x} samples {}", channel, hash(ramp), values.join(" ")).unwrap();
+        }
+    }
+}
+
+fn render() -> String {
+    let mut out = String::from("# Written by gamma::golden, see there before changing\n");
+    for &(temp, brightness, gamma, size) in CASES {
+        let setting = ColorSetting { temp: temp.into(), gamma: gamma, brightness: brightness };
+        writeln!(out, "\nsetting {}K brightness {} gamma {}:{}:{} size {}",
+                 temp, brightness, gamma[0], gamma[1], gamma[2], size).unwrap();
+
+        // randr and randr-x11rb, on linear ramps
+        write_ramps(&mut out, colorramp::RampCache::new().get(&setting, size));
+
+        let (xrandr_gamma, xrandr_brightness) = gamma_xrandr::xrandr_setting(&setting);
+        writeln!(out, "xrandr gamma {:.6}:{:.6}:{:.6} brightness {:.6}",
+                 xrandr_gamma[0], xrandr_gamma[1], xrandr_gamma[2], xrandr_brightness).unwrap();
+        writeln!(out, "backlight {}", gamma_backlight::level(BACKLIGHT.0, BACKLIGHT.1, brightness)).unwrap();
+        let emulated = brightness_as_gamma(gamma, brightness);
+        writeln!(out, "emulated gamma {:.6}:{:.6}:{:.6}", emulated[0], emulated[1], emulated[2]).unwrap();
+    }
+
+    // randr preserving a calibration, here a gamma of 1.1
+    let size = 256;
+    let calibration: Vec<u16> = (0..size)
+        .map(|i| ((i as f64 / size as f64).powf(1.1f64.recip()) * 65536.0) as u16)
+        .collect();
+    let mut ramps = (calibration.clone(), calibration.clone(), calibration);
+    let setting = ColorSetting { temp: Kelvin(3500.0), gamma: [1.0, 1.0, 1.0], brightness: 0.9 };
+    colorramp::fill(&mut ramps.0, &mut ramps.1, &mut ramps.2, &setting, size);
+    writeln!(out, "\npreserved calibration gamma 1.1, setting 3500K brightness 0.9 size {}", size).unwrap();
+    write_ramps(&mut out, &ramps);
+    out
+}
+
+#[test]
+fn golden_ramps() {
+    let rendered = render();
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        let path = Path::new(file!()).with_file_name("golden_ramps.txt");
+        File::create(&path).and_then(|mut f| f.write_all(rendered.as_bytes())).unwrap();
+        return
+    }
+    // Line by line, rather than as two huge strings
+    for (n, (got, expected)) in rendered.lines().zip(GOLDEN.lines()).enumerate() {
+        assert!(got == expected, "golden_ramps.txt line {} differs (UPDATE_GOLDEN=1 to regenerate):\n\
+                                  expected: {:.200}\n     got: {:.200}", n + 1, expected, got);
+    }
+    assert_eq!(rendered.lines().count(), GOLDEN.lines().count(),
+               "golden_ramps.txt has a different number of lines (UPDATE_GOLDEN=1 to regenerate)");
+}
@@ -1,30 +1,144 @@
+#[macro_use]
+mod registry;
+
 #[cfg(feature = "randr")]
 mod gamma_randr;
+#[cfg(feature = "randr-x11rb")]
+mod gamma_randr_x11rb;
+#[cfg(feature = "vidmode")]
+mod gamma_vidmode;
+mod gamma_xrandr;
+#[cfg(feature = "drm")]
+mod gamma_drm;
+#[cfg(feature = "seat")]
+mod seat;
+#[cfg(any(feature = "drm", feature = "vidmode"))]
+mod vt;
+mod gamma_backlight;
+#[cfg(feature = "android")]
+mod gamma_android;
 
+use colorramp;
 use transition;
-use super::{Result, RedshiftError};
+use settings::{MIN_GAMMA, MAX_GAMMA};
+use super::{Result, RedshiftError, NEUTRAL_TEMP};
+
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+
+gamma_methods! {
+    #[cfg(feature = "randr")]
+    "randr" => gamma_randr::init { auto: true, x11: true };
+    // Takes the name of the xcb based method when that is left out
+    #[cfg(all(feature = "randr-x11rb", not(feature = "randr")))]
+    "randr" => gamma_randr_x11rb::init { auto: true, x11: true };
+    #[cfg(all(feature = "randr-x11rb", feature = "randr"))]
+    "randr-x11rb" => gamma_randr_x11rb::init { auto: false, x11: true };
+    #[cfg(feature = "vidmode")]
+    "vidmode" => gamma_vidmode::init { auto: true, x11: true };
+    "xrandr" => gamma_xrandr::init { auto: true, x11: true };
+    #[cfg(feature = "drm")]
+    "drm" => gamma_drm::init { auto: true, x11: false };
+    "backlight" => gamma_backlight::init { auto: false, x11: false };
+    #[cfg(feature = "android")]
+    "android" => gamma_android::init { auto: true, x11: false };
+    "dummy" => init_dummy { auto: false, x11: false };
+}
+
+fn find_method(method_name: &str) -> Option<&'static registry::MethodInfo> {
+    METHODS.iter().find(|method| method.name == method_name)
+}
+
+/// An option for a specific gamma method, given either as
+/// `-m METHOD:KEY=VALUE` or as a key in the `[METHOD]` section of the
+/// config file
+#[derive(Debug, Clone)]
+pub struct MethodOption {
+    pub method: String,
+    pub key: String,
+    pub value: String,
+}
+
+/// What part of a setting a method can show
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    /// The color temperature and gamma
+    pub color: bool,
+    pub brightness: bool,
+}
 
-use std::collections::HashMap;
-use std::error::Error;
+/// The state of one output, as last set by a method
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputStatus {
+    pub name: String,
 
-type GammaInit = fn() -> Result<Box<GammaMethod>>;
+    /// Entries in each gamma ramp, for methods setting ramps
+    pub ramp_size: Option<usize>,
 
-lazy_static! {
-    static ref SUPPORTED_GAMMA_METHODS: HashMap<&'static str, GammaInit> = {
-        let mut m: HashMap<&'static str, GammaInit> = HashMap::with_capacity(4);
+    /// What was last set, if anything was since the start or the last
+    /// restore, and the method sets it
+    pub temp: Option<transition::Kelvin>,
+    pub brightness: Option<f64>,
 
-        #[cfg(feature = "randr")]
-        m.insert("randr", gamma_randr::init);
+    /// What is set differently on this output than on the others, in
+    /// words
+    pub overrides: Vec<String>,
+}
 
-        m.insert("dummy", init_dummy);
-        m
-    };
+impl OutputStatus {
+    /// The status of output `name`, last set to `setting`
+    pub fn new(name: &str, setting: Option<&transition::ColorSetting>) -> OutputStatus {
+        OutputStatus {
+            name: name.to_owned(),
+            ramp_size: None,
+            temp: setting.map(|setting| setting.temp),
+            brightness: setting.map(|setting| setting.brightness),
+            overrides: vec![],
+        }
+    }
+}
+
+impl fmt::Display for OutputStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(temp) = self.temp {
+            parts.push(format!("{}K", temp));
+        }
+        if let Some(brightness) = self.brightness {
+            parts.push(format!("brightness {:.2}", brightness));
+        }
+        if parts.is_empty() {
+            parts.push("not adjusted".to_owned());
+        } else {
+            let reduction = colorramp::melanopic_reduction(self.temp.unwrap_or(NEUTRAL_TEMP.into()),
+                                                          self.brightness.unwrap_or(1.0));
+            parts.push(format!("blue light -{:.0}%", reduction * 100.0));
+        }
+        if let Some(size) = self.ramp_size {
+            parts.push(format!("ramp size {}", size));
+        }
+        write!(f, "{}: {}", self.name, parts.join(", "))?;
+        if !self.overrides.is_empty() {
+            write!(f, " ({})", self.overrides.join(", "))?;
+        }
+        Ok(())
+    }
 }
 
 /// Any gamma method provider should implement this trait
 ///
 pub trait GammaMethod {
 
+    /// Set a method-specific option
+    ///
+    /// Called after initialisation, but before start(). The default
+    /// rejects every option, for methods that do not take any.
+    fn set_option(&mut self, key: &str, _value: &str) -> Result<()> {
+        Err(Box::new(RedshiftError::MalformedArgument(format!("unknown method option '{}'", key))))
+    }
+
     /// Initialization method
     ///
     /// Called before set_temperature()
@@ -39,7 +153,81 @@ pub trait GammaMethod {
 
     /// The restore method is called when Redshift exits from
     /// running in continual mode.
-    fn restore(&self) -> Result<()>;
+    ///
+    /// Methods may give up protocol objects or close devices here,
+    /// as long as a later start() or set_temperature() gets them back
+    /// (the stress test does both). On abnormal exits (errors, panics)
+    /// it is not called at all: dropping a method must release what
+    /// it holds, but leave the screen as it is, like upstream redshift
+    /// does when killed.
+    fn restore(&mut self) -> Result<()>;
+
+    /// Apply settings on top of the ramps found at start, rather
+    /// than on top of linear ramps (the default)
+    ///
+    /// Methods that do not save the ramps ignore this.
+    fn set_preserve(&mut self, _preserve: bool) {}
+
+    /// Keep the brightness of the named outputs at or above the given
+    /// floors
+    ///
+    /// The default is for methods that do not know about outputs, and
+    /// fails unless there are no floors.
+    fn set_brightness_floors(&mut self, floors: &[(String, f64)]) -> Result<()> {
+        if floors.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::new(RedshiftError::MalformedArgument(
+                format!("this method does not support per-output brightness floors"))))
+        }
+    }
+
+    /// Keep the settings of the displays of each named seat within its
+    /// limit
+    ///
+    /// The default is for methods that do not know about seats. As a
+    /// config file may be shared between machines, the limits are only
+    /// warned about rather than failing.
+    fn set_seat_limits(&mut self, limits: &[(String, AdjustmentLimit)]) -> Result<()> {
+        if !limits.is_empty() {
+            warn!("Only the drm method knows about seats, ignoring the [seat:...] settings");
+        }
+        Ok(())
+    }
+
+    /// Whether set_temperature() should be called again although the
+    /// setting did not change, because where it should go did
+    fn needs_refresh(&mut self) -> bool {
+        false
+    }
+
+    /// Forget what was set, so that the next set_temperature() sends
+    /// it again even if it did not change, as the display may have
+    /// been reset behind the method's back
+    fn invalidate(&mut self) {}
+
+    /// Names of the connected outputs, for methods that know about
+    /// them
+    fn outputs(&self) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// The state of each output, for methods that know about them
+    fn output_status(&self) -> Vec<OutputStatus> {
+        vec![]
+    }
+
+    /// What the method sets, for sharing a setting out between several
+    /// methods. The default is everything.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { color: true, brightness: true }
+    }
+
+    /// Files the method writes to after start(), which the sandbox
+    /// must leave writable
+    fn writable_paths(&self) -> Vec<PathBuf> {
+        vec![]
+    }
 }
 
 fn init_dummy() -> Result<Box<GammaMethod>> {
@@ -47,54 +235,785 @@ fn init_dummy() -> Result<Box<GammaMethod>> {
 }
 
 pub fn is_method_available(method_name: &str) -> bool {
-    SUPPORTED_GAMMA_METHODS.contains_key(method_name)
+    find_method(method_name).is_some()
+}
+
+/// The names of all compiled-in gamma methods
+pub fn method_names() -> Vec<&'static str> {
+    let mut names: Vec<_> = METHODS.iter().map(|method| method.name).collect();
+    names.sort();
+    names
+}
+
+/// Output names used by virtual displays (Xvnc, Xwayland and the
+/// virtual heads of some drivers), which do not support gamma ramps
+const VIRTUAL_OUTPUT_PREFIXES: &[&str] = &["VNC-", "VIRTUAL", "XWAYLAND"];
+
+/// The one output of Xvfb, named in full as a prefix would catch real
+/// outputs too
+const VIRTUAL_OUTPUT_NAMES: &[&str] = &["screen"];
+
+/// Whether `name` looks like the output of a virtual display
+pub fn is_virtual_output(name: &str) -> bool {
+    VIRTUAL_OUTPUT_NAMES.contains(&name) || VIRTUAL_OUTPUT_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// The output property holding the colorimetry sent to the display,
+/// mirrored by RandR from the DRM connector
+pub const COLORSPACE_PROPERTY: &'static str = "Colorspace";
+
+/// Colorspace values (as named by DRM) wider than sRGB, used for HDR
+const WIDE_GAMUT_PREFIXES: &'static [&'static str] = &["BT2020", "DCI-P3", "opRGB"];
+
+/// Whether an output in `colorspace` is likely to ignore or misapply
+/// the gamma ramps
+pub fn is_wide_gamut(colorspace: &str) -> bool {
+    WIDE_GAMUT_PREFIXES.iter().any(|prefix| colorspace.starts_with(prefix))
+}
+
+/// Warn about an output the ramps may look wrong on
+pub fn warn_wide_gamut(output: &str, colorspace: &str) {
+    warn!("Output {} uses the {} colorspace (HDR or wide gamut), where gamma ramps may be ignored or look wrong",
+          output, colorspace);
+}
+
+/// The desktops' own night lights, with the command printing "true"
+/// when one is on
+const NIGHT_LIGHTS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("GNOME's Night Light", &["gsettings", "get", "org.gnome.settings-daemon.plugins.color", "night-light-enabled"]),
+    ("KDE's Night Color", &["kreadconfig6", "--file", "kwinrc", "--group", "NightColor", "--key", "Active"]),
+    ("KDE's Night Color", &["kreadconfig5", "--file", "kwinrc", "--group", "NightColor", "--key", "Active"]),
+];
+
+/// The desktop's own night light, if one is on in a Wayland session
+///
+/// There the compositor adjusts the screen too, so that with a method
+/// of ours the setting would be applied twice, at least to some
+/// windows. Under X11 the desktops set the same ramps we do, replacing
+/// our setting rather than adding to it, so the commands are not even
+/// run. Desktops without one, where the commands are missing, have
+/// none on.
+pub fn active_night_light() -> Option<&'static str> {
+    if env::var_os("WAYLAND_DISPLAY").is_none() && detect_session() != Session::Wayland {
+        return None
+    }
+    NIGHT_LIGHTS.iter()
+        .find(|&&(_, command)| {
+            Command::new(command[0]).args(&command[1..]).output()
+                .map(|output| output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "true")
+                .unwrap_or(false)
+        })
+        .map(|&(name, _)| name)
+}
+
+/// A RandR provider (a GPU, or a display link device), with the CRTCs
+/// it drives
+pub struct Provider {
+    pub name: String,
+    pub crtcs: Vec<u32>,
+}
+
+/// The CRTCs of the provider selected with the `provider` option of
+/// the randr methods, by its name (as in `xrandr --listproviders`) or,
+/// for GPUs of the same driver, its index
+pub fn provider_crtcs(providers: &[Provider], wanted: &str) -> Result<Vec<u32>> {
+    let found = providers.iter().find(|provider| provider.name == wanted)
+        .or_else(|| wanted.parse().ok().and_then(|i: usize| providers.get(i)));
+    match found {
+        // Outputs of a GPU driven through another one (reverse PRIME)
+        // only get CRTCs once it is made an output sink
+        Some(provider) if provider.crtcs.is_empty() => Err(Box::new(RedshiftError::NoAdjustableOutputs(format!(
+            "provider {} drives no CRTCs (for reverse PRIME, run xrandr --setprovideroutputsource first)",
+            provider.name)))),
+        Some(provider) => Ok(provider.crtcs.clone()),
+        None => Err(Box::new(RedshiftError::MalformedArgument(format!(
+            "no RandR provider '{}' (found {})", wanted,
+            providers.iter().map(|provider| provider.name.as_str()).collect::<Vec<_>>().join(", ")))))
+    }
+}
+
+/// The kind of graphical session we are running in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    X11,
+    Wayland,
+    Unknown,
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Session::X11 => "x11",
+            Session::Wayland => "wayland",
+            Session::Unknown => "unknown",
+        })
+    }
+}
+
+/// Guess the session type from XDG_SESSION_TYPE, falling back to
+/// which display variables are set
+pub fn detect_session() -> Session {
+    match env::var("XDG_SESSION_TYPE").as_ref().map(|s| s.as_str()) {
+        Ok("wayland") => Session::Wayland,
+        Ok("x11") => Session::X11,
+        _ if env::var_os("WAYLAND_DISPLAY").is_some() => Session::Wayland,
+        _ if env::var_os("DISPLAY").is_some() => Session::X11,
+        _ => Session::Unknown,
+    }
 }
 
 /// Initialise the gamma adjustment method
 ///
 /// If a specific method is requsted (ie method_name is `Some(..)`)
-/// then it is assumed that the method exists and we can call its
-/// initialisation function. If a requested method does not exist,
-/// this function panics.
+/// then its initialisation function is called, or
+/// `GammaMethodNotFound` returned if there is no method by that name.
 ///
-/// If `method_name` is `None` then all available methods (except for
-/// the dummy) are tried in turn until one successfully starts - and
-/// then that method is used.
-pub fn init_gamma_method(method_name: Option<&str>) -> Result<Box<GammaMethod>> {
+/// If `method_name` is `None` then the available methods in
+/// `METHODS` marked `auto` are tried in turn until one successfully starts - and
+/// then that method is used. In a Wayland session the X11 methods
+/// are left out, since they would only adjust XWayland clients.
+///
+/// Several methods can be given separated by commas, as in
+/// "randr,backlight", to use them all at once through a `Dispatcher`.
+///
+/// Options in `options` that belong to the selected method are set
+/// before the method is returned.
+pub fn init_gamma_method(method_name: Option<&str>, options: &[MethodOption]) -> Result<Box<GammaMethod>> {
+    if let Some(names) = method_name.filter(|names| names.contains(',')) {
+        let methods = names.split(',')
+            .map(|name| init_gamma_method(Some(name), options))
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Box::new(Dispatcher { methods: methods }))
+    }
+
     match method_name {
-        Some(m) => {
-            SUPPORTED_GAMMA_METHODS[m]()
+        Some(name) => {
+            let method = find_method(name)
+                .ok_or_else(|| RedshiftError::GammaMethodNotFound(name.to_owned()))?;
+            if method.x11 && detect_session() == Session::Wayland {
+                warn!("Method {} only adjusts Xwayland windows in a Wayland session, not those of the compositor", name);
+            }
+            let mut state = (method.init)()?;
+            set_options(name, &mut *state, options)?;
+            Ok(state)
+        }
+        None => init_auto_method(options).map(|(_, state)| state)
+    }
+}
+
+/// Initialise the first of the methods marked `auto` that can be,
+/// as `init_gamma_method(None, ...)` does, and return its name too
+pub fn init_auto_method(options: &[MethodOption]) -> Result<(&'static str, Box<GammaMethod>)> {
+    let session = detect_session();
+    debug!("Detected session type: {:?}", session);
+
+    let mut candidates = vec![];
+    let mut skipped = vec![];
+    for method in METHODS.iter().filter(|method| method.auto) {
+        if session == Session::Wayland && method.x11 {
+            verbose!("Wayland session detected, not trying method {} (it only affects X11 clients)", method.name);
+            skipped.push(method.name);
+        } else {
+            candidates.push(method);
+        }
+    }
+
+    let started = candidates.into_iter()
+        .filter_map(|method| {
+            debug!("Trying method {}", method.name);
+            match (method.init)() {
+                Ok(state) => Some((method.name, state)),
+                Err(e) => { verbose!("Method {} failed: {}", method.name, e); None }
+            }
+        })
+        .next();
+
+    match started {
+        Some((name, mut state)) => {
+            verbose!("Using method {}", name);
+            set_options(name, &mut *state, options)?;
+            Ok((name, state))
+        }
+        None if !skipped.is_empty() => {
+            Err(Box::new(RedshiftError::NoGammaMethod(
+                format!("skipped {} in a Wayland session, choose one with -m to use it anyway",
+                        skipped.join(", ")))))
         }
         None => {
-            // Loop over each method and try their init function
-            // (skipping the dummy)
-            SUPPORTED_GAMMA_METHODS.iter()
-                .filter_map(|(name, method_init)| {
-                    if &name[..] == "dummy" { None }
-                    else {
-                        method_init()
-                            .map(|s| { println!("Using method {}", name); s })
-                            .ok()
-                    }
-                })
-                .take(1)
-                .next()
-                .ok_or_else(|| Box::new(RedshiftError::GammaMethodNotFound("None".to_owned())) as Box<Error>)
+            Err(Box::new(RedshiftError::NoGammaMethod(
+                format!("none of the available methods could be started"))))
+        }
+    }
+}
+
+fn set_options(name: &str, state: &mut GammaMethod, options: &[MethodOption]) -> Result<()> {
+    for option in options.iter().filter(|o| o.method == name) {
+        debug!("Setting {} option {}={}", name, option.key, option.value);
+        state.set_option(&option.key, &option.value)?;
+    }
+    Ok(())
+}
+
+/// `gamma` with `brightness` folded in, for methods that only set the
+/// color: darker by `brightness` at mid grey, with black and white
+/// left as they are
+pub fn brightness_as_gamma(gamma: [f64; 3], brightness: f64) -> [f64; 3] {
+    let mut folded = [1.0; 3];
+    for c in 0..3 {
+        let exponent = gamma[c].recip() - brightness.log2();
+        folded[c] = exponent.recip().max(MIN_GAMMA).min(MAX_GAMMA);
+    }
+    folded
+}
+
+/// The ramps of a CRTC (or of a whole screen), as found at start and
+/// as set since, for the methods that set gamma ramps
+pub struct CrtcRamps {
+    /// The ramps found at start, used for restore
+    pub saved: colorramp::Ramps,
+
+    /// Whether the saved ramps are linear, so that preserving them
+    /// makes no difference
+    pub saved_identity: bool,
+
+    /// The ramps to set next, kept to avoid allocating them on every
+    /// update
+    pub next: colorramp::Ramps,
+
+    /// Lowest brightness allowed, from the floors of the outputs
+    /// driven
+    pub floor: f64,
+
+    /// The setting of the ramps last set, to skip setting them again
+    pub last_applied: Option<transition::ColorSetting>,
+}
+
+impl CrtcRamps {
+    pub fn new(saved: colorramp::Ramps) -> CrtcRamps {
+        CrtcRamps {
+            saved_identity: colorramp::is_identity(&saved.0)
+                && colorramp::is_identity(&saved.1)
+                && colorramp::is_identity(&saved.2),
+            next: saved.clone(),
+            saved: saved,
+            floor: 0.0,
+            last_applied: None,
+        }
+    }
+
+    /// The number of entries in each ramp
+    pub fn size(&self) -> usize {
+        self.saved.0.len()
+    }
+
+    /// Whether settings go on top of the saved ramps, with the
+    /// calibration in them preserved
+    pub fn preserves(&self, preserve: bool) -> bool {
+        preserve && !self.saved_identity
+    }
+
+    /// Set the floor from those of the outputs in `outputs`
+    pub fn set_floor(&mut self, outputs: &[String], floors: &[(String, f64)]) {
+        self.floor = floors.iter()
+            .filter(|&&(ref name, _)| outputs.contains(name))
+            .fold(0.0, |min, &(_, floor)| min.max(floor));
+    }
+
+    /// `setting` with the brightness kept at or above the floor
+    pub fn floored(&self, setting: &transition::ColorSetting) -> transition::ColorSetting {
+        let mut setting = setting.clone();
+        setting.brightness = setting.brightness.max(self.floor);
+        setting
+    }
+
+    /// The ramps for `setting`: made in `next` on top of the saved
+    /// ramps when preserving them, or else taken from `cache`
+    pub fn ramps_for<'a>(&'a mut self, setting: &transition::ColorSetting, preserve: bool,
+                         cache: &'a mut colorramp::RampCache) -> &'a colorramp::Ramps {
+        let size = self.size();
+        if !self.preserves(preserve) {
+            return cache.get(setting, size)
+        }
+        {
+            let (ref mut r, ref mut g, ref mut b) = self.next;
+            r.copy_from_slice(&self.saved.0);
+            g.copy_from_slice(&self.saved.1);
+            b.copy_from_slice(&self.saved.2);
+            colorramp::fill(&mut r[..], &mut g[..], &mut b[..], setting, size);
+        }
+        &self.next
+    }
+
+    /// Make the ramps for `setting` in `next`, for methods that send
+    /// them later
+    pub fn fill_next(&mut self, setting: &transition::ColorSetting, preserve: bool,
+                     cache: &mut colorramp::RampCache) {
+        if self.preserves(preserve) {
+            self.ramps_for(setting, preserve, cache);
+            return
+        }
+        let ramps = cache.get(setting, self.size());
+        let (ref mut r, ref mut g, ref mut b) = self.next;
+        r.copy_from_slice(&ramps.0);
+        g.copy_from_slice(&ramps.1);
+        b.copy_from_slice(&ramps.2);
+    }
+
+    /// What the output status shows about the floor and the saved
+    /// ramps
+    pub fn overrides(&self, preserve: bool) -> Vec<String> {
+        let mut overrides = vec![];
+        if self.floor > 0.0 {
+            overrides.push(format!("brightness floor {:.2}", self.floor));
+        }
+        if self.preserves(preserve) {
+            overrides.push("on top of its calibration".to_owned());
+        }
+        overrides
+    }
+}
+
+/// The most a setting may adjust the screen, whatever was asked for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdjustmentLimit {
+    /// Lowest color temperature
+    pub min_temp: Option<i32>,
+
+    /// Lowest brightness
+    pub min_brightness: Option<f64>,
+}
+
+impl AdjustmentLimit {
+    pub fn is_set(&self) -> bool {
+        self.min_temp.is_some() || self.min_brightness.is_some()
+    }
+
+    /// `setting`, brought within the limit
+    pub fn apply(&self, setting: &transition::ColorSetting) -> transition::ColorSetting {
+        let mut setting = setting.clone();
+        if let Some(min_temp) = self.min_temp {
+            setting.temp = transition::Kelvin(setting.temp.0.max(min_temp as f64));
+        }
+        if let Some(min_brightness) = self.min_brightness {
+            setting.brightness = setting.brightness.max(min_brightness);
         }
+        setting
+    }
+}
+
+/// A method getting every setting changed on the way, for the options
+/// that apply whatever the method is
+pub struct Adjusted {
+    pub inner: Box<GammaMethod>,
+    adjust: Box<FnMut(&transition::ColorSetting) -> transition::ColorSetting>,
+    /// The changed settings show the brightness through the color
+    emulates_brightness: bool,
+}
+
+impl Adjusted {
+    fn new<F>(inner: Box<GammaMethod>, adjust: F) -> Adjusted
+        where F: FnMut(&transition::ColorSetting) -> transition::ColorSetting + 'static {
+        Adjusted { inner: inner, adjust: Box::new(adjust), emulates_brightness: false }
+    }
+
+    /// Keeps the brightness at or above `floor`
+    pub fn brightness_floor(inner: Box<GammaMethod>, floor: f64) -> Adjusted {
+        Adjusted::new(inner, move |setting| {
+            let mut setting = setting.clone();
+            setting.brightness = setting.brightness.max(floor);
+            setting
+        })
+    }
+
+    /// Approximates the brightness with the gamma, for methods that
+    /// cannot set it
+    pub fn emulated_brightness(inner: Box<GammaMethod>) -> Adjusted {
+        let mut adjusted = Adjusted::new(inner, |setting| {
+            let mut setting = setting.clone();
+            setting.gamma = brightness_as_gamma(setting.gamma, setting.brightness);
+            setting.brightness = 1.0;
+            setting
+        });
+        adjusted.emulates_brightness = true;
+        adjusted
+    }
+
+    /// Keeps every setting within `limit`
+    ///
+    /// This is the outermost wrapper, so that it applies to whatever
+    /// mode or option produced the setting.
+    pub fn limited(inner: Box<GammaMethod>, limit: AdjustmentLimit) -> Adjusted {
+        Adjusted::new(inner, move |setting| {
+            let limited = limit.apply(setting);
+            if limited != *setting {
+                debug!("Limited to {}K, brightness {:.2}", limited.temp, limited.brightness);
+            }
+            limited
+        })
+    }
+}
+
+impl GammaMethod for Adjusted {
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        self.inner.set_option(key, value)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.inner.start()
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        let setting = (self.adjust)(setting);
+        self.inner.set_temperature(&setting)
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        self.inner.restore()
+    }
+
+    fn set_preserve(&mut self, preserve: bool) {
+        self.inner.set_preserve(preserve)
+    }
+
+    fn set_brightness_floors(&mut self, floors: &[(String, f64)]) -> Result<()> {
+        self.inner.set_brightness_floors(floors)
+    }
+
+    fn set_seat_limits(&mut self, limits: &[(String, AdjustmentLimit)]) -> Result<()> {
+        self.inner.set_seat_limits(limits)
+    }
+
+    fn needs_refresh(&mut self) -> bool {
+        self.inner.needs_refresh()
+    }
+
+    fn invalidate(&mut self) {
+        self.inner.invalidate()
+    }
+
+    fn outputs(&self) -> Result<Vec<String>> {
+        self.inner.outputs()
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        self.inner.output_status()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let capabilities = self.inner.capabilities();
+        Capabilities { brightness: capabilities.brightness || self.emulates_brightness, ..capabilities }
+    }
+
+    fn writable_paths(&self) -> Vec<PathBuf> {
+        self.inner.writable_paths()
+    }
+}
+
+/// Shares each setting out between several methods, each getting the
+/// parts it can show
+///
+/// When a method only sets the brightness (like the backlight), the
+/// brightness is left to it and the others only set the color, so that
+/// the screen is not dimmed twice.
+pub struct Dispatcher {
+    pub methods: Vec<Box<GammaMethod>>,
+}
+
+impl Dispatcher {
+    /// The part of `setting` for a method with `capabilities`
+    fn setting_for(&self, capabilities: Capabilities, setting: &transition::ColorSetting) -> transition::ColorSetting {
+        let dedicated_brightness = self.methods.iter()
+            .map(|method| method.capabilities())
+            .any(|c| c.brightness && !c.color);
+        let mut setting = setting.clone();
+        if !capabilities.color {
+            setting.temp = NEUTRAL_TEMP.into();
+            setting.gamma = [1.0, 1.0, 1.0];
+        }
+        if !capabilities.brightness || (capabilities.color && dedicated_brightness) {
+            setting.brightness = 1.0;
+        }
+        setting
+    }
+
+    /// Call `f` on every method, even after one fails, and return the
+    /// first error
+    fn each<F: FnMut(&mut Box<GammaMethod>) -> Result<()>>(&mut self, mut f: F) -> Result<()> {
+        let mut result = Ok(());
+        for method in self.methods.iter_mut() {
+            if let Err(e) = f(method) {
+                if result.is_ok() {
+                    result = Err(e);
+                } else {
+                    warn!("{}", e);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl GammaMethod for Dispatcher {
+    fn set_option(&mut self, key: &str, _value: &str) -> Result<()> {
+        Err(Box::new(RedshiftError::MalformedArgument(
+            format!("option '{}' must be given for one of the methods", key))))
+    }
+
+    fn start(&mut self) -> Result<()> {
+        for method in self.methods.iter_mut() {
+            method.start()?;
+        }
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        let settings: Vec<_> = self.methods.iter()
+            .map(|method| self.setting_for(method.capabilities(), setting))
+            .collect();
+        let mut settings = settings.iter();
+        self.each(|method| method.set_temperature(settings.next().unwrap()))
+    }
+
+    fn restore(&mut self) -> Result<()> {
+        self.each(|method| method.restore())
+    }
+
+    fn set_preserve(&mut self, preserve: bool) {
+        for method in self.methods.iter_mut() {
+            method.set_preserve(preserve);
+        }
+    }
+
+    /// Per-output floors only make sense for the methods setting the
+    /// color, which know the outputs
+    fn set_brightness_floors(&mut self, floors: &[(String, f64)]) -> Result<()> {
+        for method in self.methods.iter_mut().filter(|method| method.capabilities().color) {
+            method.set_brightness_floors(floors)?;
+        }
+        Ok(())
+    }
+
+    fn set_seat_limits(&mut self, limits: &[(String, AdjustmentLimit)]) -> Result<()> {
+        for method in self.methods.iter_mut().filter(|method| method.capabilities().color) {
+            method.set_seat_limits(limits)?;
+        }
+        Ok(())
+    }
+
+    fn needs_refresh(&mut self) -> bool {
+        // Every method is asked, so that each one's state is updated
+        self.methods.iter_mut().fold(false, |refresh, method| method.needs_refresh() || refresh)
+    }
+
+    fn invalidate(&mut self) {
+        for method in self.methods.iter_mut() {
+            method.invalidate();
+        }
+    }
+
+    fn outputs(&self) -> Result<Vec<String>> {
+        let mut outputs = vec![];
+        for method in self.methods.iter() {
+            outputs.extend(method.outputs()?);
+        }
+        Ok(outputs)
+    }
+
+    fn output_status(&self) -> Vec<OutputStatus> {
+        self.methods.iter().flat_map(|method| method.output_status()).collect()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let all: Vec<_> = self.methods.iter().map(|method| method.capabilities()).collect();
+        Capabilities {
+            color: all.iter().any(|c| c.color),
+            brightness: all.iter().any(|c| c.brightness),
+        }
+    }
+
+    fn writable_paths(&self) -> Vec<PathBuf> {
+        self.methods.iter().flat_map(|method| method.writable_paths()).collect()
     }
 }
 
 pub struct DummyMethod;
 impl GammaMethod for DummyMethod {
-    fn restore(&self) -> Result<()> { Ok(()) }
+    fn restore(&mut self) -> Result<()> { Ok(()) }
+
+    fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
+        info!("Temperature: {}", setting.temp);
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        warn!("Using dummy gamma method! Display will not affected by this gamma method.");
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { color: true, brightness: false }
+    }
+}
+
+/// Used with --observe: logs what would be set, and never touches the
+/// display
+pub struct Observer;
+impl GammaMethod for Observer {
+    fn restore(&mut self) -> Result<()> {
+        debug!("Observing: would restore the display");
+        Ok(())
+    }
 
     fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
-        println!("Temperature: {}", setting.temp);
+        debug!("Observing: would set {}K, brightness {:.2}, gamma {:.2}:{:.2}:{:.2}", setting.temp,
+              setting.brightness, setting.gamma[0], setting.gamma[1], setting.gamma[2]);
         Ok(())
     }
 
     fn start(&mut self) -> Result<()> {
-        println!("WARNING: Using dummy gamma method! Display will not affected by this gamma method.");
+        info!("Observing only, the display is left alone (-v shows the settings)");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod golden;
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use colorramp;
+    use transition::{ColorSetting, Kelvin};
+    use super::super::{Result, RedshiftError};
+    use super::{Adjusted, AdjustmentLimit, Capabilities, CrtcRamps, Dispatcher, GammaMethod, OutputStatus, Provider,
+                brightness_as_gamma, init_gamma_method, is_virtual_output, provider_crtcs};
+
+    /// Records the settings it gets
+    struct Recorder {
+        capabilities: Capabilities,
+        set: Rc<RefCell<Vec<ColorSetting>>>,
+    }
+
+    impl GammaMethod for Recorder {
+        fn start(&mut self) -> Result<()> { Ok(()) }
+        fn restore(&mut self) -> Result<()> { Ok(()) }
+
+        fn set_temperature(&mut self, setting: &ColorSetting) -> Result<()> {
+            self.set.borrow_mut().push(setting.clone());
+            Ok(())
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            self.capabilities
+        }
+    }
+
+    #[test]
+    fn dispatcher_shares_out_settings() {
+        let color = Rc::new(RefCell::new(vec![]));
+        let backlight = Rc::new(RefCell::new(vec![]));
+        let mut dispatcher = Dispatcher { methods: vec![
+            Box::new(Recorder { capabilities: Capabilities { color: true, brightness: true }, set: color.clone() }),
+            Box::new(Recorder { capabilities: Capabilities { color: false, brightness: true }, set: backlight.clone() }),
+        ] };
+        let setting = ColorSetting { temp: Kelvin(3500.0), gamma: [0.9, 1.0, 1.0], brightness: 0.7 };
+        dispatcher.set_temperature(&setting).unwrap();
+
+        assert_eq!(color.borrow()[0], ColorSetting { temp: Kelvin(3500.0), gamma: [0.9, 1.0, 1.0], brightness: 1.0 });
+        assert_eq!(backlight.borrow()[0], ColorSetting { temp: Kelvin(6500.0), gamma: [1.0, 1.0, 1.0], brightness: 0.7 });
+    }
+
+    #[test]
+    fn adjusted_changes_settings() {
+        let set = Rc::new(RefCell::new(vec![]));
+        let recorder = Recorder { capabilities: Capabilities { color: true, brightness: false }, set: set.clone() };
+        let floor = Adjusted::brightness_floor(Box::new(recorder), 0.8);
+        let limit = AdjustmentLimit { min_temp: Some(4000), min_brightness: None };
+        let mut adjusted = Adjusted::limited(Box::new(Adjusted::emulated_brightness(Box::new(floor))), limit);
+        assert_eq!(adjusted.capabilities(), Capabilities { color: true, brightness: true });
+
+        adjusted.set_temperature(&ColorSetting { temp: Kelvin(3500.0), gamma: [1.0; 3], brightness: 0.5 }).unwrap();
+        let gamma = brightness_as_gamma([1.0; 3], 0.5);
+        assert_eq!(set.borrow()[0], ColorSetting { temp: Kelvin(4000.0), gamma: gamma, brightness: 1.0 });
+    }
+
+    #[test]
+    fn test_provider_crtcs() {
+        let providers = vec![
+            Provider { name: "modesetting".to_owned(), crtcs: vec![63, 64] },
+            Provider { name: "modesetting".to_owned(), crtcs: vec![] },
+            Provider { name: "NVIDIA-G0".to_owned(), crtcs: vec![90] },
+        ];
+        assert_eq!(provider_crtcs(&providers, "NVIDIA-G0").unwrap(), vec![90]);
+        assert_eq!(provider_crtcs(&providers, "modesetting").unwrap(), vec![63, 64]);
+        assert_eq!(provider_crtcs(&providers, "2").unwrap(), vec![90]);
+        assert!(provider_crtcs(&providers, "1").is_err());
+        assert!(provider_crtcs(&providers, "3").is_err());
+        assert!(provider_crtcs(&providers, "amdgpu").is_err());
+    }
+
+    #[test]
+    fn test_is_virtual_output() {
+        assert!(is_virtual_output("VNC-0"));
+        assert!(is_virtual_output("XWAYLAND3"));
+        assert!(is_virtual_output("screen"));
+        assert!(!is_virtual_output("screen-1"));
+        assert!(!is_virtual_output("DP-1"));
+    }
+
+    #[test]
+    fn test_brightness_as_gamma() {
+        assert_eq!(brightness_as_gamma([1.0, 0.8, 1.2], 1.0), [1.0, 0.8, 1.2]);
+        let gamma = brightness_as_gamma([1.0, 1.0, 1.0], 0.5);
+        assert_eq!(gamma, [0.5, 0.5, 0.5]);
+        // Mid grey, as the ramps compute it
+        assert!((0.5f64.powf(gamma[0].recip()) - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_output_status() {
+        let setting = ColorSetting { temp: Kelvin(3499.6), gamma: [1.0; 3], brightness: 0.8 };
+        let mut status = OutputStatus::new("DP-1", Some(&setting));
+        status.ramp_size = Some(1024);
+        assert_eq!(status.to_string(), "DP-1: 3500K, brightness 0.80, blue light -48%, ramp size 1024");
+        status.overrides = vec!["brightness floor 0.90".to_owned(), "on top of its calibration".to_owned()];
+        assert_eq!(status.to_string(),
+                   "DP-1: 3500K, brightness 0.80, blue light -48%, ramp size 1024 \
+                    (brightness floor 0.90, on top of its calibration)");
+        assert_eq!(OutputStatus::new("HDMI-1", None).to_string(), "HDMI-1: not adjusted");
+    }
+
+    #[test]
+    fn unknown_method_is_an_error() {
+        let e = init_gamma_method(Some("nonexistent"), &[]).err().unwrap();
+        assert_eq!(e.to_string(), RedshiftError::GammaMethodNotFound("nonexistent".to_owned()).to_string());
+    }
+
+    #[test]
+    fn test_crtc_ramps() {
+        let mut cache = colorramp::RampCache::new();
+        let linear: Vec<u16> = (0..4).map(|i| i * 16384).collect();
+        let setting = ColorSetting { temp: Kelvin(3500.0), gamma: [1.0; 3], brightness: 0.5 };
+
+        let mut ramps = CrtcRamps::new((linear.clone(), linear.clone(), linear.clone()));
+        assert!(!ramps.preserves(true));
+        ramps.set_floor(&["DP-1".to_owned()], &[("DP-1".to_owned(), 0.7), ("HDMI-1".to_owned(), 0.9)]);
+        assert_eq!(ramps.floored(&setting).brightness, 0.7);
+        assert_eq!(ramps.overrides(true), vec!["brightness floor 0.70".to_owned()]);
+        let expected = cache.get(&setting, 4).clone();
+        ramps.fill_next(&setting, true, &mut cache);
+        assert_eq!(ramps.next, expected);
+
+        // Calibrated ramps are kept under the setting
+        let dim = vec![0u16, 8192, 16384, 24576];
+        let mut ramps = CrtcRamps::new((dim.clone(), linear.clone(), linear.clone()));
+        assert!(ramps.preserves(true));
+        assert!(!ramps.preserves(false));
+        assert_eq!(ramps.overrides(true), vec!["on top of its calibration".to_owned()]);
+        assert!(ramps.ramps_for(&setting, true, &mut cache).0 != expected.0);
+        assert_eq!(*ramps.ramps_for(&setting, false, &mut cache), expected);
+        assert_eq!(ramps.saved.0, dim);
+    }
+}
@@ -0,0 +1,41 @@
+//! Registration of the gamma methods
+//!
+//! Backends are listed once, with the `gamma_methods!` macro, under
+//! whatever feature flag they need; there is no need for stubs in
+//! the disabled case, as a disabled entry simply is not there.
+
+use super::GammaMethod;
+use super::super::Result;
+
+pub type GammaInit = fn() -> Result<Box<GammaMethod>>;
+
+/// A compiled-in gamma method
+pub struct MethodInfo {
+    pub name: &'static str,
+    pub init: GammaInit,
+
+    /// Tried when no method is requested
+    pub auto: bool,
+
+    /// Talks to an X server, and so only affects X11 clients (through
+    /// XWayland) in a Wayland session
+    pub x11: bool,
+}
+
+/// Declare the gamma methods as the static `METHODS`, in order of
+/// preference for automatic selection:
+///
+/// ```ignore
+/// gamma_methods! {
+///     #[cfg(feature = "randr")]
+///     "randr" => gamma_randr::init { auto: true, x11: true };
+///     "dummy" => init_dummy { auto: false, x11: false };
+/// }
+/// ```
+macro_rules! gamma_methods {
+    ($($(#[$attr:meta])* $name:literal => $init:path { auto: $auto:expr, x11: $x11:expr };)*) => {
+        static METHODS: &'static [registry::MethodInfo] = &[
+            $($(#[$attr])* registry::MethodInfo { name: $name, init: $init, auto: $auto, x11: $x11 },)*
+        ];
+    }
+}
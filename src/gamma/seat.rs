@@ -0,0 +1,156 @@
+//! Opening devices through libseat
+//!
+//! libseat asks logind (or seatd) for the devices of the seat, so that
+//! the drm method works without root or membership of the video group,
+//! and the session manager makes us the DRM master while our session is
+//! active. When the user switches to another VT or session, libseat
+//! asks for the devices to be let go of first, and gives them back on
+//! the way back.
+
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+
+use libc;
+
+use super::Result;
+
+mod ffi {
+    use libc::{c_char, c_int, c_void};
+
+    pub enum Libseat {}
+
+    #[repr(C)]
+    pub struct Listener {
+        pub enable_seat: extern "C" fn(*mut Libseat, *mut c_void),
+        pub disable_seat: extern "C" fn(*mut Libseat, *mut c_void),
+    }
+
+    #[link(name = "seat")]
+    extern "C" {
+        pub fn libseat_open_seat(listener: *const Listener, userdata: *mut c_void) -> *mut Libseat;
+        pub fn libseat_close_seat(seat: *mut Libseat) -> c_int;
+        pub fn libseat_disable_seat(seat: *mut Libseat) -> c_int;
+        pub fn libseat_open_device(seat: *mut Libseat, path: *const c_char, fd: *mut c_int) -> c_int;
+        pub fn libseat_close_device(seat: *mut Libseat, device_id: c_int) -> c_int;
+        pub fn libseat_seat_name(seat: *mut Libseat) -> *const c_char;
+        pub fn libseat_dispatch(seat: *mut Libseat, timeout: c_int) -> c_int;
+    }
+}
+
+/// What the listener was told, kept apart from `Seat` so that its
+/// address does not change
+struct Events {
+    active: Cell<bool>,
+    changed: Cell<bool>,
+}
+
+extern "C" fn enable_seat(_seat: *mut ffi::Libseat, userdata: *mut libc::c_void) {
+    let events = unsafe { &*(userdata as *const Events) };
+    events.active.set(true);
+    events.changed.set(true);
+}
+
+extern "C" fn disable_seat(_seat: *mut ffi::Libseat, userdata: *mut libc::c_void) {
+    let events = unsafe { &*(userdata as *const Events) };
+    events.active.set(false);
+    events.changed.set(true);
+}
+
+static LISTENER: ffi::Listener = ffi::Listener {
+    enable_seat: enable_seat,
+    disable_seat: disable_seat,
+};
+
+/// A device opened on the seat
+pub struct Device {
+    pub id: i32,
+    pub file: File,
+}
+
+/// Whether the session got the seat back or lost it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Enabled,
+    Disabled,
+}
+
+pub struct Seat {
+    seat: *mut ffi::Libseat,
+    events: Box<Events>,
+}
+
+impl Seat {
+    /// Open the seat of our session
+    ///
+    /// Fails unless the session is active, as devices cannot be opened
+    /// otherwise.
+    pub fn open() -> Result<Seat> {
+        let events = Box::new(Events { active: Cell::new(false), changed: Cell::new(false) });
+        let seat = unsafe {
+            ffi::libseat_open_seat(&LISTENER, &*events as *const Events as *mut libc::c_void)
+        };
+        if seat.is_null() {
+            return Err(From::from(format!("could not open the seat: {}", io::Error::last_os_error())))
+        }
+        let seat = Seat { seat: seat, events: events };
+        if unsafe { ffi::libseat_dispatch(seat.seat, 0) } < 0 {
+            return Err(From::from(format!("could not open the seat: {}", io::Error::last_os_error())))
+        }
+        if !seat.events.active.get() {
+            return Err(From::from(format!("the session is not active on {}", seat.name())))
+        }
+        seat.events.changed.set(false);
+        Ok(seat)
+    }
+
+    pub fn name(&self) -> String {
+        unsafe { CStr::from_ptr(ffi::libseat_seat_name(self.seat)) }.to_string_lossy().into_owned()
+    }
+
+    pub fn open_device(&mut self, path: &Path) -> io::Result<Device> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+        let mut fd = -1;
+        let id = unsafe { ffi::libseat_open_device(self.seat, c_path.as_ptr(), &mut fd) };
+        if id < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(Device { id: id, file: unsafe { File::from_raw_fd(fd) } })
+    }
+
+    /// Give the device with `id` back, before closing its file
+    pub fn close_device(&mut self, id: i32) {
+        unsafe { ffi::libseat_close_device(self.seat, id) };
+    }
+
+    /// Handle what the seat sent, without waiting, returning the last
+    /// change of the session if there was one
+    ///
+    /// After `Change::Disabled`, the devices must be let go of with
+    /// `disabled()`.
+    pub fn dispatch(&mut self) -> Result<Option<Change>> {
+        if unsafe { ffi::libseat_dispatch(self.seat, 0) } < 0 {
+            return Err(From::from(format!("lost the connection to the seat: {}", io::Error::last_os_error())))
+        }
+        if !self.events.changed.replace(false) {
+            return Ok(None)
+        }
+        Ok(Some(if self.events.active.get() { Change::Enabled } else { Change::Disabled }))
+    }
+
+    /// Tell the seat we are done with the devices, after it was disabled
+    pub fn disabled(&mut self) {
+        unsafe { ffi::libseat_disable_seat(self.seat) };
+    }
+}
+
+impl Drop for Seat {
+    fn drop(&mut self) {
+        unsafe { ffi::libseat_close_seat(self.seat) };
+    }
+}
@@ -0,0 +1,107 @@
+//! Following which virtual terminal is shown
+//!
+//! The kernel names the VT in the foreground in
+//! /sys/class/tty/tty0/active. Methods drawing on a VT of their own
+//! pause while another one is shown, rather than adjusting (or failing
+//! to adjust) the screen of another session.
+
+use std::fs::File;
+use std::io::Read;
+
+const ACTIVE_PATH: &'static str = "/sys/class/tty/tty0/active";
+
+/// Major device number of the VTs
+#[cfg(feature = "drm")]
+const TTY_MAJOR: u32 = 4;
+
+/// VT numbers go up to 63, higher minors are serial ports
+const MAX_VT: u32 = 63;
+
+fn read(path: &str) -> Option<String> {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut contents)).ok()?;
+    Some(contents)
+}
+
+/// The number of a VT named like "tty2"
+fn parse_vt(name: &str) -> Option<u32> {
+    if name.starts_with("tty") {
+        name["tty".len()..].parse().ok().filter(|&n| n > 0 && n <= MAX_VT)
+    } else {
+        None
+    }
+}
+
+/// The VT of the device number `tty_nr`, if it is one
+#[cfg(feature = "drm")]
+fn vt_of_device(tty_nr: u32) -> Option<u32> {
+    let major = (tty_nr >> 8) & 0xfff;
+    let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00);
+    if major == TTY_MAJOR && minor > 0 && minor <= MAX_VT {
+        Some(minor)
+    } else {
+        None
+    }
+}
+
+/// The number of the VT shown
+pub fn active() -> Option<u32> {
+    parse_vt(read(ACTIVE_PATH)?.trim())
+}
+
+/// The VT our controlling terminal is, if it is one
+#[cfg(feature = "drm")]
+pub fn own() -> Option<u32> {
+    let stat = read("/proc/self/stat")?;
+    // The command name may hold spaces and parentheses, so the fields
+    // are counted from the last ')': state, ppid, pgrp, session, tty_nr
+    let fields = &stat[stat.rfind(')')? + 1..];
+    vt_of_device(fields.split_whitespace().nth(4)?.parse().ok()?)
+}
+
+/// Whether one VT is shown, as last seen
+pub struct Watch {
+    vt: u32,
+    shown: bool,
+}
+
+impl Watch {
+    pub fn new(vt: u32) -> Watch {
+        Watch { vt: vt, shown: active().map_or(true, |active| active == vt) }
+    }
+
+    pub fn shown(&self) -> bool {
+        self.shown
+    }
+
+    /// Whether the VT is shown now, if that changed since the last call
+    pub fn poll(&mut self) -> Option<bool> {
+        let shown = active().map_or(true, |active| active == self.vt);
+        if shown == self.shown {
+            return None
+        }
+        self.shown = shown;
+        Some(shown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vt_numbers() {
+        assert_eq!(parse_vt("tty2"), Some(2));
+        assert_eq!(parse_vt("tty0"), None);
+        assert_eq!(parse_vt("ttyS0"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "drm")]
+    fn test_vt_of_device() {
+        assert_eq!(vt_of_device(0x0403), Some(3));
+        assert_eq!(vt_of_device(0x0440), None);
+        assert_eq!(vt_of_device(0x8801), None);
+        assert_eq!(vt_of_device(0), None);
+    }
+}
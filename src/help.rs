@@ -0,0 +1,209 @@
+//! The --help text grouped by topic, and the man page
+//!
+//! clap 2 lists every option in one alphabetical block. The options in
+//! its help text are regrouped under the topics of `TOPICS`, and the
+//! `EXAMPLES` added, for both --help and the man page generated with
+//! --man, so that the two cannot drift apart.
+
+/// Width of the regrouped help, as clap's when not on a terminal
+const WIDTH: usize = 120;
+
+/// Options by topic, by their long names. Every option must be in one.
+pub const TOPICS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("MODES", &["oneshot", "oneshot-stateful", "oneshot-manual", "reset", "demo", "list-schemes", "write-default-config",
+                "help", "version"]),
+    ("PRINTING", &["print", "at", "sweep", "exit-period"]),
+    ("SCHEDULE", &["location", "temperature", "day-neutral", "jitter", "break-reminder"]),
+    ("COLOR", &["brightness", "emulate-brightness", "gamma", "no-preserve"]),
+    ("FADING", &["no-transition", "no-startup-fade", "no-exit-fade", "instant-start", "smooth"]),
+    ("ADJUSTMENT METHODS", &["method", "observe"]),
+    ("SERVICE", &["system", "log-elevation", "log-period-times"]),
+    ("OUTPUT", &["verbose", "quiet", "no-color"]),
+];
+
+/// Example command lines, with what they do
+pub const EXAMPLES: &'static [(&'static str, &'static str)] = &[
+    ("redshift-rs -l 55.7:12.6 -t 5700:3600",
+     "Run continually at a fixed location, with 5700K at day and 3600K at night"),
+    ("redshift-rs -l place:Copenhagen -o",
+     "Look up a place by name, and set the temperature for now once"),
+    ("redshift-rs -O 4500", "Set 4500K until reset"),
+    ("redshift-rs -x", "Remove the adjustment"),
+    ("redshift-rs --oneshot-stateful", "Set the temperature for now from a timer, fading from the last run"),
+    ("redshift-rs -p --sweep 24h/1h", "Show the settings for the next day, every hour"),
+    ("redshift-rs -m randr,backlight", "Set the color with RandR, and dim the backlight instead of the colors"),
+    ("redshift-rs --demo", "See what the night setting looks like"),
+    ("redshift-rs --observe -v", "Check a config, or run only the hooks, without adjusting the display"),
+    ("redshift-rs -t 6500:3500 install-service", "Start with these settings with every session"),
+    ("redshift-rs import-flux flux.reg > ~/.config/redshift.conf", "Take over the location and temperatures of f.lux"),
+];
+
+/// An option in clap's help text
+struct OptionHelp {
+    /// As in "-b, --brightness <DAY:NIGHT>"
+    flags: String,
+    help: String,
+}
+
+impl OptionHelp {
+    fn long_name(&self) -> &str {
+        let long = self.flags.find("--").map_or("", |i| &self.flags[i+2..]);
+        long.split(|c: char| c == ' ' || c == ',' || c == '<').next().unwrap_or("")
+    }
+}
+
+/// Split clap's help text into what comes before the options, the
+/// options, and what comes after
+fn parse(help: &str) -> (String, Vec<OptionHelp>, String) {
+    let (mut before, mut after) = (String::new(), String::new());
+    let mut options: Vec<OptionHelp> = vec![];
+    let mut in_options = false;
+    for line in help.lines() {
+        if line == "OPTIONS:" {
+            in_options = true;
+            continue
+        }
+        if in_options && line.is_empty() {
+            in_options = false;
+        }
+        let indent = line.len() - line.trim_start().len();
+        if in_options && indent <= 8 && line.trim_start().starts_with('-') {
+            let line = line.trim();
+            let (flags, help) = match line.find("  ") {
+                Some(i) => (&line[..i], line[i..].trim()),
+                None => (line, "")
+            };
+            options.push(OptionHelp { flags: flags.to_owned(), help: help.to_owned() });
+        } else if in_options {
+            if let Some(option) = options.last_mut() {
+                option.help.push(' ');
+                option.help.push_str(line.trim());
+            }
+        } else if options.is_empty() {
+            before.push_str(line);
+            before.push('\n');
+        } else {
+            after.push_str(line);
+            after.push('\n');
+        }
+    }
+    (before, options, after)
+}
+
+/// The options grouped by topic, with those in no topic last
+fn grouped(options: Vec<OptionHelp>) -> Vec<(&'static str, Vec<OptionHelp>)> {
+    let mut rest = options;
+    let mut groups = vec![];
+    for &(topic, names) in TOPICS {
+        let mut group = vec![];
+        for name in names {
+            if let Some(i) = rest.iter().position(|option| option.long_name() == *name) {
+                group.push(rest.remove(i));
+            }
+        }
+        groups.push((topic, group));
+    }
+    if !rest.is_empty() {
+        groups.push(("OTHER OPTIONS", rest));
+    }
+    groups
+}
+
+/// clap's help text, with the options grouped by topic and followed by
+/// the examples
+pub fn regroup(help: &str) -> String {
+    let (before, options, after) = parse(help);
+    if options.is_empty() {
+        return help.to_owned()
+    }
+    let width = options.iter().map(|option| option.flags.len()).max().unwrap_or(0);
+    let mut out = before;
+    for (topic, group) in grouped(options) {
+        out.push_str(&format!("{}:\n", topic));
+        for option in group {
+            let indent = if option.flags.starts_with("--") { "        " } else { "    " };
+            let pad = width + 8 - indent.len() - option.flags.len();
+            out.push_str(&format!("{}{}{}", indent, option.flags, " ".repeat(pad)));
+            out.push_str(&wrap(&option.help, width + 8));
+        }
+        out.push('\n');
+    }
+    out.push_str(after.trim_end());
+    out.push_str("\n\nEXAMPLES:\n");
+    for &(command, description) in EXAMPLES {
+        out.push_str(&format!("    {}\n        {}\n", command, description));
+    }
+    out
+}
+
+/// `text` wrapped to `WIDTH` columns, starting at column `column`,
+/// with the lines after the first indented to it
+fn wrap(text: &str, column: usize) -> String {
+    let mut out = String::new();
+    let mut at = column;
+    for word in text.split_whitespace() {
+        if at > column && at + 1 + word.len() > WIDTH {
+            out.push('\n');
+            out.push_str(&" ".repeat(column));
+            at = column;
+        }
+        if at > column {
+            out.push(' ');
+            at += 1;
+        }
+        out.push_str(word);
+        at += word.len();
+    }
+    out.push('\n');
+    out
+}
+
+/// Escape text for roff
+fn roff(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('-', "\\-")
+}
+
+/// A man page in roff, from clap's help text and the `EXAMPLES`
+pub fn man_page(help: &str, about: &str, usage: &str, version: &str) -> String {
+    let (_, options, _) = parse(help);
+    let mut out = format!(".TH REDSHIFT\\-RS 1 \"\" \"redshift\\-rs {}\"\n", roff(version));
+    out.push_str(".SH NAME\nredshift\\-rs \\- set the color temperature of the display according to the time of day\n");
+    out.push_str(".SH SYNOPSIS\n");
+    for line in usage.lines() {
+        out.push_str(&format!("{}\n.br\n", roff(line.trim())));
+    }
+    out.push_str(".SH DESCRIPTION\n");
+    for paragraph in about.trim().split("\n\n") {
+        out.push_str(&format!("{}\n.PP\n", roff(paragraph.trim())));
+    }
+    out.push_str(".SH OPTIONS\n");
+    for (topic, group) in grouped(options) {
+        out.push_str(&format!(".SS {}\n", roff(topic)));
+        for option in group {
+            out.push_str(&format!(".TP\n.B {}\n{}\n", roff(&option.flags), roff(&option.help)));
+        }
+    }
+    out.push_str(".SH EXAMPLES\n");
+    for &(command, description) in EXAMPLES {
+        out.push_str(&format!(".TP\n.B {}\n{}\n", roff(command), roff(description)));
+    }
+    out.push_str(".SH FILES\n.TP\n.I ~/.config/redshift.conf\nThe config file; see \\-\\-write\\-default\\-config\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grouped, parse};
+
+    #[test]
+    fn test_every_option_has_a_topic() {
+        let mut help = vec![];
+        ::app().write_help(&mut help).unwrap();
+        let (_, options, _) = parse(&String::from_utf8(help).unwrap());
+        assert!(options.len() > 20);
+        for (topic, group) in grouped(options) {
+            assert!(topic != "OTHER OPTIONS", "no topic for {:?}",
+                    group.iter().map(|option| option.long_name().to_owned()).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Pausing while the desktop's night light is inhibited
+//!
+//! Color-critical applications, such as photo editors and calibration
+//! tools, ask KWin to inhibit its night light while they run, and
+//! GNOME has a switch to disable its night light until tomorrow. With
+//! `follow-inhibit=1`, continual mode watches both over D-Bus. It
+//! restores the screen and pauses while either is on, and resumes,
+//! fading in, once both are off.
+//!
+//! The pause is the same as the one USR2 or `ctl enable` start, so a
+//! clearing inhibition also ends a pause started meanwhile by those.
+
+use chan;
+
+use engine::Event;
+use super::Result;
+
+/// Fails if redshift-rs was built without the inhibit feature
+pub fn check() -> Result<()> {
+    dbus_inhibit::check()
+}
+
+/// Send `Enable(false)` when the night light becomes inhibited and
+/// `Enable(true)` when it no longer is, until the session bus goes
+/// away. For `Engine::add_event_source`.
+pub fn watch(events: chan::Sender<Event>) {
+    let mut follower = Follower::new();
+    let result = dbus_inhibit::watch(|inhibited| {
+        if let Some(event) = follower.event(inhibited) {
+            info!("The night light is {}inhibited", if inhibited { "" } else { "no longer " });
+            events.send(event);
+        }
+    });
+    if let Err(e) = result {
+        warn!("Stopped following the night light inhibition: {}", e);
+    }
+    // Not left paused by an inhibition no one can clear any more
+    if let Some(event) = follower.event(false) {
+        events.send(event);
+    }
+}
+
+/// Turns the inhibition, as read, into events for its changes
+struct Follower {
+    inhibited: bool,
+}
+
+impl Follower {
+    fn new() -> Follower {
+        Follower { inhibited: false }
+    }
+
+    /// The event for the night light being `inhibited` now
+    fn event(&mut self, inhibited: bool) -> Option<Event> {
+        if inhibited == self.inhibited {
+            return None
+        }
+        self.inhibited = inhibited;
+        Some(Event::Enable(!inhibited))
+    }
+}
+
+#[cfg(feature = "inhibit")]
+mod dbus_inhibit {
+    use dbus::{BusType, Connection, ConnectionItem, Message};
+    use dbus::arg::Variant;
+
+    use super::super::Result;
+
+    const PROPERTIES: &'static str = "org.freedesktop.DBus.Properties";
+
+    /// The service, object path and interface of each desktop's night
+    /// light, and its property that is true while inhibited
+    const WATCHED: &'static [(&'static str, &'static str, &'static str, &'static str)] = &[
+        ("org.kde.KWin", "/org/kde/KWin/NightLight", "org.kde.KWin.NightLight", "inhibited"),
+        ("org.gnome.SettingsDaemon.Color", "/org/gnome/SettingsDaemon/Color",
+         "org.gnome.SettingsDaemon.Color", "DisabledUntilTomorrow"),
+    ];
+
+    pub fn check() -> Result<()> {
+        Ok(())
+    }
+
+    /// Call `changed` with whether any desktop inhibits its night
+    /// light, once at first and again after each signal that may have
+    /// changed it
+    pub fn watch<F: FnMut(bool)>(mut changed: F) -> Result<()> {
+        let c = Connection::get_private(BusType::Session)?;
+        for &(service, path, _, _) in WATCHED {
+            c.add_match(&format!("type='signal',path='{}',interface='{}',member='PropertiesChanged'",
+                                 path, PROPERTIES))?;
+            // A desktop that quits no longer inhibits anything
+            c.add_match(&format!("type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0='{}'",
+                                 service))?;
+        }
+        changed(inhibited(&c));
+        for item in c.iter(60000) {
+            if let ConnectionItem::Signal(_) = item {
+                changed(inhibited(&c));
+            }
+        }
+        Ok(())
+    }
+
+    fn inhibited(c: &Connection) -> bool {
+        WATCHED.iter().any(|&(service, path, interface, property)| {
+            match get(c, service, path, interface, property) {
+                Ok(inhibited) => inhibited,
+                Err(e) => {
+                    // As for a desktop that is not running
+                    debug!("Could not read {} of {}: {}", property, service, e);
+                    false
+                }
+            }
+        })
+    }
+
+    fn get(c: &Connection, service: &str, path: &str, interface: &str, property: &str) -> Result<bool> {
+        let m = Message::new_method_call(service, path, PROPERTIES, "Get")?
+            .append2(interface, property);
+        let r = c.send_with_reply_and_block(m, 2000)?;
+        r.get1::<Variant<bool>>()
+            .map(|value| value.0)
+            .ok_or_else(|| From::from(format!("{} is not a boolean", property)))
+    }
+}
+
+#[cfg(not(feature = "inhibit"))]
+mod dbus_inhibit {
+    use super::super::Result;
+
+    pub fn check() -> Result<()> {
+        Err(From::from("redshift-rs was built without the inhibit feature"))
+    }
+
+    pub fn watch<F: FnMut(bool)>(_changed: F) -> Result<()> {
+        check()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use engine::Event;
+    use super::Follower;
+
+    #[test]
+    fn test_follower() {
+        let mut follower = Follower::new();
+        assert_eq!(follower.event(false), None);
+        assert_eq!(follower.event(true), Some(Event::Enable(false)));
+        assert_eq!(follower.event(true), None);
+        assert_eq!(follower.event(false), Some(Event::Enable(true)));
+        assert_eq!(follower.event(false), None);
+    }
+}
@@ -0,0 +1,393 @@
+//! Just enough JSON for the control socket
+//!
+//! Requests are small and come one per line, so a `Value` tree is
+//! built for each. Objects keep their keys in order, which keeps the
+//! responses in the order they are documented in.
+
+use std::char;
+use std::fmt;
+
+use super::{Result, RedshiftError};
+
+/// Deepest nesting accepted, so that a client cannot make the parser
+/// recurse without end
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+
+    /// An object of `members`
+    pub fn object(members: Vec<(&str, Value)>) -> Value {
+        Value::Object(members.into_iter().map(|(key, value)| (key.to_owned(), value)).collect())
+    }
+
+    /// The member `key` of an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref members) => members.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Bool(b) => Some(b),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value {
+        Value::String(s.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Compact, on one line
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            // Whole numbers print without a fraction, and JSON has no
+            // NaN or infinity
+            Value::Number(n) if !n.is_finite() => write!(f, "null"),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(ref s) => write_string(f, s),
+            Value::Array(ref values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(ref members) => {
+                write!(f, "{{")?;
+                for (i, &(ref key, ref value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Parse a JSON document
+pub fn parse(input: &str) -> Result<Value> {
+    let mut parser = Parser { input: input.as_bytes(), pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos < parser.input.len() {
+        return parser.fail("trailing characters")
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn fail<T>(&self, msg: &str) -> Result<T> {
+        Err(Box::new(RedshiftError::MalformedArgument(format!("JSON: {} at offset {}", msg, self.pos))))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len() && b" \t\r\n".contains(&self.input[self.pos]) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return self.fail(&format!("expected '{}'", byte as char))
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value> {
+        if self.input[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            self.fail("unknown literal")
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return self.fail("nested too deep")
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(values))
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(values))
+                        }
+                        _ => return self.fail("expected ',' or ']'")
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(members))
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() != Some(b'"') {
+                        return self.fail("expected a key")
+                    }
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(members))
+                        }
+                        _ => return self.fail("expected ',' or '}'")
+                    }
+                }
+            }
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => self.fail("unexpected character"),
+            None => self.fail("unexpected end"),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        while self.peek().map_or(false, |b| b"+-.eE0123456789".contains(&b)) {
+            self.pos += 1;
+        }
+        // The input is a str, and these are all ASCII
+        let text = ::std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+        let digits = text.trim_start_matches('-');
+        let leading_zero = digits.len() > 1 && digits.starts_with('0') &&
+            !digits[1..].starts_with(|c: char| c == '.' || c == 'e' || c == 'E');
+        if digits.starts_with('.') || leading_zero {
+            return self.fail("malformed number")
+        }
+        match text.parse::<f64>() {
+            Ok(n) if n.is_finite() => Ok(Value::Number(n)),
+            _ => self.fail("malformed number")
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let hex = self.input.get(self.pos..self.pos + 4)
+            .and_then(|hex| ::std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(code) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            None => self.fail("malformed \\u escape")
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        // At the opening quote
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while self.peek().map_or(false, |b| b != b'"' && b != b'\\' && b >= 0x20) {
+                self.pos += 1;
+            }
+            // Split at ASCII bytes only, so still UTF-8
+            out.push_str(::std::str::from_utf8(&self.input[start..self.pos]).unwrap());
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out)
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = self.peek();
+                    self.pos += 1;
+                    match escape {
+                        Some(b'"') => out.push('"'),
+                        Some(b'\\') => out.push('\\'),
+                        Some(b'/') => out.push('/'),
+                        Some(b'b') => out.push('\u{8}'),
+                        Some(b'f') => out.push('\u{c}'),
+                        Some(b'n') => out.push('\n'),
+                        Some(b'r') => out.push('\r'),
+                        Some(b't') => out.push('\t'),
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            // A surrogate pair, for characters outside
+                            // the basic plane
+                            if code >= 0xd800 && code < 0xdc00 && self.input[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if low < 0xdc00 || low >= 0xe000 {
+                                    return self.fail("unpaired surrogate")
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => out.push(c),
+                                None => return self.fail("unpaired surrogate")
+                            }
+                        }
+                        _ => return self.fail("unknown escape")
+                    }
+                }
+                Some(_) => return self.fail("control character in string"),
+                None => return self.fail("unterminated string")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Value};
+
+    #[test]
+    fn test_parse() {
+        let value = parse(r#" {"jsonrpc": "2.0", "id": 7, "method": "set_offsets",
+                              "params": {"temp": -500, "brightness": -0.1e0, "note": "a\"b\u00e6\ud83d\ude00"},
+                              "list": [true, false, null, []]} "#).unwrap();
+        assert_eq!(value.get("id"), Some(&Value::Number(7.0)));
+        assert_eq!(value.get("method").and_then(Value::as_str), Some("set_offsets"));
+        let params = value.get("params").unwrap();
+        assert_eq!(params.get("temp").and_then(Value::as_f64), Some(-500.0));
+        assert_eq!(params.get("brightness").and_then(Value::as_f64), Some(-0.1));
+        assert_eq!(params.get("note").and_then(Value::as_str), Some("a\"bæ\u{1f600}"));
+        assert_eq!(value.get("list"), Some(&Value::Array(vec![Value::Bool(true), Value::Bool(false), Value::Null,
+                                                              Value::Array(vec![])])));
+        assert_eq!(value.get("missing"), None);
+
+        for bad in &["", "{", "[1,]", "{\"a\" 1}", "01", ".5", "\"\\x\"", "\"\\ud800\"", "nul", "1 2",
+                     "\"a\nb\"", "1e999"] {
+            assert!(parse(bad).is_err(), "{:?}", bad);
+        }
+        assert!(parse(&"[".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let value = Value::object(vec![
+            ("temp", 3500.into()),
+            ("brightness", 0.85.into()),
+            ("period", "night".into()),
+            ("error", Value::from(None::<String>)),
+            ("text", "tab\t\"quote\" \u{1}".into()),
+            ("list", Value::Array(vec![true.into(), ::std::f64::NAN.into()])),
+        ]);
+        let text = value.to_string();
+        assert_eq!(text, r#"{"temp":3500,"brightness":0.85,"period":"night","error":null,"text":"tab\t\"quote\" \u0001","list":[true,null]}"#);
+        assert_eq!(parse(&text).unwrap().get("text"), value.get("text"));
+    }
+}
@@ -0,0 +1,119 @@
+//!
+//! # Redshift in Rust
+//!
+//! The color adjustment, location, configuration and parsing code
+//! behind the redshift-rs binary, split out so that it can be reused
+//! and fuzzed.
+//!
+
+extern crate time;
+extern crate atty;
+extern crate libc;
+#[macro_use] extern crate chan;
+
+// Reading the config file
+#[cfg(feature = "config")] #[macro_use] extern crate lazy_static;
+#[cfg(feature = "config")] extern crate ini;
+
+// Optional features for gamma method providers
+#[cfg(any(feature = "randr", feature = "vidmode"))] extern crate xcb;
+#[cfg(feature = "randr-x11rb")] extern crate x11rb;
+
+// Optional features for location providers, notifications and
+// following the desktop's night light
+#[cfg(any(feature = "geoclue2", feature = "notify", feature = "inhibit"))] extern crate dbus;
+
+use std::fmt;
+use std::result;
+use std::error::Error;
+
+#[macro_use]
+pub mod output;
+pub mod transition;
+pub mod clock;
+pub mod engine;
+pub mod json;
+pub mod control;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "config")]
+pub mod policy;
+pub mod colorramp;
+pub mod location;
+pub mod solar;
+pub mod gamma;
+pub mod settings;
+pub mod privilege;
+pub mod sandbox;
+pub mod service;
+pub mod watchdog;
+pub mod theme;
+pub mod exceptions;
+pub mod notify;
+pub mod inhibit;
+pub mod state;
+pub mod datalog;
+pub mod wizard;
+pub mod flux;
+
+pub type Result<T> = result::Result<T, Box<Error>>;
+
+// Constants
+pub const NEUTRAL_TEMP:        i32 = 6500;
+pub const DEFAULT_DAY_TEMP:    i32 = 5500;
+pub const DEFAULT_NIGHT_TEMP:  i32 = 3500;
+pub const DEFAULT_BRIGHTNESS:  f64 = 1.0;
+pub const DEFAULT_GAMMA:       f64 = 1.0;
+
+// The bounds live with the parsers that check them
+pub use settings::{MIN_TEMP, MAX_TEMP, MIN_GAMMA, MAX_GAMMA, MIN_BRIGHTNESS, MAX_BRIGHTNESS};
+
+// Error codes returned
+#[derive(Debug, PartialEq, Eq)]
+pub enum RedshiftError {
+    MalformedArgument(String),
+    MalformedConfig(String),
+    GammaMethodNotFound(String),
+    NoGammaMethod(String),
+    NoAdjustableOutputs(String),
+    OutputNotFound(String),
+    PolicyViolation(String),
+    MissingExtension(String),
+    PrivilegeDrop(String),
+    Sandbox(String),
+}
+
+impl fmt::Display for RedshiftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RedshiftError::*;
+        match *self {
+            MalformedArgument(ref msg) =>
+                write!(f, "malformed argument: {}", msg),
+            MalformedConfig(ref msg) =>
+                write!(f, "malformed configuration: {}", msg),
+            GammaMethodNotFound(ref method_name) =>
+                write!(f, "gamma method '{}' not found (available: {})",
+                       method_name, gamma::method_names().join(", ")),
+            NoGammaMethod(ref reason) =>
+                write!(f, "no usable gamma method: {}", reason),
+            NoAdjustableOutputs(ref outputs) =>
+                write!(f, "none of the outputs support gamma adjustment ({})", outputs),
+            OutputNotFound(ref output) =>
+                write!(f, "output '{}' not found", output),
+            PolicyViolation(ref msg) =>
+                write!(f, "not allowed by the system policy: {}", msg),
+            MissingExtension(ref name) =>
+                write!(f, "the X server does not support the {} extension", name),
+            PrivilegeDrop(ref msg) =>
+                write!(f, "could not drop privileges: {}", msg),
+            Sandbox(ref msg) =>
+                write!(f, "could not set up the sandbox: {}", msg),
+        }
+    }
+}
+
+impl Error for RedshiftError {
+    fn description(&self) -> &str {
+        "redshift error"
+    }
+}
@@ -0,0 +1,149 @@
+//! Looking up coordinates by place name
+//!
+//! `-l place:NAME` asks a Nominatim compatible search endpoint for
+//! the coordinates of NAME, by running curl. Results are kept in
+//! `$XDG_CACHE_HOME/redshift-rs/places` (by default
+//! `~/.cache/redshift-rs/places`), so the lookup only goes over the
+//! network once per place.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::{Location, malformed};
+use super::super::Result;
+
+/// The endpoint used unless `geocoding-url` is set in the config file
+pub const DEFAULT_URL: &'static str = "https://nominatim.openstreetmap.org/search";
+
+/// Seconds to wait for the endpoint
+const TIMEOUT: &'static str = "10";
+
+/// The coordinates of `place`, from the cache or from the endpoint at
+/// `url`
+pub fn resolve(place: &str, url: &str) -> Result<Location> {
+    let key = place.trim().to_lowercase();
+    if key.is_empty() {
+        return malformed(format!("location: missing place name after place:"))
+    }
+
+    if let Some(location) = cached(&key) {
+        debug!("Found {} in the geocoding cache", place);
+        return Ok(location)
+    }
+
+    let location = lookup(place.trim(), url)?;
+    verbose!("Found {} at {:.4}, {:.4}", place, location.lat, location.lon);
+    if let Err(e) = store(&key, &location) {
+        warn!("Could not cache the location of {}: {}", place, e);
+    }
+    Ok(location)
+}
+
+fn lookup(place: &str, url: &str) -> Result<Location> {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let url = format!("{}{}format=json&limit=1&q={}", url, separator, percent_encode(place));
+
+    let mut cmd = Command::new("curl");
+    cmd.args(&["--silent", "--show-error", "--fail", "--location", "--max-time", TIMEOUT])
+        .args(&["--user-agent", concat!("redshift-rs/", env!("CARGO_PKG_VERSION"))])
+        .arg(&url);
+    debug!("Running {:?}", cmd);
+    let output = cmd.output()
+        .map_err(|e| format!("could not run curl to look up {}: {}", place, e))?;
+    if !output.status.success() {
+        return Err(From::from(format!("looking up {} failed: {}", place,
+                                      String::from_utf8_lossy(&output.stderr).trim())))
+    }
+
+    let body = String::from_utf8_lossy(&output.stdout);
+    match (json_number(&body, "lat"), json_number(&body, "lon")) {
+        (Some(lat), Some(lon)) if lat >= -90.0 && lat <= 90.0 && lon >= -180.0 && lon <= 180.0 =>
+            Ok(Location::new(lat, lon)),
+        _ => malformed(format!("location: no place found for {}", place))
+    }
+}
+
+/// The first value of `key` in a JSON document, given either as a
+/// number or as a string holding one, as Nominatim does
+///
+/// This is not a JSON parser, but the search results are flat enough
+/// for it.
+fn json_number(body: &str, key: &str) -> Option<f64> {
+    let quoted = format!("\"{}\"", key);
+    let start = body.find(&quoted)? + quoted.len();
+    let rest = body[start..].trim_start();
+    if !rest.starts_with(':') {
+        return None
+    }
+    let rest = rest[1..].trim_start();
+    let (rest, end) = if rest.starts_with('"') {
+        (&rest[1..], '"')
+    } else {
+        (rest, ',')
+    };
+    let value = rest.split(|c| c == end || c == '}' || c == ']').next()?;
+    value.trim().parse().ok()
+}
+
+fn percent_encode(input: &str) -> String {
+    let mut encoded = String::new();
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' =>
+                encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    encoded
+}
+
+fn cache_path() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|mut home| { home.push(".cache"); home }))
+        .map(|mut path| { path.push("redshift-rs"); path.push("places"); path })
+}
+
+/// The cache is a file of `LAT<TAB>LON<TAB>PLACE` lines
+fn cached(key: &str) -> Option<Location> {
+    let mut contents = String::new();
+    File::open(cache_path()?).ok()?.read_to_string(&mut contents).ok()?;
+    contents.lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(lat), Some(lon), Some(place)) if place == key =>
+                    Some(Location::new(lat.parse().ok()?, lon.parse().ok()?)),
+                _ => None
+            }
+        })
+        .last()
+}
+
+fn store(key: &str, location: &Location) -> Result<()> {
+    let path = cache_path().ok_or("could not find the home directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(f, "{}\t{}\t{}", location.lat, location.lon, key)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_number, percent_encode};
+
+    #[test]
+    fn test_search_results() {
+        let body = r#"[{"place_id":1,"licence":"ODbL","lat":"55.6867243","lon":"12.5700724","display_name":"København, Danmark"}]"#;
+        assert_eq!(json_number(body, "lat"), Some(55.6867243));
+        assert_eq!(json_number(body, "lon"), Some(12.5700724));
+        assert_eq!(json_number("[{\"lat\": -33.9, \"lon\": 18.4}]", "lon"), Some(18.4));
+        assert_eq!(json_number("[]", "lat"), None);
+        assert_eq!(percent_encode("Copenhagen, DK"), "Copenhagen%2C%20DK");
+    }
+}
@@ -5,12 +5,19 @@
 
 #[cfg(feature = "geoclue2")]
 mod geoclue2;
+pub mod geocode;
+pub mod timezone;
 
 use std::str::FromStr;
 use super::{Result, RedshiftError};
 use std::error::Error;
 use std::fmt;
 
+/// The names of the supported location providers
+pub fn provider_names() -> Vec<&'static str> {
+    vec!["manual", "timezone"]
+}
+
 /// Location by latitude and longitude
 pub struct Location {
     pub lat: f64,
@@ -34,27 +41,145 @@ impl Location {
 }
 
 
+/// Prefix of locations given by name, as in "place:Copenhagen, DK"
+pub const PLACE_PREFIX: &'static str = "place:";
+
+#[inline]
+fn malformed<T>(msg: String) -> Result<T> {
+    Err(Box::new(RedshiftError::MalformedArgument(msg)))
+}
+
+/// Parse a single coordinate
+///
+/// Either a decimal number like "55.7", or degrees with optional
+/// minutes and seconds like "55°42'" or "55°42′30″". Both may be
+/// followed by a hemisphere letter (`positive` or `negative`), like
+/// "55.7N" or "12°34'W", in which case the number itself must not be
+/// negative.
+fn parse_coordinate(input: &str, positive: char, negative: char) -> Option<f64> {
+    let input = input.trim();
+    let (input, sign) = match input.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some(c) if c == positive => (&input[..input.len()-1], Some(1.0)),
+        Some(c) if c == negative => (&input[..input.len()-1], Some(-1.0)),
+        _ => (input, None)
+    };
+    let input = input.trim_end();
+
+    let value = if input.contains(|c| "°'′\"″".contains(c)) {
+        let (degrees, rest) = split_unit(input, &['°'])?;
+        let (minutes, rest) = split_unit(rest, &['\'', '′'])?;
+        let (seconds, rest) = split_unit(rest, &['"', '″'])?;
+        if !rest.is_empty() || degrees.is_none() {
+            return None
+        }
+        let degrees: f64 = degrees?;
+        let minutes = minutes.unwrap_or(0.0);
+        let seconds = seconds.unwrap_or(0.0);
+        if !(minutes >= 0.0 && minutes < 60.0 && seconds >= 0.0 && seconds < 60.0) {
+            return None
+        }
+        let magnitude = degrees.abs() + minutes / 60.0 + seconds / 3600.0;
+        if degrees.is_sign_negative() { -magnitude } else { magnitude }
+    } else {
+        input.parse().ok()?
+    };
+
+    match sign {
+        Some(_) if value.is_sign_negative() => None,
+        Some(sign) => Some(sign * value),
+        None => Some(value)
+    }
+}
+
+/// Split off a number followed by one of the `units` from the start
+/// of `input`, if there is one
+fn split_unit<'a>(input: &'a str, units: &[char]) -> Option<(Option<f64>, &'a str)> {
+    match input.find(units) {
+        Some(i) => {
+            let unit_len = input[i..].chars().next().unwrap().len_utf8();
+            let number = input[..i].trim().parse().ok()?;
+            Some((Some(number), input[i+unit_len..].trim_start()))
+        }
+        None => Some((None, input))
+    }
+}
+
+/// Parse a latitude, in any of the forms accepted by `Location`, and
+/// check that it lies between -90 and 90
+pub fn parse_latitude(input: &str) -> Result<f64> {
+    match parse_coordinate(input, 'N', 'S') {
+        Some(lat) if lat >= -90.0 && lat <= 90.0 => Ok(lat),
+        Some(lat) => malformed(format!("latitude must be between -90 and 90 (was {})", lat)),
+        None => malformed(format!("latitude: {}", input))
+    }
+}
+
+/// Parse a longitude, in any of the forms accepted by `Location`, and
+/// check that it lies between -180 and 180
+pub fn parse_longitude(input: &str) -> Result<f64> {
+    match parse_coordinate(input, 'E', 'W') {
+        Some(lon) if lon >= -180.0 && lon <= 180.0 => Ok(lon),
+        Some(lon) => malformed(format!("longitude must be between -180 and 180 (was {})", lon)),
+        None => malformed(format!("longitude: {}", input))
+    }
+}
+
+/// Parses "LAT:LON", where each coordinate is either decimal or in
+/// degrees, minutes and seconds, optionally with a hemisphere letter:
+/// "55.7:12.6", "55.7N:12.6E" and "55°42'N:12°34'E" are the same
+/// place.
 impl FromStr for Location {
     type Err = Box<Error>;
 
     fn from_str(s: &str) -> Result<Location> {
-        #[inline]
-        fn m<T>(msg: String) -> Result<T> {
-            Err(Box::new(RedshiftError::MalformedArgument(msg)))
+        if s.starts_with(PLACE_PREFIX) {
+            return malformed(format!("location: {} needs to be looked up with geocode::resolve()", s))
         }
-
         let mut parts = s.split(':');
 
         let lat = parts.next()
-            .map_or(m(format!("location: {}", s)),
-                    |l| l.parse().or(m(format!("location: {} (of {})", l, s))))?;
+            .map_or(malformed(format!("location: {}", s)), parse_latitude)?;
 
         let lon = parts.next()
-            .map_or(m(format!("location: {}", s)),
-                    |l| l.parse::<f64>().or(m(format!("location: {} (of {})", l, s))))?;
+            .map_or(malformed(format!("location: {}", s)), parse_longitude)?;
 
         parts.next()
-            .map_or(Ok(Location::new(lat, lon)),
-                    |trailing| m(format!("location: trailing {} (of {})", trailing, s)))
+            .map_or(Ok(()),
+                    |trailing| malformed(format!("location: trailing {} (of {})", trailing, s)))?;
+
+        Ok(Location::new(lat, lon))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<(f64, f64)> {
+        s.parse::<Location>().ok().map(|l| (l.lat, l.lon))
+    }
+
+    fn close(a: Option<(f64, f64)>, b: (f64, f64)) -> bool {
+        a.map_or(false, |a| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9)
+    }
+
+    #[test]
+    fn test_location_formats() {
+        assert!(close(parse("55.7:12.6"), (55.7, 12.6)));
+        assert!(close(parse("-33.9:-70.7"), (-33.9, -70.7)));
+        assert!(close(parse("55.7N:12.6E"), (55.7, 12.6)));
+        assert!(close(parse("33.9s:70.7w"), (-33.9, -70.7)));
+        assert!(close(parse("55°42'N:12°34'E"), (55.7, 12.0 + 34.0 / 60.0)));
+        assert!(close(parse("55° 42′ 36″ N:12°W"), (55.71, -12.0)));
+        assert!(close(parse("-33°54':18°25.5'"), (-33.9, 18.425)));
+        assert!(close(parse("55°42'36\":12°"), (55.71, 12.0)));
+    }
+
+    #[test]
+    fn test_location_rejects() {
+        for input in &["", "55.7", "55.7:12.6:1", "91:0", "0:181", "55.7E:12.6", "-55.7N:12.6",
+                       "55°61'N:12E", "42':12", "55°42'30:12", "55°42'°:12", "N:E", "NaN:0"] {
+            assert!(parse(input).is_none(), "{}", input);
+        }
     }
 }
@@ -0,0 +1,105 @@
+//! Estimating the longitude from the system timezone
+//!
+//! When travelling, the timezone is often updated automatically even
+//! where no location service is available. The standard (non-DST) UTC
+//! offset gives a rough longitude, 15° per hour, which is enough to get
+//! sunrise and sunset within the hour or so that timezones are off by
+//! anyway. The latitude cannot be guessed this way and is configured.
+//!
+//! `/etc/localtime` is watched, so that a change of timezone moves the
+//! location without a restart.
+
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use libc;
+use time;
+
+use super::Location;
+
+pub const LOCALTIME: &'static str = "/etc/localtime";
+
+extern "C" {
+    fn tzset();
+}
+
+pub struct Timezone {
+    lat: f64,
+    /// The link target and modification time of /etc/localtime when
+    /// the location was last estimated
+    seen: (Option<PathBuf>, Option<SystemTime>),
+}
+
+impl Timezone {
+
+    pub fn new(lat: f64) -> Timezone {
+        Timezone { lat: lat, seen: localtime_stamp() }
+    }
+
+    /// The location estimated from the current timezone
+    pub fn location(&self) -> Location {
+        Location::new(self.lat, offset_longitude(standard_offset()))
+    }
+
+    /// A new location, if the timezone changed since the last call
+    pub fn changed(&mut self) -> Option<Location> {
+        let stamp = localtime_stamp();
+        if stamp == self.seen {
+            return None
+        }
+        self.seen = stamp;
+        Some(self.location())
+    }
+}
+
+fn localtime_stamp() -> (Option<PathBuf>, Option<SystemTime>) {
+    let path = Path::new(LOCALTIME);
+    (fs::read_link(path).ok(),
+     fs::metadata(path).and_then(|m| m.modified()).ok())
+}
+
+/// The UTC offset in seconds outside daylight saving time, taken as the
+/// smaller offset of January and July this year
+fn standard_offset() -> i64 {
+    // Make libc read /etc/localtime again if it has changed
+    unsafe { tzset() };
+    let year = time::now_utc().tm_year;
+    [0, 6].iter()
+        .map(|&month| {
+            let tm = time::Tm { tm_year: year, tm_mon: month, tm_mday: 1, ..time::empty_tm() };
+            utc_offset(tm.to_timespec().sec)
+        })
+        .min()
+        .unwrap()
+}
+
+fn utc_offset(t: i64) -> i64 {
+    let t = t as libc::time_t;
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        if libc::localtime_r(&t, &mut tm).is_null() {
+            return 0
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+fn offset_longitude(offset: i64) -> f64 {
+    let lon = offset as f64 / 3600.0 * 15.0;
+    lon.max(-180.0).min(180.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::offset_longitude;
+
+    #[test]
+    fn test_offset_longitude() {
+        assert_eq!(offset_longitude(3600), 15.0);
+        assert_eq!(offset_longitude(-5 * 3600), -75.0);
+        assert_eq!(offset_longitude(5 * 3600 + 1800), 82.5);
+        assert_eq!(offset_longitude(14 * 3600), 180.0);
+    }
+}
@@ -9,32 +9,40 @@ extern crate time;
 #[macro_use]
 extern crate chan;
 extern crate chan_signal;
-#[macro_use] extern crate lazy_static;
-extern crate ini;
-
 extern crate clap;
-
-// Optional features for gamma method providers
-#[cfg(feature = "randr")] extern crate xcb;
-
-// Optional features for location providers
-#[cfg(feature = "geoclue2")] extern crate dbus;
+#[macro_use]
+extern crate redshift_rs;
 
 use std::thread;
+use std::fs;
 use std::fmt;
 use std::result;
-use std::error::Error;
 
-use clap::{App, AppSettings, Arg};
-
-use transition::{TransitionScheme, ColorSetting, Period};
-use location::Location;
-
-mod transition;
-mod colorramp;
-mod location;
-mod solar;
-mod gamma;
+use std::path::{Path, PathBuf};
+
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+
+use redshift_rs::{Result, RedshiftError, NEUTRAL_TEMP, DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP,
+                  DEFAULT_BRIGHTNESS, DEFAULT_GAMMA};
+use redshift_rs::{clock, colorramp, control, datalog, engine, exceptions, flux, gamma, inhibit, json, location, output, privilege, sandbox, service, settings, solar, state, notify, theme, transition, watchdog, wizard};
+use redshift_rs::settings::{parse_break_interval, parse_brightness, parse_gamma, parse_manual_temperature,
+                            parse_method_option, parse_sweep, parse_temperature, parse_time};
+#[cfg(feature = "config")]
+use redshift_rs::{config, policy};
+#[cfg(feature = "config")]
+use redshift_rs::settings::{is_config_key, parse_brightness_floors, parse_brightness_value, parse_keyframe, parse_quantize_temp,
+                            parse_temperature_expr, parse_time_format, parse_verbose_interval, seat_section};
+use redshift_rs::settings::{MIN_TEMP, MAX_TEMP, MIN_BRIGHTNESS, MAX_BRIGHTNESS};
+use redshift_rs::transition::{TransitionScheme, ColorSetting, Period};
+use redshift_rs::location::Location;
+use redshift_rs::clock::systemtime_get_time;
+
+mod help;
+
+/// Constants generated by build.rs
+mod build_info {
+    include!(concat!(env!("OUT_DIR"), "/build_info.rs"));
+}
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -45,51 +53,37 @@ A Rust clone of the original Redshift written in C by Jon Lund Steffensen.";
 
 const USAGE: &'static str = "\
     redshift-rs [OPTIONS]
+    redshift-rs [OPTIONS] install-service [--autostart]
+    redshift-rs (enable | disable)
+    redshift-rs ctl METHOD [PARAMS]
+    redshift-rs neutral SECONDS
     redshift-rs (-h | --help)
     redshift-rs (-V | --version)";
 
-pub type Result<T> = result::Result<T, Box<Error>>;
+// Exit codes for --print --exit-period
+const EXIT_DAY:            i32 = 10;
+const EXIT_NIGHT:          i32 = 11;
+const EXIT_TRANSITION:     i32 = 12;
 
-// Constants
-const NEUTRAL_TEMP:        i32 = 6500;
-const DEFAULT_DAY_TEMP:    i32 = 5500;
-const DEFAULT_NIGHT_TEMP:  i32 = 3500;
-const MIN_TEMP:            i32 = 1000;
-const MAX_TEMP:            i32 = 25000;
-const DEFAULT_BRIGHTNESS:  f64 = 1.0;
-const DEFAULT_GAMMA:       f64 = 1.0;
-const MIN_GAMMA:           f64 = 0.1;
-const MAX_GAMMA:           f64 = 10.0;
-const MIN_BRIGHTNESS:      f64 = 0.1;
-const MAX_BRIGHTNESS:      f64 = 1.0;
+/// Configuration file read in --system mode
+#[cfg(feature = "config")]
+const SYSTEM_CONFIG_PATH: &'static str = "/etc/redshift-rs/config";
 
+/// User to switch to in --system mode, unless configured with user=
+///
+/// A dedicated system user rather than nobody, as it is handed the
+/// state directory
+const DEFAULT_SYSTEM_USER: &'static str = "redshift-rs";
 
-// Error codes returned
-#[derive(Debug, PartialEq, Eq)]
-pub enum RedshiftError {
-    MalformedArgument(String),
-    MalformedConfig(String),
-    GammaMethodNotFound(String),
-}
+/// The method selecting one automatically like no method does, but
+/// trying the one that started last in the same session type first
+const AUTO_METHOD: &'static str = "auto";
 
-impl fmt::Display for RedshiftError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use RedshiftError::*;
-        match *self {
-            MalformedArgument(ref msg) =>
-                write!(f, "malformed argument: {}", msg),
-            MalformedConfig(ref msg) =>
-                write!(f, "malformed configuration: {}", msg),
-            GammaMethodNotFound(ref method_name) =>
-                write!(f, "gamma method '{}' not found", method_name),
-        }
-    }
-}
 
-impl Error for RedshiftError {
-    fn description(&self) -> &str {
-        "redshift error"
-    }
+/// Turn one of the parse_* functions below into a clap validator, so
+/// that malformed values are reported as usage errors
+fn validate<T: 'static>(parse: fn(&str) -> Result<T>) -> impl Fn(String) -> result::Result<(), String> {
+    move |input: String| parse(&input).map(|_| ()).map_err(|e| e.to_string())
 }
 
 fn app<'app>() -> App<'app, 'app> {
@@ -104,36 +98,130 @@ fn app<'app>() -> App<'app, 'app> {
         .arg(arg("brightness")
              .short("b")
              .value_name("DAY:NIGHT")
-             .help("Screen brightness to apply (between 0.1 and 1.0)"))
+             .validator(validate(parse_brightness))
+             .help("Screen brightness to apply (between 0.1 and 1.0), day/night or one for both"))
+        .arg(arg("emulate-brightness")
+             .help("Approximate the brightness with the gamma, with methods that cannot set it"))
+        .arg(arg("observe").conflicts_with("method")
+             .help("Run as usual, but only log the settings instead of setting them, leaving the display alone"))
         .arg(arg("method")
              .short("m")
-             .value_name("METHOD")
-             .help("Method to use to set color temperature"))
+             .value_name("METHOD[:OPTIONS],...")
+             .help("Method to use to set color temperature, with options as KEY=VALUE pairs separated by colons. Several methods separated by commas are used together. auto chooses one, trying the one chosen last in the same session type first"))
         .arg(arg("location")
              .short("l")
              .value_name("LAT:LON")
-             .help("Your current location"))
+             .validator(validate(|input| if input.starts_with(location::PLACE_PREFIX) {
+                 Ok(())
+             } else {
+                 input.parse::<Location>().map(|_| ())
+             }))
+             .help("Your current location, or place:NAME to look up its coordinates"))
         .arg(arg("temperature")
              .short("t")
              .value_name("DAY:NIGHT")
-             .help("Set day/night color temperatures"))
+             .allow_hyphen_values(true)
+             .validator(validate(parse_temperature))
+             .help("Set day/night color temperatures, or one for both; +N or -N for an offset from the defaults"))
         .arg(arg("gamma")
              .short("g")
              .value_name("R:G:B")
+             .validator(validate(parse_gamma))
              .help("Additional gamma correction to apply"))
-        .arg(arg("no-transition").short("r").help("Disable temperature transitions"))
+        .arg(arg("no-preserve").short("P")
+             .help("Reset existing gamma ramps before applying new settings"))
+        .arg(arg("no-transition").short("r").help("Disable fading in on start and out on exit"))
+        .arg(arg("no-startup-fade").help("Apply the current setting right away on start"))
+        .arg(arg("no-exit-fade").help("Restore the screen right away on exit"))
+        .arg(arg("smooth").help("Update more often during transitions"))
+        .arg(arg("log-elevation")
+             .value_name("FILE")
+             .help("Append elevation, period and color setting to FILE as CSV on every update"))
+        .arg(arg("log-period-times")
+             .value_name("FILE")
+             .help("Append the minutes spent in day, transition and night to FILE as CSV, once a day"))
+        .arg(arg("instant-start")
+             .help("Skip the startup fade if the screen is likely still adjusted from a previous run"))
+        .arg(arg("jitter").help("Vary the color temperature slightly from day to day"))
+        .arg(arg("break-reminder")
+             .value_name("MINUTES")
+             .validator(validate(parse_break_interval))
+             .help("Briefly dim the screen every MINUTES minutes as a reminder to take a break"))
+        .arg(arg("day-neutral").help("Leave the screen unadjusted during the day"))
         .arg(arg("print").short("p")
-             .help("Print parameters and exit")
-             .conflicts_with_all(&["oneshot", "reset", "oneshot-manual"]))
+             .help("Print parameters and exit"))
+        .arg(arg("exit-period").requires("print")
+             .help("With --print, exit with 10 at day, 11 at night and 12 during transition"))
+        .arg(arg("at").requires("print")
+             .value_name("TIME")
+             .validator(validate(|input| parse_time(input, 0.0)))
+             .help("With --print, show the settings at TIME (HH:MM, YYYY-MM-DD HH:MM, @SECONDS or +DURATION)"))
+        .arg(arg("sweep").requires("print").conflicts_with("exit-period")
+             .value_name("DURATION[/STEP]")
+             .validator(validate(parse_sweep))
+             .help("With --print, show the settings every STEP (30m by default) for DURATION, like 24h"))
         .arg(arg("oneshot").short("o")
-             .help("One shot mode")
-             .conflicts_with_all(&["print", "reset", "oneshot-manual"]))
+             .help("One shot mode"))
+        .arg(arg("oneshot-stateful")
+             .help("One shot mode for running from a timer: fade from the setting of the last run, kept in the state file"))
         .arg(arg("oneshot-manual").short("O")
              .help("One shot mode (set color temperature)")
              .value_name("TEMP")
-             .conflicts_with_all(&["print", "oneshot", "reset"]))
+             .validator(validate(parse_manual_temperature)))
         .arg(arg("reset").short("x").help("Reset (remove adjustments to screen)"))
-        .arg(arg("verbose").short("v").help("Verbose output"))
+        .arg(arg("stress")
+             .value_name("ITERATIONS")
+             .hidden(true)
+             .validator(validate(|input| input.parse::<u32>().map_err(From::from)))
+             .help("Exercise the gamma method with random settings, restores and restarts"))
+        .arg(arg("demo")
+             .help("Fade to the night temperature and back over 30 seconds, to see the effect"))
+        .arg(arg("list-schemes").help("List the preset elevation schemes and exit"))
+        .arg(arg("man")
+             .hidden(true)
+             .help("Print the man page in roff and exit"))
+        .arg(arg("write-default-config")
+             .value_name("PATH")
+             .min_values(0).max_values(1)
+             .help("Write a commented config file with all the defaults to PATH (- for stdout, ~/.config/redshift.conf if not given) and exit"))
+        .group(ArgGroup::with_name("mode")
+               .args(&["print", "oneshot", "oneshot-stateful", "oneshot-manual", "reset", "demo", "list-schemes", "man", "stress",
+                       "write-default-config"]))
+        .arg(arg("verbose").short("v").multiple(true)
+             .help("Verbose output (twice for debug output)"))
+        .arg(arg("quiet").short("q").conflicts_with("verbose")
+             .help("Only print errors"))
+        .arg(arg("system")
+             .help("Run for the whole system (e.g. a greeter), with /etc/redshift-rs/config"))
+        .arg(arg("no-color")
+             .help("Do not color the output (also disabled by setting NO_COLOR)"))
+        .arg(arg("version").short("V")
+             .help("Print version information (with --verbose: build information)"))
+        .subcommand(SubCommand::with_name("install-service")
+                    .about("Start redshift-rs with the session, with the options given before this")
+                    .arg(arg("autostart")
+                         .help("Write an XDG autostart entry instead of a systemd user unit")))
+        .subcommand(SubCommand::with_name("import-flux")
+                    .about("Print the location and temperatures of f.lux as a config file")
+                    .arg(Arg::with_name("PATH").required(true)
+                         .help("A registry export of f.lux's preferences, or its macOS property list")))
+        .subcommand(SubCommand::with_name("enable")
+                    .about("Start the installed service now and with every session"))
+        .subcommand(SubCommand::with_name("disable")
+                    .about("Stop the installed service and no longer start it"))
+        .subcommand(SubCommand::with_name("ctl")
+                    .about("Call a method of the running instance over its control socket, and print the result")
+                    .arg(Arg::with_name("METHOD").required(true)
+                         .help("get_state, set_enabled, set_offsets or subscribe, which prints the events until redshift-rs exits"))
+                    .arg(Arg::with_name("PARAMS")
+                         .help("The parameters, as a JSON object like '{\"enabled\": false}'")))
+        .subcommand(SubCommand::with_name("neutral")
+                    .about("Restore the screen of the running instance for SECONDS, as for taking a screenshot")
+                    .arg(Arg::with_name("SECONDS").required(true)
+                         .validator(validate(|input| match input.parse::<f64>() {
+                             Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Ok(seconds),
+                             _ => Err(From::from("not a number of seconds"))
+                         }))))
 }
 
 /// Selected run mode
@@ -142,6 +230,9 @@ enum Mode {
     /// Run the color adjustment method once and exit
     OneShot,
 
+    /// As OneShot, fading from the setting of the last run
+    OneShotStateful,
+
     /// Continually run the color adjustment
     ///
     /// This is the default mode
@@ -154,17 +245,94 @@ enum Mode {
     Print,
 
     /// One shot manual mode - set color temperature
-    Manual(i32)
+    Manual(i32),
+
+    /// Fade to the night setting and back once
+    Demo,
+
+    /// List the preset elevation schemes and exit
+    ListSchemes,
+
+    /// Write a config file with all the defaults and exit
+    WriteDefaultConfig,
+
+    /// Print the man page and exit
+    Man,
+
+    /// Stress test the gamma method for the given number of iterations
+    Stress(u32),
+
+    /// Install a service running with the given options
+    InstallService(service::Kind),
+
+    /// Enable or disable the installed service
+    EnableService(bool),
+
+    /// Print the settings of f.lux, from `Args::import_path`, as a
+    /// config file
+    ImportFlux,
+
+    /// Call a method of the running instance, from `Args::control_call`
+    Control,
+
+    /// Print version information and exit
+    Version
 }
 
 struct Args {
-    pub verbose: bool,
+    pub level: output::Level,
+    pub no_color: bool,
     pub brightness: (f64, f64),
-    pub gamma: (f64, f64, f64),
+    pub gamma: ((f64, f64, f64), (f64, f64, f64)),
     pub location: Location,
+    pub location_given: bool,
+    pub timezone: Option<location::timezone::Timezone>,
+    pub exceptions: Option<exceptions::Exceptions>,
+    pub geocoding_url: String,
     pub method: Option<String>,
+    pub method_options: Vec<gamma::MethodOption>,
+    pub start_attempts: u32,
+    pub start_interval: u64,
+    pub wait_for_output: Option<String>,
+    pub system: bool,
+    pub user: Option<String>,
     pub temperatures: (i32, i32),
-    pub transition: bool,
+    pub elevation: (f64, f64),
+    pub fade_on_start: bool,
+    pub fade_on_exit: bool,
+    pub smooth: bool,
+    pub emulate_brightness: bool,
+    pub observe: bool,
+    pub instant_start: bool,
+    pub jitter: bool,
+    pub quantize_temp: i32,
+    pub keyframes: transition::Keyframes,
+    pub verbose_interval: f64,
+    pub time_format: clock::TimeFormat,
+    pub day_neutral: bool,
+    pub break_interval: Option<u32>,
+    pub preserve_gamma: bool,
+    pub min_brightness: Option<f64>,
+    pub max_adjustment: gamma::AdjustmentLimit,
+    pub seat_limits: Vec<(String, gamma::AdjustmentLimit)>,
+    pub brightness_floors: Vec<(String, f64)>,
+    pub exit_period: bool,
+    pub print_at: Option<f64>,
+    pub sweep: Option<(f64, f64)>,
+    pub log_elevation: Option<String>,
+    pub log_period_times: Option<String>,
+    pub sandbox: bool,
+    pub default_config_path: Option<String>,
+    pub import_path: Option<String>,
+    pub control_socket: bool,
+    /// The method and parameters of `ctl`
+    pub control_call: Option<(String, Option<String>)>,
+    pub color_scheme: Option<theme::ColorScheme>,
+    pub announcer: Option<notify::Announcer>,
+    pub cues: Option<notify::Cues>,
+    pub follow_inhibit: bool,
+    #[cfg(feature = "config")]
+    pub policy: Option<policy::Policy>,
     pub mode: Mode,
 }
 
@@ -172,82 +340,438 @@ impl Args {
 
     pub fn defaults() -> Args {
         Args {
-            verbose: false,
+            level: output::Level::Normal,
+            no_color: false,
             brightness: (DEFAULT_BRIGHTNESS, DEFAULT_BRIGHTNESS),
-            gamma: (DEFAULT_GAMMA, DEFAULT_GAMMA, DEFAULT_GAMMA),
+            gamma: ((DEFAULT_GAMMA, DEFAULT_GAMMA, DEFAULT_GAMMA), (DEFAULT_GAMMA, DEFAULT_GAMMA, DEFAULT_GAMMA)),
             location: Location::new(55.7, 12.6),
+            location_given: false,
+            timezone: None,
+            exceptions: None,
+            geocoding_url: location::geocode::DEFAULT_URL.to_owned(),
             method: None,
+            method_options: vec![],
+            start_attempts: 1,
+            start_interval: 1,
+            wait_for_output: None,
+            system: false,
+            user: None,
             temperatures: (DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP),
-            transition: true,
+            elevation: (TransitionScheme::new().high, TransitionScheme::new().low),
+            fade_on_start: true,
+            fade_on_exit: true,
+            smooth: false,
+            emulate_brightness: false,
+            observe: false,
+            instant_start: false,
+            jitter: false,
+            quantize_temp: 0,
+            keyframes: transition::Keyframes::default(),
+            verbose_interval: 1.0,
+            time_format: clock::TimeFormat::Locale,
+            day_neutral: false,
+            break_interval: None,
+            preserve_gamma: true,
+            min_brightness: None,
+            max_adjustment: gamma::AdjustmentLimit::default(),
+            seat_limits: vec![],
+            brightness_floors: vec![],
+            exit_period: false,
+            print_at: None,
+            sweep: None,
+            log_elevation: None,
+            log_period_times: None,
+            sandbox: false,
+            default_config_path: None,
+            import_path: None,
+            control_socket: true,
+            control_call: None,
+            color_scheme: None,
+            announcer: None,
+            cues: None,
+            follow_inhibit: false,
+            #[cfg(feature = "config")]
+            policy: None,
             mode: Mode::Continual,
         }
     }
 
-    pub fn update_from_config(mut self) -> Result<Args> {
-        let conf = std::env::home_dir()
-            .map(|mut path| { path.push(".config/redshift.conf"); path })
-            .and_then(|home| ini::Ini::load_from_file(&home).ok());
+    #[cfg(feature = "config")]
+    pub fn update_from_config(mut self, system: bool) -> Result<Args> {
+        let path = if system {
+            self.system = true;
+            Some(PathBuf::from(SYSTEM_CONFIG_PATH))
+        } else {
+            user_config_path()
+        };
 
-        let conf = if let Some(c) = conf { c } else { return Ok(self) };
+        let mut conf = match path {
+            Some(path) => config::Config::load(&path)?,
+            None => None
+        };
+        let policy = policy::Policy::load()?;
 
-        let section = conf.section(Some("redshift")).map(Ok)
-            .unwrap_or(malformed_config(format!("config file does not have a 'redshift' section")))?;
+        // The policy goes on top, so nothing in the config file can
+        // change what it sets
+        if let Some(ref policy) = policy {
+            conf = Some(policy.layer_onto(conf.take())?);
+        }
+        if let Some(ref conf) = conf {
+            self = self.apply_config(conf)?;
+            if let (Some(policy), Some(exceptions)) = (policy.as_ref(), self.exceptions.as_ref()) {
+                let path = conf.get_from(conf.main_section(), "exceptions").unwrap_or("exceptions");
+                policy.check_exceptions(exceptions, path)?;
+            }
+        }
+        self.policy = policy;
+        Ok(self)
+    }
 
+    #[cfg(feature = "config")]
+    fn apply_config(mut self, conf: &config::Config) -> Result<Args> {
+        let main = conf.main_section();
+
+        // Misspelled keys would otherwise be silently ignored
+        for (section, key) in conf.keys() {
+            let section = section.as_ref().map(|s| s.as_str());
+            let known = match section {
+                _ if section == main => is_config_key(None, &key),
+                Some("manual") | Some("timezone") | Some("max-adjustment") => is_config_key(section, &key),
+                Some(name) if seat_section(name).is_some() => is_config_key(section, &key),
+                _ => true
+            };
+            if !known {
+                warn!("{}: unknown key '{}'",
+                      conf.position(section, &key).unwrap_or_else(|| format!("[{}]", section.unwrap_or("redshift"))), key);
+            }
+        }
+        let no_keys = config::Properties::new();
+        let section = conf.section(main).unwrap_or(&no_keys);
+
+        // As with the gamma, one value for both, which the day and
+        // night keys override
+        if let Some(brightness) = section.get("brightness") {
+            let brightness = parse_brightness_value(brightness)
+                .or_else(|e| conf.error(main, "brightness", format!("could not parse brightness: {}", e)))?;
+            self.brightness = (brightness, brightness);
+        }
         if let Some(brightness_day) = section.get("brightness-day") {
-            self.brightness.0 = brightness_day.parse()
-                .or_else(|e| malformed_config(format!("could not parse brightness-day: {}", e)))?;
+            self.brightness.0 = parse_brightness_value(brightness_day)
+                .or_else(|e| conf.error(main, "brightness-day", format!("could not parse brightness-day: {}", e)))?;
         }
         if let Some(brightness_night) = section.get("brightness-night") {
-            self.brightness.1 = brightness_night.parse()
-                .or_else(|e| malformed_config(format!("could not parse brightness-night: {}", e)))?;
+            self.brightness.1 = parse_brightness_value(brightness_night)
+                .or_else(|e| conf.error(main, "brightness-night", format!("could not parse brightness-night: {}", e)))?;
         }
 
+        if let Some(temp) = section.get("temp") {
+            self.temperatures = parse_temperature_expr(temp, DEFAULT_DAY_TEMP)
+                .and_then(|day| Ok((day, parse_temperature_expr(temp, DEFAULT_NIGHT_TEMP)?)))
+                .or_else(|e| conf.error(main, "temp", format!("could not parse temp: {}", e)))?;
+        }
         if let Some(temp_day) = section.get("temp-day") {
-            self.temperatures.0 = temp_day.parse()
-                .or_else(|e| malformed_config(format!("could not parse temp-day: {}", e)))?;
+            self.temperatures.0 = parse_temperature_expr(temp_day, DEFAULT_DAY_TEMP)
+                .or_else(|e| conf.error(main, "temp-day", format!("could not parse temp-day: {}", e)))?;
         }
         if let Some(temp_night) = section.get("temp-night") {
-            self.temperatures.1 = temp_night.parse()
-                .or_else(|e| malformed_config(format!("could not parse temp-night: {}", e)))?;
+            self.temperatures.1 = parse_temperature_expr(temp_night, DEFAULT_NIGHT_TEMP)
+                .or_else(|e| conf.error(main, "temp-night", format!("could not parse temp-night: {}", e)))?;
+        }
+
+        if let Some(name) = section.get("scheme") {
+            let scheme = transition::elevation_scheme(name).map(Ok)
+                .unwrap_or_else(|| conf.error(main, "scheme", format!(
+                    "unknown scheme '{}' (see --list-schemes)", name)))?;
+            self.elevation = (scheme.high, scheme.low);
         }
 
         if let Some(gamma) = section.get("gamma") {
-            self.gamma = parse_gamma(gamma)?;
+            let gamma = parse_gamma(gamma)
+                .or_else(|e| conf.error(main, "gamma", format!("{}", e)))?;
+            self.gamma = (gamma, gamma);
+        }
+        // Like the temperature, the gamma may differ between day and
+        // night, and is interpolated during transitions
+        if let Some(gamma) = section.get("gamma-day") {
+            self.gamma.0 = parse_gamma(gamma)
+                .or_else(|e| conf.error(main, "gamma-day", format!("{}", e)))?;
+        }
+        if let Some(gamma) = section.get("gamma-night") {
+            self.gamma.1 = parse_gamma(gamma)
+                .or_else(|e| conf.error(main, "gamma-night", format!("{}", e)))?;
         }
 
         if let Some(transition) = section.get("transition") {
-            self.transition = transition != "0";
-        }
-
-        if let Some("manual") = section.get("location-provider").map(|s| s.as_str()) {
-            let lat = conf.get_from(Some("manual"), "lat");
-            let lon = conf.get_from(Some("manual"), "lon");
-            match (lat, lon) {
-                (Some(lat), Some(lon)) => {
-                    let lat = lat.parse()
-                        .or_else(|e| malformed_config(format!("could not parse latitude: {}", e)))?;
-                    let lon = lon.parse()
-                        .or_else(|e| malformed_config(format!("could not parse longitude: {}", e)))?;
-                    self.location = Location::new(lat, lon);
-                }
-                _ => {
-                    return malformed_config(format!("missing 'lat' or 'lon' value for 'manual' location provider"));
+            self.fade_on_start = transition != "0";
+            self.fade_on_exit = transition != "0";
+        }
+        if let Some(fade) = section.get("fade-on-start") {
+            self.fade_on_start = fade != "0";
+        }
+        if let Some(fade) = section.get("fade-on-exit") {
+            self.fade_on_exit = fade != "0";
+        }
+
+        if let Some(smooth) = section.get("smooth") {
+            self.smooth = smooth != "0";
+        }
+
+        if let Some(emulate) = section.get("emulate-brightness") {
+            self.emulate_brightness = emulate != "0";
+        }
+
+        if let Some(instant_start) = section.get("instant-start") {
+            self.instant_start = instant_start != "0";
+        }
+
+        if let Some(jitter) = section.get("jitter") {
+            self.jitter = jitter != "0";
+        }
+
+        if let Some(step) = section.get("quantize-temp") {
+            self.quantize_temp = parse_quantize_temp(step)
+                .or_else(|e| conf.error(main, "quantize-temp", format!("{}", e)))?;
+        }
+
+        for (key, keyframe) in vec![("start-dim", &mut self.keyframes.start_dim),
+                                    ("full-night", &mut self.keyframes.full_night),
+                                    ("start-brighten", &mut self.keyframes.start_brighten),
+                                    ("full-day", &mut self.keyframes.full_day)] {
+            if let Some(value) = section.get(key) {
+                *keyframe = Some(parse_keyframe(key, value).or_else(|e| conf.error(main, key, format!("{}", e)))?);
+            }
+        }
+
+        if let Some(format) = section.get("time-format") {
+            self.time_format = parse_time_format(format)
+                .or_else(|e| conf.error(main, "time-format", format!("{}", e)))?;
+        }
+
+        if let Some(interval) = section.get("verbose-interval") {
+            self.verbose_interval = parse_verbose_interval(interval)
+                .or_else(|e| conf.error(main, "verbose-interval", format!("{}", e)))?;
+        }
+
+        // Limits for shared machines, which nothing on the command
+        // line can go past
+        self.max_adjustment = adjustment_limit(conf, "max-adjustment")?;
+
+        // The same for the displays of one seat, for a system instance
+        // serving several
+        let mut seats: Vec<String> = conf.keys().into_iter()
+            .filter_map(|(section, _)| section.as_ref().and_then(|s| seat_section(s)).map(|seat| seat.to_owned()))
+            .collect();
+        seats.sort();
+        seats.dedup();
+        for seat in seats {
+            let limit = adjustment_limit(conf, &format!("seat:{}", seat))?;
+            self.seat_limits.push((seat, limit));
+        }
+
+        if let Some(floors) = section.get("min-brightness") {
+            let (all, outputs) = parse_brightness_floors(floors)
+                .or_else(|e| conf.error(main, "min-brightness", format!("{}", e)))?;
+            self.min_brightness = all;
+            self.brightness_floors = outputs;
+        }
+
+        if let Some(path) = section.get("whitepoint-table") {
+            let table = colorramp::WhitepointTable::load(Path::new(path))
+                .or_else(|e| conf.error(main, "whitepoint-table", format!("{}: {}", path, e)))?;
+            colorramp::set_whitepoint_table(table);
+        }
+
+        if let Some(path) = section.get("exceptions") {
+            self.exceptions = Some(exceptions::Exceptions::load(Path::new(path))
+                .or_else(|e| conf.error(main, "exceptions", format!("{}: {}", path, e)))?);
+        }
+
+        if let Some(preserve) = section.get("preserve-luminance") {
+            colorramp::set_preserve_luminance(preserve != "0");
+        }
+
+        if let Some(preserve) = section.get("preserve-gamma") {
+            self.preserve_gamma = preserve != "0";
+        }
+
+        if let Some(day_neutral) = section.get("day-neutral") {
+            self.day_neutral = day_neutral != "0";
+        }
+
+        if let Some(minutes) = section.get("break-reminder") {
+            self.break_interval = parse_break_interval(minutes)
+                .or_else(|e| conf.error(main, "break-reminder", format!("{}", e)))?;
+        }
+
+        // A lone [manual] section is enough to pick the manual provider
+        let provider = section.get("location-provider").map(|s| s.as_str())
+            .or_else(|| conf.section(Some("manual")).map(|_| "manual"));
+        match provider {
+            Some("manual") => {
+                let lat = conf.get_from(Some("manual"), "lat");
+                let lon = conf.get_from(Some("manual"), "lon");
+                match (lat, lon) {
+                    (Some(lat), Some(lon)) => {
+                        let lat = location::parse_latitude(lat)
+                            .or_else(|e| conf.error(Some("manual"), "lat", format!("could not parse latitude: {}", e)))?;
+                        let lon = location::parse_longitude(lon)
+                            .or_else(|e| conf.error(Some("manual"), "lon", format!("could not parse longitude: {}", e)))?;
+                        self.location = Location::new(lat, lon);
+                        self.location_given = true;
+                    }
+                    _ => {
+                        return conf.error(main, "location-provider",
+                                          format!("missing 'lat' or 'lon' value for 'manual' location provider"));
+                    }
                 }
             }
+            Some("timezone") => {
+                let lat = conf.get_from(Some("timezone"), "lat")
+                    .map_or(conf.error(main, "location-provider",
+                                       format!("missing 'lat' value for 'timezone' location provider")),
+                            |lat| location::parse_latitude(lat)
+                            .or_else(|e| conf.error(Some("timezone"), "lat", format!("could not parse latitude: {}", e))))?;
+                let timezone = location::timezone::Timezone::new(lat);
+                self.location = timezone.location();
+                self.location_given = true;
+                self.timezone = Some(timezone);
+            }
+            Some(provider) => {
+                return conf.error(main, "location-provider",
+                                  format!("unknown location provider '{}' (available: {})",
+                                          provider, location::provider_names().join(", ")));
+            }
+            None => {}
         }
 
         if let Some(method) = section.get("adjustment-method") {
             self.method = determine_gamma_method(method.to_owned())
-                .or_else(|e| malformed_config(format!("{}", e)))
+                .or_else(|e| conf.error(main, "adjustment-method", format!("{}", e)))
                 .map(Some)?;
         }
 
+        if let Some(kind) = section.get("color-scheme") {
+            self.color_scheme = Some(theme::ColorScheme::new(
+                kind,
+                section.get("color-scheme-day").map(|s| s.as_str()),
+                section.get("color-scheme-night").map(|s| s.as_str()))
+                .or_else(|e| conf.error(main, "color-scheme", format!("{}", e)))?);
+        }
+
+        if let Some(announce) = section.get("announce") {
+            if announce != "0" {
+                self.announcer = Some(notify::Announcer::new()
+                    .or_else(|e| conf.error(main, "announce", format!("{}", e)))?);
+            }
+        }
+
+        if let Some(follow) = section.get("follow-inhibit") {
+            self.follow_inhibit = follow != "0";
+            if self.follow_inhibit {
+                inhibit::check().or_else(|e| conf.error(main, "follow-inhibit", format!("{}", e)))?;
+            }
+        }
+
+        let cue_day = section.get("cue-day").filter(|cue| !cue.is_empty());
+        let cue_night = section.get("cue-night").filter(|cue| !cue.is_empty());
+        if cue_day.is_some() || cue_night.is_some() {
+            self.cues = Some(notify::Cues::new(cue_day.cloned(), cue_night.cloned()));
+        }
+
+        if let Some(attempts) = section.get("start-attempts") {
+            self.start_attempts = attempts.parse()
+                .or_else(|e| conf.error(main, "start-attempts", format!("could not parse start-attempts: {}", e)))?;
+            if self.start_attempts == 0 {
+                return conf.error(main, "start-attempts", format!("start-attempts must be at least 1"));
+            }
+        }
+        if let Some(interval) = section.get("start-interval") {
+            self.start_interval = interval.parse()
+                .or_else(|e| conf.error(main, "start-interval", format!("could not parse start-interval: {}", e)))?;
+        }
+        if let Some(output) = section.get("wait-for-output") {
+            self.wait_for_output = Some(output.to_owned());
+        }
+        if let Some(sandbox) = section.get("sandbox") {
+            self.sandbox = sandbox != "0";
+        }
+        if let Some(control_socket) = section.get("control-socket") {
+            self.control_socket = control_socket != "0";
+        }
+        if let Some(user) = section.get("user") {
+            self.user = Some(user.to_owned());
+        }
+        if let Some(url) = section.get("geocoding-url") {
+            self.geocoding_url = url.to_owned();
+        }
+
+        // Options for gamma methods live in a section named after the method
+        for method in gamma::method_names() {
+            if let Some(options) = conf.section(Some(method)) {
+                for (key, value) in options.iter() {
+                    self.method_options.push(gamma::MethodOption {
+                        method: method.to_owned(),
+                        key: key.to_owned(),
+                        value: value.to_owned()
+                    });
+                }
+            }
+        }
+
         Ok(self)
     }
 
+    /// Without the config feature, only --system itself has an effect
+    #[cfg(not(feature = "config"))]
+    pub fn update_from_config(mut self, system: bool) -> Result<Args> {
+        self.system = system;
+        Ok(self)
+    }
+
+    /// Fail if the system policy locks settings an option changes
+    #[cfg(feature = "config")]
+    fn check_policy(&self, matches: &ArgMatches) -> Result<()> {
+        match self.policy {
+            Some(ref policy) => policy.check_options(|option| matches.is_present(option)),
+            None => Ok(())
+        }
+    }
+
+    #[cfg(not(feature = "config"))]
+    fn check_policy(&self, _matches: &ArgMatches) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "config")]
+    fn allows_disable(&self) -> bool {
+        self.policy.as_ref().map_or(true, |policy| policy.allows_disable())
+    }
+
+    #[cfg(not(feature = "config"))]
+    fn allows_disable(&self) -> bool {
+        true
+    }
+
+    /// What clients of the control socket may not change
+    #[cfg(feature = "config")]
+    fn control_locks(&self) -> control::Locks {
+        let locked = |option: &str| self.policy.as_ref()
+            .map_or(false, |policy| policy.check_options(|given| given == option).is_err());
+        control::Locks {
+            disable: !self.allows_disable(),
+            temperature: locked("temperature"),
+            brightness: locked("brightness"),
+        }
+    }
+
+    #[cfg(not(feature = "config"))]
+    fn control_locks(&self) -> control::Locks {
+        control::Locks::default()
+    }
+
     /// Parse the command-line arguments into a Redshift configuration
-    pub fn update_from_args(mut self) -> Result<Args> {
-        let matches = app().get_matches();
+    pub fn update_from_args(mut self, matches: &ArgMatches) -> Result<Args> {
+        self.check_policy(matches)?;
 
         if let Some(input) = matches.value_of("brightness") {
             self.brightness = parse_brightness(input)?;
@@ -258,374 +782,1383 @@ impl Args {
         }
 
         if let Some(input) = matches.value_of("gamma") {
-            self.gamma = parse_gamma(input)?;
+            let gamma = parse_gamma(input)?;
+            self.gamma = (gamma, gamma);
         }
 
         // Determine run mode
-        self.mode = if matches.is_present("print") {
+        self.mode = if matches.is_present("version") {
+            Mode::Version
+        } else if matches.is_present("print") {
             Mode::Print
         } else if matches.is_present("oneshot") {
             Mode::OneShot
+        } else if matches.is_present("oneshot-stateful") {
+            Mode::OneShotStateful
         } else if let Some(temp) = matches.value_of("oneshot-manual") {
-            let t = temp.parse()?;
-            if t < MIN_TEMP || t > MAX_TEMP {
-                return malformed(format!("Temperature must be between {} and {} (was {})", MIN_TEMP, MAX_TEMP, t));
-            }
-            Mode::Manual(t)
+            Mode::Manual(parse_manual_temperature(temp)?)
         } else if matches.is_present("reset") {
             Mode::Reset
+        } else if matches.is_present("demo") {
+            Mode::Demo
+        } else if matches.is_present("list-schemes") {
+            Mode::ListSchemes
+        } else if matches.is_present("man") {
+            Mode::Man
+        } else if matches.is_present("write-default-config") {
+            self.default_config_path = matches.value_of("write-default-config").map(|path| path.to_owned());
+            Mode::WriteDefaultConfig
+        } else if let Some(iterations) = matches.value_of("stress") {
+            Mode::Stress(iterations.parse()?)
+        } else if let Some(sub) = matches.subcommand_matches("install-service") {
+            Mode::InstallService(if sub.is_present("autostart") {
+                service::Kind::Autostart
+            } else {
+                service::Kind::Systemd
+            })
+        } else if let Some(sub) = matches.subcommand_matches("import-flux") {
+            self.import_path = sub.value_of("PATH").map(|path| path.to_owned());
+            Mode::ImportFlux
+        } else if let Some(sub) = matches.subcommand_matches("ctl") {
+            self.control_call = sub.value_of("METHOD")
+                .map(|method| (method.to_owned(), sub.value_of("PARAMS").map(|params| params.to_owned())));
+            Mode::Control
+        } else if let Some(sub) = matches.subcommand_matches("neutral") {
+            let seconds: f64 = sub.value_of("SECONDS").unwrap_or("").parse()?;
+            self.control_call = Some(("neutral".to_owned(), Some(format!("{{\"seconds\": {}}}", seconds))));
+            Mode::Control
+        } else if matches.subcommand_matches("enable").is_some() {
+            Mode::EnableService(true)
+        } else if matches.subcommand_matches("disable").is_some() {
+            Mode::EnableService(false)
         } else {
             self.mode
         };
+        match self.mode {
+            // -O, -x and --observe are rejected with the other options
+            Mode::EnableService(false) if !self.allows_disable() =>
+                return Err(Box::new(RedshiftError::PolicyViolation(
+                    format!("redshift-rs cannot be disabled")))),
+            _ => {}
+        }
 
         if let Some(location) = matches.value_of("location") {
-            self.location = location.parse()?;
+            self.location = if location.starts_with(location::PLACE_PREFIX) {
+                location::geocode::resolve(&location[location::PLACE_PREFIX.len()..], &self.geocoding_url)?
+            } else {
+                location.parse()?
+            };
+            self.location_given = true;
+            self.timezone = None;
         }
 
-        if let Some(method) = matches.value_of("method") {
-            self.method = determine_gamma_method(method.to_owned()).map(Some)?;
+        if let Some(methods) = matches.value_of("method") {
+            let mut names = vec![];
+            for method in methods.split(',') {
+                let mut parts = method.split(':');
+                let name = parts.next().unwrap_or(method);
+                names.push(name);
+                for option in parts {
+                    self.method_options.push(parse_method_option(name, option)?);
+                }
+            }
+            self.method = determine_gamma_method(names.join(",")).map(Some)?;
         }
 
-        self.verbose = matches.is_present("verbose");
-        self.transition = !matches.is_present("no-transition");
+        self.level = match matches.occurrences_of("verbose") {
+            _ if matches.is_present("quiet") => output::Level::Quiet,
+            0 => output::Level::Normal,
+            1 => output::Level::Verbose,
+            _ => output::Level::Debug
+        };
+        self.no_color = matches.is_present("no-color");
+        if matches.is_present("no-transition") || matches.is_present("no-startup-fade") {
+            self.fade_on_start = false;
+        }
+        if matches.is_present("no-transition") || matches.is_present("no-exit-fade") {
+            self.fade_on_exit = false;
+        }
+        self.exit_period = matches.is_present("exit-period");
+        if let Some(at) = matches.value_of("at") {
+            self.print_at = Some(parse_time(at, systemtime_get_time())?);
+        }
+        if let Some(sweep) = matches.value_of("sweep") {
+            self.sweep = Some(parse_sweep(sweep)?);
+        }
+        if let Some(path) = matches.value_of("log-elevation") {
+            self.log_elevation = Some(path.to_owned());
+        }
+        if let Some(path) = matches.value_of("log-period-times") {
+            self.log_period_times = Some(path.to_owned());
+        }
+        if matches.is_present("smooth") {
+            self.smooth = true;
+        }
+        if matches.is_present("emulate-brightness") {
+            self.emulate_brightness = true;
+        }
+        if matches.is_present("observe") {
+            self.observe = true;
+        }
+        if matches.is_present("instant-start") {
+            self.instant_start = true;
+        }
+        if let Some(minutes) = matches.value_of("break-reminder") {
+            self.break_interval = parse_break_interval(minutes)?;
+        }
+        if matches.is_present("no-preserve") {
+            self.preserve_gamma = false;
+        }
+        if matches.is_present("day-neutral") {
+            self.day_neutral = true;
+        }
+        if matches.is_present("jitter") {
+            self.jitter = true;
+        }
 
         Ok(self)
     }
 }
 
-#[inline]
-fn malformed<T>(msg: String) -> Result<T> {
-    Err(Box::new(RedshiftError::MalformedArgument(msg)))
-}
-
-#[inline]
-fn malformed_config<T>(msg: String) -> Result<T> {
-    Err(Box::new(RedshiftError::MalformedConfig(msg)))
-}
-
+/// Check that the method, or each of a comma separated list of
+/// methods, is available
 fn determine_gamma_method(method: String) -> Result<String> {
-    if gamma::is_method_available(&method[..]) {
-        Ok(method)
-    } else {
-        Err(Box::new(RedshiftError::GammaMethodNotFound(method)))
-    }
-}
-
-/// Parse the temperature argument
-///
-/// Expected as "DAY:NIGHT", where DAY and NIGHT are 32-bit
-/// integers. Any other input produces an error.
-fn parse_temperature(input: &str) -> Result<(i32, i32)> {
-    let mut parts = input.split(':');
-
-    let day = parts.next()
-        .map_or(malformed(format!("temperature argument: {}", input)),
-                |l| l.parse().or(
-                    malformed(format!("temperature argument: {} (of {})", l, input))))?;
-
-    let night = parts.next()
-        .map_or(malformed(format!("temperature argument: {}", input)),
-                |l| l.parse().or(
-                    malformed(format!("temperature argument: {} (of {})", l, input))))?;
-
-    parts.next().map_or(Ok((day, night)),
-                        |_| malformed(format!("temperature argument: {}", input)))
-}
-
-/// Parse brightness argument
-///
-/// Expected format is "DAY:NIGHT" where DAY and NIGHT are floating
-/// point numbers. Any other input produces an error.
-fn parse_brightness(input: &str) -> Result<(f64, f64)> {
-    let mut parts = input.split(':');
-
-    let day = parts.next()
-        .map_or(malformed(format!("brightness: {}", input)),
-                |l| l.parse().or(
-                    malformed(format!("brightness: {} (of {})", l, input))))?;
-
-    let night = parts.next()
-        .map_or(Ok(day),
-                |l| l.parse().or(malformed(format!("brightness: {} (of {})", l, input))))?;
-
-    parts.next()
-        .map_or(Ok(()),
-                |trailing| malformed(format!("brightness: trailing {} (of {})", trailing, input)))?;
-
-    // Ensure numbers are in range
-    if day < MIN_BRIGHTNESS
-        || day > MAX_BRIGHTNESS
-        || night < MIN_BRIGHTNESS
-        || night > MAX_BRIGHTNESS {
-        malformed(format!("Brightness values must be between {:.1} and {:.1}", MIN_BRIGHTNESS, MAX_BRIGHTNESS))
-    } else {
-        Ok((day, night))
+    if method == AUTO_METHOD {
+        return Ok(method)
     }
-}
-
-/// A gamma string contains either one floating point value, or three
-/// separated by colons
-fn parse_gamma(input: &str) -> Result<(f64, f64, f64)> {
-
-    let validate_gamma = |g| if g < MIN_GAMMA || g > MAX_GAMMA {
-        malformed(format!("Gamma value must be between {} and {}. Was {}",
-                          MIN_GAMMA, MAX_GAMMA, g))
-    } else {
-        Ok(())
-    };
-
-    let mut parts = input.split(':');
-
-    let fst = parts.next()
-        .map_or(malformed(format!("gamma: {}", input)),
-                |l| l.parse().or(
-                    malformed(format!("gamma: {} (of {})", l,
-                                      input))))?;
-    validate_gamma(fst)?;
-
-    if let Some(l) = parts.next() {
-        let g = l.parse().or(malformed(format!("gamma: {} (of {})", l,
-                                               input)))?;
-        validate_gamma(g)?;
-
-        let b = parts.next()
-            .map_or(malformed(format!("gamma: {} (of {})", l, input)),
-                    |l| l.parse().or(
-                        malformed(format!("gamma: {} (of {})", l,
-                                          input))))?;
-        validate_gamma(b)?;
-        Ok((fst, g, b))
-    } else {
-        Ok((fst, fst, fst))
+    match method.split(',').find(|name| !gamma::is_method_available(name)) {
+        Some(name) => Err(Box::new(RedshiftError::GammaMethodNotFound(name.to_owned()))),
+        None => Ok(method)
     }
 }
 
 fn main() {
-    let result = Args::defaults().update_from_config()
-        .and_then(|args| args.update_from_args())
-        .and_then(run);
+    let matches = match app().get_matches_safe() {
+        Ok(matches) => matches,
+        // Only the top level help is grouped, not that of subcommands
+        Err(ref e) if e.kind == clap::ErrorKind::HelpDisplayed && e.message.starts_with("redshift-rs ") => {
+            print!("{}", help::regroup(&e.message));
+            ::std::process::exit(0)
+        }
+        Err(e) => e.exit()
+    };
+    let result = Args::defaults().update_from_config(matches.is_present("system"))
+        .and_then(|args| args.update_from_args(&matches))
+        .and_then(|args| {
+            output::set_level(args.level);
+            output::set_throttle(std::time::Duration::from_millis((args.verbose_interval * 1000.0) as u64));
+            output::init_color(args.no_color);
+            clock::set_time_format(args.time_format);
+            run(args)
+        });
     ::std::process::exit(match result {
         Ok(exit_code) => {
             exit_code
         }
         Err(e) => {
-            println!("{}", e);
+            eprintln!("{}", e);
             1
         }
     });
 }
 
-fn run(args: Args) -> Result<i32> {
+/// The `temp` and `brightness` limits set in `section`
+#[cfg(feature = "config")]
+fn adjustment_limit(conf: &config::Config, section: &str) -> Result<gamma::AdjustmentLimit> {
+    let mut limit = gamma::AdjustmentLimit::default();
+    if let Some(temp) = conf.get_from(Some(section), "temp") {
+        limit.min_temp = Some(parse_manual_temperature(temp)
+            .or_else(|e| conf.error(Some(section), "temp", format!("{}", e)))?);
+    }
+    if let Some(brightness) = conf.get_from(Some(section), "brightness") {
+        limit.min_brightness = Some(parse_brightness_value(brightness)
+            .or_else(|e| conf.error(Some(section), "brightness", format!("{}", e)))?);
+    }
+    Ok(limit)
+}
+
+/// The config file read outside of --system mode
+fn user_config_path() -> Option<PathBuf> {
+    std::env::home_dir()
+        .map(|mut path| { path.push(".config/redshift.conf"); path })
+}
+
+/// Where desktops keep their settings, $XDG_CONFIG_HOME or ~/.config
+fn user_config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|mut path| { path.push(".config"); path }))
+}
+
+fn run(mut args: Args) -> Result<i32> {
+    if args.mode == Mode::Version {
+        print_version(args.level >= output::Level::Verbose);
+        return Ok(0)
+    }
+    if args.mode == Mode::ListSchemes {
+        list_schemes();
+        return Ok(0)
+    }
+    if args.mode == Mode::WriteDefaultConfig {
+        write_default_config(args.default_config_path.as_ref().map(|path| path.as_str()))?;
+        return Ok(0)
+    }
+    if args.mode == Mode::Man {
+        let mut help = vec![];
+        app().write_help(&mut help)?;
+        print!("{}", help::man_page(&String::from_utf8_lossy(&help), ABOUT, USAGE, VERSION));
+        return Ok(0)
+    }
+    if let Mode::InstallService(kind) = args.mode {
+        let flags: Vec<_> = std::env::args().skip(1)
+            .take_while(|arg| arg != "install-service")
+            .collect();
+        let path = service::install(kind, &flags)?;
+        info!("Installed {}", path.display());
+        return Ok(0)
+    }
+    if args.mode == Mode::ImportFlux {
+        let path = args.import_path.as_ref().map_or("", |path| path.as_str());
+        print!("{}", flux::Preferences::load(Path::new(path))?.to_config(path));
+        return Ok(0)
+    }
+    if let Mode::EnableService(enabled) = args.mode {
+        if enabled { service::enable()? } else { service::disable()? }
+        return Ok(0)
+    }
+    if let (Mode::Control, Some(&(ref method, ref params))) = (args.mode, args.control_call.as_ref()) {
+        let params = match *params {
+            Some(ref params) => json::parse(params)
+                .map_err(|e| RedshiftError::MalformedArgument(format!("PARAMS: {}", e)))?,
+            None => json::Value::object(vec![])
+        };
+        let path = control::socket_path()?;
+        if method == "subscribe" {
+            let subscription = control::subscribe(&path, params)?;
+            println!("{}", subscription.state);
+            for notification in subscription {
+                println!("{}", notification?);
+            }
+        } else {
+            println!("{}", control::request(&path, method, params)?);
+        }
+        return Ok(0)
+    }
+
+    let needs_location = match args.mode {
+        Mode::OneShot | Mode::OneShotStateful | Mode::Continual | Mode::Print => true,
+        _ => false
+    };
+    if needs_location && !args.location_given {
+        let asked = match user_config_path() {
+            Some(ref path) if cfg!(feature = "config") && !args.system && wizard::is_interactive() =>
+                wizard::ask_location(path, &args.geocoding_url)?,
+            _ => None
+        };
+        match asked {
+            Some(location) => args.location = location,
+            None => warn!("No location configured, using {:.1}:{:.1} (set one with -l or in the config file)",
+                          args.location.lat, args.location.lon)
+        }
+    }
+
     let (temp_day, temp_night) = args.temperatures;
     let (bright_day, bright_night) = args.brightness;
 
     // Init transition scheme
     let mut scheme = TransitionScheme::new();
-    scheme.day.temp = temp_day;
-    scheme.night.temp = temp_night;
+    scheme.day.temp = temp_day.into();
+    scheme.night.temp = temp_night.into();
     scheme.day.brightness = bright_day;
     scheme.night.brightness = bright_night;
 
-    scheme.day.gamma[0] = args.gamma.0;
-    scheme.day.gamma[1] = args.gamma.1;
-    scheme.day.gamma[2] = args.gamma.2;
-
-    scheme.night.gamma[0] = args.gamma.0;
-    scheme.night.gamma[1] = args.gamma.1;
-    scheme.night.gamma[2] = args.gamma.2;
-
-    if args.verbose {
-        println!("Temperatures: {}K at day, {}K at night", temp_day, temp_night);
-        println!("{}", args.location);
+    let (gamma_day, gamma_night) = args.gamma;
+    scheme.day.gamma = [gamma_day.0, gamma_day.1, gamma_day.2];
+    scheme.night.gamma = [gamma_night.0, gamma_night.1, gamma_night.2];
+
+    scheme.high = args.elevation.0;
+    scheme.low = args.elevation.1;
+    scheme.jitter = args.jitter;
+    scheme.quantize_temp = args.quantize_temp;
+    scheme.keyframes = args.keyframes.clone();
+
+    // Only the day end of the transition changes, so dusk and dawn
+    // are still gradual
+    if args.day_neutral {
+        scheme.day.temp = NEUTRAL_TEMP.into();
+        scheme.day.brightness = 1.0;
     }
 
-    #[inline]
-    fn oneshot(scheme: &TransitionScheme, loc: &Location) -> (f64, Period, ColorSetting) {
-        let now = systemtime_get_time();
-        // Compute elevation
-        let elev = solar::elevation(now, loc);
+    verbose!("Temperatures: {}K at day, {}K at night", scheme.day.temp, scheme.night.temp);
+    verbose!("{}", args.location);
 
-        let period = scheme.get_period(elev);
-
-        // Interpolate between 6500K and calculated temperature
-        let color_setting = scheme.interpolate_color_settings(elev);
-
-        (elev, period, color_setting)
-    }
-
-    // Print elevation, period and color setting
+    // Print elevation, period, color setting and when the period changes
     #[inline]
-    fn print_settings(elev: f64, period: &Period, color_setting: &ColorSetting) {
-        println!("Solar elevation: {}", elev);
-        println!("{}", period);
-        println!("Color temperature: {}K", color_setting.temp);
-        println!("Brightness: {:.2}", color_setting.brightness);
+    fn print_settings(scheme: &TransitionScheme, loc: &Location, now: f64,
+                      elev: f64, period: &Period, color_setting: &ColorSetting) {
+        let (style, name) = describe_period(period);
+        output::field("Solar elevation", &format!("{:.2}", elev));
+        output::field("Period", &output::paint(style, &name));
+        output::field("Color temperature", &format!("{}K", color_setting.temp));
+        output::field("Brightness", &format!("{:.2}", color_setting.brightness));
+        let reduction = colorramp::melanopic_reduction(color_setting.temp, color_setting.brightness);
+        output::field("Blue light", &format!("-{:.0}% (melanopic, against 6500K)", reduction * 100.0));
+        if let Some(next) = next_period(scheme, loc, now) {
+            output::field("Next", &describe_next_period(&next, now));
+        }
     }
 
     match args.mode {
         Mode::Reset => {
-            let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-            gamma_state.start()?;
+            let mut gamma_state = start_gamma_method(&args)?;
             gamma_state.set_temperature(&ColorSetting {
-                temp: NEUTRAL_TEMP,
+                temp: NEUTRAL_TEMP.into(),
                 gamma: [1.0, 1.0, 1.0],
                 brightness: 1.0
             })?;
         }
         Mode::OneShot => {
-            let (elev, period, color_setting) = oneshot(&scheme, &args.location);
-            if args.verbose {
-                print_settings(elev, &period, &color_setting);
+            let now = systemtime_get_time();
+            let base = (scheme.day.clone(), scheme.night.clone());
+            let location = apply_exception(args.exceptions.as_ref(), &base, &args.location, &mut scheme, now);
+            let (elev, period, color_setting) = settings_at(&scheme, &location, now);
+            if output::enabled(output::Level::Verbose) {
+                print_settings(&scheme, &location, now, elev, &period, &color_setting);
             }
-            let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-            gamma_state.start()?;
+            let mut gamma_state = start_gamma_method(&args)?;
             gamma_state.set_temperature(&color_setting)?;
         }
+        Mode::OneShotStateful => {
+            let now = systemtime_get_time();
+            let base = (scheme.day.clone(), scheme.night.clone());
+            let location = apply_exception(args.exceptions.as_ref(), &base, &args.location, &mut scheme, now);
+            let (elev, period, color_setting) = settings_at(&scheme, &location, now);
+            if output::enabled(output::Level::Verbose) {
+                print_settings(&scheme, &location, now, elev, &period, &color_setting);
+            }
+            run_oneshot_stateful(&args, &color_setting, now)?;
+        }
         Mode::Print => {
-            let (elev, period, color_setting) = oneshot(&scheme, &args.location);
-            print_settings(elev, &period, &color_setting);
+            let now = args.print_at.unwrap_or_else(systemtime_get_time);
+            let base = (scheme.day.clone(), scheme.night.clone());
+            let location = apply_exception(args.exceptions.as_ref(), &base, &args.location, &mut scheme, now);
+            if let Some((duration, step)) = args.sweep {
+                print_sweep(&scheme, &location, now, duration, step);
+                return Ok(0)
+            }
+            let (elev, period, color_setting) = settings_at(&scheme, &location, now);
+            if args.print_at.is_some() {
+                output::field("Time", &clock::format_time(now));
+            }
+            print_settings(&scheme, &location, now, elev, &period, &color_setting);
+            if args.exit_period {
+                return Ok(match period {
+                    Period::Day => EXIT_DAY,
+                    Period::Night => EXIT_NIGHT,
+                    Period::Transition(_) => EXIT_TRANSITION,
+                    Period::None => 0
+                })
+            }
         }
         Mode::Manual(temp) => {
-            if args.verbose {
-                println!("Color temperature: {}", temp);
-            }
+            verbose!("Color temperature: {}", temp);
             let color_setting = ColorSetting {
-                temp: temp,
+                temp: temp.into(),
                 gamma: scheme.day.gamma.clone(),
                 brightness: scheme.day.brightness
             };
 
-            let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-            gamma_state.start()?;
+            let mut gamma_state = start_gamma_method(&args)?;
             gamma_state.set_temperature(&color_setting)?;
         }
         Mode::Continual => {
             run_continual_mode(args, scheme)?;
         }
+        Mode::Demo => {
+            run_demo(&args, &scheme)?;
+        }
+        Mode::Stress(iterations) => {
+            run_stress(&args, iterations)?;
+        }
+        Mode::Version | Mode::ListSchemes | Mode::WriteDefaultConfig | Mode::Man | Mode::InstallService(_) |
+        Mode::EnableService(_) | Mode::ImportFlux | Mode::Control => {
+            // Handled above
+        }
     }
     Ok(0)
 }
 
+/// Change the day and night settings of `scheme` for the calendar
+/// exception on the date at `now`, if any, starting from the configured
+/// `base` ones, and return the location to use that day
+fn apply_exception(exceptions: Option<&exceptions::Exceptions>, base: &(ColorSetting, ColorSetting),
+                   location: &Location, scheme: &mut TransitionScheme, now: f64) -> Location {
+    scheme.day = base.0.clone();
+    scheme.night = base.1.clone();
+    let mut location = Location::new(location.lat, location.lon);
+    match exceptions.and_then(|exceptions| exceptions.on(now)) {
+        Some(&exceptions::Action::Off) => {
+            for setting in &mut [&mut scheme.day, &mut scheme.night] {
+                setting.temp = NEUTRAL_TEMP.into();
+                setting.brightness = 1.0;
+            }
+        }
+        Some(&exceptions::Action::Location(lat, lon)) => location = Location::new(lat, lon),
+        Some(&exceptions::Action::Temperatures(day, night)) => {
+            scheme.day.temp = day.into();
+            scheme.night.temp = night.into();
+        }
+        None => {}
+    }
+    location
+}
+
+/// The elevation, period and color setting at `now`, as they would be
+/// set in one shot mode
+fn settings_at(scheme: &TransitionScheme, loc: &Location, now: f64) -> (f64, Period, ColorSetting) {
+    let elev = solar::elevation(now, loc);
+    let schedule_elev = scheme.schedule_elevation(now, loc);
+    let period = scheme.get_period(schedule_elev);
+
+    // Interpolate between 6500K and calculated temperature
+    let mut color_setting = scheme.interpolate_color_settings(schedule_elev);
+    scheme.apply_jitter(&mut color_setting, now);
+    scheme.quantize(&mut color_setting);
+
+    (elev, period, color_setting)
+}
+
+/// The name and style of a period in --print output
+fn describe_period(period: &Period) -> (output::Style, String) {
+    match *period {
+        Period::Day => (output::Style::Yellow, format!("Day")),
+        Period::Night => (output::Style::Blue, format!("Night")),
+        Period::Transition(t) => (output::Style::Cyan, format!("Transition ({:.2}% day)", t * 100.0)),
+        Period::None => (output::Style::Bold, format!("None")),
+    }
+}
+
+/// Print the settings every `step` seconds from `start` for
+/// `duration` seconds, as a table
+fn print_sweep(scheme: &TransitionScheme, loc: &Location, start: f64, duration: f64, step: f64) {
+    // Wider for "AM" and "PM", and the hours up to 12
+    let width = if clock::format_time(start).len() > "YYYY-MM-DD HH:MM".len() { 19 } else { 16 };
+    println!("{}", output::paint(output::Style::Bold,
+                                 &format!("{:<width$} {:>9}  {:<24} {:>6} {:>10}",
+                                          "TIME", "ELEVATION", "PERIOD", "TEMP", "BRIGHTNESS", width = width)));
+    let steps = (duration / step).floor() as usize;
+    for (now, period, color_setting) in scheme.iter_schedule(loc, start, step).take(steps + 1) {
+        let (style, name) = describe_period(&period);
+        println!("{:<width$} {:>9.2}  {} {:>5}K {:>10.2}",
+                 clock::format_time(now), solar::elevation(now, loc), output::paint(style, &format!("{:<24}", name)),
+                 color_setting.temp, color_setting.brightness, width = width);
+    }
+}
+
+/// When it will next be fully Day or Night, and which one
+fn next_period(scheme: &TransitionScheme, loc: &Location, now: f64) -> Option<(Period, f64)> {
+    scheme.next_period(now, |t| scheme.schedule_elevation(t, loc))
+        .map(|(period, secs)| (period, now + secs))
+}
+
+/// `next`, from `next_period`, like "Night in 23 min (21:45)"
+fn describe_next_period(next: &(Period, f64), now: f64) -> String {
+    let (ref period, at) = *next;
+    let minutes = ((at - now).max(0.0) / 60.0).round() as i64;
+    if minutes < 60 {
+        format!("{:?} in {} min ({})", period, minutes, clock::format_clock(at))
+    } else {
+        format!("{:?} in {}h {:02}min ({})", period, minutes / 60, minutes % 60, clock::format_clock(at))
+    }
+}
+
+/// Write the default config file to `path`, stdout for "-", or the
+/// user config file if not given
+///
+/// An existing file is never overwritten.
+fn write_default_config(path: Option<&str>) -> Result<()> {
+    let config = settings::default_config();
+    let path = match path {
+        Some("-") => {
+            print!("{}", config);
+            return Ok(())
+        }
+        Some(path) => PathBuf::from(path),
+        None => user_config_path().ok_or("could not find the home directory")?
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut f = fs::OpenOptions::new().write(true).create_new(true).open(&path)
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut f, config.as_bytes())?;
+    info!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Print the preset elevation schemes as a table
+fn list_schemes() {
+    println!("{}", output::paint(output::Style::Bold,
+                                 &format!("{:<18} {:>6} {:>6}  {}", "NAME", "HIGH", "LOW", "DESCRIPTION")));
+    for scheme in transition::SCHEMES {
+        println!("{:<18} {:>6.1} {:>6.1}  {}", scheme.name, scheme.high, scheme.low, scheme.description);
+    }
+}
+
+/// The state key of the method that started last in `session`
+fn remembered_method_key(session: gamma::Session) -> String {
+    format!("method-{}", session)
+}
+
+/// With `adjustment-method=auto`, start the method that started last
+/// in the same session type, if it still does
+fn start_remembered_method(args: &Args) -> Option<Box<gamma::GammaMethod>> {
+    let session = gamma::detect_session();
+    let name = state::State::load(args.system).get::<String>(&remembered_method_key(session))?;
+    if !gamma::is_method_available(&name) {
+        return None
+    }
+    debug!("Trying method {} first, which started last in this session type ({})", name, session);
+    let started = gamma::init_gamma_method(Some(&name), &args.method_options)
+        .and_then(|mut gamma_state| gamma_state.start().map(|()| gamma_state));
+    match started {
+        Ok(gamma_state) => {
+            verbose!("Using method {}", name);
+            Some(gamma_state)
+        }
+        Err(e) => {
+            verbose!("Method {} failed: {}, trying the others", name, e);
+            None
+        }
+    }
+}
+
+/// Keep `name` as the method to try first in this session type
+fn remember_method(system: bool, name: &str) {
+    let mut state = state::State::load(system);
+    let key = remembered_method_key(gamma::detect_session());
+    if state.get::<String>(&key).as_ref().map(|s| s.as_str()) != Some(name) {
+        state.set(&key, name);
+        save_state(&state);
+    }
+}
+
+/// Print the version, and with `verbose` also how this binary was
+/// built, for bug reports
+fn print_version(verbose: bool) {
+    println!("redshift-rs {}", VERSION);
+    if verbose {
+        println!("commit: {}", build_info::GIT_COMMIT.unwrap_or("unknown"));
+        println!("target: {}", build_info::TARGET);
+        println!("features: {}", build_info::FEATURES.join(", "));
+        println!("gamma methods: {}", gamma::method_names().join(", "));
+    }
+}
+
+/// Initialise and start the selected gamma method
+///
+/// When started with the session, the display may not be ready
+/// yet. Starting is attempted up to `start-attempts` times,
+/// `start-interval` seconds apart, and only succeeds once the
+/// `wait-for-output` output (if any) is connected.
+fn start_gamma_method(args: &Args) -> Result<Box<gamma::GammaMethod>> {
+    let mut attempt = 1;
+    loop {
+        match try_start_gamma_method(args) {
+            Ok(gamma_state) => return Ok(gamma_state),
+            Err(e) => {
+                if attempt >= args.start_attempts {
+                    return Err(e)
+                }
+                warn!("Could not start gamma method ({}), retrying in {}s", e, args.start_interval);
+                thread::sleep(std::time::Duration::from_secs(args.start_interval));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn try_start_gamma_method(args: &Args) -> Result<Box<gamma::GammaMethod>> {
+    if args.observe {
+        // Not even connecting to the display, which may not be there
+        let mut observer: Box<gamma::GammaMethod> = Box::new(gamma::Observer);
+        observer.start()?;
+        return Ok(observer)
+    }
+    let auto = args.method.as_ref().map_or(false, |method| method == AUTO_METHOD);
+    let remembered = if auto { start_remembered_method(args) } else { None };
+    let mut gamma_state = match remembered {
+        Some(gamma_state) => gamma_state,
+        None => {
+            let (name, mut gamma_state) = if auto {
+                let (name, gamma_state) = gamma::init_auto_method(&args.method_options)?;
+                (Some(name), gamma_state)
+            } else {
+                (None, gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()), &args.method_options)?)
+            };
+            match gamma_state.start() {
+                Ok(()) => if let Some(name) = name {
+                    remember_method(args.system, name);
+                },
+                // In a VNC or Xvfb session every update would fail, so
+                // rather than erroring once a minute, say so once and do
+                // nothing
+                Err(e) => match e.downcast_ref::<RedshiftError>() {
+                    Some(&RedshiftError::NoAdjustableOutputs(_)) if args.method.is_none() || auto => {
+                        warn!("{}, probably a virtual display. Falling back to the dummy method.", e);
+                        gamma_state = Box::new(gamma::DummyMethod);
+                    }
+                    _ => return Err(e)
+                }
+            }
+            gamma_state
+        }
+    };
+    if let Some(ref output) = args.wait_for_output {
+        if !gamma_state.outputs()?.contains(output) {
+            return Err(Box::new(RedshiftError::OutputNotFound(output.clone())))
+        }
+    }
+    gamma_state.set_preserve(preserves_gamma(args.mode, args.preserve_gamma));
+    gamma_state.set_brightness_floors(&args.brightness_floors)?;
+    gamma_state.set_seat_limits(&args.seat_limits)?;
+
+    // Say so when the method leaves out part of the settings, rather
+    // than ignoring it silently
+    let capabilities = gamma_state.capabilities();
+    if args.mode != Mode::Reset {
+        if !capabilities.color && args.temperatures != (NEUTRAL_TEMP, NEUTRAL_TEMP) {
+            warn!("The adjustment method cannot set the color temperature, only the brightness");
+        }
+        if !capabilities.brightness && (args.brightness.0 < 1.0 || args.brightness.1 < 1.0) {
+            if args.emulate_brightness && capabilities.color {
+                verbose!("Approximating the brightness with the gamma");
+            } else if capabilities.color {
+                warn!("The adjustment method cannot set the brightness (--emulate-brightness approximates it with the gamma)");
+            } else {
+                warn!("The adjustment method cannot set the brightness");
+            }
+        }
+        if capabilities.color {
+            if let Some(night_light) = gamma::active_night_light() {
+                warn!("{} is on too, so windows it adjusts are adjusted twice; turn one of them off", night_light);
+            }
+        }
+    }
+    let gamma_state: Box<gamma::GammaMethod> = if args.emulate_brightness && !capabilities.brightness && capabilities.color {
+        Box::new(gamma::Adjusted::emulated_brightness(gamma_state))
+    } else {
+        gamma_state
+    };
+    let gamma_state = match args.min_brightness {
+        Some(floor) => Box::new(gamma::Adjusted::brightness_floor(gamma_state, floor)),
+        None => gamma_state
+    };
+    Ok(if args.max_adjustment.is_set() {
+        Box::new(gamma::Adjusted::limited(gamma_state, args.max_adjustment.clone()))
+    } else {
+        gamma_state
+    })
+}
+
+/// Whether settings go on top of the existing ramps, as with upstream
+/// redshift
+///
+/// Preserving is the default (`preserve-gamma=1`), and -P turns it
+/// off. Reset mode never preserves, since the ramps it would preserve
+/// are the adjusted ones it is meant to get rid of.
+fn preserves_gamma(mode: Mode, preserve_gamma: bool) -> bool {
+    match mode {
+        Mode::Reset => false,
+        _ => preserve_gamma
+    }
+}
+
+/// Runs that ended less than this many seconds ago count as recent
+/// for --instant-start
+const RECENT_RUN_SECS: f64 = 600.0;
+
+/// How often (in seconds) the time spent in each period is saved, so
+/// that little is lost if redshift-rs is killed
+const PERIOD_TIMES_SAVE_SECS: f64 = 600.0;
+
+/// Length (in seconds) of the brightness dip of a break reminder
+const BREAK_DIP_SECS: f64 = 2.0;
+
+/// How much of the brightness the break reminder takes away at most
+const BREAK_DIP_DEPTH: f64 = 0.3;
+
+/// Counters of what continual mode has done, printed on SIGUSR1 to
+/// help with reports of flicker or of adjustments not sticking
+#[derive(Default)]
+struct Stats {
+    /// Times the temperature was set
+    applies: u64,
+
+    /// Updates skipped because nothing changed
+    skipped: u64,
+
+    /// Updates that failed even after retrying
+    errors: u64,
+
+    /// Failed attempts that were retried
+    retries: u64,
+
+    /// How long the last successful update took
+    last_apply: Option<std::time::Duration>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} updates, {} skipped, {} failed, {} retried",
+               self.applies, self.skipped, self.errors, self.retries)?;
+        if let Some(duration) = self.last_apply {
+            write!(f, ", last update took {:.1} ms",
+                   duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// What continual mode does besides setting the temperature, called
+/// by the engine at each update
+struct Continual {
+    state: state::State,
+    first_apply: bool,
+    /// With --observe, the screen is never adjusted, which the state
+    /// must not claim
+    observe: bool,
+    failing: bool,
+    stats: Stats,
+
+    timezone: Option<location::timezone::Timezone>,
+    exceptions: Option<exceptions::Exceptions>,
+    /// The configured location and day and night settings, before the
+    /// calendar exceptions
+    location: Location,
+    base: (ColorSetting, ColorSetting),
+    today: Option<u32>,
+    /// When it is next Day or Night, found when the period changes
+    next_period: Option<(Period, f64)>,
+
+    color_scheme: Option<theme::ColorScheme>,
+    announcer: Option<notify::Announcer>,
+    cues: Option<notify::Cues>,
+    elevation_log: Option<datalog::ElevationLog>,
+    period_times: state::PeriodTimes,
+    period_times_log: Option<datalog::PeriodTimesLog>,
+    /// When the period times were last saved
+    period_times_saved: f64,
+    break_interval: Option<u32>,
+    next_break: Option<f64>,
+    break_start: Option<f64>,
+    watchdog: Option<watchdog::Notifier>,
+}
+
+impl Continual {
+    /// Count the time spent in `period` since the last update, write
+    /// out the day when it is over, and save the counters now and then
+    fn count_period_time(&mut self, period: &Period, now: f64) {
+        let date = exceptions::date_key(now);
+        let adjusted = !self.failing && !self.first_apply;
+        if let Some(ended) = self.period_times.tick(now, date, period, adjusted) {
+            self.log_period_times(&ended);
+        }
+        if now - self.period_times_saved >= PERIOD_TIMES_SAVE_SECS {
+            self.period_times.store(&mut self.state);
+            save_state(&self.state);
+            self.period_times_saved = now;
+        }
+    }
+
+    fn log_period_times(&mut self, times: &state::PeriodTimes) {
+        verbose!("Time spent on {}: {}", times.date, times);
+        let log_failed = match self.period_times_log {
+            Some(ref mut log) => log.record(times)
+                .map_err(|e| warn!("Could not write the period times log, disabling it: {}", e))
+                .is_err(),
+            None => false
+        };
+        if log_failed {
+            self.period_times_log = None;
+        }
+    }
+
+    /// The update went through, which is what the systemd watchdog
+    /// waits for
+    fn healthy(&mut self) {
+        if let Some(ref mut watchdog) = self.watchdog {
+            if let Err(e) = watchdog.ping() {
+                debug!("Could not ping the systemd watchdog: {}", e);
+            }
+        }
+    }
+}
+
+impl engine::Hook for Continual {
+    fn status(&mut self, outputs: &[gamma::OutputStatus]) {
+        info!("{}", self.stats);
+        info!("Today: {}", self.period_times);
+        if let Some(ref next) = self.next_period {
+            info!("Next: {}", describe_next_period(next, systemtime_get_time()));
+        }
+        for output in outputs {
+            info!("Output {}", output);
+        }
+    }
+
+    fn before_update(&mut self, scheme: &mut TransitionScheme, location: &mut Location, now: f64) {
+        if let Some(ref mut timezone) = self.timezone {
+            if let Some(new_location) = timezone.changed() {
+                verbose!("Timezone changed, now at {}", new_location);
+                self.location = new_location;
+                self.today = None;
+            }
+        }
+
+        // Calendar exceptions are looked up again each day
+        let date = exceptions::date_key(now);
+        if self.today != Some(date) {
+            if let Some(exception) = self.exceptions.as_ref().and_then(|e| e.on(now)) {
+                verbose!("Calendar exception today: {:?}", exception);
+            }
+            *location = apply_exception(self.exceptions.as_ref(), &self.base, &self.location, scheme, now);
+            self.today = Some(date);
+            if self.next_period.is_some() {
+                self.next_period = next_period(scheme, location, now);
+            }
+        }
+    }
+
+    fn period_changed(&mut self, period: &Period, _prev: &Period, scheme: &TransitionScheme,
+                      location: &Location, now: f64) {
+        verbose!("{}", period);
+        // Stepping through the next two days takes a while with
+        // keyframes, so it is done once per period
+        self.next_period = next_period(scheme, location, now);
+        if let Some(ref next) = self.next_period {
+            verbose!("{}", describe_next_period(next, now));
+        }
+        if let Some(ref color_scheme) = self.color_scheme {
+            if let Err(e) = color_scheme.apply(period) {
+                warn!("Could not switch color scheme: {}", e);
+            }
+        }
+        if let Some(ref mut announcer) = self.announcer {
+            let target = if *period == Period::Night { &scheme.night } else { &scheme.day };
+            if let Err(e) = announcer.announce(period, target, now) {
+                warn!("Could not send a notification: {}", e);
+            }
+        }
+        if let Some(ref mut cues) = self.cues {
+            let target = if *period == Period::Night { &scheme.night } else { &scheme.day };
+            if let Err(e) = cues.play(period, target, now) {
+                warn!("{}", e);
+            }
+        }
+    }
+
+    fn adjust(&mut self, color_setting: &mut ColorSetting, elev: f64, period: &Period, now: f64) -> bool {
+        // Break reminder: dip the brightness and bring it
+        // back over a couple of seconds
+        if let (Some(at), Some(minutes)) = (self.next_break, self.break_interval) {
+            if now >= at {
+                verbose!("Time for a break");
+                self.break_start = Some(now);
+                self.next_break = Some(now + minutes as f64 * 60.0);
+            }
+        }
+        if let Some(start) = self.break_start {
+            let t = (now - start) / BREAK_DIP_SECS;
+            if t >= 1.0 {
+                self.break_start = None;
+            } else {
+                color_setting.brightness *= 1.0 - BREAK_DIP_DEPTH * (t * std::f64::consts::PI).sin();
+            }
+        }
+
+        self.count_period_time(period, now);
+
+        let log_failed = match self.elevation_log {
+            Some(ref mut log) => log.record(now, elev, period, color_setting)
+                .map_err(|e| warn!("Could not write elevation log, disabling it: {}", e))
+                .is_err(),
+            None => false
+        };
+        if log_failed {
+            self.elevation_log = None;
+        }
+
+        self.break_start.is_some()
+    }
+
+    fn applied(&mut self, result: &Result<()>, retries: u32, elapsed: std::time::Duration, now: f64) {
+        self.stats.retries += retries as u64;
+        match *result {
+            Ok(()) => {
+                self.stats.applies += 1;
+                self.stats.last_apply = Some(elapsed);
+                if self.failing {
+                    self.state.remove("error");
+                    save_state(&self.state);
+                    self.failing = false;
+                }
+                if self.first_apply && !self.observe {
+                    self.state.set("adjusted", 1);
+                    self.state.set("time", now);
+                    save_state(&self.state);
+                }
+                self.first_apply = false;
+                self.healthy();
+            }
+            Err(ref e) => {
+                self.stats.errors += 1;
+                if !self.failing {
+                    self.state.set("error", e);
+                    save_state(&self.state);
+                    self.failing = true;
+                }
+            }
+        }
+    }
+
+    fn skipped(&mut self) {
+        self.stats.skipped += 1;
+        if !self.failing {
+            self.healthy();
+        }
+    }
+
+    fn stopped(&mut self, now: f64) {
+        verbose!("{}", self.stats);
+        if !self.observe {
+            self.state.set("adjusted", 0);
+            self.state.set("time", now);
+        }
+        self.period_times.store(&mut self.state);
+        save_state(&self.state);
+    }
+}
+
 /// Continual mode
 ///
 /// The default functionality of Redshift is to run continually
 /// adjusting the temperature as the day progresses. It is interrupted
-/// by signals INT and TERM that both cause it to terminate.
-///
-/// TODO: Respect the transition scheme, espectially in the presence
-///       of the --no-transition flag
+/// by signals INT and TERM that both cause it to terminate. USR1
+/// prints counters of the updates made so far, and what each output
+/// was last set to. USR2 resets the screen at once and pauses, as
+/// before a presentation, until the next USR2, unless the policy
+/// forbids disabling. HUP sets the temperature again, for when
+/// something else reset the screen. Frontends talk to it over the
+/// control socket, see the control module, and with follow-inhibit it
+/// pauses while the desktop's night light is inhibited.
 fn run_continual_mode(args: Args, mut scheme: transition::TransitionScheme) -> Result<()> {
-    let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-    gamma_state.start()?;
+    let gamma_state = start_gamma_method(&args)?;
+    let allows_disable = args.allows_disable();
+
+    // If the previous run ended only recently, or never restored the
+    // screen, fading in from 6500K would make the screen flash
+    let state = state::State::load(args.system);
+
+    if args.system && privilege::is_root() {
+        let user = args.user.as_ref().map_or(DEFAULT_SYSTEM_USER, |user| user.as_str());
+        fs::create_dir_all(state::SYSTEM_STATE_DIR)?;
+        privilege::drop_to(user, &[Path::new(state::SYSTEM_STATE_DIR)])?;
+        verbose!("Dropped privileges to user {}", user);
+    }
+    if !args.fade_on_start {
+        scheme.short_trans_delta = 0;
+        scheme.adjustment_alpha = 0.0;
+    } else if args.instant_start {
+        let recent = state.get::<f64>("time")
+            .map_or(false, |t| systemtime_get_time() - t < RECENT_RUN_SECS);
+        let adjusted = state.get::<i32>("adjusted") == Some(1);
+        if recent || adjusted {
+            debug!("Skipping startup fade (recent run: {}, still adjusted: {})", recent, adjusted);
+            scheme.short_trans_delta = 0;
+            scheme.adjustment_alpha = 0.0;
+        }
+    }
+
+    let elevation_log = match args.log_elevation {
+        Some(ref path) => Some(datalog::ElevationLog::open(std::path::Path::new(path))?),
+        None => None
+    };
+
+    // Bound before the sandbox, which forbids creating the socket
+    let control_socket = if args.control_socket && !args.system {
+        control::socket_path()
+            .and_then(|path| control::Server::bind(&path, args.control_locks()))
+            .map_err(|e| warn!("Not listening on the control socket: {}", e))
+            .ok()
+    } else {
+        None
+    };
 
-    // Create signal thread
-    let sigint = chan_signal::notify(&[chan_signal::Signal::INT,
-                                       chan_signal::Signal::TERM]);
-    let (signal_tx, signal_rx) = chan::sync(0);
-    thread::spawn(move || {
-        for sig in sigint.iter() {
-            signal_tx.send(sig);
+    if args.sandbox {
+        let mut sandbox = sandbox::Sandbox::new();
+        if let Some(dir) = state.dir() {
+            fs::create_dir_all(dir)?;
+            sandbox.allow_write(dir);
+        }
+        if let Some(ref log) = elevation_log {
+            for path in log.writable_paths()? {
+                sandbox.allow_write(&path);
+            }
+        }
+        if let Some(ref path) = args.log_period_times {
+            sandbox.allow_write(Path::new(path));
+        }
+        for path in gamma_state.writable_paths() {
+            sandbox.allow_write(&path);
+        }
+        // Switching the color scheme writes the desktop's settings
+        if args.color_scheme.is_some() {
+            if let Some(dir) = user_config_dir() {
+                sandbox.allow_write(&dir);
+            }
         }
+        sandbox.apply()?;
+        verbose!("Restricted writing to the state directory");
+    }
+
+    // Before any thread is started, so that they all leave the
+    // signals to chan_signal, and after the sandbox, which only
+    // restricts the threads started after it
+    let signals = chan_signal::notify(&[chan_signal::Signal::INT,
+                                        chan_signal::Signal::TERM,
+                                        chan_signal::Signal::HUP,
+                                        chan_signal::Signal::USR1,
+                                        chan_signal::Signal::USR2]);
+
+    let watchdog = watchdog::Notifier::from_env().unwrap_or_else(|e| {
+        warn!("Not notifying systemd: {}", e);
+        None
     });
+    if let Some(ref watchdog) = watchdog {
+        if let Err(e) = watchdog.ready() {
+            warn!("Could not notify systemd: {}", e);
+        }
+    }
+
+    let now = systemtime_get_time();
+    // Counting goes on from the last run today, and the day of an
+    // earlier run is over
+    let today = exceptions::date_key(now);
+    let mut period_times_log = match args.log_period_times {
+        Some(ref path) => Some(datalog::PeriodTimesLog::open(Path::new(path))?),
+        None => None
+    };
+    let period_times = match state::PeriodTimes::load(&state) {
+        Some(ref times) if times.date == today => times.clone(),
+        Some(ref times) => {
+            if let Some(ref mut log) = period_times_log {
+                log.record(times)?;
+            }
+            state::PeriodTimes::new(today)
+        }
+        None => state::PeriodTimes::new(today)
+    };
 
-    let (timer_tx, timer_rx) = chan::sync(0);
-    let (sleep_tx, sleep_rx) = chan::sync(0);
-    thread::spawn(move || {
-        for ms in sleep_rx.iter() {
-            thread::sleep(std::time::Duration::from_millis(ms));
-            timer_tx.send(());
+    let continual = Continual {
+        state: state,
+        first_apply: true,
+        observe: args.observe,
+        failing: false,
+        stats: Stats::default(),
+        timezone: args.timezone,
+        exceptions: args.exceptions,
+        location: Location::new(args.location.lat, args.location.lon),
+        base: (scheme.day.clone(), scheme.night.clone()),
+        today: None,
+        next_period: None,
+        color_scheme: args.color_scheme,
+        announcer: args.announcer,
+        cues: args.cues,
+        elevation_log: elevation_log,
+        period_times: period_times,
+        period_times_log: period_times_log,
+        period_times_saved: now,
+        break_interval: args.break_interval,
+        next_break: args.break_interval.map(|minutes| now + minutes as f64 * 60.0),
+        break_start: None,
+        watchdog: watchdog,
+    };
+
+    let mut engine = engine::Engine::new(scheme, args.location);
+    engine.smooth = args.smooth;
+    engine.fade_on_exit = args.fade_on_exit;
+    engine.add_adjuster(gamma_state);
+    engine.add_hook(continual);
+    // Last, so that it sees the setting the other hooks leave
+    if let Some((server, hook)) = control_socket {
+        engine.add_hook(hook);
+        engine.add_event_source(server);
+    }
+    if args.follow_inhibit {
+        if allows_disable {
+            engine.add_event_source(inhibit::watch);
+        } else {
+            warn!("Not following the night light inhibition, redshift-rs cannot be disabled");
+        }
+    }
+    engine.add_event_source(move |events: chan::Sender<engine::Event>| {
+        for signal in signals.iter() {
+            if let Some(event) = signal_event(signal, allows_disable) {
+                events.send(event);
+            }
         }
     });
+    engine.run()
+}
 
-    let mut now;
-    let mut exiting = false;
-    let mut prev_color_setting = ColorSetting::new();
-    let mut prev_period = Period::None;
-    sleep_tx.send(0);
-    loop {
-        chan_select! {
-            signal_rx.recv() -> _signal => {
-                if exiting {
-                    break // If already exiting, just exit immediately
-                }
-                exiting = true;
-                scheme.short_trans_delta = 1;
-                scheme.short_trans_len = 2;
-                scheme.adjustment_alpha = 0.1;
-            },
-            timer_rx.recv() => {
-                now = systemtime_get_time();
-
-                // Compute elevation
-                let elev = solar::elevation(now, &args.location);
-
-                let period = scheme.get_period(elev);
-                if period != prev_period {
-                    if args.verbose {
-                        println!("{}", period);
-                    }
-                    prev_period = period;
-                }
+/// The event for `signal` in continual mode
+///
+/// USR2 is ignored when the policy forbids disabling, as resetting the
+/// screen would.
+fn signal_event(signal: chan_signal::Signal, allows_disable: bool) -> Option<engine::Event> {
+    match signal {
+        chan_signal::Signal::HUP => Some(engine::Event::Refresh),
+        chan_signal::Signal::USR1 => Some(engine::Event::Status),
+        chan_signal::Signal::USR2 if !allows_disable => {
+            warn!("Ignoring USR2, redshift-rs cannot be disabled");
+            None
+        }
+        chan_signal::Signal::USR2 => Some(engine::Event::Reset),
+        _ => Some(engine::Event::Quit)
+    }
+}
+
+/// Longest time (in seconds) since the last --oneshot-stateful run for
+/// the screen to be taken to still show its setting
+const STATEFUL_MAX_GAP_SECS: f64 = 3600.0;
 
-                // Interpolate between 6500K and calculated temperature
-                let mut color_setting = scheme.interpolate_color_settings(elev);
+/// Length (in seconds) of the fade of --oneshot-stateful
+const STATEFUL_FADE_SECS: f64 = 5.0;
 
-                /* Ongoing short transition? */
-                if scheme.short_transition() {
-                    scheme.adjust_transition_alpha();
-                    color_setting.temp = (scheme.adjustment_alpha * NEUTRAL_TEMP as f64 +
-                                          (1.0-scheme.adjustment_alpha) * color_setting.temp as f64) as i32;
-                    color_setting.brightness = scheme.adjustment_alpha * 1.0 +
-                        (1.0-scheme.adjustment_alpha) * color_setting.brightness;
+/// Stateful one shot mode
+///
+/// For running from a timer, where plain one shots jump from one
+/// setting to the next. `color_setting`, the setting for `now`, is
+/// saved with the state, and the next run fades from it, as continual
+/// mode would. Without a recent run to fade from, it fades in from
+/// neutral like continual mode at start.
+///
+/// The ramps found at start are those of the last run, so they are
+/// not preserved again. A calibration loaded before the first run is
+/// only kept by that one.
+fn run_oneshot_stateful(args: &Args, color_setting: &ColorSetting, now: f64) -> Result<()> {
+    // Started first, as it may save the method in the state
+    let mut gamma_state = start_gamma_method(args)?;
+    let mut state = state::State::load(args.system);
+    let recent = state.get::<f64>("time").map_or(false, |t| now - t >= 0.0 && now - t < STATEFUL_MAX_GAP_SECS);
+    let last = if recent && state.get::<i32>("adjusted") == Some(1) {
+        saved_setting(&state)
+    } else {
+        None
+    };
+
+    if last.is_some() {
+        gamma_state.set_preserve(false);
+    }
+    let from = match last {
+        Some(setting) => Some(setting),
+        None if args.fade_on_start => Some(ColorSetting { temp: NEUTRAL_TEMP.into(), gamma: [1.0, 1.0, 1.0], brightness: 1.0 }),
+        None => None
+    };
+    match from {
+        Some(ref from) if from != color_setting => {
+            debug!("Fading from {}K to {}K", from.temp, color_setting.temp);
+            let steps = (STATEFUL_FADE_SECS * 10.0) as u32;
+            for step in 1..steps + 1 {
+                gamma_state.set_temperature(&from.mix(color_setting, step as f64 / steps as f64))?;
+                if step < steps {
+                    thread::sleep(std::time::Duration::from_millis(100));
                 }
+            }
+        }
+        _ => gamma_state.set_temperature(color_setting)?
+    }
 
-                if args.verbose {
-                    if color_setting.temp != prev_color_setting.temp {
-                        println!("Color temperature: {:?}K", color_setting.temp);
-                    }
-                    if color_setting.brightness != prev_color_setting.brightness {
-                        println!("Brightness: {:?}", color_setting.brightness);
-                    }
+    if args.observe {
+        return Ok(())
+    }
+    state.set("adjusted", 1);
+    state.set("time", now);
+    state.set("temp", color_setting.temp);
+    state.set("brightness", color_setting.brightness);
+    state.set("gamma", format!("{}:{}:{}", color_setting.gamma[0], color_setting.gamma[1], color_setting.gamma[2]));
+    save_state(&state);
+    Ok(())
+}
+
+/// The setting saved by --oneshot-stateful
+fn saved_setting(state: &state::State) -> Option<ColorSetting> {
+    let gamma = state.get::<String>("gamma").and_then(|gamma| parse_gamma(&gamma).ok());
+    match (state.get::<i32>("temp"), gamma, state.get("brightness")) {
+        (Some(temp), Some((r, g, b)), Some(brightness)) => Some(ColorSetting {
+            temp: temp.into(),
+            gamma: [r, g, b],
+            brightness: brightness
+        }),
+        _ => None
+    }
+}
+
+/// Length (in seconds) of --demo, down to the night setting and back
+const DEMO_SECS: f64 = 30.0;
+
+/// Demo mode
+///
+/// Fades from neutral to the night setting and back, so that new users
+/// see the effect, and that the method works, right away. INT and TERM
+/// end it early, and the screen is restored either way.
+fn run_demo(args: &Args, scheme: &TransitionScheme) -> Result<()> {
+    let signals = chan_signal::notify(&[chan_signal::Signal::INT, chan_signal::Signal::TERM]);
+    let mut gamma_state = start_gamma_method(args)?;
+    info!("Fading to {}K and back over {}s", scheme.night.temp, DEMO_SECS);
+
+    let neutral = ColorSetting { temp: NEUTRAL_TEMP.into(), gamma: [1.0, 1.0, 1.0], brightness: 1.0 };
+    let night = &scheme.night;
+    let tick = chan::tick_ms(100);
+    let start = std::time::Instant::now();
+    let mut temp_log = output::Throttle::new();
+    let result = loop {
+        chan_select! {
+            signals.recv() => break Ok(()),
+            tick.recv() => {
+                let elapsed = start.elapsed();
+                let t = (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9) / DEMO_SECS;
+                if t >= 1.0 {
+                    break Ok(())
                 }
-                if color_setting != prev_color_setting {
-                    gamma_state.set_temperature(&color_setting)?;
+                // 0 at both ends, full night halfway
+                let alpha = (1.0 - (t * 2.0 * std::f64::consts::PI).cos()) / 2.0;
+                let setting = neutral.mix(night, alpha);
+                if temp_log.allow(false) {
+                    verbose!("Color temperature: {}K", setting.temp);
                 }
-
-                if exiting && !scheme.short_transition() {
-                    break
+                if let Err(e) = gamma_state.set_temperature(&setting) {
+                    break Err(e)
                 }
+            }
+        }
+    };
+    gamma_state.restore()?;
+    result
+}
 
-                // Sleep for 5 seconds or 0.1 second
-                sleep_tx.send(if scheme.short_transition() { 100 } else { 5000 });
+/// Stress test mode, for backend authors
+///
+/// Sets random color settings as fast as possible, and now and then
+/// restores the ramps or restarts the method (which re-reads the
+/// outputs, like after a hotplug). Prints timings and how much the
+/// resident memory grew, then restores the screen.
+fn run_stress(args: &Args, iterations: u32) -> Result<()> {
+    let mut gamma_state = start_gamma_method(args)?;
+    let rss_before = resident_memory();
+
+    // xorshift64, seeded from the clock; quality does not matter here
+    let mut seed = (systemtime_get_time() * 1000.0) as u64 | 1;
+    let mut random = move |n: u64| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed % n
+    };
 
-                /* Save temperature */
-                prev_color_setting = color_setting;
+    let (mut sets, mut restores, mut restarts) = (vec![], 0, 0);
+    let start = std::time::Instant::now();
+    for _ in 0..iterations {
+        let setting = ColorSetting {
+            temp: (MIN_TEMP + random((MAX_TEMP - MIN_TEMP) as u64) as i32).into(),
+            gamma: [1.0, 1.0, 1.0],
+            brightness: MIN_BRIGHTNESS + random(101) as f64 / 100.0 * (MAX_BRIGHTNESS - MIN_BRIGHTNESS)
+        };
+        let set_start = std::time::Instant::now();
+        gamma_state.set_temperature(&setting)?;
+        sets.push(set_start.elapsed());
+
+        match random(100) {
+            0..=4 => {
+                gamma_state.restore()?;
+                restores += 1;
+            }
+            5 => {
+                gamma_state.restore()?;
+                gamma_state.start()?;
+                restarts += 1;
             }
+            _ => {}
         }
     }
-    gamma_state.restore()
+    gamma_state.restore()?;
+
+    let ms = |d: std::time::Duration| d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1e6;
+    sets.sort();
+    if !sets.is_empty() {
+        let total: f64 = sets.iter().map(|&d| ms(d)).sum();
+        output::field("set_temperature", &format!("{} calls, {:.3} ms avg, {:.3} ms median, {:.3} ms max",
+                                                  sets.len(), total / sets.len() as f64,
+                                                  ms(sets[sets.len() / 2]), ms(sets[sets.len() - 1])));
+    }
+    output::field("restore", &format!("{} calls", restores));
+    output::field("restart", &format!("{} calls", restarts));
+    output::field("Total time", &format!("{:.1} ms", ms(start.elapsed())));
+    if let (Some(before), Some(after)) = (rss_before, resident_memory()) {
+        output::field("Memory growth", &format!("{} KiB ({} KiB resident)", after as i64 - before as i64, after));
+    }
+    Ok(())
 }
 
-fn systemtime_get_time() -> f64 {
-    let now = time::get_time();
-    now.sec as f64 + (now.nsec as f64 / 1_000_000_000.0)
+/// Resident memory of this process in KiB, where /proc has it
+fn resident_memory() -> Option<u64> {
+    let mut status = String::new();
+    fs::File::open("/proc/self/status")
+        .and_then(|mut f| std::io::Read::read_to_string(&mut f, &mut status))
+        .ok()?;
+    status.lines()
+        .find(|line| line.starts_with("VmRSS:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kib| kib.parse().ok())
+}
+
+/// Save the state, which is only a hint, so failing to save it is not
+/// an error
+fn save_state(state: &state::State) {
+    if let Err(e) = state.save() {
+        warn!("Could not save state: {}", e);
+    }
 }
 
+
 #[cfg(test)]
-pub mod tests {
+mod tests {
     use super::*;
 
     #[test]
-    pub fn test_parse_brightness() {
-        let input = "4500:3500";
-
-        let r = parse_brightness(input);
-        assert!(r.is_err());
-
-        let e = r.unwrap_err();
-        assert!(e.is::<RedshiftError>());
+    fn test_preserves_gamma() {
+        for &mode in &[Mode::Continual, Mode::OneShot, Mode::Manual(4500)] {
+            assert!(preserves_gamma(mode, true));
+            assert!(!preserves_gamma(mode, false));
+        }
+        assert!(!preserves_gamma(Mode::Reset, true));
+        assert!(!preserves_gamma(Mode::Reset, false));
+    }
 
-        let e: RedshiftError = *e.downcast().unwrap();
-        assert_eq!(RedshiftError::MalformedArgument("Brightness values must be between 0.1 and 1.0".to_string()), e);
+    #[test]
+    fn test_signal_event() {
+        let is_reset = |event: Option<engine::Event>| match event {
+            Some(engine::Event::Reset) => true,
+            _ => false
+        };
+        assert!(is_reset(signal_event(chan_signal::Signal::USR2, true)));
+        assert!(signal_event(chan_signal::Signal::USR2, false).is_none());
+        assert!(!is_reset(signal_event(chan_signal::Signal::USR1, false)));
+        assert!(signal_event(chan_signal::Signal::TERM, false).is_some());
+        assert!(match signal_event(chan_signal::Signal::HUP, false) {
+            Some(engine::Event::Refresh) => true,
+            _ => false
+        });
     }
 }
@@ -0,0 +1,215 @@
+//! Desktop notifications and cues when the period changes
+//!
+//! With `announce=1`, continual mode sends a notification like "Night
+//! mode: 3500K" through the org.freedesktop.Notifications D-Bus
+//! interface when day or night begins. Each notification replaces the
+//! previous one. With `cue-day` and `cue-night`, it runs a command or
+//! plays a sound file instead, for those who would not see the
+//! notification.
+//!
+//! At most one notification, and one cue, is sent every
+//! `MIN_INTERVAL` seconds, so that a location hovering around a
+//! transition elevation cannot flood the desktop.
+
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+
+use transition::{ColorSetting, Period};
+use super::Result;
+
+/// Fewest seconds between two notifications, or two cues
+pub const MIN_INTERVAL: f64 = 600.0;
+
+/// Cues ending in these are sound files, played with paplay
+const SOUND_EXTENSIONS: &'static [&'static str] = &["oga", "ogg", "wav", "flac"];
+
+/// Lets through at most one of something every `MIN_INTERVAL`
+struct Debounce {
+    last: Option<f64>,
+}
+
+impl Debounce {
+    fn new() -> Debounce {
+        Debounce { last: None }
+    }
+
+    /// Whether `what` may go out at `now`
+    fn ready(&mut self, what: &str, now: f64) -> bool {
+        if let Some(last) = self.last {
+            if now - last < MIN_INTERVAL {
+                debug!("Not {}, the last one was {:.0}s ago", what, now - last);
+                return false
+            }
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+/// How long the notification server should show a notification, in
+/// milliseconds
+const TIMEOUT: i32 = 5000;
+
+pub struct Announcer {
+    debounce: Debounce,
+    /// The id of the last notification, for replacing it
+    id: u32,
+}
+
+impl Announcer {
+
+    /// Fails if redshift-rs was built without the notify feature
+    pub fn new() -> Result<Announcer> {
+        dbus_notify::check()?;
+        Ok(Announcer { debounce: Debounce::new(), id: 0 })
+    }
+
+    /// Announce that `period` began at `now`, with `setting` as its
+    /// target. Transitions are not announced.
+    pub fn announce(&mut self, period: &Period, setting: &ColorSetting, now: f64) -> Result<()> {
+        let summary = match summary(period, setting) {
+            Some(summary) => summary,
+            None => return Ok(())
+        };
+        if !self.debounce.ready(&format!("announcing '{}'", summary), now) {
+            return Ok(())
+        }
+        self.id = dbus_notify::notify(self.id, &summary, TIMEOUT)?;
+        Ok(())
+    }
+}
+
+/// Commands or sound files for the start of day and of night
+pub struct Cues {
+    day: Option<String>,
+    night: Option<String>,
+    debounce: Debounce,
+}
+
+impl Cues {
+    pub fn new(day: Option<String>, night: Option<String>) -> Cues {
+        Cues { day: day, night: night, debounce: Debounce::new() }
+    }
+
+    /// Play the cue for `period`, which began at `now` with `setting`
+    /// as its target. The cue runs in the background, with the period
+    /// and temperature in REDSHIFT_PERIOD and REDSHIFT_TEMP.
+    pub fn play(&mut self, period: &Period, setting: &ColorSetting, now: f64) -> Result<()> {
+        let (cue, name) = match (period, &self.day, &self.night) {
+            (&Period::Day, &Some(ref cue), _) => (cue, "day"),
+            (&Period::Night, _, &Some(ref cue)) => (cue, "night"),
+            _ => return Ok(())
+        };
+        if !self.debounce.ready(&format!("playing the {} cue", name), now) {
+            return Ok(())
+        }
+        let mut cmd = if is_sound_file(cue) {
+            let mut cmd = Command::new("paplay");
+            cmd.arg(cue);
+            cmd
+        } else {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(cue);
+            cmd
+        };
+        cmd.env("REDSHIFT_PERIOD", name).env("REDSHIFT_TEMP", setting.temp.to_string());
+        debug!("Playing the {} cue: {:?}", name, cmd);
+        let mut child = cmd.spawn()
+            .map_err(|e| format!("could not run the {} cue '{}': {}", name, cue, e))?;
+        // Only to reap it, and to tell why there was nothing to hear
+        let cue = cue.clone();
+        thread::spawn(move || {
+            match child.wait() {
+                Ok(status) if !status.success() => warn!("The {} cue '{}' failed: {}", name, cue, status),
+                Ok(_) => {}
+                Err(e) => warn!("The {} cue '{}' failed: {}", name, cue, e)
+            }
+        });
+        Ok(())
+    }
+}
+
+/// A cue that is a sound file, rather than a command
+fn is_sound_file(cue: &str) -> bool {
+    !cue.contains(char::is_whitespace) &&
+        Path::new(cue).extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| SOUND_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn summary(period: &Period, setting: &ColorSetting) -> Option<String> {
+    match *period {
+        Period::Day => Some(format!("Day mode: {}K", setting.temp)),
+        Period::Night => Some(format!("Night mode: {}K", setting.temp)),
+        Period::None | Period::Transition(_) => None
+    }
+}
+
+#[cfg(feature = "notify")]
+mod dbus_notify {
+    use dbus::{BusType, Connection, Message};
+    use dbus::arg::{Dict, Variant};
+
+    use super::super::Result;
+
+    const NOTIFICATIONS: &'static str = "org.freedesktop.Notifications";
+
+    pub fn check() -> Result<()> {
+        Ok(())
+    }
+
+    /// Send a notification replacing `replaces_id` (0 for none), and
+    /// return the id of the new one
+    pub fn notify(replaces_id: u32, summary: &str, timeout: i32) -> Result<u32> {
+        let c = Connection::get_private(BusType::Session)?;
+        let actions: &[&str] = &[];
+        let hints: Dict<&str, Variant<u8>, _> = Dict::new(Vec::new().into_iter());
+        let m = Message::new_method_call(NOTIFICATIONS, "/org/freedesktop/Notifications", NOTIFICATIONS, "Notify")?
+            .append3("redshift-rs", replaces_id, "redshift")
+            .append3(summary, "", actions)
+            .append2(hints, timeout);
+        let r = c.send_with_reply_and_block(m, 2000)?;
+        r.get1().ok_or_else(|| From::from("no id in the reply to Notify"))
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+mod dbus_notify {
+    use super::super::Result;
+
+    pub fn check() -> Result<()> {
+        Err(From::from("redshift-rs was built without the notify feature"))
+    }
+
+    pub fn notify(_replaces_id: u32, _summary: &str, _timeout: i32) -> Result<u32> {
+        check().map(|_| 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use transition::{ColorSetting, Kelvin, Period};
+    use super::{is_sound_file, summary, Debounce, MIN_INTERVAL};
+
+    #[test]
+    fn test_summary() {
+        let mut setting = ColorSetting::new();
+        setting.temp = Kelvin(3500.0);
+        assert_eq!(summary(&Period::Night, &setting), Some("Night mode: 3500K".to_owned()));
+        assert_eq!(summary(&Period::Transition(0.5), &setting), None);
+    }
+
+    #[test]
+    fn test_cues() {
+        assert!(is_sound_file("/usr/share/sounds/freedesktop/stereo/complete.oga"));
+        assert!(is_sound_file("chime.WAV"));
+        assert!(!is_sound_file("paplay chime.wav"));
+        assert!(!is_sound_file("notify-send Night"));
+
+        let mut debounce = Debounce::new();
+        assert!(debounce.ready("test", 1000.0));
+        assert!(!debounce.ready("test", 1000.0 + MIN_INTERVAL - 1.0));
+        assert!(debounce.ready("test", 1000.0 + MIN_INTERVAL));
+    }
+}
@@ -0,0 +1,243 @@
+//! Output levels and formatting
+//!
+//! Everything but errors and the output explicitly asked for (like
+//! --print) goes through the macros defined here, so that --quiet
+//! and --verbose apply the same way in every module.
+//!
+//! Warnings go to stderr, the rest to stdout. Each stream is colored
+//! only when it is a terminal, and never when NO_COLOR is set (see
+//! https://no-color.org) or --no-color is given.
+
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use atty;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Nothing but errors
+    Quiet,
+
+    /// Notable events, like the selected gamma method
+    ///
+    /// This is the default
+    Normal,
+
+    /// Temperature, brightness and period changes
+    Verbose,
+
+    /// Details useful when tracking down problems
+    Debug,
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Normal as usize);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Whether messages at `level` are currently shown
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Shortest time (in ms) between two verbose messages of one kind
+static THROTTLE_MS: AtomicUsize = AtomicUsize::new(1000);
+
+/// Show verbose messages of one kind at most once per `interval`
+/// (`verbose-interval`), or all of them if it is zero
+pub fn set_throttle(interval: Duration) {
+    THROTTLE_MS.store((interval.as_secs() * 1000 + interval.subsec_millis() as u64) as usize, Ordering::Relaxed);
+}
+
+/// Rate limit for verbose messages of one kind, like the temperature,
+/// which would otherwise be printed ten times a second during fades
+pub struct Throttle {
+    last: Option<Instant>,
+    held: bool,
+}
+
+impl Throttle {
+    pub fn new() -> Throttle {
+        Throttle { last: None, held: false }
+    }
+
+    /// Whether to show a message now. When `settled`, no more messages
+    /// are expected for a while, so it is shown anyway: the last value
+    /// is never held back.
+    pub fn allow(&mut self, settled: bool) -> bool {
+        let interval = Duration::from_millis(THROTTLE_MS.load(Ordering::Relaxed) as u64);
+        if settled || self.last.map_or(true, |last| last.elapsed() >= interval) {
+            self.last = Some(Instant::now());
+            self.held = false;
+            true
+        } else {
+            self.held = true;
+            false
+        }
+    }
+
+    /// A message was held back since the last one shown
+    pub fn held(&self) -> bool {
+        self.held
+    }
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+static COLOR_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Width of the label column in `field`
+const LABEL_WIDTH: usize = 19;
+
+/// Decide whether to use colors, `no_color` being --no-color
+pub fn init_color(no_color: bool) {
+    let wanted = !no_color
+        && env::var_os("NO_COLOR").map_or(true, |value| value.is_empty());
+    COLOR.store(wanted && atty::is(atty::Stream::Stdout), Ordering::Relaxed);
+    COLOR_STDERR.store(wanted && atty::is(atty::Stream::Stderr), Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Bold,
+    Yellow,
+    Blue,
+    Cyan,
+}
+
+/// Wrap `text` in the escape codes for `style`, if colors are enabled
+/// on stdout
+pub fn paint(style: Style, text: &str) -> String {
+    paint_if(COLOR.load(Ordering::Relaxed), style, text)
+}
+
+/// Like `paint`, for text printed to stderr
+pub fn paint_stderr(style: Style, text: &str) -> String {
+    paint_if(COLOR_STDERR.load(Ordering::Relaxed), style, text)
+}
+
+fn paint_if(color: bool, style: Style, text: &str) -> String {
+    if !color {
+        return text.to_owned()
+    }
+    let code = match style {
+        Style::Bold => "1",
+        Style::Yellow => "1;33",
+        Style::Blue => "1;34",
+        Style::Cyan => "1;36",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// Print `label` and `value`, with the values lined up in a column
+pub fn field(label: &str, value: &str) {
+    println!("{:<2$} {}", format!("{}:", label), value, LABEL_WIDTH);
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if $crate::output::enabled($crate::output::Level::Normal) {
+            eprintln!("{}", $crate::output::paint_stderr($crate::output::Style::Yellow,
+                                                         &format!("Warning: {}", format!($($arg)*))))
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::output::enabled($crate::output::Level::Normal) { println!($($arg)*) }
+    }
+}
+
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::output::enabled($crate::output::Level::Verbose) { println!($($arg)*) }
+    }
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::output::enabled($crate::output::Level::Debug) { println!($($arg)*) }
+    }
+}
+
+/// Time between two summaries of an error repeated at every update
+const SUMMARY_SECS: u64 = 300;
+
+/// An error repeated at every update, like a method failing to set the
+/// temperature: the first one is shown, then every five minutes a
+/// summary of those since, rather than all of them
+pub struct ErrorSummary {
+    /// What failed, as in "Setting the temperature"
+    what: &'static str,
+    total: u64,
+
+    /// Failures since the last message, and when that was
+    count: u64,
+    shown: Option<Instant>,
+}
+
+impl ErrorSummary {
+    pub fn new(what: &'static str) -> ErrorSummary {
+        ErrorSummary { what: what, total: 0, count: 0, shown: None }
+    }
+
+    /// Count a failure, and show it if it is the first or a summary is
+    /// due
+    pub fn failed(&mut self, error: &str) {
+        if let Some(message) = self.message(error, Instant::now()) {
+            warn!("{}", message);
+        }
+    }
+
+    fn message(&mut self, error: &str, now: Instant) -> Option<String> {
+        self.total += 1;
+        self.count += 1;
+        let message = match self.shown {
+            None => format!("{} failed, will keep trying: {}", self.what, error),
+            Some(shown) if now.duration_since(shown) >= Duration::from_secs(SUMMARY_SECS) =>
+                format!("{} failed {} times in the last {} min: {}", self.what, self.count,
+                        now.duration_since(shown).as_secs() / 60, error),
+            Some(_) => return None
+        };
+        self.count = 0;
+        self.shown = Some(now);
+        Some(message)
+    }
+
+    /// The failures are over. Returns how many there were.
+    pub fn succeeded(&mut self) -> u64 {
+        let total = self.total;
+        *self = ErrorSummary::new(self.what);
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::ErrorSummary;
+
+    #[test]
+    fn test_error_summary() {
+        let mut errors = ErrorSummary::new("Setting the temperature");
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(errors.message("no such CRTC", at(0)).unwrap(),
+                   "Setting the temperature failed, will keep trying: no such CRTC");
+        for secs in 1..57 {
+            assert_eq!(errors.message("no such CRTC", at(secs * 5)), None);
+        }
+        assert_eq!(errors.message("bad match", at(300)).unwrap(),
+                   "Setting the temperature failed 57 times in the last 5 min: bad match");
+        assert_eq!(errors.message("bad match", at(305)), None);
+        assert_eq!(errors.succeeded(), 59);
+        assert!(errors.message("bad match", at(310)).unwrap().ends_with("will keep trying: bad match"));
+    }
+}
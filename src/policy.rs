@@ -0,0 +1,235 @@
+//! Settings locked by the administrator
+//!
+//! `/etc/redshift-rs/policy.conf` has the same format as the config
+//! file. Its values are applied after everything else, and cannot be
+//! changed: setting one of its keys to another value in the config
+//! file, or giving a command line option that would change it, is an
+//! error. A `[policy]` section holds rules that are not settings
+//! themselves:
+//!
+//! - `allow-disable=0` forbids resetting the screen (-x), setting a
+//!   fixed temperature (-O), only observing (--observe) and disabling
+//!   the service.
+//!
+//! The calendar exceptions of the user may not change locked settings
+//! either, nor turn the adjustment off when it may not be disabled.
+
+use std::path::Path;
+
+use config::Config;
+use exceptions::{Action, Exceptions};
+use super::{Result, RedshiftError};
+
+pub const POLICY_PATH: &'static str = "/etc/redshift-rs/policy.conf";
+
+/// The config keys each command line option would change, with the
+/// section they are in (`None` for the main section)
+const OPTION_KEYS: &'static [(&'static str, &'static [(Option<&'static str>, &'static str)])] = &[
+    ("brightness", &[(None, "brightness"), (None, "brightness-day"), (None, "brightness-night")]),
+    ("temperature", &[(None, "temp"), (None, "temp-day"), (None, "temp-night")]),
+    ("gamma", &[(None, "gamma"), (None, "gamma-day"), (None, "gamma-night")]),
+    ("location", &[(None, "location-provider"), (Some("manual"), "lat"), (Some("manual"), "lon"),
+                  (Some("timezone"), "lat")]),
+    ("method", &[(None, "adjustment-method")]),
+    ("no-preserve", &[(None, "preserve-gamma")]),
+    ("no-transition", &[(None, "fade-on-start"), (None, "fade-on-exit"), (None, "transition")]),
+    ("no-startup-fade", &[(None, "fade-on-start"), (None, "transition")]),
+    ("no-exit-fade", &[(None, "fade-on-exit"), (None, "transition")]),
+    ("smooth", &[(None, "smooth")]),
+    ("emulate-brightness", &[(None, "emulate-brightness")]),
+    ("instant-start", &[(None, "instant-start")]),
+    ("jitter", &[(None, "jitter")]),
+    ("break-reminder", &[(None, "break-reminder")]),
+    ("day-neutral", &[(None, "day-neutral")]),
+];
+
+/// The command line options that leave the screen unadjusted, which
+/// `allow-disable=0` forbids
+const DISABLING_OPTIONS: &'static [&'static str] = &["oneshot-manual", "reset", "observe"];
+
+/// Keys setting both the day and the night value, and the keys that
+/// override them for one of the two
+const GENERAL_KEYS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("temp", &["temp-day", "temp-night"]),
+    ("gamma", &["gamma-day", "gamma-night"]),
+    ("brightness", &["brightness-day", "brightness-night"]),
+];
+
+pub struct Policy {
+    conf: Config,
+}
+
+impl Policy {
+
+    /// Load the policy file, if there is one
+    pub fn load() -> Result<Option<Policy>> {
+        Ok(Config::load(Path::new(POLICY_PATH))?.map(Policy::new))
+    }
+
+    pub fn new(conf: Config) -> Policy {
+        Policy { conf: conf }
+    }
+
+    /// The locked settings, to apply like a config file
+    pub fn config(&self) -> &Config {
+        &self.conf
+    }
+
+    /// Whether the screen may be reset or set to a fixed temperature
+    pub fn allows_disable(&self) -> bool {
+        self.conf.get_from(Some("policy"), "allow-disable") != Some("0")
+    }
+
+    /// The user's configuration `user`, if any, with the locked settings
+    /// on top
+    ///
+    /// Fails if `user` changes a locked setting, as `check_config`
+    /// does. The result is applied as one config file, so that keys
+    /// of the user cannot override the policy's by being applied after
+    /// them, like temp-night after a locked temp.
+    pub fn layer_onto(&self, user: Option<Config>) -> Result<Config> {
+        let mut merged = user.unwrap_or_else(Config::new);
+        self.check_config(&merged)?;
+        merged.overlay(&self.conf);
+        Ok(merged)
+    }
+
+    /// Fail if `user` sets a locked key to a different value, or sets
+    /// the day or night key overriding a locked general key (like
+    /// temp-night when temp is locked)
+    pub fn check_config(&self, user: &Config) -> Result<()> {
+        for (section, key) in self.conf.keys() {
+            let section = section.as_ref().map(|s| s.as_str());
+            if section == Some("policy") {
+                continue
+            }
+            // The main section may be [redshift] in one file and the
+            // top of the file in the other
+            let user_section = if section == self.conf.main_section() {
+                user.main_section()
+            } else {
+                section
+            };
+            let locked = self.conf.get_from(section, &key);
+            let overriding = match section == self.conf.main_section() {
+                true => GENERAL_KEYS.iter().find(|&&(general, _)| general == key).map_or(&[][..], |&(_, keys)| keys),
+                false => &[],
+            };
+            for user_key in Some(key.as_str()).into_iter().chain(overriding.iter().cloned()) {
+                match user.get_from(user_section, user_key) {
+                    Some(value) if Some(value) != locked => {
+                        let position = user.position(user_section, user_key)
+                            .map_or(String::new(), |position| format!(" (set at {})", position));
+                        return self.violation(section, &key, format!("{} cannot be changed{}", user_key, position))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail if a command line option given, as told by `given`, would
+    /// change a locked key
+    pub fn check_options<F: Fn(&str) -> bool>(&self, given: F) -> Result<()> {
+        if !self.allows_disable() {
+            if let Some(option) = DISABLING_OPTIONS.iter().find(|option| given(option)) {
+                return Err(Box::new(RedshiftError::PolicyViolation(
+                    format!("--{} cannot be used, redshift-rs cannot be disabled", option))))
+            }
+        }
+        for &(option, _) in OPTION_KEYS.iter().filter(|&&(option, _)| given(option)) {
+            if let Some((section, key)) = self.locked_by(option) {
+                return self.violation(section, key, format!("--{} cannot be used", option))
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail if a calendar exception in `exceptions`, read from `path`,
+    /// would change a locked setting
+    ///
+    /// Exceptions the policy itself points to are the administrator's,
+    /// and not checked.
+    pub fn check_exceptions(&self, exceptions: &Exceptions, path: &str) -> Result<()> {
+        if self.conf.get_from(self.conf.main_section(), "exceptions").is_some() {
+            return Ok(())
+        }
+        for (line, action) in exceptions.actions() {
+            let (what, locked) = match *action {
+                Action::Off if !self.allows_disable() => {
+                    return Err(Box::new(RedshiftError::PolicyViolation(format!(
+                        "{}: line {}: off cannot be used, redshift-rs cannot be disabled", path, line))))
+                }
+                Action::Off | Action::Temperatures(..) => ("temp", self.locked_by("temperature")),
+                Action::Location(..) => ("location", self.locked_by("location")),
+            };
+            if let Some((section, key)) = locked {
+                return self.violation(section, key, format!("{}: line {}: {} cannot be used", path, line, what))
+            }
+        }
+        Ok(())
+    }
+
+    /// The first locked key that the command line option `option`
+    /// would change, with its section
+    fn locked_by(&self, option: &str) -> Option<(Option<&str>, &'static str)> {
+        let &(_, keys) = OPTION_KEYS.iter().find(|&&(name, _)| name == option)?;
+        keys.iter()
+            .map(|&(section, key)| (section.or(self.conf.main_section()), key))
+            .find(|&(section, key)| self.conf.get_from(section, key).is_some())
+    }
+
+    fn violation<T>(&self, section: Option<&str>, key: &str, msg: String) -> Result<T> {
+        let locked_at = self.conf.position(section, key).unwrap_or(POLICY_PATH.to_owned());
+        Err(Box::new(RedshiftError::PolicyViolation(format!("{}, {} is locked at {}", msg, key, locked_at))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::Config;
+    use exceptions::Exceptions;
+    use super::Policy;
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy::new(Config::parse("[redshift]\ntemp-night=4000\n\n[policy]\nallow-disable=0\n").unwrap());
+        assert!(!policy.allows_disable());
+
+        assert!(policy.check_config(&Config::parse("[redshift]\ntemp-night=4000\ntemp-day=6000\n").unwrap()).is_ok());
+        assert!(policy.check_config(&Config::parse("temp-night=3000\n").unwrap()).is_err());
+        assert!(policy.check_config(&Config::parse("[redshift]\ntemp-night=3000\n").unwrap()).is_err());
+
+        let policy = Policy::new(Config::parse("temp=4000\n").unwrap());
+        assert!(policy.check_config(&Config::parse("[redshift]\ntemp-night=3000\n").unwrap()).is_err());
+        assert!(policy.check_config(&Config::parse("temp-day=4000\ntemp-night=4000\n").unwrap()).is_ok());
+        assert!(policy.check_config(&Config::parse("gamma-night=0.8\n").unwrap()).is_ok());
+
+        // Layered on a config file without a [redshift] section, the
+        // policy's main section still lands in the file's
+        let mut merged = Config::parse("temp-day=5000\n").unwrap();
+        merged.overlay(&Config::parse("[redshift]\ntemp-night=4000\n").unwrap());
+        assert_eq!(merged.main_section(), None);
+        assert_eq!(merged.get_from(None, "temp-night"), Some("4000"));
+
+        assert!(policy.check_options(|option| option == "temperature").is_err());
+        assert!(policy.check_options(|option| option == "brightness" || option == "verbose").is_ok());
+        assert!(policy.check_options(|option| option == "observe").is_ok());
+        let locked = Policy::new(Config::parse("[policy]\nallow-disable=0\n").unwrap());
+        for option in &["observe", "reset", "oneshot-manual"] {
+            assert!(locked.check_options(|given| given == *option).is_err());
+        }
+        assert!(locked.check_options(|option| option == "temperature").is_ok());
+
+        let exceptions = Exceptions::parse("2026-12-24 location -33.9:18.4\n2026-12-25 temp 6500:4500\n").unwrap();
+        assert!(policy.check_exceptions(&exceptions, "exceptions").is_err());
+        let policy = Policy::new(Config::parse("[manual]\nlat=55.7\n\n[policy]\nallow-disable=0\n").unwrap());
+        assert!(policy.check_exceptions(&Exceptions::parse("2026-12-25 temp 6500:4500\n").unwrap(), "exceptions").is_ok());
+        assert!(policy.check_exceptions(&exceptions, "exceptions").is_err());
+        assert!(policy.check_exceptions(&Exceptions::parse("2026-12-25 off\n").unwrap(), "exceptions").is_err());
+        let policy = Policy::new(Config::parse("brightness=0.9\n").unwrap());
+        assert!(policy.check_exceptions(&exceptions, "exceptions").is_ok());
+        assert!(policy.check_exceptions(&Exceptions::parse("2026-12-25 off\n").unwrap(), "exceptions").is_ok());
+    }
+}
@@ -0,0 +1,64 @@
+//! Dropping root privileges
+//!
+//! In --system mode redshift-rs may be started as root, so that it
+//! can open display devices before any user has logged in. Once they
+//! are open it switches to an unprivileged user for good.
+
+use std::ffi::CString;
+use std::io;
+use std::path::Path;
+
+use libc;
+use super::{Result, RedshiftError};
+
+/// Whether we are running as root
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Switch to `user` (and its primary group) for good, first handing
+/// it the directories in `own` so that it can still write there
+pub fn drop_to(user: &str, own: &[&Path]) -> Result<()> {
+    let name = CString::new(user)
+        .or_else(|_| fail(format!("invalid user name '{}'", user)))?;
+
+    let (uid, gid) = unsafe {
+        let pw = libc::getpwnam(name.as_ptr());
+        if pw.is_null() {
+            return fail(format!("no such user '{}' (create it, or choose another with user=)", user))
+        }
+        ((*pw).pw_uid, (*pw).pw_gid)
+    };
+
+    for dir in own {
+        let path = CString::new(dir.to_string_lossy().into_owned())
+            .or_else(|_| fail(format!("invalid path {}", dir.display())))?;
+        if unsafe { libc::chown(path.as_ptr(), uid, gid) } != 0 {
+            return fail(format!("could not hand {} to '{}': {}", dir.display(), user, last_error()))
+        }
+    }
+
+    // Groups first, as changing them needs root
+    unsafe {
+        if libc::setgroups(0, ::std::ptr::null()) != 0 || libc::setgid(gid) != 0 {
+            return fail(format!("could not change group: {}", last_error()))
+        }
+        if libc::setuid(uid) != 0 {
+            return fail(format!("could not change user: {}", last_error()))
+        }
+        // Make sure there is no way back
+        if libc::setuid(0) == 0 {
+            return fail(format!("still able to regain root after dropping privileges"))
+        }
+    }
+    Ok(())
+}
+
+fn last_error() -> io::Error {
+    io::Error::last_os_error()
+}
+
+#[inline]
+fn fail<T>(msg: String) -> Result<T> {
+    Err(Box::new(RedshiftError::PrivilegeDrop(msg)))
+}
@@ -0,0 +1,176 @@
+//! Restricting where the daemon can write
+//!
+//! With `sandbox=1`, continual mode restricts itself with Landlock
+//! once everything it needs is open. The filesystem stays readable
+//! and programs can still be run (the xrandr method and the color
+//! scheme switchers need both), but writing is only allowed to the
+//! paths given with `allow_write`. no_new_privs is set too, so the
+//! programs run cannot gain privileges either.
+//!
+//! Kernels without Landlock (before 5.13, or with it disabled) fail
+//! with an error, rather than carrying on unprotected.
+//!
+//! Landlock restricts the calling thread and the threads it starts
+//! afterwards, so the sandbox is applied before continual mode starts
+//! any thread, the signal thread included.
+//!
+//! System calls are not filtered: a seccomp filter is out of scope,
+//! since the gamma methods, D-Bus and the programs run between them
+//! need too many of them to make a useful list.
+
+use std::path::{Path, PathBuf};
+
+use super::{Result, RedshiftError};
+
+pub struct Sandbox {
+    writable: Vec<PathBuf>,
+}
+
+impl Sandbox {
+
+    pub fn new() -> Sandbox {
+        Sandbox { writable: vec![] }
+    }
+
+    /// Allow writing to `path`, or anywhere below it for a directory
+    pub fn allow_write(&mut self, path: &Path) {
+        self.writable.push(path.to_owned());
+    }
+
+    /// Restrict this process (and the programs it runs) for good
+    pub fn apply(&self) -> Result<()> {
+        landlock::restrict(&self.writable)
+    }
+}
+
+#[inline]
+fn fail<T>(msg: String) -> Result<T> {
+    Err(Box::new(RedshiftError::Sandbox(msg)))
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::ffi::CString;
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::ptr;
+
+    use libc;
+    use super::fail;
+    use super::super::Result;
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    /// Removing and making files of all kinds, up to MAKE_SYM
+    const ACCESS_FS_MAKE_AND_REMOVE: u64 = 0x1ff0;
+    /// Linking and renaming across directories, from ABI 2
+    const ACCESS_FS_REFER: u64 = 1 << 13;
+    /// Truncating, from ABI 3
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+    /// The rights that make sense on a file rather than a directory
+    const FILE_RIGHTS: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    pub fn restrict(writable: &[PathBuf]) -> Result<()> {
+        let abi = unsafe {
+            libc::syscall(libc::SYS_landlock_create_ruleset,
+                          ptr::null::<RulesetAttr>(), 0, CREATE_RULESET_VERSION)
+        };
+        if abi < 1 {
+            return fail(format!("the kernel does not support Landlock ({})", io::Error::last_os_error()))
+        }
+
+        let read = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+        let mut all = read | ACCESS_FS_WRITE_FILE | ACCESS_FS_MAKE_AND_REMOVE;
+        if abi >= 2 {
+            all |= ACCESS_FS_REFER;
+        }
+        if abi >= 3 {
+            all |= ACCESS_FS_TRUNCATE;
+        }
+
+        let attr = RulesetAttr { handled_access_fs: all };
+        let ruleset = unsafe {
+            libc::syscall(libc::SYS_landlock_create_ruleset,
+                          &attr as *const RulesetAttr, mem::size_of::<RulesetAttr>(), 0)
+        } as libc::c_int;
+        if ruleset < 0 {
+            return fail(format!("could not create a Landlock ruleset: {}", io::Error::last_os_error()))
+        }
+
+        let result = add_rules(ruleset, writable, read, all);
+        unsafe { libc::close(ruleset) };
+        result
+    }
+
+    fn add_rules(ruleset: libc::c_int, writable: &[PathBuf], read: u64, all: u64) -> Result<()> {
+        add_rule(ruleset, &PathBuf::from("/"), read)?;
+        for path in writable {
+            add_rule(ruleset, path, all)?;
+        }
+        restrict_self(ruleset)
+    }
+
+    fn add_rule(ruleset: libc::c_int, path: &PathBuf, access: u64) -> Result<()> {
+        let name = CString::new(path.as_os_str().as_bytes())
+            .or_else(|_| fail(format!("invalid path {}", path.display())))?;
+        let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return fail(format!("could not open {}: {}", path.display(), io::Error::last_os_error()))
+        }
+        let access = if path.is_dir() { access } else { access & FILE_RIGHTS };
+        let attr = PathBeneathAttr { allowed_access: access, parent_fd: fd };
+        let added = unsafe {
+            libc::syscall(libc::SYS_landlock_add_rule, ruleset, RULE_PATH_BENEATH,
+                          &attr as *const PathBeneathAttr, 0)
+        };
+        let error = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        if added < 0 {
+            return fail(format!("could not allow access to {}: {}", path.display(), error))
+        }
+        Ok(())
+    }
+
+    fn restrict_self(ruleset: libc::c_int) -> Result<()> {
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return fail(format!("could not set no_new_privs: {}", io::Error::last_os_error()))
+            }
+            if libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) != 0 {
+                return fail(format!("could not restrict the process: {}", io::Error::last_os_error()))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod landlock {
+    use std::path::PathBuf;
+
+    use super::fail;
+    use super::super::Result;
+
+    pub fn restrict(_writable: &[PathBuf]) -> Result<()> {
+        fail(format!("only supported on Linux"))
+    }
+}
@@ -0,0 +1,233 @@
+//! Installing redshift-rs to start with the session
+//!
+//! `install-service` writes a systemd user unit (or, with
+//! `--autostart`, an XDG autostart entry) that runs redshift-rs with
+//! the flags given before the subcommand. `enable` and `disable`
+//! switch whichever of the two is installed on and off.
+//!
+//! Along with the unit comes a socket unit for the control socket, so
+//! that `ctl` starts the service if it is not running.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::{Result, RedshiftError};
+
+const UNIT_NAME: &'static str = "redshift-rs.service";
+const SOCKET_UNIT_NAME: &'static str = "redshift-rs.socket";
+const DESKTOP_NAME: &'static str = "redshift-rs.desktop";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `~/.config/systemd/user/redshift-rs.service`
+    Systemd,
+
+    /// `~/.config/autostart/redshift-rs.desktop`
+    Autostart,
+}
+
+impl Kind {
+    fn path(&self) -> Result<PathBuf> {
+        let mut path = config_dir()?;
+        match *self {
+            Kind::Systemd => { path.push("systemd/user"); path.push(UNIT_NAME); }
+            Kind::Autostart => { path.push("autostart"); path.push(DESKTOP_NAME); }
+        }
+        Ok(path)
+    }
+}
+
+/// Write the unit or autostart entry running this binary with `flags`
+pub fn install(kind: Kind, flags: &[String]) -> Result<PathBuf> {
+    let exe = env::current_exe()?;
+    let quote: fn(&str) -> String = match kind {
+        Kind::Systemd => systemd_quote,
+        Kind::Autostart => desktop_exec_quote,
+    };
+    let mut command = quote(&exe.to_string_lossy());
+    for flag in flags {
+        command.push(' ');
+        command.push_str(&quote(flag));
+    }
+
+    let contents = match kind {
+        Kind::Systemd => format!("\
+[Unit]
+Description=Adjust the color temperature of the screen
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+ExecStart={}
+Restart=on-failure
+# Restarted when the adjustment keeps failing, see the watchdog module
+NotifyAccess=main
+WatchdogSec=60
+
+[Install]
+WantedBy=graphical-session.target
+Also={}
+", command, SOCKET_UNIT_NAME),
+        Kind::Autostart => format!("\
+[Desktop Entry]
+Type=Application
+Name=redshift-rs
+Comment=Adjust the color temperature of the screen
+Exec={}
+Hidden=false
+", command),
+    };
+
+    let path = kind.path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    File::create(&path)?.write_all(contents.as_bytes())?;
+    if kind == Kind::Systemd {
+        File::create(socket_unit_path()?)?.write_all(b"\
+[Unit]
+Description=Control socket of redshift-rs
+PartOf=graphical-session.target
+
+[Socket]
+ListenStream=%t/redshift-rs.sock
+SocketMode=0600
+
+[Install]
+WantedBy=graphical-session.target
+")?;
+        systemctl(&["daemon-reload"])?;
+    }
+    Ok(path)
+}
+
+/// Start the installed service now and with every session
+pub fn enable() -> Result<()> {
+    set_enabled(true)
+}
+
+/// Stop the installed service and no longer start it
+pub fn disable() -> Result<()> {
+    set_enabled(false)
+}
+
+fn set_enabled(enabled: bool) -> Result<()> {
+    if Kind::Systemd.path()?.is_file() {
+        let mut args = vec![if enabled { "enable" } else { "disable" }, "--now", UNIT_NAME];
+        // Not there when installed by an earlier version
+        if socket_unit_path()?.is_file() {
+            args.push(SOCKET_UNIT_NAME);
+        }
+        return systemctl(&args)
+    }
+
+    let path = Kind::Autostart.path()?;
+    if !path.is_file() {
+        return Err(Box::new(RedshiftError::MalformedArgument(
+            format!("not installed, run install-service first"))))
+    }
+    let mut contents = String::new();
+    File::open(&path)?.read_to_string(&mut contents)?;
+    let contents: Vec<_> = contents.lines()
+        .map(|line| if line.starts_with("Hidden=") {
+            format!("Hidden={}", !enabled)
+        } else {
+            line.to_owned()
+        })
+        .collect();
+    File::create(&path)?.write_all((contents.join("\n") + "\n").as_bytes())?;
+    Ok(())
+}
+
+fn socket_unit_path() -> Result<PathBuf> {
+    Ok(Kind::Systemd.path()?.with_file_name(SOCKET_UNIT_NAME))
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    let mut cmd = Command::new("systemctl");
+    cmd.arg("--user").args(args);
+    debug!("Running {:?}", cmd);
+    let status = cmd.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(From::from(format!("{:?} failed ({})", cmd, status)))
+    }
+}
+
+fn config_dir() -> Result<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|mut home| { home.push(".config"); home }))
+        .ok_or_else(|| From::from("could not find the home directory"))
+}
+
+/// Quote an argument for systemd's ExecStart=, which takes C escapes
+/// in double quotes, and $$ and %% for a literal $ and %
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if !arg.is_empty() && !arg.chars().any(|c| c.is_whitespace() || "\"'\\;".contains(c)) {
+        return arg
+    }
+    double_quote(&arg, "\"\\")
+}
+
+/// Quote an argument for the desktop entry Exec= key
+///
+/// Arguments with reserved characters are double quoted, with ", `, $
+/// and \ escaped. The key's value is a string, whose escapes come on
+/// top, so every backslash is then doubled.
+fn desktop_exec_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    let quoted = if !arg.is_empty() && !arg.chars().any(|c| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c)) {
+        arg
+    } else {
+        double_quote(&arg, "\"`$\\")
+    };
+    quoted.replace('\\', "\\\\")
+}
+
+/// `arg` in double quotes, with a backslash before each of `escaped`
+fn double_quote(arg: &str, escaped: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if escaped.contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{desktop_exec_quote, systemd_quote};
+
+    #[test]
+    fn test_systemd_quote() {
+        assert_eq!(systemd_quote("-l"), "-l");
+        assert_eq!(systemd_quote("55.7:12.6"), "55.7:12.6");
+        assert_eq!(systemd_quote("/opt/my apps/redshift-rs"), "\"/opt/my apps/redshift-rs\"");
+        assert_eq!(systemd_quote("50%"), "50%%");
+        assert_eq!(systemd_quote("$HOME/bin"), "$$HOME/bin");
+        assert_eq!(systemd_quote("C:\\redshift"), "\"C:\\\\redshift\"");
+        assert_eq!(systemd_quote("a \"$x\" 5%"), "\"a \\\"$$x\\\" 5%%\"");
+        assert_eq!(systemd_quote(""), "\"\"");
+    }
+
+    #[test]
+    fn test_desktop_exec_quote() {
+        assert_eq!(desktop_exec_quote("-l"), "-l");
+        assert_eq!(desktop_exec_quote("55.7:12.6"), "55.7:12.6");
+        assert_eq!(desktop_exec_quote("/opt/my apps/redshift-rs"), "\"/opt/my apps/redshift-rs\"");
+        assert_eq!(desktop_exec_quote("50%"), "50%%");
+        assert_eq!(desktop_exec_quote("$HOME/bin"), "\"\\\\$HOME/bin\"");
+        assert_eq!(desktop_exec_quote("C:\\redshift"), "\"C:\\\\\\\\redshift\"");
+        assert_eq!(desktop_exec_quote("a \"`x`\" 5%"), "\"a \\\\\"\\\\`x\\\\`\\\\\" 5%%\"");
+        assert_eq!(desktop_exec_quote(""), "\"\"");
+    }
+}
@@ -0,0 +1,705 @@
+//! Parsing of the values given on the command line and in the
+//! config file
+//!
+//! These only deal with strings, and must never panic on any input.
+//! The bounds they check against are public, so that frontends can
+//! limit their own inputs the same way.
+
+use time;
+
+use clock;
+use gamma;
+use location;
+use solar;
+use transition;
+use super::{Result, RedshiftError};
+use super::{DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP, DEFAULT_BRIGHTNESS, DEFAULT_GAMMA};
+
+/// Lowest color temperature in Kelvin, the start of the blackbody table
+pub const MIN_TEMP:            i32 = 1000;
+/// Highest color temperature in Kelvin, the end of the blackbody table
+pub const MAX_TEMP:            i32 = 25000;
+pub const MIN_GAMMA:           f64 = 0.1;
+pub const MAX_GAMMA:           f64 = 10.0;
+/// Lowest brightness, so that the screen never goes completely black
+pub const MIN_BRIGHTNESS:      f64 = 0.1;
+pub const MAX_BRIGHTNESS:      f64 = 1.0;
+
+/// Most rows a sweep may print
+pub const MAX_SWEEP_STEPS: u32 = 10000;
+
+/// The allowed color temperatures, as (MIN_TEMP, MAX_TEMP)
+pub fn temperature_bounds() -> (i32, i32) {
+    (MIN_TEMP, MAX_TEMP)
+}
+
+/// The allowed gamma values, as (MIN_GAMMA, MAX_GAMMA)
+pub fn gamma_bounds() -> (f64, f64) {
+    (MIN_GAMMA, MAX_GAMMA)
+}
+
+/// The allowed brightness values, as (MIN_BRIGHTNESS, MAX_BRIGHTNESS)
+pub fn brightness_bounds() -> (f64, f64) {
+    (MIN_BRIGHTNESS, MAX_BRIGHTNESS)
+}
+
+/// Whether `temp` lies within the temperature bounds
+pub fn is_valid_temperature(temp: i32) -> bool {
+    temp >= MIN_TEMP && temp <= MAX_TEMP
+}
+
+/// Whether `gamma` lies within the gamma bounds (never true for NaN)
+pub fn is_valid_gamma(gamma: f64) -> bool {
+    gamma >= MIN_GAMMA && gamma <= MAX_GAMMA
+}
+
+/// Whether `brightness` lies within the brightness bounds (never true
+/// for NaN)
+pub fn is_valid_brightness(brightness: f64) -> bool {
+    brightness >= MIN_BRIGHTNESS && brightness <= MAX_BRIGHTNESS
+}
+
+#[inline]
+fn malformed<T>(msg: String) -> Result<T> {
+    Err(Box::new(RedshiftError::MalformedArgument(msg)))
+}
+
+/// Parse a gamma method option
+///
+/// Expected as "KEY=VALUE".
+pub fn parse_method_option(method: &str, input: &str) -> Result<gamma::MethodOption> {
+    let mut parts = input.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => Ok(gamma::MethodOption {
+            method: method.to_owned(),
+            key: key.to_owned(),
+            value: value.to_owned()
+        }),
+        _ => malformed(format!("method option: {} (expected KEY=VALUE)", input))
+    }
+}
+
+/// Parse the temperature argument
+///
+/// Expected as "DAY:NIGHT", or as a single one for both, each as in
+/// `parse_temperature_expr` with the default day or night temperature.
+/// Any other input produces an error.
+pub fn parse_temperature(input: &str) -> Result<(i32, i32)> {
+    let mut parts = input.split(':');
+    let day = parts.next().unwrap_or("");
+    let night = parts.next().unwrap_or(day);
+    parts.next().map_or(Ok(()),
+                        |_| malformed(format!("temperature argument: {}", input)))?;
+
+    Ok((parse_temperature_expr(day, DEFAULT_DAY_TEMP)?, parse_temperature_expr(night, DEFAULT_NIGHT_TEMP)?))
+}
+
+/// Parse one temperature, an integer between MIN_TEMP and MAX_TEMP, or
+/// with a sign an offset from `default`, as in "+200" or "-500"
+pub fn parse_temperature_expr(input: &str, default: i32) -> Result<i32> {
+    let (sign, number) = match input.chars().next() {
+        Some('+') => (1, &input[1..]),
+        Some('-') => (-1, &input[1..]),
+        _ => (0, input)
+    };
+    // parse() would take a second sign
+    if !number.starts_with(|c: char| c.is_ascii_digit()) {
+        return malformed(format!("temperature: {}", input))
+    }
+    let n: i32 = number.parse().or(malformed(format!("temperature: {}", input)))?;
+    let t = if sign == 0 { n } else { default.saturating_add(sign * n) };
+
+    // Temperatures outside the range would index past the end of the
+    // blackbody table
+    if !is_valid_temperature(t) {
+        return malformed(format!("Temperature must be between {} and {} (was {})", MIN_TEMP, MAX_TEMP, t))
+    }
+    Ok(t)
+}
+
+/// Parse the brightness floors
+///
+/// A comma separated list of either a single brightness, applied to
+/// all outputs, or OUTPUT:BRIGHTNESS for a single output, like
+/// "0.2,eDP-1:0.4".
+pub fn parse_brightness_floors(input: &str) -> Result<(Option<f64>, Vec<(String, f64)>)> {
+    let mut all = None;
+    let mut outputs = vec![];
+    for entry in input.split(',').map(|entry| entry.trim()) {
+        let (output, value) = match entry.rfind(':') {
+            Some(i) => (Some(&entry[..i]), &entry[i+1..]),
+            None => (None, entry)
+        };
+        let floor = value.parse::<f64>()
+            .or(malformed(format!("min-brightness: {} (of {})", value, input)))?;
+        if !(floor >= 0.0 && floor <= MAX_BRIGHTNESS) {
+            return malformed(format!("min-brightness must be between 0 and {:.1} (was {})", MAX_BRIGHTNESS, floor))
+        }
+        match output {
+            Some(name) if !name.is_empty() => outputs.push((name.to_owned(), floor)),
+            Some(_) => return malformed(format!("min-brightness: missing output name in {}", entry)),
+            None => all = Some(floor)
+        }
+    }
+    Ok((all, outputs))
+}
+
+/// Parse the interval of the break reminder
+///
+/// A number of minutes, where 0 turns the reminder off.
+pub fn parse_break_interval(input: &str) -> Result<Option<u32>> {
+    match input.parse() {
+        Ok(0) => Ok(None),
+        Ok(minutes) => Ok(Some(minutes)),
+        Err(_) => malformed(format!("break reminder: {} (expected a number of minutes)", input))
+    }
+}
+
+/// Parse how times of day are printed: "locale", "24h" or "12h"
+pub fn parse_time_format(input: &str) -> Result<clock::TimeFormat> {
+    match input {
+        "locale" => Ok(clock::TimeFormat::Locale),
+        "24h" => Ok(clock::TimeFormat::Hours24),
+        "12h" => Ok(clock::TimeFormat::Hours12),
+        _ => malformed(format!("time-format: {} (expected locale, 24h or 12h)", input))
+    }
+}
+
+/// Parse the step temperatures are rounded to, in Kelvin
+///
+/// 0 turns rounding off.
+pub fn parse_quantize_temp(input: &str) -> Result<i32> {
+    match input.parse() {
+        Ok(step) if step >= 0 && step <= MAX_TEMP - MIN_TEMP => Ok(step),
+        _ => malformed(format!("quantize-temp: {} (expected a number of Kelvin between 0 and {})",
+                               input, MAX_TEMP - MIN_TEMP))
+    }
+}
+
+/// Longest `verbose-interval`, in seconds
+pub const MAX_VERBOSE_INTERVAL: f64 = 3600.0;
+
+/// Parse the shortest time between two verbose messages of one kind,
+/// in seconds
+///
+/// 0 shows every message.
+pub fn parse_verbose_interval(input: &str) -> Result<f64> {
+    match input.parse() {
+        Ok(secs) if secs >= 0.0 && secs <= MAX_VERBOSE_INTERVAL => Ok(secs),
+        _ => malformed(format!("verbose-interval: {} (expected a number of seconds between 0 and {})",
+                               input, MAX_VERBOSE_INTERVAL))
+    }
+}
+
+/// Parse a duration like "24h", "90m" or "1h30m", in seconds
+///
+/// The units are s, m, h and d, and a number without a unit is in
+/// seconds. The duration must be positive.
+pub fn parse_duration(input: &str) -> Result<f64> {
+    let bad_duration = || malformed(format!("duration: {} (expected something like 24h or 1h30m)", input));
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in input.trim().chars() {
+        let unit = match c {
+            '0'..='9' | '.' => { number.push(c); continue }
+            's' => 1.0,
+            'm' => 60.0,
+            'h' => 3600.0,
+            'd' => 86400.0,
+            _ => return bad_duration()
+        };
+        total += number.parse::<f64>().or_else(|_| bad_duration())? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        total += number.parse::<f64>().or_else(|_| bad_duration())?;
+    }
+    if !(total > 0.0 && total.is_finite()) {
+        return malformed(format!("duration must be positive (was {})", input))
+    }
+    Ok(total)
+}
+
+/// Parse the length and step of a sweep, as "DURATION[/STEP]"
+///
+/// The step defaults to 30 minutes, and is at most the duration.
+pub fn parse_sweep(input: &str) -> Result<(f64, f64)> {
+    let mut parts = input.splitn(2, '/');
+    let duration = parse_duration(parts.next().unwrap_or(""))?;
+    let step = parts.next().map_or(Ok(30.0 * 60.0), parse_duration)?.min(duration);
+    if duration / step > MAX_SWEEP_STEPS as f64 {
+        return malformed(format!("sweep: {} would take more than {} steps", input, MAX_SWEEP_STEPS))
+    }
+    Ok((duration, step))
+}
+
+/// Largest offset of a keyframe from sunrise or sunset, in hours
+pub const MAX_KEYFRAME_OFFSET: i64 = 12;
+
+/// Parse a time relative to sunrise or sunset, like "sunset-00:30", for
+/// the config key `key`
+///
+/// The offset is optional, and at most MAX_KEYFRAME_OFFSET hours.
+pub fn parse_keyframe(key: &str, input: &str) -> Result<transition::Keyframe> {
+    let bad_keyframe = || malformed(format!("{}: {} (expected sunrise or sunset, optionally followed by +HH:MM or -HH:MM)",
+                                            key, input));
+    let input = input.trim();
+    let (event, rest) = if input.starts_with("sunrise") {
+        (solar::Event::Sunrise, &input["sunrise".len()..])
+    } else if input.starts_with("sunset") {
+        (solar::Event::Sunset, &input["sunset".len()..])
+    } else {
+        return bad_keyframe()
+    };
+    if rest.is_empty() {
+        return Ok(transition::Keyframe { event: event, offset: 0.0 })
+    }
+    let sign = match rest.chars().next() {
+        Some('+') => 1.0,
+        Some('-') => -1.0,
+        _ => return bad_keyframe()
+    };
+    let mut parts = rest[1..].splitn(2, ':');
+    let (hours, minutes) = match (parts.next().map(str::parse::<i64>), parts.next().map(str::parse::<i64>)) {
+        (Some(Ok(hours)), Some(Ok(minutes))) if hours >= 0 && minutes >= 0 && minutes < 60 => (hours, minutes),
+        _ => return bad_keyframe()
+    };
+    // Hours first, as hours * 60 may overflow
+    if hours > MAX_KEYFRAME_OFFSET || hours * 60 + minutes > MAX_KEYFRAME_OFFSET * 60 {
+        return malformed(format!("{}: {} (at most {} hours from {})", key, input, MAX_KEYFRAME_OFFSET, event))
+    }
+    Ok(transition::Keyframe { event: event, offset: sign * (hours * 3600 + minutes * 60) as f64 })
+}
+
+/// Parse a point in time, relative to `now` (in seconds since the
+/// epoch)
+///
+/// Accepts "HH:MM" (today) and "YYYY-MM-DD HH:MM" in local time,
+/// "@SECONDS" since the epoch, and "+DURATION" from now.
+pub fn parse_time(input: &str, now: f64) -> Result<f64> {
+    let input = input.trim();
+    if input.starts_with('+') {
+        return parse_duration(&input[1..]).map(|duration| now + duration)
+    }
+    if input.starts_with('@') {
+        return match input[1..].parse::<f64>() {
+            Ok(t) if t.is_finite() && t.abs() < 1e12 => Ok(t),
+            _ => malformed(format!("time: {}", input))
+        }
+    }
+
+    let bad_time = || malformed(format!("time: {} (expected HH:MM, YYYY-MM-DD HH:MM, @SECONDS or +DURATION)", input));
+
+    // strptime ignores trailing input, so check the length too
+    let mut tm = clock::local_tm(now);
+    if input.len() > "YYYY-MM-DD HH:MM".len() {
+        return bad_time()
+    }
+    if let Ok(date) = time::strptime(input, "%Y-%m-%d %H:%M").or_else(|_| time::strptime(input, "%Y-%m-%dT%H:%M")) {
+        tm.tm_year = date.tm_year;
+        tm.tm_mon = date.tm_mon;
+        tm.tm_mday = date.tm_mday;
+        tm.tm_hour = date.tm_hour;
+        tm.tm_min = date.tm_min;
+    } else if let (true, Ok(clock)) = (input.len() <= "HH:MM".len(), time::strptime(input, "%H:%M")) {
+        tm.tm_hour = clock.tm_hour;
+        tm.tm_min = clock.tm_min;
+    } else {
+        return bad_time()
+    }
+    tm.tm_sec = 0;
+    tm.tm_nsec = 0;
+    Ok(tm.to_timespec().sec as f64)
+}
+
+/// Parse the temperature for one shot manual mode
+///
+/// A single integer between MIN_TEMP and MAX_TEMP.
+pub fn parse_manual_temperature(input: &str) -> Result<i32> {
+    let t = input.parse()
+        .or(malformed(format!("temperature: {}", input)))?;
+    if !is_valid_temperature(t) {
+        malformed(format!("Temperature must be between {} and {} (was {})", MIN_TEMP, MAX_TEMP, t))
+    } else {
+        Ok(t)
+    }
+}
+
+/// Parse brightness argument
+///
+/// Expected format is "DAY:NIGHT" where DAY and NIGHT are floating
+/// point numbers, or a single one for both. Any other input produces
+/// an error.
+pub fn parse_brightness(input: &str) -> Result<(f64, f64)> {
+    let mut parts = input.split(':');
+
+    let day = parts.next()
+        .map_or(malformed(format!("brightness: {}", input)),
+                |l| l.parse().or(
+                    malformed(format!("brightness: {} (of {})", l, input))))?;
+
+    let night = parts.next()
+        .map_or(Ok(day),
+                |l| l.parse().or(malformed(format!("brightness: {} (of {})", l, input))))?;
+
+    parts.next()
+        .map_or(Ok(()),
+                |trailing| malformed(format!("brightness: trailing {} (of {})", trailing, input)))?;
+
+    if !is_valid_brightness(day) || !is_valid_brightness(night) {
+        malformed(format!("Brightness values must be between {:.1} and {:.1}", MIN_BRIGHTNESS, MAX_BRIGHTNESS))
+    } else {
+        Ok((day, night))
+    }
+}
+
+/// Parse a single brightness value, as in brightness-day and
+/// brightness-night in the config file, either as a fraction or a
+/// percentage like "70%"
+pub fn parse_brightness_value(input: &str) -> Result<f64> {
+    let b = match input.find('%') {
+        Some(i) if i + 1 == input.len() => input[..i].trim().parse::<f64>().map(|p| p / 100.0),
+        _ => input.parse(),
+    }.or(malformed(format!("brightness: {}", input)))?;
+    if !is_valid_brightness(b) {
+        malformed(format!("Brightness values must be between {:.1} and {:.1}", MIN_BRIGHTNESS, MAX_BRIGHTNESS))
+    } else {
+        Ok(b)
+    }
+}
+
+/// A gamma string contains either one floating point value, or three
+/// separated by colons
+pub fn parse_gamma(input: &str) -> Result<(f64, f64, f64)> {
+
+    let validate_gamma = |g: f64| if !is_valid_gamma(g) {
+        malformed(format!("Gamma value must be between {} and {}. Was {}",
+                          MIN_GAMMA, MAX_GAMMA, g))
+    } else {
+        Ok(())
+    };
+
+    let mut parts = input.split(':');
+
+    let fst = parts.next()
+        .map_or(malformed(format!("gamma: {}", input)),
+                |l| l.parse().or(
+                    malformed(format!("gamma: {} (of {})", l,
+                                      input))))?;
+    validate_gamma(fst)?;
+
+    if let Some(l) = parts.next() {
+        let g = l.parse().or(malformed(format!("gamma: {} (of {})", l,
+                                               input)))?;
+        validate_gamma(g)?;
+
+        let b = parts.next()
+            .map_or(malformed(format!("gamma: {} (of {})", l, input)),
+                    |l| l.parse().or(
+                        malformed(format!("gamma: {} (of {})", l,
+                                          input))))?;
+        validate_gamma(b)?;
+        Ok((fst, g, b))
+    } else {
+        Ok((fst, fst, fst))
+    }
+}
+
+/// A key the config file may set
+pub struct ConfigKey {
+    /// `None` for the main section, `[redshift]`
+    pub section: Option<&'static str>,
+    pub name: &'static str,
+
+    /// The value used when the key is not set, or an example if
+    /// nothing is set by default
+    pub default: String,
+    pub is_example: bool,
+    pub help: &'static str,
+}
+
+fn key(section: Option<&'static str>, name: &'static str, default: String, help: &'static str) -> ConfigKey {
+    ConfigKey { section: section, name: name, default: default, is_example: false, help: help }
+}
+
+fn example(section: Option<&'static str>, name: &'static str, value: &str, help: &'static str) -> ConfigKey {
+    ConfigKey { section: section, name: name, default: value.to_owned(), is_example: true, help: help }
+}
+
+/// All the keys of the config file, apart from the options of the
+/// gamma methods (which live in a section named after the method)
+pub fn config_keys() -> Vec<ConfigKey> {
+    vec![
+        example(None, "include", "redshift-local.conf",
+            "Another config file to read after this one, relative to it"),
+        example(None, "temp", "4500",
+            "Color temperature both day and night, instead of temp-day and temp-night; \
+             +N or -N for an offset from the defaults"),
+        key(None, "temp-day", DEFAULT_DAY_TEMP.to_string(),
+            "Color temperature during the day, in Kelvin, or +N or -N from the default"),
+        key(None, "temp-night", DEFAULT_NIGHT_TEMP.to_string(),
+            "Color temperature at night, in Kelvin, or +N or -N from the default"),
+        example(None, "brightness", "0.8",
+            "Screen brightness both day and night, instead of brightness-day and brightness-night"),
+        key(None, "brightness-day", DEFAULT_BRIGHTNESS.to_string(),
+            "Screen brightness during the day (0.1 to 1.0, or a percentage)"),
+        key(None, "brightness-night", DEFAULT_BRIGHTNESS.to_string(),
+            "Screen brightness at night (0.1 to 1.0, or a percentage)"),
+        key(None, "gamma", DEFAULT_GAMMA.to_string(),
+            "Gamma correction, either one value or R:G:B"),
+        example(None, "gamma-day", "1.0",
+            "Gamma correction during the day, instead of gamma"),
+        example(None, "gamma-night", "0.9:0.9:0.9",
+            "Gamma correction at night, instead of gamma"),
+        key(None, "scheme", "redshift-default".to_owned(),
+            "Solar elevations of the transition (see --list-schemes)"),
+        example(None, "start-dim", "sunset-00:30",
+            "When the evening transition starts, relative to sunrise or sunset, instead of at the scheme's elevation"),
+        example(None, "full-night", "sunset+01:00",
+            "When the evening transition ends, relative to sunrise or sunset"),
+        example(None, "start-brighten", "sunrise-01:00",
+            "When the morning transition starts, relative to sunrise or sunset"),
+        example(None, "full-day", "sunrise+00:30",
+            "When the morning transition ends, relative to sunrise or sunset"),
+        key(None, "transition", "1".to_owned(),
+            "Fade in on start and out on exit"),
+        key(None, "fade-on-start", "1".to_owned(),
+            "Fade in on start"),
+        key(None, "fade-on-exit", "1".to_owned(),
+            "Fade out on exit"),
+        key(None, "smooth", "0".to_owned(),
+            "Update more often during transitions"),
+        key(None, "emulate-brightness", "0".to_owned(),
+            "Approximate the brightness with the gamma, with methods that cannot set it"),
+        key(None, "instant-start", "0".to_owned(),
+            "Skip the startup fade if the screen is likely still adjusted from a previous run"),
+        key(None, "jitter", "0".to_owned(),
+            "Vary the color temperature slightly from day to day"),
+        key(None, "quantize-temp", "0".to_owned(),
+            "Round the temperature to multiples of this many Kelvin, for fewer updates (0 is off)"),
+        key(None, "time-format", "locale".to_owned(),
+            "How times of day are printed: locale (as LC_TIME does), 24h or 12h"),
+        key(None, "verbose-interval", "1".to_owned(),
+            "With -v, show the temperature and brightness at most once per this many seconds during fades (0 shows all)"),
+        key(None, "day-neutral", "0".to_owned(),
+            "Leave the screen unadjusted during the day"),
+        key(None, "break-reminder", "0".to_owned(),
+            "Briefly dim the screen every so many minutes as a reminder to take a break (0 is off)"),
+        key(None, "preserve-gamma", "1".to_owned(),
+            "Apply settings on top of the gamma ramps found at start, like a calibration"),
+        key(None, "preserve-luminance", "0".to_owned(),
+            "Keep the brightness of white as the temperature drops, as far as possible"),
+        example(None, "min-brightness", "0.2,eDP-1:0.4",
+            "Lowest brightness, for all outputs or OUTPUT:BRIGHTNESS for one"),
+        example(None, "whitepoint-table", "/path/to/whitepoints.csv",
+            "A CSV file of KELVIN,R,G,B rows replacing the built-in white points"),
+        example(None, "exceptions", "/path/to/exceptions",
+            "A file of dates on which adjustment is off or uses another location or temperatures"),
+        example(None, "location-provider", "manual",
+            "Where the location comes from; a [manual] section on its own selects manual"),
+        example(None, "adjustment-method", "randr",
+            "Method used to set the gamma ramps, chosen automatically if not set; several can be given, as in randr,backlight; auto also remembers the one chosen for each session type (X11 or Wayland)"),
+        example(None, "color-scheme", "gsettings",
+            "Switch the desktop's light and dark color schemes too (gsettings or plasma)"),
+        example(None, "color-scheme-day", "BreezeLight",
+            "Plasma color scheme during the day"),
+        example(None, "color-scheme-night", "BreezeDark",
+            "Plasma color scheme at night"),
+        key(None, "announce", "0".to_owned(),
+            "Show a desktop notification when day or night begins (needs the notify feature)"),
+        key(None, "follow-inhibit", "0".to_owned(),
+            "Pause while a color-critical application inhibits the desktop's night light (needs the inhibit feature)"),
+        example(None, "cue-day", "/usr/share/sounds/freedesktop/stereo/bell.oga",
+            "Sound file to play, or shell command to run, when day begins"),
+        example(None, "cue-night", "/usr/share/sounds/freedesktop/stereo/complete.oga",
+            "Sound file to play, or shell command to run, when night begins"),
+        key(None, "sandbox", "0".to_owned(),
+            "Once running, only allow writing to the state directory and the elevation log (Linux 5.13 or later)"),
+        key(None, "control-socket", "1".to_owned(),
+            "In continual mode, listen for frontends on $XDG_RUNTIME_DIR/redshift-rs.sock (see ctl)"),
+        key(None, "start-attempts", "1".to_owned(),
+            "Times to try starting the adjustment method, for sessions where the display comes up late"),
+        key(None, "start-interval", "1".to_owned(),
+            "Seconds between attempts to start the adjustment method"),
+        example(None, "wait-for-output", "HDMI-1",
+            "Wait for this output to be connected before starting"),
+        key(None, "user", "redshift-rs".to_owned(),
+            "With --system, the system user to run as after starting"),
+        key(None, "geocoding-url", location::geocode::DEFAULT_URL.to_owned(),
+            "Nominatim compatible search endpoint used for place names"),
+        example(Some("manual"), "lat", "55.7",
+            "Latitude, in degrees or like 55°40'N"),
+        example(Some("manual"), "lon", "12.6",
+            "Longitude, in degrees or like 12°34'E"),
+        example(Some("timezone"), "lat", "55.7",
+            "Latitude for the timezone provider, which estimates the longitude from the system timezone"),
+        example(Some("max-adjustment"), "temp", &MIN_TEMP.to_string(),
+            "Lowest temperature that may be set, whatever else is configured"),
+        example(Some("max-adjustment"), "brightness", &MIN_BRIGHTNESS.to_string(),
+            "Lowest brightness that may be set, whatever else is configured"),
+        example(Some(EXAMPLE_SEAT_SECTION), "temp", "4500",
+            "With the drm method, lowest temperature set on the displays of this seat"),
+        example(Some(EXAMPLE_SEAT_SECTION), "brightness", "0.7",
+            "With the drm method, lowest brightness set on the displays of this seat"),
+    ]
+}
+
+/// Prefix of the sections holding the settings of one seat
+const SEAT_SECTION_PREFIX: &'static str = "seat:";
+
+/// The seat section shown in the default config, standing for all
+const EXAMPLE_SEAT_SECTION: &'static str = "seat:seat1";
+
+/// The seat a `[seat:NAME]` section is for
+pub fn seat_section(section: &str) -> Option<&str> {
+    if section.starts_with(SEAT_SECTION_PREFIX) && section.len() > SEAT_SECTION_PREFIX.len() {
+        Some(&section[SEAT_SECTION_PREFIX.len()..])
+    } else {
+        None
+    }
+}
+
+/// Whether `name` is a known key of `section` (`None` for the main
+/// section)
+pub fn is_config_key(section: Option<&str>, name: &str) -> bool {
+    let section = match section.and_then(seat_section) {
+        Some(_) => Some(EXAMPLE_SEAT_SECTION),
+        None => section
+    };
+    config_keys().iter().any(|key| key.section == section && key.name == name)
+}
+
+/// A config file setting every key to its default, commented out, with
+/// a line describing each
+pub fn default_config() -> String {
+    let mut config = String::from("; redshift-rs configuration\n;\n\
+                                   ; Every setting is commented out, with its default value (or an\n\
+                                   ; example, where there is no default).\n");
+    let mut section = Some("");
+    for key in config_keys() {
+        if key.section != section {
+            config.push_str(&format!("\n[{}]\n", key.section.unwrap_or("redshift")));
+            section = key.section;
+        }
+        config.push_str(&format!("; {}{}\n;{}={}\n",
+                                 key.help, if key.is_example { " (example)" } else { "" },
+                                 key.name, key.default));
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = default_config();
+        for key in config_keys() {
+            assert!(config.contains(&format!("\n;{}={}\n", key.name, key.default)));
+        }
+        assert!(config.contains("\n[redshift]\n"));
+        assert!(config.contains("\n[max-adjustment]\n"));
+        assert!(is_config_key(None, "temp-day"));
+        assert!(is_config_key(Some("manual"), "lat"));
+        assert!(!is_config_key(None, "lat"));
+        assert!(is_config_key(Some("seat:seat2"), "brightness"));
+        assert!(!is_config_key(Some("seat:"), "brightness"));
+        assert_eq!(seat_section("seat:seat0"), Some("seat0"));
+    }
+
+    #[test]
+    pub fn test_parse_brightness() {
+        let input = "4500:3500";
+
+        let r = parse_brightness(input);
+        assert!(r.is_err());
+
+        let e = r.unwrap_err();
+        assert!(e.is::<RedshiftError>());
+
+        let e: RedshiftError = *e.downcast().unwrap();
+        assert_eq!(RedshiftError::MalformedArgument("Brightness values must be between 0.1 and 1.0".to_string()), e);
+    }
+
+    #[test]
+    fn test_parse_rejects_out_of_range() {
+        for input in &["", ":", "4500:", "4500:3500:3000", "999:4500", "4500:99999999999", "４５００:3000", "4500：3000"] {
+            assert!(parse_temperature(input).is_err(), "{}", input);
+        }
+        assert!(parse_brightness("NaN").is_err());
+        assert!(parse_gamma("0.8:NaN:1.0").is_err());
+        assert!(parse_gamma("inf").is_err());
+        assert_eq!(parse_temperature("6500:3500").unwrap(), (6500, 3500));
+        assert_eq!(parse_temperature("4500").unwrap(), (4500, 4500));
+        assert_eq!(parse_temperature("-500:-1500").unwrap(), (DEFAULT_DAY_TEMP - 500, DEFAULT_NIGHT_TEMP - 1500));
+        assert_eq!(parse_temperature("+200").unwrap(), (DEFAULT_DAY_TEMP + 200, DEFAULT_NIGHT_TEMP + 200));
+        assert_eq!(parse_temperature("6000:+200").unwrap(), (6000, DEFAULT_NIGHT_TEMP + 200));
+        for input in &["+-200", "--200", "+", "-3000", "+2147483647", "- 200"] {
+            assert!(parse_temperature_expr(input, DEFAULT_NIGHT_TEMP).is_err(), "{}", input);
+        }
+        assert_eq!(parse_brightness("0.8").unwrap(), (0.8, 0.8));
+        assert!(parse_brightness("0.8:").is_err());
+        assert_eq!(parse_verbose_interval("0.5").unwrap(), 0.5);
+        assert_eq!(parse_time_format("12h").unwrap(), clock::TimeFormat::Hours12);
+        assert!(parse_time_format("12").is_err());
+        for input in &["-1", "NaN", "inf", "1s"] {
+            assert!(parse_verbose_interval(input).is_err(), "{}", input);
+        }
+        assert!(parse_brightness_value("0.05").is_err());
+        assert_eq!(parse_brightness_value("0.7").unwrap(), 0.7);
+        assert_eq!(parse_brightness_value("70%").unwrap(), 0.7);
+        assert_eq!(parse_brightness_value("100 %").unwrap(), 1.0);
+        assert!(parse_brightness_value("5%").is_err());
+        assert!(parse_brightness_value("%").is_err());
+        assert!(parse_brightness_value("70%%").is_err());
+    }
+
+    #[test]
+    fn test_parse_brightness_floors() {
+        assert_eq!(parse_brightness_floors("0.2, eDP-1:0.4").unwrap(),
+                   (Some(0.2), vec![("eDP-1".to_owned(), 0.4)]));
+        assert!(parse_brightness_floors(":0.4").is_err());
+        assert!(parse_brightness_floors("eDP-1:2").is_err());
+        assert!(parse_brightness_floors("").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_and_time() {
+        assert_eq!(parse_duration("24h").unwrap(), 86400.0);
+        assert_eq!(parse_duration("1h30m").unwrap(), 5400.0);
+        assert_eq!(parse_duration("90").unwrap(), 90.0);
+        assert_eq!(parse_sweep("24h").unwrap(), (86400.0, 1800.0));
+        assert_eq!(parse_sweep("10m/1m").unwrap(), (600.0, 60.0));
+        assert!(parse_sweep("365d/1s").is_err());
+        for input in &["", "h", "0m", "1x", "1.2.3h", "-5m"] {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+
+        let now = 1_500_000_000.0;
+        assert_eq!(parse_time("+2h", now).unwrap(), now + 7200.0);
+        assert_eq!(parse_time("@1000", now).unwrap(), 1000.0);
+        let noon = parse_time("12:00", now).unwrap();
+        assert!((noon - now).abs() < 86400.0);
+        // The date of `now` depends on the timezone the tests run in
+        let date = |t: f64| time::strftime("%Y-%m-%d", &clock::local_tm(t)).unwrap();
+        assert_eq!(parse_time(&format!("{} 12:00", date(now)), now).unwrap(), noon);
+        let tomorrow = parse_time(&format!("{}T12:00", date(noon + 86400.0)), now).unwrap();
+        assert!((tomorrow - noon - 86400.0).abs() <= 3600.0);
+        for input in &["", "25:00", "noon", "2017-13-01 12:00", "12:00 trailing", "@inf"] {
+            assert!(parse_time(input, now).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_keyframe() {
+        let keyframe = |input| parse_keyframe("start-dim", input).map(|k| (k.event, k.offset));
+        assert_eq!(keyframe("sunset").unwrap(), (solar::Event::Sunset, 0.0));
+        assert_eq!(keyframe("sunset-00:30").unwrap(), (solar::Event::Sunset, -1800.0));
+        assert_eq!(keyframe("sunrise+1:15").unwrap(), (solar::Event::Sunrise, 4500.0));
+        for input in &["", "dusk", "sunset-30", "sunset+00:60", "sunset--1:00", "sunset-13:00", "sunset 00:30",
+                       "sunrise+99999999:00", "sunrise+9223372036854775807:59"] {
+            assert!(parse_keyframe("start-dim", input).is_err(), "{}", input);
+        }
+    }
+}
@@ -5,20 +5,21 @@
  * from some JavaScript code)
  */
 use location;
+use std::fmt;
 use std::ops;
 
 /**
  * Model of atmospheric refraction near horizon (in degrees)
  */
-#[cfg(test)] pub const SOLAR_ATM_REFRAC: f64 = 0.833;
+pub const SOLAR_ATM_REFRAC: f64 = 0.833;
 
 /**
  * Various elevation constants
  */
 #[cfg(test)] pub const ASTRO_TWILIGHT_ELEV: f64 = -18.0;
-#[cfg(test)] pub const NAUT_TWILIGHT_ELEV:  f64 = -12.0;
+             pub const NAUT_TWILIGHT_ELEV:  f64 = -12.0;
              pub const CIVIL_TWILIGHT_ELEV: f64 = -6.0;
-#[cfg(test)] pub const DAYTIME_ELEV:        f64 = (0.0 - SOLAR_ATM_REFRAC);
+             pub const DAYTIME_ELEV:        f64 = 0.0 - SOLAR_ATM_REFRAC;
 
 /**
  * Solar times - see the time_angle[] array
@@ -184,6 +185,63 @@ pub fn elevation(t: f64, loc: &location::Location) -> f64 {
     elevation_from_time(jd, loc).to_degrees()
 }
 
+/// Precision of the times found by `crossing`, in seconds
+const CROSSING_PRECISION: f64 = 1.0;
+
+/// The start of the local mean solar day (at mean solar midnight)
+/// containing `t`
+pub fn day_start(t: f64, loc: &location::Location) -> f64 {
+    // Four minutes per degree of longitude
+    let offset = loc.lon * 240.0;
+    ((t + offset) / 86400.0).floor() * 86400.0 - offset
+}
+
+/// When the sun crosses `elev` on the day starting at `day_start`,
+/// rising in the morning or setting in the evening
+///
+/// `None` if it does not that day, as near the poles. The sun is taken
+/// to rise steadily from midnight to noon and set from noon to
+/// midnight, so the time is found by bisection.
+pub fn crossing(day_start: f64, loc: &location::Location, elev: f64, rising: bool) -> Option<f64> {
+    let noon = day_start + 43200.0;
+    let (mut below, mut above) = if rising { (day_start, noon) } else { (day_start + 86400.0, noon) };
+    if elevation(below, loc) >= elev || elevation(above, loc) < elev {
+        return None
+    }
+    while (above - below).abs() > CROSSING_PRECISION {
+        let middle = (below + above) / 2.0;
+        if elevation(middle, loc) < elev {
+            below = middle;
+        } else {
+            above = middle;
+        }
+    }
+    Some((below + above) / 2.0)
+}
+
+/// Solar events that times can be given relative to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Sunrise,
+    Sunset,
+}
+
+impl Event {
+    /// When the event happens on the day starting at `day_start`
+    pub fn time(self, day_start: f64, loc: &location::Location) -> Option<f64> {
+        crossing(day_start, loc, DAYTIME_ELEV, self == Event::Sunrise)
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Sunrise => write!(f, "sunrise"),
+            Event::Sunset => write!(f, "sunset"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -206,6 +264,55 @@ mod test {
         }
     }
 
+    /// The largest change in elevation from one minute to the next
+    /// over the two hours around `t`
+    fn max_step(t: f64, loc: &location::Location) -> f64 {
+        (-60..60)
+            .map(|m| (elevation(t + (m + 1) as f64 * 60.0, loc) - elevation(t + m as f64 * 60.0, loc)).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn continuous_at_boundaries() {
+        // The sun moves at most 0.25° a minute, so anything much larger
+        // is a jump that would show as a blip in the period
+        let places = [location::Location::new(55.7, 12.6), location::Location::new(-33.9, 18.4),
+                      location::Location::new(40.7, -74.0), location::Location::new(0.0, 179.9)];
+        // UTC midnight, where the Julian day is rounded
+        let midnight = 1609459200.0; // 2021-01-01 00:00 UTC, also a year boundary
+        // 01:00 UTC on 2021-03-28 and 2021-10-31, EU daylight saving changes
+        let dst = [1616893200.0, 1635642000.0];
+        for loc in places.iter() {
+            for &t in [midnight].iter().chain(dst.iter()) {
+                assert!(max_step(t, loc) < 0.3, "jump at {} for {}", t, loc);
+            }
+        }
+    }
+
+    #[test]
+    fn noon_elevation() {
+        // Solar noon at 0°N 0°E on the March equinox is about 12:07 UTC,
+        // with the sun nearly overhead
+        let elev = elevation(1616328420.0, &location::Location::new(0.0, 0.0));
+        assert!(elev > 89.0, "{}", elev);
+    }
+
+    #[test]
+    fn sunrise_and_sunset() {
+        // Copenhagen on 2021-06-21: sunrise 04:25 and sunset 21:57 CEST
+        let loc = location::Location::new(55.7, 12.6);
+        let day = day_start(1624276800.0, &loc);
+        let sunrise = Event::Sunrise.time(day, &loc).unwrap();
+        let sunset = Event::Sunset.time(day, &loc).unwrap();
+        assert!((sunrise - 1624242300.0).abs() < 120.0, "{}", sunrise);
+        assert!((sunset - 1624305420.0).abs() < 120.0, "{}", sunset);
+        assert!((elevation(sunset, &loc) - DAYTIME_ELEV).abs() < 0.01);
+
+        // No night at all in Tromsø in June
+        let tromso = location::Location::new(69.6, 19.0);
+        assert_eq!(Event::Sunset.time(day_start(1624276800.0, &tromso), &tromso), None);
+    }
+
     #[test]
     fn zero_zero() {
         let jd1k = JulianDay::from_epoch(1000.0);
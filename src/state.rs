@@ -0,0 +1,209 @@
+//! State kept between runs
+//!
+//! A small file of `key=value` lines in
+//! `$XDG_STATE_HOME/redshift-rs/state` (by default
+//! `~/.local/state/redshift-rs/state`). A missing or unreadable file
+//! is the same as an empty one: the state is only ever a hint.
+//!
+//! In --system mode the state lives in `/var/lib/redshift-rs/state`
+//! instead.
+//!
+//! Continual mode also keeps the time spent in each period today in
+//! it, as `PeriodTimes`. With `adjustment-method=auto`, the method
+//! that started last in each session type is kept as `method-x11`,
+//! `method-wayland` and `method-unknown`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use transition::Period;
+use super::Result;
+
+/// The state directory in --system mode
+pub const SYSTEM_STATE_DIR: &'static str = "/var/lib/redshift-rs";
+
+pub struct State {
+    path: Option<PathBuf>,
+    values: BTreeMap<String, String>,
+}
+
+impl State {
+
+    pub fn load(system: bool) -> State {
+        let path = if system {
+            Some(Path::new(SYSTEM_STATE_DIR).join("state"))
+        } else {
+            state_path()
+        };
+        let mut values = BTreeMap::new();
+
+        let mut contents = String::new();
+        let read = path.as_ref()
+            .and_then(|path| File::open(path).ok())
+            .and_then(|mut f| f.read_to_string(&mut contents).ok());
+        if read.is_some() {
+            for line in contents.lines() {
+                let mut parts = line.splitn(2, '=');
+                if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                    values.insert(key.trim().to_owned(), value.trim().to_owned());
+                }
+            }
+        }
+
+        State { path: path, values: values }
+    }
+
+    /// The directory the state is saved in
+    pub fn dir(&self) -> Option<&Path> {
+        self.path.as_ref().and_then(|path| path.parent())
+    }
+
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.values.get(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn set<T: ToString>(&mut self, key: &str, value: T) {
+        self.values.insert(key.to_owned(), value.to_string());
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(())
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut f = File::create(path)?;
+        for (key, value) in self.values.iter() {
+            writeln!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Longest time (in seconds) between two updates that is counted.
+/// Updates are at most 5 seconds apart, so a longer gap was spent
+/// suspended, or paused.
+pub const MAX_COUNTED_GAP: f64 = 60.0;
+
+const PERIOD_NAMES: [&'static str; 3] = ["day", "transition", "night"];
+
+/// Time spent in Day, Transition and Night on one day, with the screen
+/// adjusted
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodTimes {
+    /// The local date, as YYYYMMDD
+    pub date: u32,
+
+    /// Seconds in each of `PERIOD_NAMES`
+    pub seconds: [f64; 3],
+
+    /// The time and period of the last tick
+    last: Option<(f64, Option<usize>)>,
+}
+
+impl PeriodTimes {
+    pub fn new(date: u32) -> PeriodTimes {
+        PeriodTimes { date: date, seconds: [0.0; 3], last: None }
+    }
+
+    /// The counters saved in `state`, of whichever day they were for
+    pub fn load(state: &State) -> Option<PeriodTimes> {
+        let date = state.get("period-date")?;
+        let mut times = PeriodTimes::new(date);
+        for (seconds, name) in times.seconds.iter_mut().zip(PERIOD_NAMES.iter()) {
+            *seconds = state.get(&format!("period-{}", name)).unwrap_or(0.0);
+        }
+        Some(times)
+    }
+
+    pub fn store(&self, state: &mut State) {
+        state.set("period-date", self.date);
+        for (seconds, name) in self.seconds.iter().zip(PERIOD_NAMES.iter()) {
+            state.set(&format!("period-{}", name), seconds.round());
+        }
+    }
+
+    /// Count the time since the last tick towards the period then, if
+    /// the screen was `adjusted` all along, and go on to `date`.
+    /// Returns the counters of the day that ended, if one did.
+    pub fn tick(&mut self, now: f64, date: u32, period: &Period, adjusted: bool) -> Option<PeriodTimes> {
+        if let Some((last, Some(slot))) = self.last {
+            if adjusted && now > last && now - last <= MAX_COUNTED_GAP {
+                self.seconds[slot] += now - last;
+            }
+        }
+        self.last = Some((now, match *period {
+            Period::Day => Some(0),
+            Period::Transition(_) => Some(1),
+            Period::Night => Some(2),
+            Period::None => None
+        }));
+        if date == self.date {
+            return None
+        }
+        let mut ended = PeriodTimes::new(date);
+        ended.last = self.last;
+        ::std::mem::swap(self, &mut ended);
+        ended.last = None;
+        Some(ended)
+    }
+
+    /// Whole minutes in each period
+    pub fn minutes(&self) -> [u64; 3] {
+        [(self.seconds[0] / 60.0) as u64, (self.seconds[1] / 60.0) as u64, (self.seconds[2] / 60.0) as u64]
+    }
+}
+
+impl fmt::Display for PeriodTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = self.minutes();
+        write!(f, "{} min day, {} min transition, {} min night", minutes[0], minutes[1], minutes[2])
+    }
+}
+
+fn state_path() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|mut home| { home.push(".local/state"); home }))
+        .map(|mut dir| { dir.push("redshift-rs/state"); dir })
+}
+
+#[cfg(test)]
+mod tests {
+    use transition::Period;
+    use super::{PeriodTimes, State, MAX_COUNTED_GAP};
+
+    #[test]
+    fn test_period_times() {
+        let mut times = PeriodTimes::new(20261016);
+        assert_eq!(times.tick(1000.0, 20261016, &Period::Night, true), None);
+        times.tick(1005.0, 20261016, &Period::Transition(0.5), true);
+        // Not adjusted since the last tick
+        times.tick(1010.0, 20261016, &Period::Day, false);
+        // Suspended
+        times.tick(1010.0 + MAX_COUNTED_GAP + 1.0, 20261016, &Period::Day, true);
+        assert_eq!(times.seconds, [0.0, 0.0, 5.0]);
+
+        let mut state = State { path: None, values: Default::default() };
+        times.store(&mut state);
+        assert_eq!(PeriodTimes::load(&state).map(|t| (t.date, t.seconds)), Some((20261016, [0.0, 0.0, 5.0])));
+
+        // The last seconds before midnight still count for the old day
+        let ended = times.tick(1080.0, 20261017, &Period::Day, true).unwrap();
+        assert_eq!((ended.date, ended.seconds), (20261016, [9.0, 0.0, 5.0]));
+        assert_eq!((times.date, times.seconds), (20261017, [0.0; 3]));
+        times.tick(1140.0, 20261017, &Period::Day, true);
+        assert_eq!(format!("{}", times), "1 min day, 0 min transition, 0 min night");
+    }
+}
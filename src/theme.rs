@@ -0,0 +1,68 @@
+//! Desktop color scheme switching
+//!
+//! Optionally tell the desktop to prefer a dark color scheme at night
+//! and a light one at day, by running the desktop's own settings
+//! tool when the period changes.
+
+use std::process::Command;
+
+use transition::Period;
+use super::{Result, RedshiftError};
+
+pub enum ColorScheme {
+    /// GNOME and GTK: org.gnome.desktop.interface color-scheme
+    Gsettings,
+
+    /// KDE Plasma: plasma-apply-colorscheme with the given day and
+    /// night schemes
+    Plasma { day: String, night: String },
+}
+
+impl ColorScheme {
+
+    /// Parse the `color-scheme` config value
+    pub fn new(kind: &str, day: Option<&str>, night: Option<&str>) -> Result<ColorScheme> {
+        match kind {
+            "gsettings" => Ok(ColorScheme::Gsettings),
+            "plasma" => Ok(ColorScheme::Plasma {
+                day: day.unwrap_or("BreezeLight").to_owned(),
+                night: night.unwrap_or("BreezeDark").to_owned(),
+            }),
+            _ => Err(Box::new(RedshiftError::MalformedConfig(
+                format!("unknown color-scheme '{}' (expected gsettings or plasma)", kind))))
+        }
+    }
+
+    /// Switch the color scheme to match `period`
+    ///
+    /// Nothing is changed during transitions.
+    pub fn apply(&self, period: &Period) -> Result<()> {
+        let night = match *period {
+            Period::Day => false,
+            Period::Night => true,
+            Period::Transition(_) | Period::None => return Ok(())
+        };
+
+        let mut cmd = match *self {
+            ColorScheme::Gsettings => {
+                let mut cmd = Command::new("gsettings");
+                cmd.args(&["set", "org.gnome.desktop.interface", "color-scheme",
+                           if night { "prefer-dark" } else { "default" }]);
+                cmd
+            }
+            ColorScheme::Plasma { ref day, night: ref night_scheme } => {
+                let mut cmd = Command::new("plasma-apply-colorscheme");
+                cmd.arg(if night { night_scheme } else { day });
+                cmd
+            }
+        };
+
+        debug!("Running {:?}", cmd);
+        let status = cmd.status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(From::from(format!("{:?} failed ({})", cmd, status)))
+        }
+    }
+}
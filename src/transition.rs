@@ -1,5 +1,53 @@
 use solar;
+use location::Location;
 use std::fmt;
+use settings::{MIN_TEMP, MAX_TEMP};
+
+/// Maximum temperature offset (in Kelvin, either way) applied by the
+/// burn-in jitter
+const JITTER_RANGE: f64 = 50.0;
+
+/// A named pair of solar elevations between which the transition
+/// from day to night happens
+pub struct ElevationScheme {
+    pub name: &'static str,
+    pub high: f64,
+    pub low: f64,
+    pub description: &'static str,
+}
+
+/// The preset elevation schemes, selectable with `scheme=NAME`
+pub const SCHEMES: &'static [ElevationScheme] = &[
+    ElevationScheme {
+        name: "redshift-default",
+        high: 3.0,
+        low: solar::CIVIL_TWILIGHT_ELEV,
+        description: "Upstream redshift's default, from just above the horizon to civil dusk",
+    },
+    ElevationScheme {
+        name: "civil",
+        high: 0.0,
+        low: solar::CIVIL_TWILIGHT_ELEV,
+        description: "From sunset to the end of civil twilight",
+    },
+    ElevationScheme {
+        name: "nautical",
+        high: solar::CIVIL_TWILIGHT_ELEV,
+        low: solar::NAUT_TWILIGHT_ELEV,
+        description: "From the end of civil twilight to the end of nautical twilight",
+    },
+    ElevationScheme {
+        name: "gentle",
+        high: 10.0,
+        low: solar::NAUT_TWILIGHT_ELEV,
+        description: "A long transition, starting well before sunset",
+    },
+];
+
+/// Look up a preset elevation scheme by name
+pub fn elevation_scheme(name: &str) -> Option<&'static ElevationScheme> {
+    SCHEMES.iter().find(|scheme| scheme.name == name)
+}
 
 /* Periods of day */
 #[derive(Debug, PartialEq)]
@@ -23,12 +71,89 @@ impl fmt::Display for Period {
     }
 }
 
+/// A color temperature, in Kelvin
+///
+/// Kept fractional, so that fades and transitions move smoothly rather
+/// than in whole Kelvin steps. Methods that need whole Kelvin round it
+/// with `round()`, and it is printed rounded too.
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
+pub struct Kelvin(pub f64);
+
+impl Kelvin {
+    pub fn round(self) -> i32 {
+        self.0.round() as i32
+    }
+}
+
+impl From<i32> for Kelvin {
+    fn from(temp: i32) -> Kelvin {
+        Kelvin(temp as f64)
+    }
+}
+
+impl fmt::Display for Kelvin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.round())
+    }
+}
+
+/// A time relative to sunrise or sunset, like "sunset-00:30"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub event: solar::Event,
+    /// Seconds after the event (before it if negative)
+    pub offset: f64,
+}
+
+impl Keyframe {
+    /// When the keyframe is on the day starting at `day_start`
+    fn time(&self, day_start: f64, location: &Location) -> Option<f64> {
+        self.event.time(day_start, location).map(|t| t + self.offset)
+    }
+}
+
+impl fmt::Display for Keyframe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = (self.offset.abs() / 60.0).round() as i64;
+        write!(f, "{}{}{:02}:{:02}", self.event, if self.offset < 0.0 { '-' } else { '+' },
+               minutes / 60, minutes % 60)
+    }
+}
+
+/// When the transitions start and end, instead of at the elevations of
+/// the scheme
+///
+/// Those not set stay at the elevations, so `full_night` alone moves
+/// the end of the evening transition but not its start.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Keyframes {
+    pub start_dim: Option<Keyframe>,
+    pub full_night: Option<Keyframe>,
+    pub start_brighten: Option<Keyframe>,
+    pub full_day: Option<Keyframe>,
+}
+
+impl Keyframes {
+    pub fn is_empty(&self) -> bool {
+        *self == Keyframes::default()
+    }
+}
+
+/// How far beyond `high` or `low` the schedule puts the sun when it
+/// does not agree with the keyframes, in degrees
+const KEYFRAME_MARGIN: f64 = 0.01;
+
+/// Settings closer than this are the same, for deciding whether to
+/// set them again
+const TEMP_EPSILON: f64 = 0.5;
+const EPSILON: f64 = 1e-4;
+
 /// A color setting
 ///
 ///
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct ColorSetting {
-    pub temp: i32,
+    pub temp: Kelvin,
     pub gamma: [f64; 3],
     pub brightness: f64,
 }
@@ -36,13 +161,33 @@ pub struct ColorSetting {
 impl ColorSetting {
     pub fn new() -> ColorSetting {
         ColorSetting {
-            temp: -1,
+            temp: Kelvin(-1.0),
             gamma: [::std::f64::NAN,
                     ::std::f64::NAN,
                     ::std::f64::NAN],
             brightness: ::std::f64::NAN
         }
     }
+
+    /// This setting moved `alpha` of the way (0 to 1) to `other`
+    pub fn mix(&self, other: &ColorSetting, alpha: f64) -> ColorSetting {
+        let mix = |a: f64, b: f64| (1.0 - alpha) * a + alpha * b;
+        ColorSetting {
+            temp: Kelvin(mix(self.temp.0, other.temp.0)),
+            gamma: [mix(self.gamma[0], other.gamma[0]),
+                    mix(self.gamma[1], other.gamma[1]),
+                    mix(self.gamma[2], other.gamma[2])],
+            brightness: mix(self.brightness, other.brightness),
+        }
+    }
+
+    /// Whether the two settings look the same, which `==` is too
+    /// strict for with fractional temperatures
+    pub fn approx_eq(&self, other: &ColorSetting) -> bool {
+        (self.temp.0 - other.temp.0).abs() < TEMP_EPSILON &&
+            (self.brightness - other.brightness).abs() < EPSILON &&
+            (0..3).all(|c| (self.gamma[c] - other.gamma[c]).abs() < EPSILON)
+    }
 }
 
 /**
@@ -59,7 +204,17 @@ pub struct TransitionScheme {
     /* Used for initial and final gradual transition from/to 6500K */
     pub short_trans_delta: i16,
     pub short_trans_len: u16,
-    pub adjustment_alpha: f64
+    pub adjustment_alpha: f64,
+
+    /* Vary the temperature slightly from day to day */
+    pub jitter: bool,
+
+    /// Round temperatures to multiples of this many Kelvin (0 to not
+    /// round)
+    pub quantize_temp: i32,
+
+    /// Times of the transitions relative to sunrise and sunset
+    pub keyframes: Keyframes,
 }
 
 impl TransitionScheme {
@@ -72,7 +227,49 @@ impl TransitionScheme {
 
             short_trans_delta: -1,
             short_trans_len: 10,
-            adjustment_alpha: 1.0
+            adjustment_alpha: 1.0,
+
+            jitter: false,
+            quantize_temp: 0,
+            keyframes: Keyframes::default(),
+        }
+    }
+
+    /// The solar elevation at `t`, moved so that the transitions happen
+    /// at the keyframes
+    ///
+    /// Without keyframes this is the elevation. With them, the elevation
+    /// goes from `low` to `high` at an even pace between the morning
+    /// keyframes, and back between the evening ones, and is kept below
+    /// `low` at night and above `high` during the day. The keyframes are
+    /// resolved for each day; on days they cannot be (as near the poles,
+    /// or when they are out of order) the elevation is used as it is.
+    pub fn schedule_elevation(&self, t: f64, location: &Location) -> f64 {
+        let elevation = solar::elevation(t, location);
+        if self.keyframes.is_empty() {
+            return elevation
+        }
+        let day_start = solar::day_start(t, location);
+        let resolve = |keyframe: &Option<Keyframe>, elev: f64, rising: bool| match *keyframe {
+            Some(ref keyframe) => keyframe.time(day_start, location),
+            None => solar::crossing(day_start, location, elev, rising),
+        };
+        let times = (resolve(&self.keyframes.start_brighten, self.low, true),
+                     resolve(&self.keyframes.full_day, self.high, true),
+                     resolve(&self.keyframes.start_dim, self.high, false),
+                     resolve(&self.keyframes.full_night, self.low, false));
+        let (dawn, day, dusk, night) = match times {
+            (Some(a), Some(b), Some(c), Some(d)) if a < b && b <= c && c < d => (a, b, c, d),
+            _ => return elevation
+        };
+        if t < dawn || t >= night {
+            elevation.min(self.low - KEYFRAME_MARGIN)
+        } else if t < day {
+            self.low + (self.high - self.low) * (t - dawn) / (day - dawn)
+        } else if t < dusk {
+            elevation.max(self.high + KEYFRAME_MARGIN)
+        } else {
+            self.high + (self.low - self.high) * (t - dusk) / (night - dusk)
         }
     }
 
@@ -87,7 +284,7 @@ impl TransitionScheme {
         let alpha = al.min(1.0).max(0.0); // clamp to [0.0, 1.0]
 
         ColorSetting {
-            temp: ((1.0-alpha) * night.temp as f64 + alpha * day.temp as f64) as i32,
+            temp: Kelvin((1.0-alpha) * night.temp.0 + alpha * day.temp.0),
             brightness: (1.0-alpha) * night.brightness + alpha * day.brightness,
             gamma: [
                 (1.0-alpha) * night.gamma[0] + alpha*day.gamma[0],
@@ -97,6 +294,33 @@ impl TransitionScheme {
         }
     }
 
+    /// Offset the temperature by a small amount that depends only on
+    /// the day, to avoid applying identical ramps day after day.
+    ///
+    /// The offset is interpolated between consecutive days, so it
+    /// changes by at most a few Kelvin per hour and never jumps.
+    pub fn apply_jitter(&self, setting: &mut ColorSetting, now: f64) {
+        if !self.jitter {
+            return
+        }
+        let days = now / 86400.0;
+        let day = days.floor();
+        let t = days - day;
+        let offset = (1.0-t) * day_jitter(day as i64) + t * day_jitter(day as i64 + 1);
+        setting.temp = Kelvin((setting.temp.0 + offset).max(MIN_TEMP as f64).min(MAX_TEMP as f64));
+    }
+
+    /// Round the temperature to the nearest multiple of
+    /// `quantize_temp`, so that it changes in fewer, larger steps
+    pub fn quantize(&self, setting: &mut ColorSetting) {
+        if self.quantize_temp <= 0 {
+            return
+        }
+        let step = self.quantize_temp as f64;
+        let temp = (setting.temp.0 / step).round() * step;
+        setting.temp = Kelvin(temp.max(MIN_TEMP as f64).min(MAX_TEMP as f64));
+    }
+
     pub fn get_period(&self, elevation: f64) -> Period {
         if elevation < self.low {
             Period::Night
@@ -108,6 +332,35 @@ impl TransitionScheme {
         }
     }
 
+    /// Find when it is next fully Day or Night (whichever is not the
+    /// current period), stepping a minute at a time from `now`
+    ///
+    /// `elevation` gives the solar elevation at a time. Returns the
+    /// period and the number of seconds until it starts, or `None` if
+    /// it does not happen within two days (as near the poles).
+    pub fn next_period<F: Fn(f64) -> f64>(&self, now: f64, elevation: F) -> Option<(Period, f64)> {
+        let current = self.get_period(elevation(now));
+        for minute in 1..(2 * 24 * 60) {
+            let t = now + minute as f64 * 60.0;
+            match self.get_period(elevation(t)) {
+                Period::Transition(_) | Period::None => {}
+                period => if period != current {
+                    return Some((period, t - now))
+                }
+            }
+        }
+        None
+    }
+
+    /// The period and color setting at each of `start`, `start + step`,
+    /// `start + 2 * step` and so on (without end), at `location`
+    ///
+    /// The settings are those continual mode would apply at those
+    /// times, including jitter but not the fades at start and exit.
+    pub fn iter_schedule<'a>(&'a self, location: &'a Location, start: f64, step: f64) -> Schedule<'a> {
+        Schedule { scheme: self, location: location, next: start, step: step }
+    }
+
     pub fn short_transition(&self) -> bool {
         self.short_trans_delta != 0
     }
@@ -124,3 +377,167 @@ impl TransitionScheme {
         self.adjustment_alpha = self.adjustment_alpha.max(0.0).min(1.0);
     }
 }
+
+/// Iterator over `(timestamp, Period, ColorSetting)`, from
+/// `TransitionScheme::iter_schedule`
+pub struct Schedule<'a> {
+    scheme: &'a TransitionScheme,
+    location: &'a Location,
+    next: f64,
+    step: f64,
+}
+
+impl<'a> Iterator for Schedule<'a> {
+    type Item = (f64, Period, ColorSetting);
+
+    fn next(&mut self) -> Option<(f64, Period, ColorSetting)> {
+        let t = self.next;
+        self.next += self.step;
+
+        let elevation = self.scheme.schedule_elevation(t, self.location);
+        let mut setting = self.scheme.interpolate_color_settings(elevation);
+        self.scheme.apply_jitter(&mut setting, t);
+        self.scheme.quantize(&mut setting);
+        Some((t, self.scheme.get_period(elevation), setting))
+    }
+}
+
+/// Pseudo-random offset in [-JITTER_RANGE, JITTER_RANGE] seeded by
+/// the day number (splitmix64)
+fn day_jitter(day: i64) -> f64 {
+    let mut z = (day as u64).wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z = z ^ (z >> 31);
+    let unit = (z >> 11) as f64 / (1u64 << 53) as f64;
+    (unit * 2.0 - 1.0) * JITTER_RANGE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jitter_is_bounded_and_stable() {
+        let mut scheme = TransitionScheme::new();
+        scheme.jitter = true;
+
+        let mut prev: Option<f64> = None;
+        for i in 0..(3 * 24 * 60) {
+            let mut setting = ColorSetting { temp: Kelvin(4000.0), gamma: [1.0; 3], brightness: 1.0 };
+            scheme.apply_jitter(&mut setting, 1_500_000_000.0 + i as f64 * 60.0);
+            assert!((setting.temp.0 - 4000.0).abs() <= JITTER_RANGE);
+            if let Some(p) = prev {
+                assert!((setting.temp.0 - p).abs() <= 1.0);
+            }
+            prev = Some(setting.temp.0);
+        }
+    }
+
+    #[test]
+    fn mix_ends_at_both_settings() {
+        let day = ColorSetting { temp: Kelvin(6500.0), gamma: [1.0; 3], brightness: 1.0 };
+        let night = ColorSetting { temp: Kelvin(3400.0), gamma: [0.9, 0.8, 0.7], brightness: 0.7 };
+        assert_eq!(day.mix(&night, 0.0), day);
+        assert_eq!(day.mix(&night, 1.0), night);
+        assert_eq!(day.mix(&night, 0.5).temp, Kelvin(4950.0));
+        assert!(day.approx_eq(&day.mix(&night, 1e-5)));
+        assert!(!day.approx_eq(&day.mix(&night, 1e-3)));
+    }
+
+    #[test]
+    fn quantize_rounds_to_steps() {
+        let mut scheme = TransitionScheme::new();
+        let mut setting = ColorSetting { temp: Kelvin(4449.5), gamma: [1.0; 3], brightness: 1.0 };
+        scheme.quantize(&mut setting);
+        assert_eq!(setting.temp, Kelvin(4449.5));
+
+        scheme.quantize_temp = 100;
+        scheme.quantize(&mut setting);
+        assert_eq!(setting.temp, Kelvin(4400.0));
+
+        scheme.quantize_temp = 3000;
+        setting.temp = Kelvin(1100.0);
+        scheme.quantize(&mut setting);
+        assert_eq!(setting.temp, Kelvin::from(MIN_TEMP));
+    }
+
+    #[test]
+    fn gamma_is_interpolated() {
+        let mut scheme = TransitionScheme::new();
+        scheme.day = ColorSetting { temp: Kelvin(6500.0), gamma: [1.0; 3], brightness: 1.0 };
+        scheme.night = ColorSetting { temp: Kelvin(3500.0), gamma: [0.8, 0.9, 1.0], brightness: 1.0 };
+
+        let middle = scheme.interpolate_color_settings((scheme.high + scheme.low) / 2.0);
+        assert!((middle.gamma[0] - 0.9).abs() < 1e-9);
+        assert!((middle.gamma[1] - 0.95).abs() < 1e-9);
+        assert_eq!(middle.gamma[2], 1.0);
+        assert_eq!(scheme.interpolate_color_settings(-90.0).gamma, scheme.night.gamma);
+    }
+
+    #[test]
+    fn schedule_steps_through_the_day() {
+        let mut scheme = TransitionScheme::new();
+        scheme.day = ColorSetting { temp: Kelvin(6500.0), gamma: [1.0; 3], brightness: 1.0 };
+        scheme.night = ColorSetting { temp: Kelvin(3500.0), gamma: [1.0; 3], brightness: 0.8 };
+        let location = Location::new(55.7, 12.6);
+
+        // 2020-06-21 00:00 UTC, then every hour
+        let schedule: Vec<_> = scheme.iter_schedule(&location, 1592697600.0, 3600.0).take(24).collect();
+        assert_eq!(schedule[1].0, 1592697600.0 + 3600.0);
+        assert_eq!(schedule[0].1, Period::Night);
+        assert_eq!(schedule[0].2.temp, Kelvin(3500.0));
+        assert_eq!(schedule[11].1, Period::Day);
+        assert_eq!(schedule[11].2, scheme.day);
+    }
+
+    #[test]
+    fn keyframes_move_the_transition() {
+        let mut scheme = TransitionScheme::new();
+        let location = Location::new(55.7, 12.6);
+        let noon = 1592740800.0; // 2020-06-21 12:00 UTC
+        let sunset = solar::Event::Sunset.time(solar::day_start(noon, &location), &location).unwrap();
+        let at = |scheme: &TransitionScheme, t: f64| scheme.get_period(scheme.schedule_elevation(t, &location));
+        let progress = |scheme: &TransitionScheme, t: f64| match at(scheme, t) {
+            Period::Transition(progress) => progress,
+            period => panic!("{:?}", period)
+        };
+
+        assert_eq!(scheme.schedule_elevation(noon, &location), solar::elevation(noon, &location));
+        // By the elevation, dimming has barely started half an hour
+        // before sunset
+        assert!(progress(&scheme, sunset - 1800.0) > 0.9);
+
+        scheme.keyframes.start_dim = Some(Keyframe { event: solar::Event::Sunset, offset: -3600.0 });
+        scheme.keyframes.full_night = Some(Keyframe { event: solar::Event::Sunset, offset: 3600.0 });
+        assert_eq!(at(&scheme, noon), Period::Day);
+        assert_eq!(at(&scheme, sunset - 3660.0), Period::Day);
+        assert!((progress(&scheme, sunset - 1800.0) - 0.75).abs() < 1e-9);
+        assert!((progress(&scheme, sunset) - 0.5).abs() < 1e-9);
+        assert_eq!(at(&scheme, sunset + 3660.0), Period::Night);
+        // The morning still follows the elevation
+        assert_eq!(at(&scheme, noon - 43200.0 + 1800.0), Period::Night);
+        assert_eq!(format!("{}", scheme.keyframes.start_dim.unwrap()), "sunset-01:00");
+
+        // Out of order, so the elevation is used
+        scheme.keyframes.full_night = Some(Keyframe { event: solar::Event::Sunset, offset: -7200.0 });
+        assert_eq!(scheme.schedule_elevation(sunset, &location), solar::elevation(sunset, &location));
+    }
+
+    #[test]
+    fn next_period_skips_the_transition() {
+        let scheme = TransitionScheme::new();
+        // Rises 1 degree per minute from -10 degrees
+        let elevation = |t: f64| -10.0 + t / 60.0;
+        assert_eq!(scheme.next_period(0.0, &elevation), Some((Period::Day, 14.0 * 60.0)));
+        assert_eq!(scheme.next_period(0.0, |_| 30.0), None);
+    }
+
+    #[test]
+    fn default_scheme_matches_defaults() {
+        let scheme = elevation_scheme("redshift-default").unwrap();
+        let defaults = TransitionScheme::new();
+        assert_eq!((scheme.high, scheme.low), (defaults.high, defaults.low));
+        assert!(SCHEMES.iter().all(|s| s.high > s.low));
+    }
+}
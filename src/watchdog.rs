@@ -0,0 +1,139 @@
+//! Telling systemd how the service is doing
+//!
+//! systemd passes a datagram socket in NOTIFY_SOCKET to services with
+//! Type=notify, or with NotifyAccess= set. READY=1 is sent once the
+//! adjustment method has started. With WatchdogSec= set, WATCHDOG=1 is
+//! sent after updates that set the temperature (or found it already
+//! set) without error, so that systemd restarts redshift-rs when the
+//! method keeps failing or the loop hangs. Continual mode updates
+//! every 5 seconds, so WatchdogSec= must be well above that.
+//!
+//! Abstract socket addresses (an '@' in NOTIFY_SOCKET) only exist on
+//! Linux; elsewhere they are reported and the notifier left out.
+
+use std::env;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::{Duration, Instant};
+
+use super::Result;
+
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+
+    /// Time between watchdog pings, half of WatchdogSec=, if the
+    /// watchdog is on for this process
+    interval: Option<Duration>,
+    last_ping: Option<Instant>,
+}
+
+impl Notifier {
+
+    /// The notifier for the socket systemd passed, if any
+    pub fn from_env() -> Result<Option<Notifier>> {
+        let path = match env::var_os("NOTIFY_SOCKET") {
+            Some(path) => path,
+            None => return Ok(None)
+        };
+        let usec = env::var("WATCHDOG_USEC").ok();
+        let pid = env::var("WATCHDOG_PID").ok();
+        let interval = watchdog_interval(usec.as_ref().map(|s| s.as_str()), pid.as_ref().map(|s| s.as_str()),
+                                         process::id());
+        if let Some(interval) = interval {
+            debug!("Pinging the systemd watchdog every {} s", interval.as_secs());
+        }
+        Ok(Some(Notifier {
+            socket: UnixDatagram::unbound()?,
+            addr: socket_addr(&path)?,
+            interval: interval,
+            last_ping: None,
+        }))
+    }
+
+    fn send(&self, state: &str) -> Result<()> {
+        self.socket.send_to_addr(state.as_bytes(), &self.addr)?;
+        Ok(())
+    }
+
+    /// Started, and about to set the temperature
+    pub fn ready(&self) -> Result<()> {
+        self.send("READY=1")
+    }
+
+    /// An update went through, so tell the watchdog all is well, at
+    /// most once per interval
+    pub fn ping(&mut self) -> Result<()> {
+        if let Some(interval) = self.interval {
+            if self.last_ping.map_or(true, |last| last.elapsed() >= interval) {
+                self.send("WATCHDOG=1")?;
+                self.last_ping = Some(Instant::now());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The address of NOTIFY_SOCKET, where a leading '@' stands for an
+/// abstract socket
+fn socket_addr(path: &OsStr) -> Result<SocketAddr> {
+    let bytes = path.as_bytes();
+    if bytes.first() == Some(&b'@') {
+        return abstract_name::socket_addr(&bytes[1..])
+    }
+    Ok(SocketAddr::from_pathname(path)?)
+}
+
+#[cfg(target_os = "linux")]
+mod abstract_name {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    use super::super::Result;
+
+    pub fn socket_addr(name: &[u8]) -> Result<SocketAddr> {
+        Ok(SocketAddr::from_abstract_name(name)?)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod abstract_name {
+    use std::os::unix::net::SocketAddr;
+
+    use super::super::{Result, RedshiftError};
+
+    pub fn socket_addr(_name: &[u8]) -> Result<SocketAddr> {
+        Err(Box::new(RedshiftError::MalformedArgument(
+            format!("abstract NOTIFY_SOCKET addresses are only supported on Linux"))))
+    }
+}
+
+/// Half the watchdog timeout in WATCHDOG_USEC, if WATCHDOG_PID (when
+/// set) is `pid`
+fn watchdog_interval(usec: Option<&str>, watchdog_pid: Option<&str>, pid: u32) -> Option<Duration> {
+    if watchdog_pid.map_or(false, |watchdog_pid| watchdog_pid.parse() != Ok(pid)) {
+        return None
+    }
+    match usec.and_then(|usec| usec.parse::<u64>().ok()) {
+        Some(usec) if usec > 0 => Some(Duration::from_micros(usec / 2)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::watchdog_interval;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(watchdog_interval(Some("30000000"), None, 42), Some(Duration::from_secs(15)));
+        assert_eq!(watchdog_interval(Some("30000000"), Some("42"), 42), Some(Duration::from_secs(15)));
+        assert_eq!(watchdog_interval(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(Some("soon"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+}
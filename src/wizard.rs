@@ -0,0 +1,70 @@
+//! Asking for the location on first run
+//!
+//! When no location is configured, and redshift-rs was started from a
+//! terminal, it asks for a place name or coordinates instead of
+//! silently assuming Copenhagen, and offers to save the answer to the
+//! config file.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use atty;
+use location::{self, Location};
+use super::Result;
+
+/// Whether there is someone to ask, ie both stdin and stdout are
+/// terminals
+pub fn is_interactive() -> bool {
+    atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stdout)
+}
+
+/// Ask for the location, and offer to save it to `config_path`
+///
+/// Returns `None` if the question was skipped with an empty answer.
+pub fn ask_location(config_path: &Path, geocoding_url: &str) -> Result<Option<Location>> {
+    println!("No location is configured, and it is needed to know when the sun sets.");
+    let location = loop {
+        let answer = prompt("Enter a place (like \"Copenhagen, DK\") or coordinates (LAT:LON), or nothing to skip: ")?;
+        if answer.is_empty() {
+            return Ok(None)
+        }
+        let parsed = if answer.contains(|c: char| c.is_ascii_digit()) && answer.contains(':') {
+            answer.parse()
+        } else {
+            location::geocode::resolve(&answer, geocoding_url)
+        };
+        match parsed {
+            Ok(location) => break location,
+            Err(e) => println!("{}", e)
+        }
+    };
+
+    let answer = prompt(&format!("Save this location to {}? [Y/n] ", config_path.display()))?;
+    if answer.is_empty() || answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes") {
+        save_location(config_path, &location)?;
+        info!("Saved the location to {}", config_path.display());
+    }
+    Ok(Some(location))
+}
+
+fn prompt(question: &str) -> Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_owned())
+}
+
+/// Append a [manual] section to the config file, creating it if needed
+///
+/// A [manual] section on its own selects the manual provider, so the
+/// rest of the file is left alone.
+fn save_location(config_path: &Path, location: &Location) -> Result<()> {
+    if let Some(dir) = config_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(config_path)?;
+    write!(f, "\n[manual]\nlat={}\nlon={}\n", location.lat, location.lon)?;
+    Ok(())
+}